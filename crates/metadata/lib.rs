@@ -36,7 +36,7 @@
 //! # }
 //! ```
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io;
 use std::path::Path;
 
@@ -95,10 +95,14 @@ pub enum MetadataError {
 /// targets = [ "x86_64-apple-darwin", "x86_64-pc-windows-msvc" ]
 /// rustc-args = [ "--example-rustc-arg" ]
 /// rustdoc-args = [ "--example-rustdoc-arg" ]
+///
+/// [package.metadata.docs.rs.feature-profiles.tokio]
+/// features = [ "runtime-tokio" ]
+/// no-default-features = true
 /// ```
 ///
 /// You can define one or more fields in your `Cargo.toml`.
-#[derive(Default, Deserialize)]
+#[derive(Clone, Default, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Metadata {
     /// Whether the current crate is a proc-macro (used by docs.rs to hack around cargo bugs).
@@ -138,6 +142,135 @@ pub struct Metadata {
     /// These cannot be a subcommand, they may only be options.
     #[serde(default)]
     cargo_args: Vec<String>,
+
+    /// Additional named feature configurations the crate should be documented with.
+    ///
+    /// See [`Metadata::feature_profile`].
+    #[serde(default)]
+    feature_profiles: BTreeMap<String, FeatureProfile>,
+
+    /// Problems found while parsing the manifest, reported by [`Metadata::validate`].
+    #[serde(skip)]
+    parse_diagnostics: Vec<Diagnostic>,
+}
+
+/// A named set of features to document a crate with, in addition to the main documentation.
+///
+/// This is read from the `[package.metadata.docs.rs.feature-profiles.<name>]` tables, and
+/// overrides the `features`, `all-features` and `no-default-features` settings of [`Metadata`].
+#[derive(Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FeatureProfile {
+    /// List of features to pass on to `cargo`.
+    features: Option<Vec<String>>,
+
+    /// Whether to pass `--all-features` to `cargo`.
+    #[serde(default)]
+    all_features: bool,
+
+    /// Whether to pass `--no-default-features` to `cargo`.
+    #[serde(default)]
+    no_default_features: bool,
+}
+
+/// The keys docs.rs understands in `[package.metadata.docs.rs]`.
+const METADATA_KEYS: &[&str] = &[
+    "features",
+    "all-features",
+    "no-default-features",
+    "default-target",
+    "targets",
+    "rustc-args",
+    "rustdoc-args",
+    "cargo-args",
+    "feature-profiles",
+    "proc-macro",
+];
+
+/// The keys docs.rs understands in `[package.metadata.docs.rs.feature-profiles.<name>]`.
+const FEATURE_PROFILE_KEYS: &[&str] = &["features", "all-features", "no-default-features"];
+
+/// A problem with the docs.rs metadata of a crate.
+///
+/// None of these prevent the crate from being built, but they usually mean that docs.rs
+/// will not build the crate the way its authors intended.
+///
+/// # See also
+/// - [`Metadata::validate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Diagnostic {
+    /// A key docs.rs doesn't know about, which is ignored.
+    UnknownKey {
+        /// The key relative to `[package.metadata.docs.rs]`, e.g. `all_features` or
+        /// `feature-profiles.full.rustdocflags`.
+        key: String,
+        /// The key that was probably meant instead, if any.
+        suggestion: Option<&'static str>,
+    },
+    /// Two options are set which override each other, so one of them has no effect.
+    ConflictingOptions {
+        /// The feature profile the options are set in, or `None` for the top-level options.
+        profile: Option<String>,
+        /// The option which takes precedence.
+        option: &'static str,
+        /// The option which has no effect.
+        ignored: &'static str,
+    },
+    /// A target in `default-target` or `targets` which the toolchain doesn't support.
+    UnknownTarget(String),
+    /// `default-target` or `targets` is set for a proc-macro, which is only ever built for
+    /// the host.
+    ProcMacroTargets,
+    /// A feature profile whose name can't be used in a URL, which is never built.
+    InvalidFeatureProfileName(String),
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Diagnostic::UnknownKey { key, suggestion } => {
+                write!(f, "unknown key `{}`", key)?;
+                match suggestion {
+                    Some(suggestion) => write!(f, ", did you mean `{}`?", suggestion),
+                    None => write!(f, ", it will be ignored"),
+                }
+            }
+            Diagnostic::ConflictingOptions {
+                profile,
+                option,
+                ignored,
+            } => {
+                write!(f, "`{}` has no effect because `{}` is set", ignored, option)?;
+                if let Some(profile) = profile {
+                    write!(f, " in feature profile `{}`", profile)?;
+                }
+                Ok(())
+            }
+            Diagnostic::UnknownTarget(target) => write!(f, "unknown target `{}`", target),
+            Diagnostic::ProcMacroTargets => write!(
+                f,
+                "proc-macros are only built for the host, `default-target` and `targets` are ignored",
+            ),
+            Diagnostic::InvalidFeatureProfileName(name) => write!(
+                f,
+                "feature profile `{}` is ignored, its name may only contain ASCII letters, \
+                 digits, `-` and `_`",
+                name
+            ),
+        }
+    }
+}
+
+/// Whether `name` can be used as the name of a feature profile.
+///
+/// The name ends up in the URL of the documentation built with the profile, so only ASCII
+/// letters, digits, `-` and `_` are allowed.
+pub fn is_valid_feature_profile_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
 }
 
 /// The targets that should be built for a crate.
@@ -201,7 +334,7 @@ impl Metadata {
     /// All of the above is ignored for proc-macros, which are always only compiled for the host.
     pub fn targets(&self, include_default_targets: bool) -> BuildTargets<'_> {
         // Proc macros can only be compiled for the host, so just completely ignore any configured targets.
-        // It would be nice to warn about this somehow ...
+        // `validate` warns about this with `Diagnostic::ProcMacroTargets`.
         if self.proc_macro {
             return BuildTargets {
                 default_target: HOST_TARGET,
@@ -238,6 +371,96 @@ impl Metadata {
         }
     }
 
+    /// Return the names of the feature profiles, in alphabetical order.
+    ///
+    /// Profiles with an invalid name are skipped, see [`is_valid_feature_profile_name`].
+    pub fn feature_profiles(&self) -> impl Iterator<Item = &str> {
+        self.feature_profiles
+            .keys()
+            .map(String::as_str)
+            .filter(|name| is_valid_feature_profile_name(name))
+    }
+
+    /// Return the metadata to use when documenting the feature profile `name`.
+    ///
+    /// The returned metadata has the feature selection of the profile, and otherwise the same
+    /// settings as `self`. Returns `None` if there is no profile with that name.
+    pub fn feature_profile(&self, name: &str) -> Option<Metadata> {
+        let profile = self.feature_profiles.get(name)?;
+        Some(Metadata {
+            features: profile.features.clone(),
+            all_features: profile.all_features,
+            no_default_features: profile.no_default_features,
+            feature_profiles: BTreeMap::new(),
+            parse_diagnostics: Vec::new(),
+            ..self.clone()
+        })
+    }
+
+    /// Check the metadata for settings that are ignored or probably don't do what was intended.
+    ///
+    /// If `known_targets` is given, e.g. from `rustc --print target-list`, all targets which
+    /// aren't in that list are reported too.
+    pub fn validate(&self, known_targets: Option<&[String]>) -> Vec<Diagnostic> {
+        let mut diagnostics = self.parse_diagnostics.clone();
+
+        let profiles = self.feature_profiles.iter().map(|(name, profile)| {
+            (
+                Some(name),
+                profile.all_features,
+                &profile.features,
+                profile.no_default_features,
+            )
+        });
+        for (profile, all_features, features, no_default_features) in std::iter::once((
+            None,
+            self.all_features,
+            &self.features,
+            self.no_default_features,
+        ))
+        .chain(profiles)
+        {
+            let mut conflict = |ignored| {
+                diagnostics.push(Diagnostic::ConflictingOptions {
+                    profile: profile.cloned(),
+                    option: "all-features",
+                    ignored,
+                })
+            };
+            if all_features && features.is_some() {
+                conflict("features");
+            }
+            if all_features && no_default_features {
+                conflict("no-default-features");
+            }
+        }
+
+        if self.proc_macro && (self.default_target.is_some() || self.targets.is_some()) {
+            diagnostics.push(Diagnostic::ProcMacroTargets);
+        }
+
+        for name in self.feature_profiles.keys() {
+            if !is_valid_feature_profile_name(name) {
+                diagnostics.push(Diagnostic::InvalidFeatureProfileName(name.clone()));
+            }
+        }
+
+        if let Some(known_targets) = known_targets {
+            let mut checked = HashSet::new();
+            for target in self
+                .default_target
+                .iter()
+                .chain(self.targets.iter().flatten())
+            {
+                if checked.insert(target) && !known_targets.contains(target) {
+                    diagnostics.push(Diagnostic::UnknownTarget(target.clone()));
+                }
+            }
+        }
+
+        diagnostics
+    }
+
     /// Return the arguments that should be passed to `cargo`.
     ///
     /// This will always include `rustdoc --lib`.
@@ -339,10 +562,10 @@ impl std::str::FromStr for Metadata {
             .and_then(|t| table(t, "package"))
             .and_then(|t| table(t, "metadata"))
             .and_then(|t| table(t, "docs.rs"));
-        let mut metadata = if let Some(table) = plain_table {
-            Value::Table(table.clone()).try_into()?
-        } else if let Some(table) = quoted_table {
-            Value::Table(table.clone()).try_into()?
+        let mut metadata = if let Some(table) = plain_table.or(quoted_table) {
+            let mut metadata: Metadata = Value::Table(table.clone()).try_into()?;
+            metadata.parse_diagnostics = unknown_keys(table);
+            metadata
         } else {
             Metadata::default()
         };
@@ -363,6 +586,52 @@ impl std::str::FromStr for Metadata {
     }
 }
 
+/// Returns a diagnostic for every key in the `[package.metadata.docs.rs]` table `table` that
+/// docs.rs doesn't understand.
+fn unknown_keys(table: &toml::value::Table) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut check = |key: &str, path: String, known: &[&'static str]| {
+        if !known.contains(&key) {
+            diagnostics.push(Diagnostic::UnknownKey {
+                key: path,
+                suggestion: suggest_key(key, known),
+            });
+        }
+    };
+
+    for (key, value) in table {
+        check(key, key.clone(), METADATA_KEYS);
+
+        if let ("feature-profiles", Value::Table(profiles)) = (key.as_str(), value) {
+            for (name, profile) in profiles {
+                if let Value::Table(profile) = profile {
+                    for key in profile.keys() {
+                        let path = format!("feature-profiles.{}.{}", name, key);
+                        check(key, path, FEATURE_PROFILE_KEYS);
+                    }
+                }
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Guess which of the `known` keys was meant by the unknown key `key`.
+fn suggest_key(key: &str, known: &[&'static str]) -> Option<&'static str> {
+    let key = key.to_ascii_lowercase().replace('_', "-");
+    let key = match key.as_str() {
+        "feature" => "features",
+        "target" => "targets",
+        "rustflags" | "rustc-flags" | "rustc-arg" => "rustc-args",
+        "rustdocflags" | "rustdoc-flags" | "rustdoc-arg" => "rustdoc-args",
+        "cargo-flags" | "cargo-arg" => "cargo-args",
+        "feature-profile" | "profiles" => "feature-profiles",
+        other => other,
+    };
+    known.iter().copied().find(|known| *known == key)
+}
+
 #[cfg(test)]
 mod test_parsing {
     use super::*;
@@ -519,6 +788,236 @@ mod test_parsing {
     }
 }
 
+#[cfg(test)]
+mod test_feature_profiles {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_feature_profiles() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            features = [ "runtime-async-std" ]
+            rustdoc-args = [ "--cfg", "docsrs" ]
+
+            [package.metadata.docs.rs.feature-profiles.tokio]
+            features = [ "runtime-tokio" ]
+            no-default-features = true
+
+            [package.metadata.docs.rs.feature-profiles.all]
+            all-features = true
+        "#;
+        let metadata = Metadata::from_str(manifest).unwrap();
+
+        assert_eq!(
+            metadata.feature_profiles().collect::<Vec<_>>(),
+            vec!["all", "tokio"]
+        );
+        assert!(metadata.feature_profile("missing").is_none());
+
+        let tokio = metadata.feature_profile("tokio").unwrap();
+        assert_eq!(tokio.features, Some(vec!["runtime-tokio".into()]));
+        assert!(tokio.no_default_features);
+        assert!(!tokio.all_features);
+        assert_eq!(tokio.rustdoc_args, metadata.rustdoc_args);
+        assert_eq!(tokio.feature_profiles().count(), 0);
+
+        let all = metadata.feature_profile("all").unwrap();
+        assert!(all.features.is_none());
+        assert!(all.all_features);
+    }
+
+    #[test]
+    fn test_no_feature_profiles() {
+        let metadata = Metadata::from_str(
+            r#"
+            [package.metadata.docs.rs]
+            features = [ "feature1" ]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(metadata.feature_profiles().count(), 0);
+    }
+
+    #[test]
+    fn test_invalid_feature_profile_names() {
+        let metadata = Metadata::from_str(
+            r#"
+            [package.metadata.docs.rs.feature-profiles.full]
+            all-features = true
+
+            [package.metadata.docs.rs.feature-profiles."../escape"]
+            all-features = true
+        "#,
+        )
+        .unwrap();
+        assert_eq!(metadata.feature_profiles().collect::<Vec<_>>(), ["full"]);
+        assert_eq!(
+            metadata.validate(None),
+            [Diagnostic::InvalidFeatureProfileName("../escape".into())]
+        );
+        assert!(!is_valid_feature_profile_name(""));
+        assert!(is_valid_feature_profile_name("runtime-tokio_1"));
+    }
+}
+
+#[cfg(test)]
+mod test_validate {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_valid_metadata() {
+        let metadata = Metadata::from_str(
+            r#"
+            [package.metadata.docs.rs]
+            features = [ "feature1" ]
+            default-target = "x86_64-unknown-linux-gnu"
+            targets = [ "x86_64-apple-darwin" ]
+
+            [package.metadata.docs.rs.feature-profiles.full]
+            all-features = true
+        "#,
+        )
+        .unwrap();
+        let known_targets = vec![
+            "x86_64-apple-darwin".to_string(),
+            "x86_64-unknown-linux-gnu".to_string(),
+        ];
+        assert_eq!(metadata.validate(Some(&known_targets)), vec![]);
+        assert_eq!(Metadata::default().validate(None), vec![]);
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let metadata = Metadata::from_str(
+            r#"
+            [package.metadata."docs.rs"]
+            all_features = true
+            rustdocflags = [ "--cfg", "docsrs" ]
+            something-else = 1
+
+            [package.metadata."docs.rs".feature-profiles.minimal]
+            no_default_features = true
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            metadata.validate(None),
+            vec![
+                Diagnostic::UnknownKey {
+                    key: "all_features".into(),
+                    suggestion: Some("all-features"),
+                },
+                Diagnostic::UnknownKey {
+                    key: "feature-profiles.minimal.no_default_features".into(),
+                    suggestion: Some("no-default-features"),
+                },
+                Diagnostic::UnknownKey {
+                    key: "rustdocflags".into(),
+                    suggestion: Some("rustdoc-args"),
+                },
+                Diagnostic::UnknownKey {
+                    key: "something-else".into(),
+                    suggestion: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_conflicting_options() {
+        let metadata = Metadata::from_str(
+            r#"
+            [package.metadata.docs.rs]
+            all-features = true
+            features = [ "feature1" ]
+
+            [package.metadata.docs.rs.feature-profiles.minimal]
+            all-features = true
+            no-default-features = true
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            metadata.validate(None),
+            vec![
+                Diagnostic::ConflictingOptions {
+                    profile: None,
+                    option: "all-features",
+                    ignored: "features",
+                },
+                Diagnostic::ConflictingOptions {
+                    profile: Some("minimal".into()),
+                    option: "all-features",
+                    ignored: "no-default-features",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_targets() {
+        let metadata = Metadata::from_str(
+            r#"
+            [package.metadata.docs.rs]
+            default-target = "x86_64-unknown-linux"
+            targets = [ "x86_64-unknown-linux", "x86_64-apple-darwin", "wasm32" ]
+        "#,
+        )
+        .unwrap();
+        let known_targets = vec!["x86_64-apple-darwin".to_string()];
+        assert_eq!(
+            metadata.validate(Some(&known_targets)),
+            vec![
+                Diagnostic::UnknownTarget("x86_64-unknown-linux".into()),
+                Diagnostic::UnknownTarget("wasm32".into()),
+            ]
+        );
+        assert_eq!(metadata.validate(None), vec![]);
+    }
+
+    #[test]
+    fn test_proc_macro_targets() {
+        let metadata = Metadata::from_str(
+            r#"
+            [lib]
+            proc-macro = true
+
+            [package.metadata.docs.rs]
+            targets = [ "x86_64-apple-darwin" ]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(metadata.validate(None), vec![Diagnostic::ProcMacroTargets]);
+    }
+
+    #[test]
+    fn test_display() {
+        let unknown = Diagnostic::UnknownKey {
+            key: "all_features".into(),
+            suggestion: Some("all-features"),
+        };
+        assert_eq!(
+            unknown.to_string(),
+            "unknown key `all_features`, did you mean `all-features`?"
+        );
+
+        let conflict = Diagnostic::ConflictingOptions {
+            profile: Some("full".into()),
+            option: "all-features",
+            ignored: "features",
+        };
+        assert_eq!(
+            conflict.to_string(),
+            "`features` has no effect because `all-features` is set in feature profile `full`"
+        );
+    }
+}
+
 #[cfg(test)]
 mod test_targets {
     use super::*;
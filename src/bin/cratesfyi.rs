@@ -2,16 +2,30 @@ use std::env;
 use std::fmt::Write;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{anyhow, Context as _, Error, Result};
-use docs_rs::db::{self, add_path_into_database, Pool, PoolClient};
+use docs_rs::db::{
+    self, add_path_into_database,
+    overrides::{self, Overrides},
+    Pool, PoolClient,
+};
 use docs_rs::repositories::RepositoryStatsUpdater;
+use docs_rs::storage::{
+    backfill_storage_usage, collect_garbage, convert_archive_indexes, dedup_report,
+    migrate_to_archive_storage, recompress_archives, storage_usage_report, verify_storage,
+    CompressionAlgorithm, GarbageCollectionOptions, RecompressOptions, ReleaseBatchOptions,
+    VerifyOptions,
+};
 use docs_rs::utils::{
-    get_config, queue_builder, remove_crate_priority, set_crate_priority, ConfigName,
+    get_config, remove_crate_priority, set_crate_priority, start_build_workers,
+    start_lease_expiration, ConfigName,
 };
 use docs_rs::{
-    BuildQueue, Config, Context, Index, Metrics, PackageKind, RustwideBuilder, Server, Storage,
+    BuildConfig, BuildQueue, Config, Context, Index, Metrics, PackageKind, RemoteBuilder,
+    RemoteBuilderConfig, RustwideBuilder, Server, Storage,
 };
+use docsrs_metadata::Metadata;
 use once_cell::sync::OnceCell;
 use sentry_log::SentryLogger;
 use structopt::StructOpt;
@@ -63,7 +77,7 @@ fn logger_init() -> env_logger::Logger {
             writeln!(
                 buf,
                 "{} [{}] {}: {}",
-                time::now().strftime("%Y/%m/%d %H:%M:%S").unwrap(),
+                chrono::Local::now().format("%Y/%m/%d %H:%M:%S"),
                 record.level(),
                 record.target(),
                 record.args()
@@ -107,6 +121,17 @@ enum CommandLine {
 
     StartBuildServer,
 
+    /// Starts a builder building crates leased from the build queue of another docs.rs
+    /// instance, authenticated with `DOCSRS_REMOTE_BUILDER_TOKEN`
+    StartRemoteBuilder {
+        /// The URL of the docs.rs instance
+        #[structopt(long = "server")]
+        server: String,
+        /// The name of this builder, shown in the build queue
+        #[structopt(long = "name")]
+        name: String,
+    },
+
     /// Starts the daemon
     Daemon {
         /// Enable or disable the registry watcher to automatically enqueue newly published crates
@@ -129,6 +154,24 @@ enum CommandLine {
         #[structopt(subcommand)]
         subcommand: QueueSubcommand,
     },
+
+    /// Inspect the docs.rs metadata of a crate
+    Metadata {
+        #[structopt(subcommand)]
+        subcommand: MetadataSubcommand,
+    },
+
+    /// Override the sandbox limits of specific crates
+    Limits {
+        #[structopt(subcommand)]
+        subcommand: LimitsSubcommand,
+    },
+
+    /// Maintenance of the stored documentation and sources
+    Storage {
+        #[structopt(subcommand)]
+        subcommand: StorageSubcommand,
+    },
 }
 
 impl CommandLine {
@@ -148,18 +191,28 @@ impl CommandLine {
             }
             Self::StartBuildServer => {
                 let build_queue = ctx.build_queue()?;
-                let rustwide_builder = RustwideBuilder::init(&ctx)?;
-                queue_builder(rustwide_builder, build_queue)?;
+                for worker in start_build_workers(&ctx, build_queue)? {
+                    // the workers never return
+                    let _ = worker.join();
+                }
+            }
+            Self::StartRemoteBuilder { server, name } => {
+                RemoteBuilder::new(RemoteBuilderConfig::from_env(&server)?, &name)?.run()?;
             }
             Self::StartWebServer { socket_addr } => {
+                // remote builders lease crates from the web server
+                start_lease_expiration(&ctx)?;
                 // Blocks indefinitely
-                let _ = Server::start(Some(&socket_addr), &ctx)?;
+                Server::start(Some(&socket_addr), &ctx)?.wait();
             }
             Self::Daemon { registry_watcher } => {
                 docs_rs::utils::start_daemon(&ctx, registry_watcher == Toggle::Enabled)?;
             }
             Self::Database { subcommand } => subcommand.handle_args(ctx)?,
             Self::Queue { subcommand } => subcommand.handle_args(ctx)?,
+            Self::Metadata { subcommand } => subcommand.handle_args()?,
+            Self::Limits { subcommand } => subcommand.handle_args(ctx)?,
+            Self::Storage { subcommand } => subcommand.handle_args(ctx)?,
         }
 
         Ok(())
@@ -213,6 +266,80 @@ impl QueueSubcommand {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+enum LimitsSubcommand {
+    /// Show the limits overridden for a crate
+    Get {
+        /// Crate name
+        #[structopt(name = "CRATE_NAME")]
+        crate_name: String,
+    },
+
+    /// Override limits of a crate, keeping the limits already overridden
+    Set {
+        /// Crate name
+        #[structopt(name = "CRATE_NAME")]
+        crate_name: String,
+        /// The available memory, in bytes
+        #[structopt(long)]
+        memory: Option<usize>,
+        /// The most targets the documentation is built for
+        #[structopt(long)]
+        targets: Option<usize>,
+        /// The maximum build time, in seconds
+        #[structopt(long)]
+        timeout: Option<u64>,
+        /// Whether the build can access the network
+        #[structopt(long)]
+        networking: Option<bool>,
+    },
+
+    /// Remove all overridden limits of a crate, so it's built with the defaults again
+    Unset {
+        /// Crate name
+        #[structopt(name = "CRATE_NAME")]
+        crate_name: String,
+    },
+}
+
+impl LimitsSubcommand {
+    fn handle_args(self, ctx: BinContext) -> Result<()> {
+        let conn = &mut *ctx.conn()?;
+        match self {
+            Self::Get { crate_name } => {
+                match overrides::get_overrides(conn, &crate_name)
+                    .context("failed to get the overridden limits")?
+                {
+                    Some(overrides) => print!("{}", overrides),
+                    None => println!("{} is built with the default limits", crate_name),
+                }
+            }
+
+            Self::Set {
+                crate_name,
+                memory,
+                targets,
+                timeout,
+                networking,
+            } => {
+                let overrides = Overrides {
+                    memory,
+                    targets,
+                    timeout: timeout.map(Duration::from_secs),
+                    networking,
+                };
+                let overrides = overrides::set_overrides(conn, &crate_name, &overrides)
+                    .context("failed to override the limits")?;
+                print!("{}", overrides);
+            }
+
+            Self::Unset { crate_name } => overrides::remove_overrides(conn, &crate_name)
+                .context("failed to remove the overridden limits")?,
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
 enum PrioritySubcommand {
     /// Set all crates matching a pattern to a priority level
@@ -296,6 +423,17 @@ enum BuildSubcommand {
         local: Option<PathBuf>,
     },
 
+    /// Build a local crate like docs.rs would, without the database or storage
+    Standalone {
+        /// Path of the crate root
+        #[structopt(name = "PATH")]
+        path: PathBuf,
+
+        /// Directory to write the documentation and the build log to
+        #[structopt(short = "o", long = "output", default_value = "docsrs-output")]
+        output: PathBuf,
+    },
+
     /// update the currently installed rustup toolchain
     UpdateToolchain {
         /// Update the toolchain only if no toolchain is currently installed
@@ -315,8 +453,6 @@ enum BuildSubcommand {
 
 impl BuildSubcommand {
     fn handle_args(self, ctx: BinContext, skip_if_exists: bool) -> Result<()> {
-        let build_queue = ctx.build_queue()?;
-
         let rustwide_builder = || -> Result<RustwideBuilder> {
             let mut builder = RustwideBuilder::init(&ctx)?;
             builder.set_skip_build_if_exists(skip_if_exists);
@@ -358,6 +494,20 @@ impl BuildSubcommand {
                 }
             }
 
+            Self::Standalone { path, output } => {
+                let report = RustwideBuilder::init_standalone(BuildConfig::from_env()?)?
+                    .build_standalone(&path, &output)
+                    .context("Building documentation failed")?;
+                print!("{}", report);
+                println!(
+                    "build log and documentation written to {}",
+                    output.display()
+                );
+                if !report.successful() {
+                    return Err(anyhow!("the documentation failed to build"));
+                }
+            }
+
             Self::UpdateToolchain { only_first_time } => {
                 if only_first_time {
                     let mut conn = ctx
@@ -382,14 +532,264 @@ impl BuildSubcommand {
                     .context("failed to add essential files")?;
             }
 
-            Self::Lock => build_queue.lock().context("Failed to lock")?,
-            Self::Unlock => build_queue.unlock().context("Failed to unlock")?,
+            Self::Lock => ctx.build_queue()?.lock().context("Failed to lock")?,
+            Self::Unlock => ctx.build_queue()?.unlock().context("Failed to unlock")?,
         }
 
         Ok(())
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+enum MetadataSubcommand {
+    /// Check `[package.metadata.docs.rs]` for settings that are ignored or conflicting
+    Check {
+        /// Path of the crate root or of its `Cargo.toml`
+        #[structopt(name = "PATH", default_value = ".")]
+        path: PathBuf,
+    },
+}
+
+impl MetadataSubcommand {
+    fn handle_args(self) -> Result<()> {
+        match self {
+            Self::Check { path } => {
+                let metadata = if path.is_dir() {
+                    Metadata::from_crate_root(&path)
+                } else {
+                    Metadata::from_manifest(&path)
+                }
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+
+                // Use the local toolchain to check the targets, if there is one.
+                let known_targets = std::process::Command::new("rustc")
+                    .args(["--print", "target-list"])
+                    .output()
+                    .ok()
+                    .filter(|output| output.status.success())
+                    .map(|output| {
+                        String::from_utf8_lossy(&output.stdout)
+                            .lines()
+                            .map(str::to_owned)
+                            .collect::<Vec<_>>()
+                    });
+
+                let diagnostics = metadata.validate(known_targets.as_deref());
+                for diagnostic in &diagnostics {
+                    println!("warning: {}", diagnostic);
+                }
+                if !diagnostics.is_empty() {
+                    return Err(anyhow!(
+                        "found {} problem(s) in the docs.rs metadata",
+                        diagnostics.len()
+                    ));
+                }
+                println!("no problems found in the docs.rs metadata");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+enum StorageSubcommand {
+    /// Recompress the rustdoc and source archives with another algorithm. Stopping and starting
+    /// it again continues with the remaining archives.
+    Recompress {
+        /// The algorithm to recompress the archives with, `Zstd` or `Bzip2`
+        #[structopt(long = "algorithm", default_value = "Zstd")]
+        algorithm: CompressionAlgorithm,
+        #[structopt(flatten)]
+        releases: ReleaseBatchArgs,
+    },
+
+    /// Pack the separately stored rustdoc and source files of older releases into archives and
+    /// delete the separate files. Stopping and starting it again continues with the remaining
+    /// releases.
+    MigrateToArchives {
+        #[structopt(flatten)]
+        releases: ReleaseBatchArgs,
+    },
+
+    /// Convert the archive indexes still in the legacy format to the current one. The last
+    /// processed release id is printed, pass it to `--start-after` to continue from there.
+    ConvertIndexes {
+        #[structopt(flatten)]
+        releases: ReleaseBatchArgs,
+    },
+
+    /// Check that the archives and indexes of releases exist and that their entries can be read,
+    /// recording the problems found in the `storage_failures` table. The last processed release
+    /// id is printed, pass it to `--start-after` to continue from there.
+    Verify {
+        #[structopt(flatten)]
+        releases: ReleaseBatchArgs,
+        /// The percentage of releases to check
+        #[structopt(long = "sample", default_value = "100")]
+        sample: u32,
+        /// The most entries to read from each archive, all of them by default
+        #[structopt(long = "entries")]
+        entries: Option<usize>,
+        /// Queue releases with problems for a rebuild
+        #[structopt(long = "rebuild")]
+        rebuild: bool,
+        /// The priority of the rebuilds
+        #[structopt(long = "rebuild-priority", default_value = "10")]
+        rebuild_priority: i32,
+    },
+
+    /// Delete files that don't belong to any release or build anymore
+    Gc {
+        /// Only list the orphaned files, without deleting them
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+        /// Keep files updated in the last hours, they could belong to running builds
+        #[structopt(long = "min-age", default_value = "24")]
+        min_age: u64,
+        /// Milliseconds to wait after each deleted batch, to leave resources for the builds
+        #[structopt(long = "pause", default_value = "0")]
+        pause: u64,
+    },
+
+    /// Report how much space content-addressed storage saves
+    DedupReport,
+
+    /// Report the recorded storage usage per backend and the crates using the most of it
+    Usage {
+        /// How many crates to list
+        #[structopt(long = "top", default_value = "20")]
+        top: usize,
+    },
+
+    /// Record the storage usage of releases stored before it was recorded, by reading their
+    /// files back from the storage backend
+    BackfillUsage {
+        #[structopt(flatten)]
+        releases: ReleaseBatchArgs,
+    },
+}
+
+/// The arguments of the storage commands processing one release after another.
+#[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+struct ReleaseBatchArgs {
+    /// Only process releases with a higher id
+    #[structopt(long = "start-after", default_value = "0")]
+    start_after: i32,
+    /// The most releases to process
+    #[structopt(long = "limit")]
+    limit: Option<usize>,
+    /// Milliseconds to wait after each release, to leave resources for the builds
+    #[structopt(long = "pause", default_value = "0")]
+    pause: u64,
+}
+
+impl ReleaseBatchArgs {
+    fn options(&self) -> ReleaseBatchOptions {
+        ReleaseBatchOptions {
+            start_after: self.start_after,
+            limit: self.limit,
+            pause: Duration::from_millis(self.pause),
+        }
+    }
+}
+
+impl StorageSubcommand {
+    fn handle_args(self, ctx: BinContext) -> Result<()> {
+        match self {
+            Self::Recompress {
+                algorithm,
+                releases,
+            } => {
+                let report = recompress_archives(
+                    &mut *ctx.conn()?,
+                    &*ctx.storage()?,
+                    &RecompressOptions {
+                        algorithm,
+                        releases: releases.options(),
+                    },
+                )?;
+                println!("{}", report);
+            }
+
+            Self::MigrateToArchives { releases } => {
+                let report = migrate_to_archive_storage(
+                    &mut *ctx.conn()?,
+                    &*ctx.storage()?,
+                    &releases.options(),
+                )?;
+                println!("{}", report);
+            }
+
+            Self::ConvertIndexes { releases } => {
+                let report = convert_archive_indexes(
+                    &mut *ctx.conn()?,
+                    &*ctx.storage()?,
+                    &releases.options(),
+                )?;
+                println!("{}", report);
+            }
+
+            Self::Verify {
+                releases,
+                sample,
+                entries,
+                rebuild,
+                rebuild_priority,
+            } => {
+                let report = verify_storage(
+                    &mut *ctx.conn()?,
+                    &*ctx.storage()?,
+                    &*ctx.build_queue()?,
+                    &VerifyOptions {
+                        releases: releases.options(),
+                        sample: f64::from(sample) / 100.0,
+                        entries,
+                        rebuild_priority: rebuild.then_some(rebuild_priority),
+                    },
+                )?;
+                println!("{}", report);
+            }
+
+            Self::Gc {
+                dry_run,
+                min_age,
+                pause,
+            } => {
+                let report = collect_garbage(
+                    &mut *ctx.conn()?,
+                    &*ctx.storage()?,
+                    &GarbageCollectionOptions {
+                        dry_run,
+                        min_age: Duration::from_secs(min_age * 60 * 60),
+                        pause: Duration::from_millis(pause),
+                    },
+                )?;
+                println!("{}", report);
+            }
+
+            Self::DedupReport => {
+                let report = dedup_report(&mut *ctx.conn()?, &*ctx.storage()?)?;
+                println!("{}", report);
+            }
+
+            Self::Usage { top } => {
+                let report = storage_usage_report(&mut *ctx.conn()?, top)?;
+                println!("{}", report);
+            }
+
+            Self::BackfillUsage { releases } => {
+                let report = backfill_storage_usage(
+                    &mut *ctx.conn()?,
+                    &*ctx.storage()?,
+                    &releases.options(),
+                )?;
+                println!("{}", report);
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
 enum DatabaseSubcommand {
     /// Run database migration
@@ -609,15 +1009,7 @@ impl Context for BinContext {
         )?;
         fn config(self) -> Config = Config::from_env()?;
         fn metrics(self) -> Metrics = Metrics::new()?;
-        fn index(self) -> Index = {
-            let config = self.config()?;
-            let path = config.registry_index_path.clone();
-            if let Some(registry_url) = config.registry_url.clone() {
-                Index::from_url(path, registry_url)
-            } else {
-                Index::new(path)
-            }?
-        };
+        fn index(self) -> Index = Index::from_config(&*self.config()?)?;
         fn repository_stats_updater(self) -> RepositoryStatsUpdater = {
             let config = self.config()?;
             let pool = self.pool()?;
@@ -1,5 +1,5 @@
-use crate::db::{delete_crate, Pool};
-use crate::docbuilder::PackageKind;
+use crate::db::{blacklist::is_blacklisted, delete_crate, notify_release_changed, Pool};
+use crate::docbuilder::{Limits, PackageKind, ToolchainUpdateError};
 use crate::error::Result;
 use crate::storage::Storage;
 use crate::utils::{get_config, get_crate_priority, report_error, set_config, ConfigName};
@@ -7,12 +7,11 @@ use crate::{Config, Index, Metrics, RustwideBuilder};
 use anyhow::Context;
 
 use crates_index_diff::Change;
-use log::{debug, info};
+use log::{debug, error, info, warn};
 
 use git2::Oid;
+use postgres::{Client, GenericClient, Row};
 use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
 
 #[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
 pub(crate) struct QueuedCrate {
@@ -22,6 +21,9 @@ pub(crate) struct QueuedCrate {
     pub(crate) version: String,
     pub(crate) priority: i32,
     pub(crate) registry: Option<String>,
+    /// The limits to build the crate with after its last build exceeded its limits.
+    #[serde(skip)]
+    pub(crate) escalated_limits: Option<Limits>,
 }
 
 #[derive(Debug)]
@@ -113,28 +115,33 @@ impl BuildQueue {
 
     pub(crate) fn queued_crates(&self) -> Result<Vec<QueuedCrate>> {
         let query = self.db.get()?.query(
-            "SELECT id, name, version, priority, registry
+            "SELECT id, name, version, priority, registry, escalated_limits
              FROM queue
              WHERE attempt < $1
              ORDER BY priority ASC, attempt ASC, id ASC",
             &[&self.max_attempts],
         )?;
 
-        Ok(query
+        query
             .into_iter()
-            .map(|row| QueuedCrate {
-                id: row.get("id"),
-                name: row.get("name"),
-                version: row.get("version"),
-                priority: row.get("priority"),
-                registry: row.get("registry"),
+            .map(|row| {
+                Ok(QueuedCrate {
+                    id: row.get("id"),
+                    name: row.get("name"),
+                    version: row.get("version"),
+                    priority: row.get("priority"),
+                    registry: row.get("registry"),
+                    escalated_limits: escalated_limits(&row)?,
+                })
             })
-            .collect())
+            .collect()
     }
 
+    /// Processes the next crate in the queue with `f`, which returns the escalated limits to retry
+    /// the build with if it exceeded its limits.
     pub(crate) fn process_next_crate(
         &self,
-        f: impl FnOnce(&QueuedCrate) -> Result<()>,
+        f: impl FnOnce(&QueuedCrate) -> Result<Option<Limits>>,
     ) -> Result<()> {
         let mut conn = self.db.get()?;
         let mut transaction = conn.transaction()?;
@@ -145,24 +152,23 @@ impl BuildQueue {
         // `SKIP LOCKED` here will enable another build-server to just
         // skip over taken (=locked) rows and start building the first
         // available one.
-        let to_process = match transaction
-            .query_opt(
-                "SELECT id, name, version, priority, registry
+        let to_process = match transaction.query_opt(
+            "SELECT id, name, version, priority, registry, escalated_limits
                  FROM queue
-                 WHERE attempt < $1
+                 WHERE attempt < $1 AND lease_id IS NULL
                  ORDER BY priority ASC, attempt ASC, id ASC
                  LIMIT 1 
                  FOR UPDATE SKIP LOCKED",
-                &[&self.max_attempts],
-            )?
-            .map(|row| QueuedCrate {
+            &[&self.max_attempts],
+        )? {
+            Some(row) => QueuedCrate {
                 id: row.get("id"),
                 name: row.get("name"),
                 version: row.get("version"),
                 priority: row.get("priority"),
                 registry: row.get("registry"),
-            }) {
-            Some(krate) => krate,
+                escalated_limits: escalated_limits(&row)?,
+            },
             None => return Ok(()),
         };
 
@@ -174,7 +180,16 @@ impl BuildQueue {
         });
         self.metrics.total_builds.inc();
         match res {
-            Ok(()) => {
+            Ok(Some(limits)) => {
+                retry_with_escalated_limits(
+                    &mut transaction,
+                    to_process.id,
+                    &to_process.name,
+                    &to_process.version,
+                    &limits,
+                )?;
+            }
+            Ok(None) => {
                 transaction.execute("DELETE FROM queue WHERE id = $1;", &[&to_process.id])?;
             }
             Err(e) => {
@@ -200,6 +215,251 @@ impl BuildQueue {
     }
 }
 
+/// A queued crate leased to a remote builder, see [`BuildQueue::lease_next_crate`].
+#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Lease {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) version: String,
+    pub(crate) registry: Option<String>,
+    /// The limits to build the crate with after its last build exceeded its limits.
+    pub(crate) escalated_limits: Option<Limits>,
+}
+
+impl Lease {
+    /// Returns the limits to build the leased crate with.
+    pub(crate) fn limits(&self, conn: &mut Client) -> Result<Limits> {
+        match &self.escalated_limits {
+            Some(limits) => Ok(limits.clone()),
+            None => Limits::for_crate(conn, &self.name),
+        }
+    }
+}
+
+/// Returns the escalated limits of a queued crate.
+fn escalated_limits(row: &Row) -> Result<Option<Limits>> {
+    Ok(row
+        .get::<_, Option<serde_json::Value>>("escalated_limits")
+        .map(serde_json::from_value)
+        .transpose()?)
+}
+
+/// Returns the crate to the queue to retry its build with escalated limits, after its build
+/// exceeded its memory or time limit.
+fn retry_with_escalated_limits(
+    conn: &mut impl GenericClient,
+    id: i32,
+    name: &str,
+    version: &str,
+    limits: &Limits,
+) -> Result<()> {
+    info!(
+        "{} {} exceeded its limits, retrying with {} MiB of memory and a timeout of {} seconds",
+        name,
+        version,
+        limits.memory() / 1024 / 1024,
+        limits.timeout().as_secs(),
+    );
+    conn.execute(
+        "UPDATE queue
+         SET escalated_limits = $2,
+             attempt = 0,
+             lease_id = NULL,
+             leased_by = NULL,
+             lease_expires = NULL
+         WHERE id = $1;",
+        &[&id, &serde_json::to_value(limits)?],
+    )?;
+    Ok(())
+}
+
+/// Remote builders.
+///
+/// They lease crates from the queue instead of locking them in a transaction, and have to renew
+/// the lease while building. Once a lease expires the crate returns to the queue.
+impl BuildQueue {
+    /// Leases the next crate in the queue to the remote builder, if there is one.
+    pub(crate) fn lease_next_crate(&self, builder: &str) -> Result<Option<Lease>> {
+        let mut conn = self.db.get()?;
+
+        loop {
+            let mut id = [0; 16];
+            getrandom::getrandom(&mut id)?;
+            let id: String = id.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+            let lease = match conn.query_opt(
+                "UPDATE queue
+                 SET lease_id = $1,
+                     leased_by = $2,
+                     lease_expires = NOW() + make_interval(secs => $3)
+                 WHERE id = (
+                     SELECT id
+                     FROM queue
+                     WHERE attempt < $4 AND lease_id IS NULL
+                     ORDER BY priority ASC, attempt ASC, id ASC
+                     LIMIT 1
+                     FOR UPDATE SKIP LOCKED
+                 )
+                 RETURNING name, version, registry, escalated_limits",
+                &[
+                    &id,
+                    &builder,
+                    &(self.config.remote_build_lease_duration as f64),
+                    &self.max_attempts,
+                ],
+            )? {
+                Some(row) => Lease {
+                    id,
+                    name: row.get("name"),
+                    version: row.get("version"),
+                    registry: row.get("registry"),
+                    escalated_limits: escalated_limits(&row)?,
+                },
+                None => return Ok(None),
+            };
+
+            // The local builders skip blacklisted crates while building them.
+            if is_blacklisted(&mut conn, &lease.name)? {
+                info!(
+                    "skipping build of {}, crate has been blacklisted",
+                    lease.name
+                );
+                conn.execute("DELETE FROM queue WHERE lease_id = $1", &[&lease.id])?;
+                continue;
+            }
+
+            info!(
+                "leased {} {} to remote builder {}",
+                lease.name, lease.version, builder
+            );
+            return Ok(Some(lease));
+        }
+    }
+
+    /// Extends the lease, returning the leased crate if the lease is still held by the builder.
+    ///
+    /// Leases which already expired can still be renewed until their crate is leased again.
+    pub(crate) fn renew_lease(&self, lease_id: &str) -> Result<Option<Lease>> {
+        self.db
+            .get()?
+            .query_opt(
+                "UPDATE queue
+                 SET lease_expires = NOW() + make_interval(secs => $2)
+                 WHERE lease_id = $1
+                 RETURNING name, version, registry, escalated_limits",
+                &[&lease_id, &(self.config.remote_build_lease_duration as f64)],
+            )?
+            .map(|row| {
+                Ok(Lease {
+                    id: lease_id.into(),
+                    name: row.get("name"),
+                    version: row.get("version"),
+                    registry: row.get("registry"),
+                    escalated_limits: escalated_limits(&row)?,
+                })
+            })
+            .transpose()
+    }
+
+    /// Removes the crate from the queue once its build was published, or returns it to the
+    /// queue if building or publishing it failed or the build is retried with escalated limits,
+    /// like [`BuildQueue::process_next_crate`].
+    ///
+    /// Failures are only logged with their message: a backtrace of the web server wouldn't tell
+    /// anything about a failed build on the remote builder.
+    ///
+    /// Returns whether the lease was still held by the builder.
+    pub(crate) fn finish_lease(
+        &self,
+        lease_id: &str,
+        result: Result<Option<Limits>, String>,
+    ) -> Result<bool> {
+        let mut conn = self.db.get()?;
+        let mut transaction = conn.transaction()?;
+
+        let row = match transaction.query_opt(
+            "SELECT id, name, version FROM queue WHERE lease_id = $1 FOR UPDATE",
+            &[&lease_id],
+        )? {
+            Some(row) => row,
+            None => return Ok(false),
+        };
+        let id: i32 = row.get("id");
+
+        self.metrics.total_builds.inc();
+        match result {
+            Ok(Some(limits)) => {
+                retry_with_escalated_limits(
+                    &mut transaction,
+                    id,
+                    row.get("name"),
+                    row.get("version"),
+                    &limits,
+                )?;
+            }
+            Ok(None) => {
+                transaction.execute("DELETE FROM queue WHERE id = $1;", &[&id])?;
+            }
+            Err(message) => {
+                let attempt: i32 = transaction
+                    .query_one(
+                        "UPDATE queue
+                         SET attempt = attempt + 1,
+                             lease_id = NULL,
+                             leased_by = NULL,
+                             lease_expires = NULL
+                         WHERE id = $1
+                         RETURNING attempt;",
+                        &[&id],
+                    )?
+                    .get(0);
+
+                if attempt >= self.max_attempts {
+                    self.metrics.failed_builds.inc();
+                }
+
+                error!(
+                    "Failed to build package {}-{} on a remote builder: {}",
+                    row.get::<_, String>("name"),
+                    row.get::<_, String>("version"),
+                    message,
+                );
+            }
+        }
+
+        transaction.commit()?;
+        Ok(true)
+    }
+
+    /// Returns the crates of expired leases to the queue, counting them as a failed attempt.
+    ///
+    /// This runs periodically while remote builders are enabled, see
+    /// [`crate::utils::daemon::start_lease_expiration`].
+    pub(crate) fn expire_leases(&self) -> Result<()> {
+        for row in self.db.get()?.query(
+            "UPDATE queue
+             SET attempt = attempt + 1,
+                 lease_id = NULL,
+                 leased_by = NULL,
+                 lease_expires = NULL
+             WHERE lease_expires < NOW()
+             RETURNING name, version, attempt",
+            &[],
+        )? {
+            warn!(
+                "the lease of {} {} expired, returning it to the queue",
+                row.get::<_, String>("name"),
+                row.get::<_, String>("version"),
+            );
+            self.metrics.total_builds.inc();
+            if row.get::<_, i32>("attempt") >= self.max_attempts {
+                self.metrics.failed_builds.inc();
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Locking functions.
 impl BuildQueue {
     /// Checks for the lock and returns whether it currently exists.
@@ -222,29 +482,6 @@ impl BuildQueue {
     }
 }
 
-fn retry<T>(mut f: impl FnMut() -> Result<T>, max_attempts: u32) -> Result<T> {
-    for attempt in 1.. {
-        match f() {
-            Ok(result) => return Ok(result),
-            Err(err) => {
-                if attempt > max_attempts {
-                    return Err(err);
-                } else {
-                    let sleep_for = 2u32.pow(attempt);
-                    log::warn!(
-                        "got error on attempt {}, will try again after {}s:\n{:?}",
-                        attempt,
-                        sleep_for,
-                        err
-                    );
-                    thread::sleep(Duration::from_secs(sleep_for as u64));
-                }
-            }
-        }
-    }
-    unreachable!()
-}
-
 /// Index methods.
 impl BuildQueue {
     /// Updates registry index repository and adds new crates into build queue.
@@ -274,6 +511,8 @@ impl BuildQueue {
                             ",
                             &[&release.name, &release.version],
                         )
+                        .map_err(Into::into)
+                        .and_then(|_| notify_release_changed(&mut *conn, &release.name))
                         .with_context(|| {
                             format!(
                                 "error while setting {}-{} to yanked",
@@ -349,39 +588,22 @@ impl BuildQueue {
                 .map(|r| PackageKind::Registry(r.as_str()))
                 .unwrap_or(PackageKind::CratesIo);
 
-            match retry(
-                || {
-                    builder
-                        .update_toolchain()
-                        .context("Updating toolchain failed, locking queue")
-                },
-                3,
+            let outcome = match builder.build_queued_package(
+                &krate.name,
+                &krate.version,
+                kind,
+                krate.escalated_limits.as_ref(),
             ) {
-                Err(err) => {
+                Ok(outcome) => outcome,
+                Err(err) if err.is::<ToolchainUpdateError>() => {
+                    let err = err.context("locking queue");
                     report_error(&err);
                     self.lock()?;
                     return Err(err);
                 }
-                Ok(true) => {
-                    // toolchain has changed, purge caches
-                    if let Err(err) = retry(
-                        || {
-                            builder
-                                .purge_caches()
-                                .context("purging rustwide caches failed, locking queue")
-                        },
-                        3,
-                    ) {
-                        report_error(&err);
-                        self.lock()?;
-                        return Err(err);
-                    }
-                }
-                Ok(false) => {}
-            }
-
-            builder.build_package(&krate.name, &krate.version, kind)?;
-            Ok(())
+                Err(err) => return Err(err),
+            };
+            Ok(outcome.retry_limits)
         })?;
 
         Ok(processed)
@@ -471,7 +693,7 @@ mod tests {
             let assert_next = |name| -> Result<()> {
                 queue.process_next_crate(|krate| {
                     assert_eq!(name, krate.name);
-                    Ok(())
+                    Ok(None)
                 })?;
                 Ok(())
             };
@@ -511,7 +733,7 @@ mod tests {
             let mut called = false;
             queue.process_next_crate(|_| {
                 called = true;
-                Ok(())
+                Ok(None)
             })?;
             assert!(!called, "there were still items in the queue");
 
@@ -537,7 +759,7 @@ mod tests {
 
             queue.process_next_crate(|krate| {
                 assert_eq!("foo", krate.name);
-                Ok(())
+                Ok(None)
             })?;
             assert_eq!(queue.pending_count()?, 1);
 
@@ -560,7 +782,7 @@ mod tests {
 
             queue.process_next_crate(|krate| {
                 assert_eq!("bar", krate.name);
-                Ok(())
+                Ok(None)
             })?;
             assert_eq!(queue.prioritized_count()?, 1);
 
@@ -593,7 +815,7 @@ mod tests {
 
             queue.process_next_crate(|krate| {
                 assert_eq!("bar", krate.name);
-                Ok(())
+                Ok(None)
             })?;
             assert_eq!(queue.failed_count()?, 1);
 
@@ -601,6 +823,118 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_leased_crates_are_skipped() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", -10, None)?;
+            queue.add_crate("bar", "1.0.0", 0, None)?;
+
+            let lease = queue.lease_next_crate("remote")?.expect("no crate leased");
+            assert_eq!(
+                ("foo", "1.0.0"),
+                (lease.name.as_str(), lease.version.as_str())
+            );
+
+            queue.process_next_crate(|krate| {
+                assert_eq!("bar", krate.name);
+                Ok(None)
+            })?;
+            assert!(queue.lease_next_crate("remote")?.is_none());
+            assert_eq!(queue.renew_lease(&lease.id)?, Some(lease));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_finish_lease() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+
+            let lease = queue.lease_next_crate("remote")?.expect("no crate leased");
+            assert!(queue.finish_lease(&lease.id, Err("this failed".into()))?);
+            assert!(!queue.finish_lease(&lease.id, Ok(None))?);
+
+            let lease = queue.lease_next_crate("remote")?.expect("no crate leased");
+            assert!(queue.finish_lease(&lease.id, Ok(None))?);
+            assert_eq!(queue.pending_count()?, 0);
+
+            assert_eq!(env.metrics().total_builds.get(), 2);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_retry_with_escalated_limits() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+            queue.add_crate("bar", "1.0.0", 10, None)?;
+
+            let limits = Limits::default();
+            queue.process_next_crate(|krate| {
+                assert_eq!("foo", krate.name);
+                assert_eq!(krate.escalated_limits, None);
+                Ok(Some(limits.clone()))
+            })?;
+            assert_eq!(queue.pending_count()?, 2);
+
+            // the retried crate keeps its priority
+            queue.process_next_crate(|krate| {
+                assert_eq!("foo", krate.name);
+                assert_eq!(krate.escalated_limits.as_ref(), Some(&limits));
+                Ok(None)
+            })?;
+
+            let lease = queue.lease_next_crate("remote")?.expect("no crate leased");
+            assert_eq!(lease.escalated_limits, None);
+            assert!(queue.finish_lease(&lease.id, Ok(Some(limits.clone())))?);
+
+            let lease = queue.lease_next_crate("remote")?.expect("no crate leased");
+            assert_eq!("bar", lease.name);
+            assert_eq!(lease.escalated_limits.as_ref(), Some(&limits));
+            assert_eq!(lease.limits(&mut env.db().conn())?, limits);
+            assert!(queue.finish_lease(&lease.id, Ok(None))?);
+            assert_eq!(queue.pending_count()?, 0);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_expired_leases_return_to_the_queue() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+
+            let lease = queue.lease_next_crate("remote")?.expect("no crate leased");
+            env.db().conn().execute(
+                "UPDATE queue SET lease_expires = NOW() - INTERVAL '1 minute'",
+                &[],
+            )?;
+            // expired leases are kept until they are expired periodically
+            assert!(queue.lease_next_crate("other")?.is_none());
+            queue.expire_leases()?;
+
+            let new_lease = queue.lease_next_crate("other")?.expect("no crate leased");
+            assert_eq!("foo", new_lease.name);
+            assert_ne!(lease.id, new_lease.id);
+            let attempt: i32 = env
+                .db()
+                .conn()
+                .query_one("SELECT attempt FROM queue", &[])?
+                .get(0);
+            assert_eq!(attempt, 1);
+
+            assert!(queue.renew_lease(&lease.id)?.is_none());
+            assert!(!queue.finish_lease(&lease.id, Ok(None))?);
+            assert_eq!(env.metrics().total_builds.get(), 1);
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_queued_crates() {
         crate::test::wrapper(|env| {
@@ -0,0 +1,69 @@
+use super::{CdnBackend, CdnBusy};
+use crate::error::Result;
+use aws_sdk_cloudfront::{
+    model::{InvalidationBatch, Paths},
+    types::SdkError,
+    Client, RetryConfig,
+};
+use chrono::Utc;
+use tokio::runtime::Runtime;
+
+/// Creates invalidations in an Amazon CloudFront distribution.
+pub(crate) struct CloudFront {
+    client: Client,
+    runtime: Runtime,
+    distribution_id: String,
+}
+
+impl CloudFront {
+    pub(crate) fn new(distribution_id: String) -> Result<Self> {
+        let runtime = Runtime::new()?;
+
+        let shared_config = runtime.block_on(aws_config::load_from_env());
+        let config = aws_sdk_cloudfront::config::Builder::from(&shared_config)
+            .retry_config(RetryConfig::new().with_max_attempts(3))
+            .build();
+
+        Ok(Self {
+            client: Client::from_conf(config),
+            runtime,
+            distribution_id,
+        })
+    }
+}
+
+impl CdnBackend for CloudFront {
+    fn invalidate(&self, path_patterns: &[String]) -> Result<()> {
+        let paths = Paths::builder()
+            .quantity(path_patterns.len() as i32)
+            .set_items(Some(path_patterns.to_vec()))
+            .build();
+        let batch = InvalidationBatch::builder()
+            .paths(paths)
+            // Identifies the invalidation, so retries of the same request aren't executed twice.
+            .caller_reference(format!("docs.rs-{}", Utc::now().timestamp_nanos()))
+            .build();
+
+        let result = self.runtime.block_on(
+            self.client
+                .create_invalidation()
+                .distribution_id(&self.distribution_id)
+                .invalidation_batch(batch)
+                .send(),
+        );
+        match result {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError { err, .. })
+                if err.is_too_many_invalidations_in_progress() =>
+            {
+                Err(CdnBusy.into())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// CloudFront only allows 15 paths with wildcards in invalidations that are in progress.
+    fn max_paths(&self) -> usize {
+        15
+    }
+}
@@ -0,0 +1,296 @@
+//! Invalidation of the caches of a CDN in front of docs.rs
+//!
+//! The builder and crate deletions only queue the paths to invalidate in the database, they are
+//! sent to the CDN in batches by the daemon, see [`handle_queued_invalidations`].
+
+use crate::{error::Result, Config, Metrics};
+use anyhow::Context;
+use log::{debug, info};
+use postgres::{Client, GenericClient};
+
+pub(crate) use self::cloudfront::CloudFront;
+pub(crate) use self::purge::HttpPurge;
+
+mod cloudfront;
+mod purge;
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid CDN backend")]
+pub(crate) struct InvalidCdnBackendError;
+
+/// The CDN whose caches are invalidated, as configured through `DOCSRS_CDN_BACKEND`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CdnKind {
+    CloudFront,
+    /// Any cache accepting `PURGE` requests, like Varnish or Fastly.
+    HttpPurge,
+}
+
+impl std::str::FromStr for CdnKind {
+    type Err = InvalidCdnBackendError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "cloudfront" => Ok(CdnKind::CloudFront),
+            "purge" => Ok(CdnKind::HttpPurge),
+            _ => Err(InvalidCdnBackendError),
+        }
+    }
+}
+
+/// The CDN is still busy with earlier invalidations, they have to be retried later.
+#[derive(Debug, thiserror::Error)]
+#[error("too many invalidations in progress")]
+pub(crate) struct CdnBusy;
+
+pub(crate) trait CdnBackend: Send + Sync {
+    /// Invalidates the cached responses of all paths. A trailing `*` matches any suffix.
+    fn invalidate(&self, path_patterns: &[String]) -> Result<()>;
+
+    /// The most paths passed to a single call of [`CdnBackend::invalidate`].
+    fn max_paths(&self) -> usize;
+}
+
+/// Creates the backend of the configured CDN, if there is one.
+pub(crate) fn create_backend(config: &Config) -> Result<Option<Box<dyn CdnBackend>>> {
+    Ok(match config.cdn_backend {
+        Some(CdnKind::CloudFront) => {
+            let distribution_id = config
+                .cloudfront_distribution_id
+                .clone()
+                .context("DOCSRS_CLOUDFRONT_DISTRIBUTION_ID is required for CloudFront")?;
+            Some(Box::new(CloudFront::new(distribution_id)?))
+        }
+        Some(CdnKind::HttpPurge) => {
+            let url = config
+                .cdn_purge_url
+                .as_deref()
+                .context("DOCSRS_CDN_PURGE_URL is required for the purge backend")?;
+            Some(Box::new(HttpPurge::new(url)?))
+        }
+        None => None,
+    })
+}
+
+/// The path patterns whose cached responses change when a release of the crate is built or
+/// deleted.
+///
+/// Pages of exact versions are not included: they only change when that version is rebuilt,
+/// which is rare enough to wait for their cache lifetime. Deleted versions are invalidated
+/// separately, see [`queue_version_deletion_invalidation`].
+pub(crate) fn crate_invalidation_paths(name: &str) -> Vec<String> {
+    vec![format!("/{}/latest/*", name), format!("/crate/{}/*", name)]
+}
+
+/// Queues the invalidation of all pages of the crate that might have changed.
+pub(crate) fn queue_crate_invalidation(
+    conn: &mut impl GenericClient,
+    config: &Config,
+    name: &str,
+) -> Result<()> {
+    queue_invalidation(conn, config, name, crate_invalidation_paths(name))
+}
+
+/// Queues the invalidation of the pages of a deleted version, together with the pages of the
+/// crate that might have changed.
+pub(crate) fn queue_version_deletion_invalidation(
+    conn: &mut impl GenericClient,
+    config: &Config,
+    name: &str,
+    version: &str,
+) -> Result<()> {
+    let mut paths = crate_invalidation_paths(name);
+    paths.push(format!("/{}/{}/*", name, version));
+    queue_invalidation(conn, config, name, paths)
+}
+
+/// Queues the invalidation of all pages of a deleted crate, including the pages of every version.
+pub(crate) fn queue_crate_deletion_invalidation(
+    conn: &mut impl GenericClient,
+    config: &Config,
+    name: &str,
+) -> Result<()> {
+    let paths = vec![format!("/{}/*", name), format!("/crate/{}/*", name)];
+    queue_invalidation(conn, config, name, paths)
+}
+
+fn queue_invalidation(
+    conn: &mut impl GenericClient,
+    config: &Config,
+    name: &str,
+    paths: Vec<String>,
+) -> Result<()> {
+    if config.cdn_backend.is_none() {
+        return Ok(());
+    }
+
+    for path in paths {
+        conn.execute(
+            "INSERT INTO cdn_invalidation_queue (crate, path_pattern) VALUES ($1, $2)",
+            &[&name, &path],
+        )?;
+    }
+    Ok(())
+}
+
+/// Sends the next batch of queued invalidations to the CDN, returning the amount of paths.
+///
+/// The batch is locked while it's sent, so multiple daemons can handle the queue at once.
+pub(crate) fn handle_queued_invalidations(
+    conn: &mut Client,
+    backend: &dyn CdnBackend,
+    metrics: &Metrics,
+) -> Result<usize> {
+    let mut transaction = conn.transaction()?;
+    let rows = transaction.query(
+        "SELECT id, path_pattern
+         FROM cdn_invalidation_queue
+         ORDER BY id
+         LIMIT $1
+         FOR UPDATE SKIP LOCKED",
+        &[&(backend.max_paths() as i64)],
+    )?;
+    if rows.is_empty() {
+        return Ok(0);
+    }
+
+    let ids: Vec<i64> = rows.iter().map(|row| row.get("id")).collect();
+    let mut paths: Vec<String> = rows.iter().map(|row| row.get("path_pattern")).collect();
+    paths.sort();
+    paths.dedup();
+
+    match backend.invalidate(&paths) {
+        Ok(()) => {}
+        Err(err) if err.is::<CdnBusy>() => {
+            debug!(
+                "the CDN is busy, retrying {} invalidations later",
+                paths.len()
+            );
+            return Ok(0);
+        }
+        Err(err) => return Err(err),
+    }
+
+    transaction.execute(
+        "DELETE FROM cdn_invalidation_queue WHERE id = ANY($1)",
+        &[&ids],
+    )?;
+    transaction.commit()?;
+
+    info!("invalidated {} paths in the CDN", paths.len());
+    metrics.cdn_invalidated_paths.inc_by(paths.len() as u64);
+    Ok(paths.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeCdn {
+        busy: bool,
+        invalidated: Mutex<Vec<Vec<String>>>,
+    }
+
+    impl CdnBackend for FakeCdn {
+        fn invalidate(&self, path_patterns: &[String]) -> Result<()> {
+            if self.busy {
+                return Err(CdnBusy.into());
+            }
+            self.invalidated
+                .lock()
+                .unwrap()
+                .push(path_patterns.to_vec());
+            Ok(())
+        }
+
+        fn max_paths(&self) -> usize {
+            3
+        }
+    }
+
+    fn queue_length(conn: &mut Client) -> Result<i64> {
+        Ok(conn
+            .query_one("SELECT COUNT(*) FROM cdn_invalidation_queue", &[])?
+            .get(0))
+    }
+
+    #[test]
+    fn nothing_is_queued_without_cdn() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            queue_crate_invalidation(&mut *conn, &env.config(), "foo")?;
+            assert_eq!(queue_length(&mut conn)?, 0);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn queued_invalidations_are_sent_in_batches() {
+        wrapper(|env| {
+            env.override_config(|config| config.cdn_backend = Some(CdnKind::HttpPurge));
+            let mut conn = env.db().conn();
+            for name in &["foo", "bar", "foo"] {
+                queue_crate_invalidation(&mut *conn, &env.config(), name)?;
+            }
+            assert_eq!(queue_length(&mut conn)?, 6);
+
+            let cdn = FakeCdn::default();
+            assert_eq!(
+                handle_queued_invalidations(&mut conn, &cdn, &env.metrics())?,
+                3
+            );
+            assert_eq!(
+                handle_queued_invalidations(&mut conn, &cdn, &env.metrics())?,
+                3
+            );
+            assert_eq!(
+                handle_queued_invalidations(&mut conn, &cdn, &env.metrics())?,
+                0
+            );
+            assert_eq!(queue_length(&mut conn)?, 0);
+
+            assert_eq!(
+                cdn.invalidated.into_inner().unwrap(),
+                vec![
+                    vec!["/bar/latest/*", "/crate/foo/*", "/foo/latest/*"],
+                    vec!["/crate/bar/*", "/crate/foo/*", "/foo/latest/*"],
+                ]
+            );
+            assert_eq!(env.metrics().cdn_invalidated_paths.get(), 6);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn busy_cdn_keeps_the_queue() {
+        wrapper(|env| {
+            env.override_config(|config| config.cdn_backend = Some(CdnKind::CloudFront));
+            let mut conn = env.db().conn();
+            queue_crate_invalidation(&mut *conn, &env.config(), "foo")?;
+
+            let cdn = FakeCdn {
+                busy: true,
+                ..FakeCdn::default()
+            };
+            assert_eq!(
+                handle_queued_invalidations(&mut conn, &cdn, &env.metrics())?,
+                0
+            );
+            assert_eq!(queue_length(&mut conn)?, 2);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn parse_backend() {
+        assert_eq!(
+            "cloudfront".parse::<CdnKind>().unwrap(),
+            CdnKind::CloudFront
+        );
+        assert_eq!("purge".parse::<CdnKind>().unwrap(), CdnKind::HttpPurge);
+        assert!("varnish".parse::<CdnKind>().is_err());
+    }
+}
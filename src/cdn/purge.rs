@@ -0,0 +1,69 @@
+use super::CdnBackend;
+use crate::error::Result;
+use crate::repositories::APP_USER_AGENT;
+use log::debug;
+use reqwest::{blocking::Client as HttpClient, Method};
+
+/// Sends a `PURGE` request for every path to a cache like Varnish or Fastly.
+///
+/// The path patterns are sent unchanged, so the cache has to be configured to treat a trailing
+/// `*` as a prefix match.
+pub(crate) struct HttpPurge {
+    client: HttpClient,
+    base_url: String,
+}
+
+impl HttpPurge {
+    pub(crate) fn new(base_url: &str) -> Result<Self> {
+        Ok(Self {
+            client: HttpClient::builder().user_agent(APP_USER_AGENT).build()?,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+impl CdnBackend for HttpPurge {
+    fn invalidate(&self, path_patterns: &[String]) -> Result<()> {
+        let method = Method::from_bytes(b"PURGE")?;
+        for path in path_patterns {
+            debug!("purging {} from the cache", path);
+            self.client
+                .request(method.clone(), format!("{}{}", self.base_url, path))
+                .send()?
+                .error_for_status()?;
+        }
+        Ok(())
+    }
+
+    fn max_paths(&self) -> usize {
+        100
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::mock;
+
+    #[test]
+    fn sends_purge_requests() {
+        let cdn = HttpPurge::new(&format!("{}/", mockito::server_url())).unwrap();
+
+        let m1 = mock("PURGE", "/foo/latest/*").create();
+        let m2 = mock("PURGE", "/crate/foo/*").create();
+
+        cdn.invalidate(&["/foo/latest/*".to_string(), "/crate/foo/*".to_string()])
+            .unwrap();
+        m1.assert();
+        m2.assert();
+    }
+
+    #[test]
+    fn failed_purge() {
+        let cdn = HttpPurge::new(&mockito::server_url()).unwrap();
+
+        let _m1 = mock("PURGE", "/bar/latest/*").with_status(500).create();
+
+        assert!(cdn.invalidate(&["/bar/latest/*".to_string()]).is_err());
+    }
+}
@@ -1,9 +1,12 @@
-use crate::storage::StorageKind;
+use crate::cdn::CdnKind;
+use crate::repositories::{parse_forge_list, ForgeConfig};
+use crate::storage::{CompressionAlgorithm, StorageKind};
 use anyhow::{anyhow, bail, Context, Result};
 use std::env::VarError;
 use std::error::Error;
 use std::path::PathBuf;
 use std::str::FromStr;
+use url::Url;
 
 #[derive(Debug)]
 pub struct Config {
@@ -30,8 +33,8 @@ pub struct Config {
     pub(crate) github_accesstoken: Option<String>,
     pub(crate) github_updater_min_rate_limit: u32,
 
-    // Gitlab authentication
-    pub(crate) gitlab_accesstoken: Option<String>,
+    // Other forges to collect repository stats from, with their authentication
+    pub(crate) repository_forges: Vec<ForgeConfig>,
 
     // Max size of the files served by the docs.rs frontend
     pub(crate) max_file_size: usize,
@@ -53,6 +56,19 @@ pub struct Config {
     // where do we want to store the locally cached index files
     // for the remote archives?
     pub(crate) local_archive_cache_path: PathBuf,
+    // The most bytes of index files kept in `local_archive_cache_path`, the least recently used
+    // ones are deleted when it's exceeded.
+    pub(crate) local_archive_cache_size: u64,
+    // The algorithm new rustdoc and source archives are compressed with, either `Zstd` or
+    // `Bzip2`. Existing archives are read with the algorithm they were written with.
+    pub(crate) archive_compression: CompressionAlgorithm,
+    // Store the files of new releases as content-addressed blobs shared between releases,
+    // instead of one archive per release.
+    pub(crate) content_addressed_storage: bool,
+    // Write the indexes of new archives in the sorted format instead of the legacy one. Web
+    // servers from before the sorted format can't read it, so it's only enabled once all of them
+    // are updated.
+    pub(crate) sorted_archive_indexes: bool,
 
     // Content Security Policy
     pub(crate) csp_report_only: bool,
@@ -63,38 +79,40 @@ pub struct Config {
     pub(crate) cache_control_stale_while_revalidate: Option<u32>,
     pub(crate) cache_control_max_age: Option<u32>,
 
+    // In-process caches of the web server, which are disabled when their size is 0.
+    // `crate_details_cache_size` is a number of entries, `rustdoc_html_cache_size` is in bytes.
+    // Entries are dropped when a release of their crate changes, or after the TTL in seconds
+    // to pick up repository stats.
+    pub(crate) crate_details_cache_size: usize,
+    pub(crate) rustdoc_html_cache_size: usize,
+    pub(crate) page_cache_ttl: u64,
+
+    // CDN in front of docs.rs, whose caches are invalidated after builds and deletions
+    pub(crate) cdn_backend: Option<CdnKind>,
+    pub(crate) cloudfront_distribution_id: Option<String>,
+    // Base URL the `PURGE` requests are sent to
+    pub(crate) cdn_purge_url: Option<String>,
+
     // Build params
     pub(crate) build_attempts: u16,
-    pub(crate) rustwide_workspace: PathBuf,
-    pub(crate) inside_docker: bool,
-    pub(crate) docker_image: Option<String>,
-    pub(crate) toolchain: String,
-    pub(crate) build_cpu_limit: Option<u32>,
-    pub(crate) include_default_targets: bool,
-    pub(crate) disable_memory_limit: bool,
+    pub(crate) build: BuildConfig,
+    // How many crates are built at once on this host
+    pub(crate) build_workers: usize,
+
+    // Remote builders, leasing crates from the queue through the web server
+    pub(crate) remote_builder_token: Option<String>,
+    // How long a lease lasts without being renewed, in seconds
+    pub(crate) remote_build_lease_duration: u64,
+    // The largest build archive remote builders can upload, in bytes
+    pub(crate) remote_build_max_upload_size: u64,
+
+    // The bearer token of the admin pages, which aren't served without one
+    pub(crate) admin_token: Option<String>,
 }
 
 impl Config {
     pub fn from_env() -> Result<Self> {
-        let old_vars = [
-            ("CRATESFYI_PREFIX", "DOCSRS_PREFIX"),
-            ("CRATESFYI_DATABASE_URL", "DOCSRS_DATABASE_URL"),
-            ("CRATESFYI_GITHUB_ACCESSTOKEN", "DOCSRS_GITHUB_ACCESSTOKEN"),
-            ("CRATESFYI_RUSTWIDE_WORKSPACE", "DOCSRS_RUSTWIDE_WORKSPACE"),
-            ("CRATESFYI_TOOLCHAIN", "DOCSRS_TOOLCHAIN"),
-            ("DOCS_RS_DOCKER", "DOCSRS_DOCKER"),
-            ("DOCS_RS_LOCAL_DOCKER_IMAGE", "DOCSRS_DOCKER_IMAGE"),
-            ("DOCS_RS_BULID_CPU_LIMIT", "DOCSRS_BULID_CPU_LIMIT"),
-        ];
-        for (old_var, new_var) in old_vars {
-            if std::env::var(old_var).is_ok() {
-                bail!(
-                    "env variable {} is no longer accepted; use {} instead",
-                    old_var,
-                    new_var
-                );
-            }
-        }
+        check_old_vars()?;
 
         let prefix: PathBuf = require_env("DOCSRS_PREFIX")?;
 
@@ -123,7 +141,7 @@ impl Config {
             github_accesstoken: maybe_env("DOCSRS_GITHUB_ACCESSTOKEN")?,
             github_updater_min_rate_limit: env("DOCSRS_GITHUB_UPDATER_MIN_RATE_LIMIT", 2500)?,
 
-            gitlab_accesstoken: maybe_env("DOCSRS_GITLAB_ACCESSTOKEN")?,
+            repository_forges: repository_forges()?,
 
             max_file_size: env("DOCSRS_MAX_FILE_SIZE", 50 * 1024 * 1024)?,
             max_file_size_html: env("DOCSRS_MAX_FILE_SIZE_HTML", 50 * 1024 * 1024)?,
@@ -141,11 +159,63 @@ impl Config {
             )?,
             cache_control_max_age: maybe_env("CACHE_CONTROL_MAX_AGE")?,
 
+            crate_details_cache_size: env("DOCSRS_CRATE_DETAILS_CACHE_SIZE", 1000)?,
+            rustdoc_html_cache_size: env("DOCSRS_RUSTDOC_HTML_CACHE_SIZE", 128 * 1024 * 1024)?,
+            page_cache_ttl: env("DOCSRS_PAGE_CACHE_TTL", 10 * 60)?,
+
+            cdn_backend: maybe_env("DOCSRS_CDN_BACKEND")?,
+            cloudfront_distribution_id: maybe_env("DOCSRS_CLOUDFRONT_DISTRIBUTION_ID")?,
+            cdn_purge_url: maybe_env("DOCSRS_CDN_PURGE_URL")?,
+
             local_archive_cache_path: env(
                 "DOCSRS_ARCHIVE_INDEX_CACHE_PATH",
                 prefix.join("archive_cache"),
             )?,
+            local_archive_cache_size: env(
+                "DOCSRS_ARCHIVE_INDEX_CACHE_SIZE",
+                10 * 1024 * 1024 * 1024,
+            )?,
+            archive_compression: env("DOCSRS_ARCHIVE_COMPRESSION", CompressionAlgorithm::Bzip2)?,
+            content_addressed_storage: env("DOCSRS_CONTENT_ADDRESSED_STORAGE", false)?,
+            sorted_archive_indexes: env("DOCSRS_SORTED_ARCHIVE_INDEXES", false)?,
+
+            build: BuildConfig::from_env()?,
+            build_workers: env("DOCSRS_BUILD_WORKERS", 1)?,
+
+            remote_builder_token: maybe_env("DOCSRS_REMOTE_BUILDER_TOKEN")?,
+            remote_build_lease_duration: env("DOCSRS_REMOTE_BUILD_LEASE_DURATION", 5 * 60)?,
+            remote_build_max_upload_size: env(
+                "DOCSRS_REMOTE_BUILD_MAX_UPLOAD_SIZE",
+                2 * 1024 * 1024 * 1024,
+            )?,
+
+            admin_token: maybe_env("DOCSRS_ADMIN_TOKEN")?,
+        })
+    }
+}
+
+/// The configuration of the builds themselves, which doesn't need the database or the storage,
+/// so standalone builds can run without them.
+#[derive(Debug, Clone)]
+pub struct BuildConfig {
+    pub(crate) rustwide_workspace: PathBuf,
+    pub(crate) inside_docker: bool,
+    pub(crate) docker_image: Option<String>,
+    pub(crate) toolchain: String,
+    pub(crate) build_cpu_limit: Option<u32>,
+    pub(crate) include_default_targets: bool,
+    pub(crate) disable_memory_limit: bool,
+    // The most memory (in bytes) and time (in seconds) a build is retried with after it
+    // exceeded its limits
+    pub(crate) max_escalated_memory: usize,
+    pub(crate) max_escalated_timeout: u64,
+}
 
+impl BuildConfig {
+    pub fn from_env() -> Result<Self> {
+        check_old_vars()?;
+
+        Ok(Self {
             rustwide_workspace: env("DOCSRS_RUSTWIDE_WORKSPACE", PathBuf::from(".workspace"))?,
             inside_docker: env("DOCSRS_DOCKER", false)?,
             docker_image: maybe_env("DOCSRS_LOCAL_DOCKER_IMAGE")?
@@ -154,10 +224,77 @@ impl Config {
             build_cpu_limit: maybe_env("DOCSRS_BUILD_CPU_LIMIT")?,
             include_default_targets: env("DOCSRS_INCLUDE_DEFAULT_TARGETS", true)?,
             disable_memory_limit: env("DOCSRS_DISABLE_MEMORY_LIMIT", false)?,
+            max_escalated_memory: env("DOCSRS_MAX_ESCALATED_MEMORY", 6 * 1024 * 1024 * 1024)?,
+            max_escalated_timeout: env("DOCSRS_MAX_ESCALATED_TIMEOUT", 45 * 60)?,
+        })
+    }
+}
+
+/// The configuration of a remote builder, which builds the crates leased from the build queue
+/// of another docs.rs instance and doesn't need its database or storage.
+#[derive(Debug)]
+pub struct RemoteBuilderConfig {
+    // The docs.rs instance the crates are leased from, and the token of its builder API
+    pub(crate) server: Url,
+    pub(crate) token: String,
+    // Where the builds are written before they're uploaded
+    pub(crate) prefix: PathBuf,
+    pub(crate) build: BuildConfig,
+}
+
+impl RemoteBuilderConfig {
+    pub fn from_env(server: &str) -> Result<Self> {
+        Ok(Self {
+            server: server
+                .parse()
+                .with_context(|| format!("invalid docs.rs server URL {}", server))?,
+            token: require_env("DOCSRS_REMOTE_BUILDER_TOKEN")?,
+            prefix: require_env("DOCSRS_PREFIX")?,
+            build: BuildConfig::from_env()?,
         })
     }
 }
 
+fn check_old_vars() -> Result<()> {
+    let old_vars = [
+        ("CRATESFYI_PREFIX", "DOCSRS_PREFIX"),
+        ("CRATESFYI_DATABASE_URL", "DOCSRS_DATABASE_URL"),
+        ("CRATESFYI_GITHUB_ACCESSTOKEN", "DOCSRS_GITHUB_ACCESSTOKEN"),
+        ("CRATESFYI_RUSTWIDE_WORKSPACE", "DOCSRS_RUSTWIDE_WORKSPACE"),
+        ("CRATESFYI_TOOLCHAIN", "DOCSRS_TOOLCHAIN"),
+        ("DOCS_RS_DOCKER", "DOCSRS_DOCKER"),
+        ("DOCS_RS_LOCAL_DOCKER_IMAGE", "DOCSRS_DOCKER_IMAGE"),
+        ("DOCS_RS_BULID_CPU_LIMIT", "DOCSRS_BULID_CPU_LIMIT"),
+    ];
+    for (old_var, new_var) in old_vars {
+        if std::env::var(old_var).is_ok() {
+            bail!(
+                "env variable {} is no longer accepted; use {} instead",
+                old_var,
+                new_var
+            );
+        }
+    }
+    Ok(())
+}
+
+fn repository_forges() -> Result<Vec<ForgeConfig>> {
+    let list: String = env(
+        "DOCSRS_REPOSITORY_FORGES",
+        "gitlab:gitlab.com,gitlab:gitlab.freedesktop.org".to_string(),
+    )?;
+    let mut forges = parse_forge_list(&list)
+        .context("failed to parse configuration variable DOCSRS_REPOSITORY_FORGES")?;
+    for forge in &mut forges {
+        forge.access_token = maybe_env(&ForgeConfig::access_token_var(&forge.host))?;
+        // `DOCSRS_GITLAB_ACCESSTOKEN` predates the configurable forges.
+        if forge.access_token.is_none() && forge.host == "gitlab.com" {
+            forge.access_token = maybe_env("DOCSRS_GITLAB_ACCESSTOKEN")?;
+        }
+    }
+    Ok(forges)
+}
+
 fn env<T>(var: &str, default: T) -> Result<T>
 where
     T: FromStr,
@@ -191,3 +328,59 @@ where
         Err(VarError::NotUnicode(_)) => Err(anyhow!("configuration variable {} is not UTF-8", var)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    /// Runs the test again in a child process without the database and storage variables but
+    /// with `vars`, returning whether this is the child process.
+    ///
+    /// The environment is shared with the other tests, so the variables can't be removed in this
+    /// process.
+    fn without_database(test: &str, vars: &[(&str, &str)]) -> bool {
+        if std::env::var_os("DOCSRS_TEST_WITHOUT_DATABASE").is_some() {
+            return true;
+        }
+
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", test, "--nocapture"])
+            .env("DOCSRS_TEST_WITHOUT_DATABASE", "1")
+            .env_remove("DOCSRS_DATABASE_URL")
+            .env_remove("DOCSRS_PREFIX")
+            .env_remove("DOCSRS_STORAGE_BACKEND")
+            .envs(vars.iter().copied())
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{}", stdout);
+        assert!(stdout.contains("1 passed"), "{}", stdout);
+        false
+    }
+
+    #[test]
+    fn build_config_without_database() {
+        if without_database("config::tests::build_config_without_database", &[]) {
+            BuildConfig::from_env().unwrap();
+            assert!(Config::from_env().is_err());
+        }
+    }
+
+    #[test]
+    fn remote_builder_config_without_database() {
+        let vars = [
+            ("DOCSRS_REMOTE_BUILDER_TOKEN", "secret"),
+            ("DOCSRS_PREFIX", "/tmp/docsrs-remote-builder"),
+        ];
+        if without_database(
+            "config::tests::remote_builder_config_without_database",
+            &vars,
+        ) {
+            let config = RemoteBuilderConfig::from_env("https://docs.rs").unwrap();
+            assert_eq!(config.server.as_str(), "https://docs.rs/");
+            assert_eq!(config.token, "secret");
+            assert!(Config::from_env().is_err());
+        }
+    }
+}
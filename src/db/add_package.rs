@@ -1,6 +1,8 @@
 use crate::{
-    db::types::Feature,
-    docbuilder::{BuildResult, DocCoverage},
+    db::{notify_release_changed, types::Feature},
+    docbuilder::{
+        BuildResult, DocCoverage, ExceededLimit, FeatureItem, FileCoverage, UndocumentedItem,
+    },
     error::Result,
     index::api::{CrateData, CrateOwner, ReleaseData},
     storage::CompressionAlgorithm,
@@ -34,6 +36,7 @@ pub(crate) fn add_package_into_database(
     default_target: &str,
     source_files: Value,
     doc_targets: Vec<String>,
+    doc_feature_profiles: Vec<String>,
     registry_data: &ReleaseData,
     has_docs: bool,
     has_examples: bool,
@@ -58,12 +61,13 @@ pub(crate) fn add_package_into_database(
             keywords, have_examples, downloads, files,
             doc_targets, is_library, doc_rustc_version,
             documentation_url, default_target, features,
-            repository_id, archive_storage
+            repository_id, archive_storage, doc_feature_profiles
          )
          VALUES (
             $1,  $2,  $3,  $4,  $5,  $6,  $7,  $8,  $9,
             $10, $11, $12, $13, $14, $15, $16, $17, $18,
-            $19, $20, $21, $22, $23, $24, $25, $26, $27 
+            $19, $20, $21, $22, $23, $24, $25, $26, $27,
+            $28
          )
          ON CONFLICT (crate_id, version) DO UPDATE
             SET release_time = $3,
@@ -90,7 +94,8 @@ pub(crate) fn add_package_into_database(
                 default_target = $24,
                 features = $25,
                 repository_id = $26,
-                archive_storage = $27
+                archive_storage = $27,
+                doc_feature_profiles = $28
          RETURNING id",
         &[
             &crate_id,
@@ -120,6 +125,7 @@ pub(crate) fn add_package_into_database(
             &features,
             &repository_id,
             &archive_storage,
+            &serde_json::to_value(&doc_feature_profiles)?,
         ],
     )?;
 
@@ -145,6 +151,8 @@ pub(crate) fn add_package_into_database(
         &[&crate_id, &crate_details.latest_release().id],
     )?;
 
+    notify_release_changed(conn, &metadata_pkg.name)?;
+
     Ok(release_id)
 }
 
@@ -178,6 +186,95 @@ pub(crate) fn add_doc_coverage(
     Ok(rows[0].get(0))
 }
 
+/// Stores the coverage of each file and the undocumented items of a release, replacing the ones
+/// of earlier builds.
+pub(crate) fn add_coverage_details(
+    conn: &mut Client,
+    release_id: i32,
+    files: &[FileCoverage],
+    undocumented_items: &[UndocumentedItem],
+) -> Result<()> {
+    debug!("Adding coverage details into database");
+    let mut transaction = conn.transaction()?;
+    transaction.execute(
+        "DELETE FROM doc_coverage_files WHERE release_id = $1",
+        &[&release_id],
+    )?;
+    transaction.execute(
+        "DELETE FROM undocumented_items WHERE release_id = $1",
+        &[&release_id],
+    )?;
+
+    let insert_file = transaction.prepare(
+        "INSERT INTO doc_coverage_files (
+            release_id, file, total_items, documented_items,
+            total_items_needing_examples, items_with_examples
+         )
+         VALUES ($1, $2, $3, $4, $5, $6)
+         ON CONFLICT DO NOTHING",
+    )?;
+    for file in files {
+        transaction.execute(
+            &insert_file,
+            &[
+                &release_id,
+                &file.file,
+                &file.total_items,
+                &file.documented_items,
+                &file.total_items_needing_examples,
+                &file.items_with_examples,
+            ],
+        )?;
+    }
+
+    let insert_item = transaction.prepare(
+        "INSERT INTO undocumented_items (release_id, module, kind, name, url)
+         VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT DO NOTHING",
+    )?;
+    for item in undocumented_items {
+        transaction.execute(
+            &insert_item,
+            &[&release_id, &item.module, &item.kind, &item.name, &item.url],
+        )?;
+    }
+
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Stores the items gated behind crate features for a release, replacing the ones of earlier
+/// builds.
+pub(crate) fn add_feature_items(
+    conn: &mut Client,
+    release_id: i32,
+    items: &[FeatureItem],
+) -> Result<()> {
+    debug!("Adding feature items into database");
+    let mut transaction = conn.transaction()?;
+    transaction.execute(
+        "DELETE FROM feature_items WHERE release_id = $1",
+        &[&release_id],
+    )?;
+
+    let insert_query = transaction.prepare(
+        "INSERT INTO feature_items (release_id, feature, kind, path, url)
+         VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT DO NOTHING",
+    )?;
+    for item in items {
+        for feature in &item.features {
+            transaction.execute(
+                &insert_query,
+                &[&release_id, feature, &item.kind, &item.path, &item.url],
+            )?;
+        }
+    }
+
+    transaction.commit()?;
+    Ok(())
+}
+
 /// Adds a build into database
 pub(crate) fn add_build_into_database(
     conn: &mut Client,
@@ -186,8 +283,11 @@ pub(crate) fn add_build_into_database(
 ) -> Result<i32> {
     debug!("Adding build into database");
     let rows = conn.query(
-        "INSERT INTO builds (rid, rustc_version, docsrs_version, build_status, build_server)
-        VALUES ($1, $2, $3, $4, $5)
+        "INSERT INTO builds (
+            rid, rustc_version, docsrs_version, build_status, build_server, metadata_warnings,
+            exceeded_limit, escalated_limits
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
         RETURNING id",
         &[
             &release_id,
@@ -195,8 +295,26 @@ pub(crate) fn add_build_into_database(
             &res.docsrs_version,
             &res.successful,
             &hostname::get()?.to_str().unwrap_or(""),
+            &serde_json::to_value(&res.metadata_warnings)?,
+            &res.exceeded_limit.map(ExceededLimit::as_str),
+            &res.escalated_limits
+                .as_ref()
+                .map(serde_json::to_value)
+                .transpose()?,
         ],
     )?;
+
+    let name: String = conn
+        .query_one(
+            "SELECT crates.name
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE releases.id = $1",
+            &[&release_id],
+        )?
+        .get(0);
+    notify_release_changed(conn, &name)?;
+
     Ok(rows[0].get(0))
 }
 
@@ -380,6 +498,7 @@ pub fn update_crate_data_in_database(
         .get(0);
 
     update_owners_in_database(conn, &registry_data.owners, crate_id)?;
+    notify_release_changed(conn, name)?;
 
     Ok(())
 }
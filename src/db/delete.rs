@@ -1,3 +1,5 @@
+use crate::cdn;
+use crate::db::notify_release_changed;
 use crate::error::Result;
 use crate::storage::{rustdoc_archive_path, source_archive_path, Storage};
 use crate::{Config, Context};
@@ -24,6 +26,7 @@ pub fn delete_crate(
 ) -> Result<()> {
     let crate_id = get_id(conn, name)?;
     let is_library = delete_crate_from_database(conn, name, crate_id)?;
+    cdn::queue_crate_deletion_invalidation(conn, config, name)?;
     // #899
     let paths = if is_library {
         LIBRARY_STORAGE_PATHS_TO_DELETE
@@ -55,8 +58,10 @@ pub fn delete_crate(
 pub fn delete_version(ctx: &dyn Context, name: &str, version: &str) -> Result<()> {
     let conn = &mut ctx.pool()?.get()?;
     let storage = ctx.storage()?;
+    let config = ctx.config()?;
 
     let is_library = delete_version_from_database(conn, name, version)?;
+    cdn::queue_version_deletion_invalidation(&mut **conn, &config, name, version)?;
     let paths = if is_library {
         LIBRARY_STORAGE_PATHS_TO_DELETE
     } else {
@@ -67,7 +72,7 @@ pub fn delete_version(ctx: &dyn Context, name: &str, version: &str) -> Result<()
         storage.delete_prefix(&format!("{}/{}/{}/", prefix, name, version))?;
     }
 
-    let local_archive_cache = &ctx.config()?.local_archive_cache_path;
+    let local_archive_cache = &config.local_archive_cache_path;
     let mut paths = vec![source_archive_path(name, version)];
     if is_library {
         paths.push(rustdoc_archive_path(name, version));
@@ -108,6 +113,11 @@ const METADATA: &[(&str, &str)] = &[
     ("builds", "rid"),
     ("compression_rels", "release"),
     ("doc_coverage", "release_id"),
+    ("feature_items", "release_id"),
+    ("doc_coverage_files", "release_id"),
+    ("undocumented_items", "release_id"),
+    ("storage_failures", "release_id"),
+    ("storage_usage", "release_id"),
 ];
 
 /// Returns whether this release was a library
@@ -146,6 +156,7 @@ fn delete_version_from_database(conn: &mut Client, name: &str, version: &str) ->
             &[&format!("{}/{}/{}/%", prefix, name, version)],
         )?;
     }
+    notify_release_changed(&mut transaction, name)?;
 
     transaction.commit()?;
     Ok(is_library)
@@ -178,6 +189,7 @@ fn delete_crate_from_database(conn: &mut Client, name: &str, crate_id: i32) -> R
         .get("has_library");
     transaction.execute("DELETE FROM releases WHERE crate_id = $1;", &[&crate_id])?;
     transaction.execute("DELETE FROM crates WHERE id = $1;", &[&crate_id])?;
+    notify_release_changed(&mut transaction, name)?;
 
     // Transactions automatically rollback when not committing, so if any of the previous queries
     // fail the whole transaction will be aborted.
@@ -407,4 +419,40 @@ mod tests {
             Ok(())
         })
     }
+
+    #[test]
+    fn test_delete_queues_cdn_invalidation() {
+        wrapper(|env| {
+            env.override_config(|config| config.cdn_backend = Some(cdn::CdnKind::HttpPurge));
+            env.fake_release().name("a").version("1.0.0").create()?;
+            env.fake_release().name("a").version("2.0.0").create()?;
+            env.fake_release().name("b").version("1.0.0").create()?;
+
+            delete_version(env, "a", "1.0.0")?;
+            delete_crate(&mut env.db().conn(), &env.storage(), &env.config(), "b")?;
+
+            let queued: Vec<String> = env
+                .db()
+                .conn()
+                .query(
+                    "SELECT path_pattern FROM cdn_invalidation_queue ORDER BY id",
+                    &[],
+                )?
+                .into_iter()
+                .map(|row| row.get(0))
+                .collect();
+            assert_eq!(
+                queued,
+                vec![
+                    "/a/latest/*",
+                    "/crate/a/*",
+                    "/a/1.0.0/*",
+                    "/b/*",
+                    "/crate/b/*"
+                ]
+            );
+
+            Ok(())
+        })
+    }
 }
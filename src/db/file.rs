@@ -8,7 +8,7 @@
 //! However, postgres is still available for testing and backwards compatibility.
 
 use crate::error::Result;
-use crate::storage::{CompressionAlgorithm, CompressionAlgorithms, Storage};
+use crate::storage::{ArchiveUsage, CompressionAlgorithm, CompressionAlgorithms, Storage};
 
 use serde_json::Value;
 use std::path::{Path, PathBuf};
@@ -34,15 +34,19 @@ pub fn add_path_into_database<P: AsRef<Path>>(
     ))
 }
 
+/// Store all files in a directory as the archive at `archive_path`, returning the file list as
+/// Json together with the size of the files in the storage backend.
 pub fn add_path_into_remote_archive<P: AsRef<Path>>(
     storage: &Storage,
     archive_path: &str,
     path: P,
-) -> Result<(Value, CompressionAlgorithm)> {
-    let (file_list, algorithm) = storage.store_all_in_archive(archive_path, path.as_ref())?;
+) -> Result<(Value, CompressionAlgorithm, ArchiveUsage)> {
+    let (file_list, algorithm, usage) =
+        storage.store_all_in_archive(archive_path, path.as_ref())?;
     Ok((
         file_list_to_json(file_list.into_iter().collect()),
         algorithm,
+        usage,
     ))
 }
 
@@ -843,6 +843,128 @@ pub fn migrate(version: Option<Version>, conn: &mut Client) -> crate::error::Res
             "ALTER TABLE builds ADD COLUMN build_server TEXT NOT NULL DEFAULT '';",
             "ALTER TABLE builds DROP COLUMN build_server;",
         ),
+        sql_migration!(
+            context, 34, "add feature_items table for items gated behind crate features",
+            "CREATE TABLE feature_items (
+                release_id INT NOT NULL REFERENCES releases(id),
+                feature TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                path TEXT NOT NULL,
+                url TEXT NOT NULL,
+                PRIMARY KEY (release_id, feature, kind, path)
+            );",
+            "DROP TABLE feature_items;",
+        ),
+        sql_migration!(
+            context, 35, "add doc_feature_profiles to releases",
+            "ALTER TABLE releases ADD COLUMN doc_feature_profiles JSON NOT NULL DEFAULT '[]';",
+            "ALTER TABLE releases DROP COLUMN doc_feature_profiles;",
+        ),
+        sql_migration!(
+            context, 36, "add metadata_warnings to builds",
+            "ALTER TABLE builds ADD COLUMN metadata_warnings JSON NOT NULL DEFAULT '[]';",
+            "ALTER TABLE builds DROP COLUMN metadata_warnings;",
+        ),
+        sql_migration!(
+            context, 37, "add per-file and per-item documentation coverage",
+            "CREATE TABLE doc_coverage_files (
+                release_id INT NOT NULL REFERENCES releases(id),
+                file TEXT NOT NULL,
+                total_items INT NOT NULL,
+                documented_items INT NOT NULL,
+                total_items_needing_examples INT NOT NULL,
+                items_with_examples INT NOT NULL,
+                PRIMARY KEY (release_id, file)
+            );
+            CREATE TABLE undocumented_items (
+                release_id INT NOT NULL REFERENCES releases(id),
+                module TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                name TEXT NOT NULL,
+                url TEXT NOT NULL,
+                PRIMARY KEY (release_id, module, kind, name)
+            );",
+            "DROP TABLE doc_coverage_files;
+            DROP TABLE undocumented_items;",
+        ),
+        sql_migration!(
+            context, 38, "add cdn invalidation queue",
+            "CREATE TABLE cdn_invalidation_queue (
+                id BIGSERIAL PRIMARY KEY,
+                crate VARCHAR(255) NOT NULL,
+                path_pattern TEXT NOT NULL,
+                queued TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );",
+            "DROP TABLE cdn_invalidation_queue;",
+        ),
+        sql_migration!(
+            context, 39, "add leases of queued crates to remote builders",
+            "ALTER TABLE queue ADD COLUMN lease_id TEXT UNIQUE;
+            ALTER TABLE queue ADD COLUMN leased_by TEXT;
+            ALTER TABLE queue ADD COLUMN lease_expires TIMESTAMPTZ;",
+            "ALTER TABLE queue DROP COLUMN lease_id;
+            ALTER TABLE queue DROP COLUMN leased_by;
+            ALTER TABLE queue DROP COLUMN lease_expires;",
+        ),
+        sql_migration!(
+            context, 40, "add networking to sandbox overrides",
+            "ALTER TABLE sandbox_overrides ADD COLUMN networking BOOLEAN;",
+            "ALTER TABLE sandbox_overrides DROP COLUMN networking;",
+        ),
+        sql_migration!(
+            context, 41, "add escalated limits of builds exceeding their limits",
+            "ALTER TABLE queue ADD COLUMN escalated_limits JSONB;
+            ALTER TABLE builds ADD COLUMN exceeded_limit TEXT;
+            ALTER TABLE builds ADD COLUMN escalated_limits JSONB;",
+            "ALTER TABLE queue DROP COLUMN escalated_limits;
+            ALTER TABLE builds DROP COLUMN exceeded_limit;
+            ALTER TABLE builds DROP COLUMN escalated_limits;",
+        ),
+        sql_migration!(
+            context, 42, "add storage verification failures",
+            "CREATE TABLE storage_failures (
+                release_id INT NOT NULL REFERENCES releases(id),
+                path TEXT NOT NULL,
+                error TEXT NOT NULL,
+                checked TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (release_id, path)
+            );",
+            "DROP TABLE storage_failures;",
+        ),
+        sql_migration!(
+            context, 43, "add storage usage of releases",
+            "CREATE TABLE storage_usage (
+                release_id INT NOT NULL REFERENCES releases(id),
+                kind TEXT NOT NULL,
+                backend TEXT NOT NULL,
+                compressed BIGINT NOT NULL,
+                uncompressed BIGINT NOT NULL,
+                updated TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (release_id, kind)
+            );",
+            "DROP TABLE storage_usage;",
+        ),
+        sql_migration!(
+            context, 44, "add chunks of large files",
+            "CREATE TABLE file_chunks (
+                path VARCHAR(4096) NOT NULL REFERENCES files(path) ON DELETE CASCADE,
+                chunk INT NOT NULL,
+                content BYTEA NOT NULL,
+                PRIMARY KEY (path, chunk)
+            );",
+            "DROP TABLE file_chunks;",
+        ),
+        sql_migration!(
+            context, 45, "add storage usage of content-addressed blobs",
+            "CREATE TABLE blob_usage (
+                backend TEXT NOT NULL,
+                path TEXT NOT NULL,
+                compressed BIGINT NOT NULL,
+                uncompressed BIGINT NOT NULL,
+                PRIMARY KEY (backend, path)
+            );",
+            "DROP TABLE blob_usage;",
+        ),
 
     ];
 
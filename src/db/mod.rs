@@ -2,11 +2,13 @@
 
 pub use self::add_package::update_crate_data_in_database;
 pub(crate) use self::add_package::{
-    add_build_into_database, add_doc_coverage, add_package_into_database,
+    add_build_into_database, add_coverage_details, add_doc_coverage, add_feature_items,
+    add_package_into_database,
 };
 pub use self::delete::{delete_crate, delete_version};
 pub use self::file::{add_path_into_database, add_path_into_remote_archive};
 pub use self::migrate::migrate;
+pub(crate) use self::notify::notify_release_changed;
 pub use self::pool::{Pool, PoolClient, PoolError};
 
 mod add_package;
@@ -14,5 +16,7 @@ pub mod blacklist;
 mod delete;
 pub(crate) mod file;
 mod migrate;
+pub(crate) mod notify;
+pub mod overrides;
 mod pool;
 pub(crate) mod types;
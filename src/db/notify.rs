@@ -0,0 +1,57 @@
+//! Notifications about changed releases, used by the web server to invalidate its caches.
+
+use crate::error::Result;
+use postgres::GenericClient;
+
+/// The channel used to notify the web server that a crate's releases changed.
+pub(crate) const RELEASE_CHANGED_CHANNEL: &str = "docsrs_release_changed";
+
+/// Notifies all listeners that a release of the crate was added, rebuilt, yanked or deleted,
+/// or that other details shown on its pages changed.
+///
+/// The notification is only delivered once the surrounding transaction commits. Its payload is
+/// `<schema>/<crate name>`, so tests running in parallel don't receive each other's changes.
+pub(crate) fn notify_release_changed(conn: &mut impl GenericClient, name: &str) -> Result<()> {
+    conn.execute(
+        "SELECT pg_notify($1, current_schema() || '/' || $2)",
+        &[&RELEASE_CHANGED_CHANNEL, &name],
+    )?;
+    Ok(())
+}
+
+/// Extracts the crate name from the payload of a notification, if it was sent from `schema`.
+pub(crate) fn changed_crate_name<'a>(payload: &'a str, schema: &str) -> Option<&'a str> {
+    payload.strip_prefix(schema)?.strip_prefix('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use postgres::fallible_iterator::FallibleIterator;
+    use std::time::Duration;
+
+    #[test]
+    fn payload_contains_schema_and_name() {
+        wrapper(|env| {
+            let mut listener = env.db().conn();
+            let schema: String = listener.query_one("SELECT current_schema()", &[])?.get(0);
+            listener.batch_execute(&format!("LISTEN {}", RELEASE_CHANGED_CHANNEL))?;
+
+            notify_release_changed(&mut *env.db().conn(), "foo")?;
+
+            let mut notifications = listener.notifications();
+            let notification = notifications
+                .timeout_iter(Duration::from_secs(5))
+                .next()?
+                .expect("no notification received");
+            assert_eq!(
+                changed_crate_name(notification.payload(), &schema),
+                Some("foo")
+            );
+            assert_eq!(changed_crate_name(notification.payload(), "public"), None);
+
+            Ok(())
+        })
+    }
+}
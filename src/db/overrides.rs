@@ -0,0 +1,238 @@
+//! Per-crate overrides of the sandbox limits used to build the documentation.
+
+use crate::error::Result;
+use postgres::{Client, Row};
+use serde::Serialize;
+use std::{fmt, time::Duration};
+
+#[derive(Debug, thiserror::Error)]
+enum OverridesError {
+    #[error("crate {0} has no overridden limits")]
+    NoOverrides(String),
+
+    #[error("no limit to override was given")]
+    NothingToOverride,
+
+    #[error("the {0} limit must be greater than zero")]
+    Zero(&'static str),
+
+    #[error("the {0} limit is too large")]
+    TooLarge(&'static str),
+}
+
+/// The sandbox limits overridden for a crate, the limits which are not set use the defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Overrides {
+    /// The available memory, in bytes.
+    pub memory: Option<usize>,
+    /// The most targets the documentation is built for.
+    pub targets: Option<usize>,
+    pub timeout: Option<Duration>,
+    /// Whether the build can access the network.
+    pub networking: Option<bool>,
+}
+
+impl Overrides {
+    pub(crate) fn from_row(row: &Row) -> Self {
+        Self {
+            memory: row
+                .get::<_, Option<i64>>("max_memory_bytes")
+                .map(|memory| memory as usize),
+            targets: row
+                .get::<_, Option<i32>>("max_targets")
+                .map(|targets| targets as usize),
+            timeout: row
+                .get::<_, Option<i32>>("timeout_seconds")
+                .map(|timeout| Duration::from_secs(timeout as u64)),
+            networking: row.get("networking"),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Checks that the limits can be stored and used by the builder.
+    fn validate(&self) -> Result<()> {
+        if self.memory == Some(0) {
+            return Err(OverridesError::Zero("memory").into());
+        }
+        if self.targets == Some(0) {
+            return Err(OverridesError::Zero("targets").into());
+        }
+        if self.timeout.map_or(false, |timeout| timeout.as_secs() == 0) {
+            return Err(OverridesError::Zero("timeout").into());
+        }
+
+        if self
+            .memory
+            .map_or(false, |memory| i64::try_from(memory).is_err())
+        {
+            return Err(OverridesError::TooLarge("memory").into());
+        }
+        if self
+            .targets
+            .map_or(false, |targets| i32::try_from(targets).is_err())
+        {
+            return Err(OverridesError::TooLarge("targets").into());
+        }
+        if self
+            .timeout
+            .map_or(false, |timeout| i32::try_from(timeout.as_secs()).is_err())
+        {
+            return Err(OverridesError::TooLarge("timeout").into());
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Overrides {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_limit(
+            f: &mut fmt::Formatter<'_>,
+            name: &str,
+            limit: Option<impl fmt::Display>,
+        ) -> fmt::Result {
+            match limit {
+                Some(limit) => writeln!(f, "{}: {}", name, limit),
+                None => writeln!(f, "{}: default", name),
+            }
+        }
+
+        write_limit(f, "memory (bytes)", self.memory)?;
+        write_limit(f, "targets", self.targets)?;
+        write_limit(
+            f,
+            "timeout (seconds)",
+            self.timeout.map(|timeout| timeout.as_secs()),
+        )?;
+        write_limit(f, "networking", self.networking)
+    }
+}
+
+/// Returns the overridden limits of the crate, if there are any.
+pub fn get_overrides(conn: &mut Client, name: &str) -> Result<Option<Overrides>> {
+    Ok(conn
+        .query_opt(
+            "SELECT * FROM sandbox_overrides WHERE crate_name = $1;",
+            &[&name],
+        )?
+        .map(|row| Overrides::from_row(&row)))
+}
+
+/// Overrides the given limits of the crate, keeping the other limits already overridden.
+///
+/// Returns all limits overridden for the crate afterwards.
+pub fn set_overrides(conn: &mut Client, name: &str, overrides: &Overrides) -> Result<Overrides> {
+    if overrides.is_empty() {
+        return Err(OverridesError::NothingToOverride.into());
+    }
+    overrides.validate()?;
+
+    let row = conn.query_one(
+        "INSERT INTO sandbox_overrides
+             (crate_name, max_memory_bytes, max_targets, timeout_seconds, networking)
+         VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (crate_name) DO UPDATE SET
+             max_memory_bytes = COALESCE(EXCLUDED.max_memory_bytes, sandbox_overrides.max_memory_bytes),
+             max_targets = COALESCE(EXCLUDED.max_targets, sandbox_overrides.max_targets),
+             timeout_seconds = COALESCE(EXCLUDED.timeout_seconds, sandbox_overrides.timeout_seconds),
+             networking = COALESCE(EXCLUDED.networking, sandbox_overrides.networking)
+         RETURNING *;",
+        &[
+            &name,
+            &overrides.memory.map(|memory| memory as i64),
+            &overrides.targets.map(|targets| targets as i32),
+            &overrides
+                .timeout
+                .map(|timeout| timeout.as_secs() as i32),
+            &overrides.networking,
+        ],
+    )?;
+
+    Ok(Overrides::from_row(&row))
+}
+
+/// Removes all overridden limits of the crate, so the defaults are used again.
+pub fn remove_overrides(conn: &mut Client, name: &str) -> Result<()> {
+    let removed = conn.execute(
+        "DELETE FROM sandbox_overrides WHERE crate_name = $1;",
+        &[&name],
+    )?;
+    if removed == 0 {
+        return Err(OverridesError::NoOverrides(name.into()).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn set_merges_overrides() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+            assert_eq!(get_overrides(&mut conn, "foo")?, None);
+
+            let memory = Overrides {
+                memory: Some(6 * 1024 * 1024 * 1024),
+                ..Overrides::default()
+            };
+            assert_eq!(set_overrides(&mut conn, "foo", &memory)?, memory);
+
+            let networking = Overrides {
+                networking: Some(true),
+                timeout: Some(Duration::from_secs(20 * 60)),
+                ..Overrides::default()
+            };
+            let expected = Overrides {
+                memory: memory.memory,
+                ..networking
+            };
+            assert_eq!(set_overrides(&mut conn, "foo", &networking)?, expected);
+            assert_eq!(get_overrides(&mut conn, "foo")?, Some(expected));
+
+            remove_overrides(&mut conn, "foo")?;
+            assert_eq!(get_overrides(&mut conn, "foo")?, None);
+            assert!(remove_overrides(&mut conn, "foo").is_err());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn invalid_overrides_are_rejected() {
+        wrapper(|env| {
+            let mut conn = env.db().conn();
+
+            for overrides in [
+                Overrides::default(),
+                Overrides {
+                    memory: Some(0),
+                    ..Overrides::default()
+                },
+                Overrides {
+                    targets: Some(0),
+                    ..Overrides::default()
+                },
+                Overrides {
+                    timeout: Some(Duration::from_millis(500)),
+                    ..Overrides::default()
+                },
+                Overrides {
+                    timeout: Some(Duration::from_secs(u64::MAX)),
+                    ..Overrides::default()
+                },
+            ] {
+                assert!(set_overrides(&mut conn, "foo", &overrides).is_err());
+            }
+            assert_eq!(get_overrides(&mut conn, "foo")?, None);
+
+            Ok(())
+        })
+    }
+}
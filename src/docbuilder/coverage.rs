@@ -0,0 +1,232 @@
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// The documentation coverage of a single source file, as reported by
+/// `rustdoc --show-coverage`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileCoverage {
+    /// The path of the file, relative to the crate root.
+    pub(crate) file: String,
+    pub(crate) total_items: i32,
+    pub(crate) documented_items: i32,
+    pub(crate) total_items_needing_examples: i32,
+    pub(crate) items_with_examples: i32,
+}
+
+/// A public item without documentation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct UndocumentedItem {
+    /// The path of the module containing the item, e.g. `tokio::net`.
+    pub(crate) module: String,
+    /// The item kind as rustdoc names it in its HTML output, e.g. `struct`, `fn` or `mod`.
+    pub(crate) kind: String,
+    pub(crate) name: String,
+    /// The rustdoc page of the item, relative to the root of the documentation.
+    pub(crate) url: String,
+}
+
+/// Parses a line of `rustdoc --show-coverage --output-format json` output.
+///
+/// Returns `None` if the line isn't a coverage report.
+pub(crate) fn parse_coverage_line(line: &str) -> Option<Vec<FileCoverage>> {
+    #[derive(Deserialize)]
+    struct RawCoverage {
+        total: i32,
+        with_docs: i32,
+        total_examples: i32,
+        with_examples: i32,
+    }
+
+    if !(line.starts_with('{') && line.ends_with('}')) {
+        return None;
+    }
+    let parsed = serde_json::from_str::<HashMap<String, RawCoverage>>(line).ok()?;
+    Some(
+        parsed
+            .into_iter()
+            .map(|(file, coverage)| FileCoverage {
+                file,
+                total_items: coverage.total,
+                documented_items: coverage.with_docs,
+                total_items_needing_examples: coverage.total_examples,
+                items_with_examples: coverage.with_examples,
+            })
+            .collect(),
+    )
+}
+
+/// Collects the public items of the local crate without documentation from the output of
+/// `rustdoc --output-format json`.
+///
+/// Only the items rustdoc generates a page for are included, so fields, variants and
+/// associated items are ignored.
+pub(crate) fn undocumented_items(rustdoc_json: &[u8]) -> Result<Vec<UndocumentedItem>> {
+    let krate: Value = serde_json::from_slice(rustdoc_json)?;
+    let empty = serde_json::Map::new();
+    let index = krate["index"].as_object().unwrap_or(&empty);
+    let paths = krate["paths"].as_object().unwrap_or(&empty);
+
+    let mut items = Vec::new();
+    for (id, summary) in paths {
+        if summary["crate_id"].as_u64() != Some(0) {
+            continue;
+        }
+        let item = match index.get(id) {
+            Some(item) => item,
+            None => continue,
+        };
+        let is_documented = item["docs"].as_str().map_or(false, |docs| !docs.is_empty());
+        if is_documented || item["visibility"].as_str() != Some("public") {
+            continue;
+        }
+
+        let path: Vec<&str> = match summary["path"].as_array() {
+            Some(path) => path.iter().filter_map(Value::as_str).collect(),
+            None => continue,
+        };
+        let kind = match summary["kind"].as_str().and_then(html_kind) {
+            Some(kind) => kind,
+            None => continue,
+        };
+        let (name, parent) = match path.split_last() {
+            Some((name, parent)) => (*name, parent),
+            None => continue,
+        };
+
+        let (module, url) = if kind == "mod" {
+            let module = if parent.is_empty() {
+                path.join("::")
+            } else {
+                parent.join("::")
+            };
+            (module, format!("{}/index.html", path.join("/")))
+        } else {
+            (
+                parent.join("::"),
+                format!("{}/{}.{}.html", parent.join("/"), kind, name),
+            )
+        };
+
+        items.push(UndocumentedItem {
+            module,
+            kind: kind.to_string(),
+            name: name.to_string(),
+            url,
+        });
+    }
+
+    items.sort_by(|a, b| (&a.module, &a.name, &a.kind).cmp(&(&b.module, &b.name, &b.kind)));
+    Ok(items)
+}
+
+/// Maps the item kinds of the rustdoc JSON output to the ones used in rustdoc HTML file names.
+fn html_kind(json_kind: &str) -> Option<&'static str> {
+    Some(match json_kind {
+        "module" => "mod",
+        "function" => "fn",
+        "struct" => "struct",
+        "enum" => "enum",
+        "union" => "union",
+        "trait" => "trait",
+        "trait_alias" => "traitalias",
+        "typedef" | "type_alias" => "type",
+        "constant" => "constant",
+        "static" => "static",
+        "macro" => "macro",
+        "proc_attribute" => "attr",
+        "proc_derive" => "derive",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coverage_line() {
+        let mut files = parse_coverage_line(
+            r#"{"src/lib.rs":{"total":11,"with_docs":3,"total_examples":5,"with_examples":1},"src/a.rs":{"total":2,"with_docs":2,"total_examples":0,"with_examples":0}}"#,
+        )
+        .unwrap();
+        files.sort_by(|a, b| a.file.cmp(&b.file));
+        assert_eq!(
+            files,
+            vec![
+                FileCoverage {
+                    file: "src/a.rs".into(),
+                    total_items: 2,
+                    documented_items: 2,
+                    total_items_needing_examples: 0,
+                    items_with_examples: 0,
+                },
+                FileCoverage {
+                    file: "src/lib.rs".into(),
+                    total_items: 11,
+                    documented_items: 3,
+                    total_items_needing_examples: 5,
+                    items_with_examples: 1,
+                },
+            ]
+        );
+
+        assert!(parse_coverage_line("warning: unused import").is_none());
+        assert!(parse_coverage_line("{not json}").is_none());
+    }
+
+    #[test]
+    fn items_from_rustdoc_json() {
+        let json = br#"{
+            "index": {
+                "0": {"name": "undocumented_fn", "visibility": "public", "docs": null},
+                "1": {"name": "A", "visibility": "default", "docs": null},
+                "2": {"name": "E", "visibility": "public", "docs": "doc"},
+                "44": {"name": "inner", "visibility": "public", "docs": ""},
+                "61": {"name": "Undocumented", "visibility": "public", "docs": null},
+                "79": {"name": "mac", "visibility": "public", "docs": null},
+                "80": {"name": "krate", "visibility": "public", "docs": null},
+                "81": {"name": "Private", "visibility": "crate", "docs": null}
+            },
+            "paths": {
+                "0": {"crate_id": 0, "path": ["krate", "inner", "undocumented_fn"], "kind": "function"},
+                "1": {"crate_id": 0, "path": ["krate", "inner", "E", "A"], "kind": "variant"},
+                "2": {"crate_id": 0, "path": ["krate", "inner", "E"], "kind": "enum"},
+                "44": {"crate_id": 0, "path": ["krate", "inner"], "kind": "module"},
+                "61": {"crate_id": 0, "path": ["krate", "Undocumented"], "kind": "struct"},
+                "79": {"crate_id": 0, "path": ["krate", "mac"], "kind": "macro"},
+                "80": {"crate_id": 0, "path": ["krate"], "kind": "module"},
+                "81": {"crate_id": 0, "path": ["krate", "Private"], "kind": "struct"},
+                "99": {"crate_id": 1, "path": ["core", "option", "Option"], "kind": "enum"}
+            }
+        }"#;
+
+        let item = |module: &str, kind: &str, name: &str, url: &str| UndocumentedItem {
+            module: module.into(),
+            kind: kind.into(),
+            name: name.into(),
+            url: url.into(),
+        };
+        assert_eq!(
+            undocumented_items(json).unwrap(),
+            vec![
+                item(
+                    "krate",
+                    "struct",
+                    "Undocumented",
+                    "krate/struct.Undocumented.html"
+                ),
+                item("krate", "mod", "inner", "krate/inner/index.html"),
+                item("krate", "mod", "krate", "krate/index.html"),
+                item("krate", "macro", "mac", "krate/macro.mac.html"),
+                item(
+                    "krate::inner",
+                    "fn",
+                    "undocumented_fn",
+                    "krate/inner/fn.undocumented_fn.html"
+                ),
+            ]
+        );
+    }
+}
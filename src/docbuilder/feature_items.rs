@@ -0,0 +1,244 @@
+use crate::error::Result;
+use lol_html::{element, html_content::Element, HtmlRewriter, Settings};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+/// A public item which is only available when some crate features are enabled.
+///
+/// This is extracted from the `doc(cfg(feature = "..."))` portability notes rustdoc renders
+/// next to each item in the module listings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FeatureItem {
+    /// The item kind as rustdoc names it, e.g. `struct`, `fn` or `mod`.
+    pub(crate) kind: String,
+    /// The full path of the item, e.g. `tokio::net::TcpStream`.
+    pub(crate) path: String,
+    /// The rustdoc page of the item, relative to the root of the documentation.
+    pub(crate) url: String,
+    /// All crate features the item requires.
+    pub(crate) features: Vec<String>,
+}
+
+/// Collects all items of the crate documented in `doc_dir/crate_dir` which are gated behind
+/// crate features.
+pub(crate) fn collect_feature_items(doc_dir: &Path, crate_dir: &str) -> Result<Vec<FeatureItem>> {
+    let mut items = BTreeMap::new();
+
+    for entry in WalkDir::new(doc_dir.join(crate_dir)) {
+        let entry = entry?;
+        if !entry.file_type().is_file() || entry.file_name() != "index.html" {
+            continue;
+        }
+
+        let page_dir = entry
+            .path()
+            .parent()
+            .and_then(|dir| dir.strip_prefix(doc_dir).ok())
+            .unwrap_or_else(|| Path::new(""));
+
+        let html = fs::read(entry.path())?;
+        for item in feature_items_in_module_page(&html, page_dir)? {
+            items
+                .entry((item.path.clone(), item.kind.clone()))
+                .or_insert(item);
+        }
+    }
+
+    Ok(items.into_values().collect())
+}
+
+/// Parses a rustdoc module page and returns the listed items with a crate feature portability
+/// note. `page_dir` is the directory of the page relative to the documentation root, and is
+/// used to make the item links relative to the root too.
+fn feature_items_in_module_page(html: &[u8], page_dir: &Path) -> Result<Vec<FeatureItem>> {
+    // rustdoc renders every item in the module listing as
+    // `<a class="struct" href="struct.Foo.html" title="struct krate::Foo">Foo</a>`,
+    // optionally followed by `<span class="stab portability" title="...">`.
+    let last_item: RefCell<Option<(String, String, String)>> = RefCell::new(None);
+    let items = RefCell::new(Vec::new());
+
+    let link_handler = |link: &mut Element| {
+        let item = match (link.get_attribute("href"), link.get_attribute("title")) {
+            (Some(href), Some(title)) => parse_item_link(&href, &title, page_dir),
+            _ => None,
+        };
+        *last_item.borrow_mut() = item;
+        Ok(())
+    };
+
+    let portability_handler = |stab: &mut Element| {
+        if let (Some((kind, path, url)), Some(title)) =
+            (last_item.borrow_mut().take(), stab.get_attribute("title"))
+        {
+            let features = parse_required_features(&title);
+            if !features.is_empty() {
+                items.borrow_mut().push(FeatureItem {
+                    kind,
+                    path,
+                    url,
+                    features,
+                });
+            }
+        }
+        Ok(())
+    };
+
+    let mut rewriter = HtmlRewriter::new(
+        Settings {
+            element_content_handlers: vec![
+                element!("a[href][title]", link_handler),
+                element!(".stab.portability[title]", portability_handler),
+            ],
+            ..Settings::default()
+        },
+        |_: &[u8]| {},
+    );
+    rewriter.write(html)?;
+    rewriter.end()?;
+
+    Ok(items.into_inner())
+}
+
+/// Returns the kind, path and root-relative url of an item link in a module listing.
+fn parse_item_link(href: &str, title: &str, page_dir: &Path) -> Option<(String, String, String)> {
+    if href.contains("://") || href.starts_with('/') || href.starts_with('#') {
+        return None;
+    }
+
+    let (kind, path) = title.split_once(' ')?;
+    if kind.is_empty() || !path.contains("::") || path.contains(' ') {
+        return None;
+    }
+
+    let mut url = PathBuf::new();
+    for component in page_dir.join(href).components() {
+        match component {
+            Component::ParentDir => {
+                url.pop();
+            }
+            Component::Normal(part) => url.push(part),
+            _ => {}
+        }
+    }
+
+    Some((
+        kind.to_owned(),
+        path.to_owned(),
+        url.to_str()?.replace('\\', "/"),
+    ))
+}
+
+/// Extracts the crate features from the title of a rustdoc portability note, e.g.
+/// "Available on crate features `a` and `b` only".
+///
+/// Negated features (`non-crate feature`) and other cfgs like target features are ignored.
+fn parse_required_features(title: &str) -> Vec<String> {
+    static FEATURE_LIST: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"(non-)?crate features? ((?:`[^`]+`(?:,? (?:and|or) |, )?)+)").unwrap()
+    });
+    static FEATURE_NAME: Lazy<Regex> = Lazy::new(|| Regex::new(r"`([^`]+)`").unwrap());
+
+    let mut features = Vec::new();
+    for list in FEATURE_LIST.captures_iter(title) {
+        if list.get(1).is_some() {
+            continue;
+        }
+        for name in FEATURE_NAME.captures_iter(&list[2]) {
+            let name = name[1].to_owned();
+            if !features.contains(&name) {
+                features.push(name);
+            }
+        }
+    }
+    features
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("Available on crate feature `foo` only", &["foo"])]
+    #[test_case("Available on crate features `foo` and `bar` only", &["foo", "bar"])]
+    #[test_case("Available on crate feature `foo` or crate feature `bar` only", &["foo", "bar"])]
+    #[test_case("Available on crate feature `foo` and Linux only", &["foo"])]
+    #[test_case("Available on non-crate feature `foo` only", &[])]
+    #[test_case("Available on target feature `sse2` only", &[])]
+    #[test_case("Available on Unix only", &[])]
+    fn required_features(title: &str, expected: &[&str]) {
+        assert_eq!(parse_required_features(title), expected);
+    }
+
+    #[test]
+    fn module_page() {
+        let html = br#"<html><body>
+            <div class="item-info"><span class="stab portability" title="Available on crate feature `net` only">net</span></div>
+            <div class="item-row"><div class="item-left module-item">
+                <a class="struct" href="struct.Plain.html" title="struct krate::net::Plain">Plain</a>
+            </div></div>
+            <div class="item-row"><div class="item-left module-item">
+                <a class="struct" href="struct.TcpStream.html" title="struct krate::net::TcpStream">TcpStream</a>
+                <span class="stab portability" title="Available on crate feature `tcp` only">tcp</span>
+            </div></div>
+            <div class="item-row"><div class="item-left module-item">
+                <a class="mod" href="udp/index.html" title="mod krate::net::udp">udp</a>
+                <span class="stab portability" title="Available on crate features `udp` and `unstable` only">udp and unstable</span>
+            </div></div>
+            <div class="item-row"><div class="item-left module-item">
+                <a class="fn" href="../fn.connect.html" title="fn krate::connect">connect</a>
+                <span class="stab portability" title="Available on Unix only">Unix</span>
+            </div></div>
+        </body></html>"#;
+
+        let items = feature_items_in_module_page(html, Path::new("krate/net")).unwrap();
+        assert_eq!(
+            items,
+            vec![
+                FeatureItem {
+                    kind: "struct".into(),
+                    path: "krate::net::TcpStream".into(),
+                    url: "krate/net/struct.TcpStream.html".into(),
+                    features: vec!["tcp".into()],
+                },
+                FeatureItem {
+                    kind: "mod".into(),
+                    path: "krate::net::udp".into(),
+                    url: "krate/net/udp/index.html".into(),
+                    features: vec!["udp".into(), "unstable".into()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn collect_from_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let krate = dir.path().join("krate");
+        fs::create_dir_all(krate.join("sub")).unwrap();
+        fs::write(
+            krate.join("index.html"),
+            r#"<a class="mod" href="sub/index.html" title="mod krate::sub">sub</a>
+               <span class="stab portability" title="Available on crate feature `sub` only">sub</span>"#,
+        )
+        .unwrap();
+        fs::write(
+            krate.join("sub").join("index.html"),
+            r#"<a class="fn" href="fn.f.html" title="fn krate::sub::f">f</a>
+               <span class="stab portability" title="Available on crate feature `sub` only">sub</span>"#,
+        )
+        .unwrap();
+        fs::write(krate.join("sub").join("fn.f.html"), "").unwrap();
+
+        let items = collect_feature_items(dir.path(), "krate").unwrap();
+        let urls: Vec<_> = items.iter().map(|item| item.url.as_str()).collect();
+        assert_eq!(urls, vec!["krate/sub/index.html", "krate/sub/fn.f.html"]);
+    }
+}
@@ -1,23 +1,47 @@
+use crate::db::overrides::get_overrides;
+use crate::docbuilder::BuildResult;
 use crate::error::Result;
+use crate::BuildConfig;
 use postgres::Client;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+// limits stored before a limit was added are read with its default
+#[serde(default)]
 pub(crate) struct Limits {
     memory: usize,
     targets: usize,
+    feature_profiles: usize,
     timeout: Duration,
     networking: bool,
     max_log_size: usize,
 }
 
+/// The limit a build was killed for exceeding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ExceededLimit {
+    Memory,
+    Timeout,
+}
+
+impl ExceededLimit {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Memory => "memory",
+            Self::Timeout => "timeout",
+        }
+    }
+}
+
 impl Default for Limits {
     fn default() -> Self {
         Self {
             memory: 3 * 1024 * 1024 * 1024,        // 3 GB
             timeout: Duration::from_secs(15 * 60), // 15 minutes
             targets: 10,
+            feature_profiles: 5,
             networking: false,
             max_log_size: 100 * 1024, // 100 KB
         }
@@ -28,29 +52,60 @@ impl Limits {
     pub(crate) fn for_crate(conn: &mut Client, name: &str) -> Result<Self> {
         let mut limits = Self::default();
 
-        let res = conn.query(
-            "SELECT * FROM sandbox_overrides WHERE crate_name = $1;",
-            &[&name],
-        )?;
-        if !res.is_empty() {
-            let row = &res[0];
-            if let Some(memory) = row.get::<_, Option<i64>>("max_memory_bytes") {
-                limits.memory = memory as usize;
+        if let Some(overrides) = get_overrides(conn, name)? {
+            if let Some(memory) = overrides.memory {
+                limits.memory = memory;
             }
-            let timeout = row.get::<_, Option<i32>>("timeout_seconds");
-            if let Some(timeout) = timeout {
-                limits.timeout = Duration::from_secs(timeout as u64);
+            if let Some(timeout) = overrides.timeout {
+                limits.timeout = timeout;
             }
-            if let Some(targets) = row.get::<_, Option<i32>>("max_targets") {
-                limits.targets = targets as usize;
-            } else if timeout.is_some() {
+            if let Some(targets) = overrides.targets {
+                limits.targets = targets;
+            } else if overrides.timeout.is_some() {
                 limits.targets = 1;
             }
+            if let Some(networking) = overrides.networking {
+                limits.networking = networking;
+            }
         }
 
         Ok(limits)
     }
 
+    /// Returns the limits to retry a build with after it exceeded the memory or time limit.
+    ///
+    /// The exceeded limit is doubled up to the configured ceiling and only the default target is
+    /// built. Builds which already ran with escalated limits, or with limits at the ceiling,
+    /// aren't retried.
+    pub(crate) fn escalate(&self, result: &BuildResult, config: &BuildConfig) -> Option<Self> {
+        if result.escalated_limits.is_some() {
+            return None;
+        }
+
+        let mut limits = self.clone();
+        match result.exceeded_limit? {
+            ExceededLimit::Memory => {
+                if self.memory >= config.max_escalated_memory {
+                    return None;
+                }
+                limits.memory = self
+                    .memory
+                    .saturating_mul(2)
+                    .min(config.max_escalated_memory);
+            }
+            ExceededLimit::Timeout => {
+                let max_timeout = Duration::from_secs(config.max_escalated_timeout);
+                if self.timeout >= max_timeout {
+                    return None;
+                }
+                limits.timeout = (self.timeout * 2).min(max_timeout);
+            }
+        }
+        limits.targets = 1;
+
+        Some(limits)
+    }
+
     pub(crate) fn memory(&self) -> usize {
         self.memory
     }
@@ -70,6 +125,10 @@ impl Limits {
     pub(crate) fn targets(&self) -> usize {
         self.targets
     }
+
+    pub(crate) fn feature_profiles(&self) -> usize {
+        self.feature_profiles
+    }
 }
 
 #[cfg(test)]
@@ -119,6 +178,23 @@ mod test {
         });
     }
 
+    #[test]
+    fn networking_can_be_enabled() {
+        wrapper(|env| {
+            let db = env.db();
+            let krate = "hexponent";
+            db.conn().query(
+                "INSERT INTO sandbox_overrides (crate_name, networking) VALUES ($1, TRUE);",
+                &[&krate],
+            )?;
+            let limits = Limits::for_crate(&mut db.conn(), krate)?;
+            assert!(limits.networking());
+            assert_eq!(limits.targets, Limits::default().targets);
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn targets_default_to_one_with_timeout() {
         wrapper(|env| {
@@ -134,4 +210,56 @@ mod test {
             Ok(())
         });
     }
+
+    #[test]
+    fn exceeded_limits_are_escalated_once() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.build.max_escalated_memory = 4 * 1024 * 1024 * 1024;
+                config.build.max_escalated_timeout = 45 * 60;
+            });
+            let config = env.config();
+            let mut result = BuildResult {
+                rustc_version: "rustc 1.70.0 (90c541806 2023-05-31)".into(),
+                docsrs_version: "docs.rs 1.0.0".into(),
+                successful: false,
+                metadata_warnings: Vec::new(),
+                exceeded_limit: None,
+                escalated_limits: None,
+            };
+
+            // builds failing for other reasons aren't retried
+            let limits = Limits::default();
+            assert_eq!(limits.escalate(&result, &config.build), None);
+
+            result.exceeded_limit = Some(ExceededLimit::Memory);
+            let escalated = limits.escalate(&result, &config.build).unwrap();
+            assert_eq!(
+                escalated,
+                Limits {
+                    memory: 4 * 1024 * 1024 * 1024,
+                    targets: 1,
+                    ..Limits::default()
+                }
+            );
+            // the ceiling was reached
+            assert_eq!(escalated.escalate(&result, &config.build), None);
+
+            result.exceeded_limit = Some(ExceededLimit::Timeout);
+            assert_eq!(
+                limits.escalate(&result, &config.build),
+                Some(Limits {
+                    timeout: Duration::from_secs(30 * 60),
+                    targets: 1,
+                    ..Limits::default()
+                })
+            );
+
+            // escalated builds aren't escalated again
+            result.escalated_limits = Some(escalated);
+            assert_eq!(limits.escalate(&result, &config.build), None);
+
+            Ok(())
+        });
+    }
 }
@@ -1,7 +1,20 @@
+mod coverage;
 mod crates;
+mod feature_items;
 mod limits;
+mod publish;
+mod remote;
 mod rustwide_builder;
 
-pub(crate) use self::limits::Limits;
-pub(crate) use self::rustwide_builder::{BuildResult, DocCoverage};
-pub use self::rustwide_builder::{PackageKind, RustwideBuilder};
+pub(crate) use self::coverage::{FileCoverage, UndocumentedItem};
+pub(crate) use self::feature_items::FeatureItem;
+pub(crate) use self::limits::{ExceededLimit, Limits};
+pub(crate) use self::publish::Publisher;
+#[cfg(test)]
+pub(crate) use self::remote::write_build_archive;
+pub use self::remote::RemoteBuilder;
+pub(crate) use self::remote::{extract_build_archive, LeaseResponse};
+pub(crate) use self::rustwide_builder::{
+    BuildOutput, BuildResult, DocCoverage, ToolchainUpdateError,
+};
+pub use self::rustwide_builder::{PackageKind, RustwideBuilder, StandaloneBuildReport};
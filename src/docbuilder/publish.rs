@@ -0,0 +1,157 @@
+use crate::cdn;
+use crate::db::{
+    add_build_into_database, add_coverage_details, add_doc_coverage, add_feature_items,
+    add_package_into_database, add_path_into_remote_archive, update_crate_data_in_database,
+};
+use crate::docbuilder::BuildOutput;
+use crate::error::Result;
+use crate::index::api::{Api, ReleaseData};
+use crate::repositories::RepositoryStatsUpdater;
+use crate::storage::{
+    add_storage_usage, record_storage_usage, rustdoc_archive_path, source_archive_path, UsageKind,
+};
+use crate::{Config, Metrics, Storage};
+use log::{debug, warn};
+use postgres::Client;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Stores the documentation built by a builder and adds the release to the database.
+///
+/// The builders of the daemon publish their builds directly, builds of remote builders are
+/// published by the web server once they are uploaded.
+pub(crate) struct Publisher<'a> {
+    pub(crate) storage: &'a Storage,
+    pub(crate) metrics: &'a Metrics,
+    pub(crate) config: &'a Config,
+    pub(crate) repository_stats_updater: &'a RepositoryStatsUpdater,
+    /// The registry the release data is loaded from, it's kept unchanged without one.
+    pub(crate) registry: Option<&'a Api>,
+}
+
+impl Publisher<'_> {
+    /// Publishes the documentation in `doc_dir` and the sources in `source_dir`, returning
+    /// whether the build was successful.
+    pub(crate) fn publish(
+        &self,
+        conn: &mut Client,
+        name: &str,
+        version: &str,
+        output: &BuildOutput,
+        doc_dir: &Path,
+        source_dir: &Path,
+    ) -> Result<bool> {
+        let mut algs = HashSet::new();
+        let mut usage = Vec::new();
+        if output.has_docs {
+            let (_, new_alg, archive_usage) = add_path_into_remote_archive(
+                self.storage,
+                &rustdoc_archive_path(name, version),
+                doc_dir,
+            )?;
+            algs.insert(new_alg);
+            usage.push((UsageKind::Rustdoc, archive_usage));
+        }
+
+        // Store the sources even if the build fails
+        debug!("adding sources into database");
+        let files_list = {
+            let (files_list, new_alg, archive_usage) = add_path_into_remote_archive(
+                self.storage,
+                &source_archive_path(name, version),
+                source_dir,
+            )?;
+            algs.insert(new_alg);
+            usage.push((UsageKind::Sources, archive_usage));
+            files_list
+        };
+
+        if output.result.successful {
+            self.metrics.successful_builds.inc();
+        } else if output.package.is_library() {
+            self.metrics.failed_builds.inc();
+        } else {
+            self.metrics.non_library_builds.inc();
+        }
+
+        let release_data = match self.registry.map(|api| api.get_release_data(name, version)) {
+            Some(Ok(data)) => data,
+            Some(Err(err)) => {
+                warn!("{:#?}", err);
+                ReleaseData::default()
+            }
+            None => ReleaseData::default(),
+        };
+
+        let repository = self
+            .repository_stats_updater
+            .load_repository(&output.package)?;
+
+        let release_id = add_package_into_database(
+            conn,
+            &output.package,
+            source_dir,
+            &output.result,
+            &output.default_target,
+            files_list,
+            output.successful_targets.clone(),
+            output.successful_profiles.clone(),
+            &release_data,
+            output.has_docs,
+            output.has_examples,
+            algs,
+            repository,
+            true,
+        )?;
+
+        if let Some(doc_coverage) = output.doc_coverage {
+            add_doc_coverage(conn, release_id, doc_coverage)?;
+        }
+        add_coverage_details(
+            conn,
+            release_id,
+            &output.file_coverage,
+            &output.undocumented,
+        )?;
+        add_feature_items(conn, release_id, &output.feature_items)?;
+        for (kind, archive_usage) in usage {
+            record_storage_usage(conn, self.storage, release_id, kind, &archive_usage)?;
+        }
+
+        let build_id = add_build_into_database(conn, release_id, &output.result)?;
+        let build_log_path = format!("build-logs/{}/{}.txt", build_id, output.default_target);
+        let (_, build_log_size) = self
+            .storage
+            .store_one(build_log_path, output.build_log.clone())?;
+        add_storage_usage(
+            conn,
+            self.storage,
+            release_id,
+            UsageKind::BuildLogs,
+            build_log_size,
+        )?;
+
+        // Some crates.io crate data is mutable, so we proactively update it during a release
+        if let Some(api) = self.registry {
+            match api.get_crate_data(name) {
+                Ok(crate_data) => update_crate_data_in_database(conn, name, &crate_data)?,
+                Err(err) => warn!("{:#?}", err),
+            }
+        }
+
+        if output.result.successful {
+            // delete eventually existing files from pre-archive storage.
+            // we're doing this in the end so eventual problems in the build
+            // won't lead to non-existing docs.
+            for prefix in &["rustdoc", "sources"] {
+                let prefix = format!("{}/{}/{}/", prefix, name, version);
+                debug!("cleaning old storage folder {}", prefix);
+                self.storage.delete_prefix(&prefix)?;
+            }
+        }
+
+        cdn::queue_crate_invalidation(conn, self.config, name)?;
+
+        Ok(output.result.successful)
+    }
+}
@@ -0,0 +1,246 @@
+//! Builders running on other machines than the docs.rs database and storage.
+//!
+//! A remote builder leases crates from the build queue through the builder API of the web
+//! server, renews the lease while it's building, and uploads the documentation, the sources and
+//! the [`BuildOutput`] as a single zip archive, which the web server then publishes.
+
+use crate::build_queue::Lease;
+use crate::docbuilder::{BuildOutput, Limits, RustwideBuilder};
+use crate::error::Result;
+use crate::storage::get_file_list;
+use crate::utils::{queue_builder::TEMPDIR_PREFIX, report_error};
+use crate::RemoteBuilderConfig;
+use anyhow::{anyhow, bail};
+use log::{debug, info, warn};
+use reqwest::{blocking::Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use url::Url;
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+/// The file in the uploaded archive containing the serialized [`BuildOutput`].
+const BUILD_OUTPUT_FILE: &str = "build.json";
+
+/// The response of the builder API to a successful lease request.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct LeaseResponse {
+    pub(crate) lease: Lease,
+    pub(crate) limits: Limits,
+    /// How many seconds the lease is held without renewing it.
+    pub(crate) lease_duration: u64,
+}
+
+/// Writes the build output, the documentation in `dir/doc` and the sources in `dir/source` to
+/// a zip archive, to upload them to the web server.
+pub(crate) fn write_build_archive(
+    output: &BuildOutput,
+    dir: &Path,
+    writer: impl Write + Seek,
+) -> Result<()> {
+    let options = FileOptions::default().compression_method(CompressionMethod::Bzip2);
+    let mut zip = ZipWriter::new(writer);
+
+    zip.start_file(BUILD_OUTPUT_FILE, options)?;
+    serde_json::to_writer(&mut zip, output)?;
+
+    for subdir in &["doc", "source"] {
+        let subdir = dir.join(subdir);
+        if !subdir.is_dir() {
+            continue;
+        }
+        for file_path in get_file_list(&subdir)? {
+            let path = subdir.join(&file_path);
+            let name = path
+                .strip_prefix(dir)?
+                .to_str()
+                .ok_or_else(|| anyhow!("non-utf8 path {}", file_path.display()))?;
+            zip.start_file(name, options)?;
+            io::copy(&mut File::open(&path)?, &mut zip)?;
+        }
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+/// Extracts an archive written by [`write_build_archive`] into `dir`, returning the build
+/// output. Entries which would be extracted outside of `dir` are rejected.
+pub(crate) fn extract_build_archive(reader: impl Read + Seek, dir: &Path) -> Result<BuildOutput> {
+    let mut zip = ZipArchive::new(reader)?;
+    let mut output = None;
+
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index)?;
+        let name = entry
+            .enclosed_name()
+            .ok_or_else(|| anyhow!("invalid path {} in the build archive", entry.name()))?
+            .to_owned();
+
+        if name == Path::new(BUILD_OUTPUT_FILE) {
+            output = Some(serde_json::from_reader(&mut entry)?);
+        } else if name.starts_with("doc") || name.starts_with("source") {
+            if entry.is_dir() {
+                continue;
+            }
+            let path = dir.join(&name);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            io::copy(&mut entry, &mut File::create(path)?)?;
+        } else {
+            bail!("unexpected file {} in the build archive", name.display());
+        }
+    }
+
+    // the sources are published even if the build failed, so the directory has to exist
+    fs::create_dir_all(dir.join("doc"))?;
+    fs::create_dir_all(dir.join("source"))?;
+
+    output.ok_or_else(|| anyhow!("{} is missing in the build archive", BUILD_OUTPUT_FILE))
+}
+
+/// Builds crates leased from the build queue of a docs.rs instance through its builder API.
+pub struct RemoteBuilder {
+    builder: RustwideBuilder,
+    client: Client,
+    api: Url,
+    prefix: PathBuf,
+    name: String,
+}
+
+impl RemoteBuilder {
+    /// Creates a builder leasing crates from the docs.rs instance in the configuration.
+    pub fn new(config: RemoteBuilderConfig, name: &str) -> Result<Self> {
+        let RemoteBuilderConfig {
+            server,
+            token,
+            prefix,
+            build,
+        } = config;
+        fs::create_dir_all(&prefix)?;
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            format!("Bearer {}", token).parse()?,
+        );
+        let client = Client::builder()
+            .user_agent(format!("docs.rs remote builder {}", name))
+            .default_headers(headers)
+            // uploading the documentation of large crates can take a while
+            .timeout(Duration::from_secs(30 * 60))
+            .build()?;
+
+        Ok(Self {
+            builder: RustwideBuilder::init_standalone(build)?,
+            client,
+            api: server.join("/-/builder/")?,
+            prefix,
+            name: name.into(),
+        })
+    }
+
+    /// Builds leased crates until the process is stopped.
+    pub fn run(&mut self) -> Result<()> {
+        info!(
+            "starting remote builder {} for {}",
+            self.name,
+            self.api.origin().ascii_serialization()
+        );
+        loop {
+            match self.build_next_crate() {
+                Ok(true) => {}
+                Ok(false) => {
+                    debug!("Queue is empty, going back to sleep");
+                    thread::sleep(Duration::from_secs(60));
+                }
+                Err(err) => {
+                    report_error(&err.context("Failed to build a leased crate"));
+                    thread::sleep(Duration::from_secs(60));
+                }
+            }
+        }
+    }
+
+    /// Leases the next crate from the queue and builds it, returning whether there was one.
+    fn build_next_crate(&mut self) -> Result<bool> {
+        let response = self
+            .client
+            .post(self.api.join("lease")?)
+            .query(&[("builder", &self.name)])
+            .send()?
+            .error_for_status()?;
+        if response.status() == StatusCode::NO_CONTENT {
+            return Ok(false);
+        }
+        let LeaseResponse {
+            lease,
+            limits,
+            lease_duration,
+        } = response.json()?;
+        let lease_url = self.api.join(&format!("lease/{}/", lease.id))?;
+
+        let output_dir = tempfile::Builder::new()
+            .prefix(TEMPDIR_PREFIX)
+            .tempdir_in(&self.prefix)?;
+        let (builder, client) = (&mut self.builder, &self.client);
+        let result = thread::scope(|scope| {
+            let (stop, stopped) = mpsc::channel::<()>();
+            let heartbeat_url = lease_url.join("heartbeat")?;
+            scope.spawn(move || {
+                // renew the lease well before it expires, so a failed request can be retried
+                let interval = Duration::from_secs((lease_duration / 3).max(1));
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    match client.post(heartbeat_url.clone()).send() {
+                        Ok(response) if response.status() == StatusCode::CONFLICT => {
+                            warn!("the lease of the build was lost");
+                            break;
+                        }
+                        Ok(response) if !response.status().is_success() => {
+                            warn!("failed to renew the lease: {}", response.status());
+                        }
+                        Ok(_) => {}
+                        Err(err) => warn!("failed to renew the lease: {}", err),
+                    }
+                }
+            });
+
+            let result = builder.build_leased(&lease, &limits, output_dir.path());
+            drop(stop);
+            result
+        });
+
+        let response = match result {
+            Ok(output) => {
+                let mut archive = tempfile::tempfile_in(&self.prefix)?;
+                write_build_archive(&output, output_dir.path(), &mut archive)?;
+                archive.rewind()?;
+                self.client
+                    .post(lease_url.join("result")?)
+                    .body(archive)
+                    .send()?
+            }
+            Err(err) => self
+                .client
+                .post(lease_url.join("fail")?)
+                .body(format!("{:?}", err))
+                .send()?,
+        };
+        if response.status() == StatusCode::CONFLICT {
+            warn!(
+                "the lease of {} {} was lost before the build was uploaded",
+                lease.name, lease.version
+            );
+        } else {
+            response.error_for_status()?;
+        }
+
+        output_dir.close()?;
+        Ok(true)
+    }
+}
@@ -1,19 +1,21 @@
+use crate::build_queue::Lease;
 use crate::db::file::add_path_into_database;
-use crate::db::{
-    add_build_into_database, add_doc_coverage, add_package_into_database,
-    add_path_into_remote_archive, update_crate_data_in_database, Pool,
+use crate::db::Pool;
+use crate::docbuilder::{
+    coverage::{parse_coverage_line, undocumented_items, FileCoverage, UndocumentedItem},
+    crates::crates_from_path,
+    feature_items::{collect_feature_items, FeatureItem},
+    ExceededLimit, Limits, Publisher,
 };
-use crate::docbuilder::{crates::crates_from_path, Limits};
 use crate::error::Result;
-use crate::index::api::ReleaseData;
 use crate::repositories::RepositoryStatsUpdater;
-use crate::storage::{rustdoc_archive_path, source_archive_path};
+use crate::storage::feature_profile_dir;
 use crate::utils::{
     copy_dir_all, parse_rustc_version, queue_builder, set_config, CargoMetadata, ConfigName,
 };
 use crate::{db::blacklist::is_blacklisted, utils::MetadataPackage};
-use crate::{Config, Context, Index, Metrics, Storage};
-use anyhow::{anyhow, bail, Error};
+use crate::{BuildConfig, Config, Context, Index, Metrics, Storage};
+use anyhow::{anyhow, bail, Context as _, Error};
 use docsrs_metadata::{Metadata, DEFAULT_TARGETS, HOST_TARGET};
 use failure::Error as FailureError;
 use log::{debug, info, warn, LevelFilter};
@@ -21,15 +23,50 @@ use postgres::Client;
 use rustwide::cmd::{Command, CommandError, SandboxBuilder, SandboxImage};
 use rustwide::logging::{self, LogStorage};
 use rustwide::toolchain::ToolchainError;
-use rustwide::{AlternativeRegistry, Build, Crate, Toolchain, Workspace, WorkspaceBuilder};
-use std::collections::{HashMap, HashSet};
+use rustwide::{
+    AlternativeRegistry, Build, BuildDirectory, Crate, Toolchain, Workspace, WorkspaceBuilder,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
 
 const USER_AGENT: &str = "docs.rs builder (https://github.com/rust-lang/docs.rs)";
 const DUMMY_CRATE_NAME: &str = "empty-library";
 const DUMMY_CRATE_VERSION: &str = "1.0.0";
 
+static TARGET_INSTALL_LOCK: Mutex<()> = Mutex::new(());
+
+/// How often the toolchain is updated before a build.
+const TOOLCHAIN_UPDATE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The time of the last toolchain update. It's held for reading while a crate is built and for
+/// writing while the toolchain is updated, as all builders of the process share the toolchain.
+static TOOLCHAIN_UPDATED: RwLock<Option<Instant>> = RwLock::new(None);
+
+/// The error of a build which couldn't update the toolchain beforehand.
+#[derive(Debug, thiserror::Error)]
+#[error("Updating toolchain failed")]
+pub(crate) struct ToolchainUpdateError;
+
+/// How many builds can run at once, when every build may use `cpu_limit` of the CPUs and
+/// `memory_per_build` of the available memory.
+fn parallel_builds(
+    cpus: usize,
+    cpu_limit: Option<u32>,
+    available_memory: Option<u64>,
+    memory_per_build: usize,
+    builds: usize,
+) -> usize {
+    // without a limit every build can use all CPUs
+    let mut parallel = cpu_limit.map_or(1, |limit| cpus / (limit as usize).max(1));
+    if let Some(available) = available_memory {
+        parallel = parallel.min((available / (memory_per_build as u64).max(1)) as usize);
+    }
+    parallel.min(builds).max(1)
+}
+
 pub enum PackageKind<'a> {
     Local(&'a Path),
     CratesIo,
@@ -39,20 +76,46 @@ pub enum PackageKind<'a> {
 pub struct RustwideBuilder {
     workspace: Workspace,
     toolchain: Toolchain,
+    config: BuildConfig,
+    services: Option<BuildServices>,
+    rustc_version: String,
+    skip_build_if_exists: bool,
+    tempdir_prefix: String,
+}
+
+/// The services needed to publish builds, which the standalone builder runs without.
+struct BuildServices {
     config: Arc<Config>,
     db: Pool,
     storage: Arc<Storage>,
     metrics: Arc<Metrics>,
     index: Arc<Index>,
-    rustc_version: String,
     repository_stats_updater: Arc<RepositoryStatsUpdater>,
-    skip_build_if_exists: bool,
 }
 
 impl RustwideBuilder {
     pub fn init(context: &dyn Context) -> Result<Self> {
         let config = context.config()?;
+        let build_config = config.build.clone();
+        let services = BuildServices {
+            config,
+            db: context.pool()?,
+            storage: context.storage()?,
+            metrics: context.metrics()?,
+            index: context.index()?,
+            repository_stats_updater: context.repository_stats_updater()?,
+        };
+        Self::init_inner(build_config, Some(services))
+    }
 
+    /// Create a builder which doesn't need the database, storage or the registry index.
+    ///
+    /// It can only be used for [`RustwideBuilder::build_standalone`].
+    pub fn init_standalone(config: BuildConfig) -> Result<Self> {
+        Self::init_inner(config, None)
+    }
+
+    fn init_inner(config: BuildConfig, services: Option<BuildServices>) -> Result<Self> {
         let mut builder = WorkspaceBuilder::new(&config.rustwide_workspace, USER_AGENT)
             .running_inside_docker(config.inside_docker);
         if let Some(custom_image) = &config.docker_image {
@@ -78,20 +141,34 @@ impl RustwideBuilder {
             workspace,
             toolchain,
             config,
-            db: context.pool()?,
-            storage: context.storage()?,
-            metrics: context.metrics()?,
-            index: context.index()?,
+            services,
             rustc_version: String::new(),
-            repository_stats_updater: context.repository_stats_updater()?,
             skip_build_if_exists: false,
+            tempdir_prefix: queue_builder::TEMPDIR_PREFIX.into(),
         })
     }
 
+    fn services(&self) -> Result<&BuildServices> {
+        self.services
+            .as_ref()
+            .ok_or_else(|| anyhow!("the standalone builder can't publish documentation"))
+    }
+
     pub fn set_skip_build_if_exists(&mut self, should: bool) {
         self.skip_build_if_exists = should;
     }
 
+    /// Sets the prefix of the temporary directories of the builds, which has to start with
+    /// [`queue_builder::TEMPDIR_PREFIX`].
+    pub(crate) fn set_tempdir_prefix(&mut self, prefix: String) {
+        debug_assert!(prefix.starts_with(queue_builder::TEMPDIR_PREFIX));
+        self.tempdir_prefix = prefix;
+    }
+
+    pub(crate) fn tempdir_prefix(&self) -> &str {
+        &self.tempdir_prefix
+    }
+
     fn prepare_sandbox(&self, limits: &Limits) -> SandboxBuilder {
         SandboxBuilder::new()
             .cpu_limit(self.config.build_cpu_limit.map(|limit| limit as f32))
@@ -167,12 +244,50 @@ impl RustwideBuilder {
         self.rustc_version = self.detect_rustc_version()?;
 
         let has_changed = old_version.as_deref() != Some(&self.rustc_version);
-        if has_changed {
+        // The standalone builder doesn't publish anything, so it doesn't need the essential files.
+        if has_changed && self.services.is_some() {
             self.add_essential_files()?;
         }
         Ok(has_changed)
     }
 
+    /// Updates the toolchain and purges the caches if it changed, unless a builder did so in the
+    /// last [`TOOLCHAIN_UPDATE_INTERVAL`]. The returned guard keeps other builders from updating
+    /// the toolchain until the build finished.
+    ///
+    /// Failed updates are retried, and then returned as a [`ToolchainUpdateError`].
+    fn update_toolchain_before_build(
+        &mut self,
+    ) -> Result<RwLockReadGuard<'static, Option<Instant>>> {
+        {
+            // a failed update is retried by the next build, even if it panicked
+            let mut updated = TOOLCHAIN_UPDATED
+                .write()
+                .unwrap_or_else(PoisonError::into_inner);
+            if updated.map_or(true, |updated| {
+                updated.elapsed() >= TOOLCHAIN_UPDATE_INTERVAL
+            }) {
+                (|| -> Result<()> {
+                    if retry(|| self.update_toolchain(), 3)? {
+                        // toolchain has changed, purge caches
+                        retry(|| self.purge_caches(), 3)
+                            .context("purging rustwide caches failed")?;
+                    }
+                    Ok(())
+                })()
+                .context(ToolchainUpdateError)?;
+                *updated = Some(Instant::now());
+            }
+        }
+
+        // another builder might have updated the toolchain in the meantime
+        let toolchain = TOOLCHAIN_UPDATED
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.rustc_version = self.detect_rustc_version()?;
+        Ok(toolchain)
+    }
+
     fn detect_rustc_version(&self) -> Result<String> {
         info!("detecting rustc's version...");
         let res = Command::new(&self.workspace, self.toolchain.rustc())
@@ -188,13 +303,22 @@ impl RustwideBuilder {
         }
     }
 
+    fn detect_target_list(&self) -> Result<Vec<String>> {
+        let res = Command::new(&self.workspace, self.toolchain.rustc())
+            .args(&["--print", "target-list"])
+            .log_output(false)
+            .run_capture()?;
+        Ok(res.stdout_lines().to_vec())
+    }
+
     pub fn add_essential_files(&mut self) -> Result<()> {
         self.rustc_version = self.detect_rustc_version()?;
         let rustc_version = parse_rustc_version(&self.rustc_version)?;
 
         info!("building a dummy crate to get essential files");
 
-        let mut conn = self.db.get()?;
+        let services = self.services()?;
+        let mut conn = services.db.get()?;
         let limits = Limits::for_crate(&mut conn, DUMMY_CRATE_NAME)?;
 
         let mut build_dir = self
@@ -225,7 +349,7 @@ impl RustwideBuilder {
                         .prefix("essential-files")
                         .tempdir()?;
                     copy_dir_all(source, &dest)?;
-                    add_path_into_database(&self.storage, "", &dest)?;
+                    add_path_into_database(&services.storage, "", &dest)?;
 
                     set_config(
                         &mut conn,
@@ -246,19 +370,17 @@ impl RustwideBuilder {
     }
 
     pub fn build_world(&mut self) -> Result<()> {
-        crates_from_path(
-            &self.config.registry_index_path.clone(),
-            &mut |name, version| {
-                let registry_url = self.config.registry_url.clone();
-                let package_kind = registry_url
-                    .as_ref()
-                    .map(|r| PackageKind::Registry(r.as_str()))
-                    .unwrap_or(PackageKind::CratesIo);
-                if let Err(err) = self.build_package(name, version, package_kind) {
-                    warn!("failed to build package {} {}: {}", name, version, err);
-                }
-            },
-        )
+        let config = self.services()?.config.clone();
+        crates_from_path(&config.registry_index_path, &mut |name, version| {
+            let package_kind = config
+                .registry_url
+                .as_ref()
+                .map(|r| PackageKind::Registry(r.as_str()))
+                .unwrap_or(PackageKind::CratesIo);
+            if let Err(err) = self.build_package(name, version, package_kind) {
+                warn!("failed to build package {} {}: {}", name, version, err);
+            }
+        })
     }
 
     pub fn build_local_package(&mut self, path: &Path) -> Result<bool> {
@@ -271,35 +393,237 @@ impl RustwideBuilder {
         self.build_package(&package.name, &package.version, PackageKind::Local(path))
     }
 
+    /// Build the documentation of a local crate the way docs.rs would, without publishing it.
+    ///
+    /// The documentation and the build log are written to `output_dir`, laid out like the
+    /// documentation archive docs.rs serves.
+    pub fn build_standalone(
+        &mut self,
+        path: &Path,
+        output_dir: &Path,
+    ) -> Result<StandaloneBuildReport> {
+        self.update_toolchain()?;
+
+        let cargo_metadata =
+            CargoMetadata::load(&self.workspace, &self.toolchain, path).map_err(|err| {
+                err.context(format!("failed to load local package {}", path.display()))
+            })?;
+        let package = cargo_metadata.root();
+        let (name, version) = (package.name.clone(), package.version.clone());
+        info!(
+            "building local package {} {} in standalone mode",
+            name, version
+        );
+
+        let limits = Limits::default();
+        let mut build_dir = self
+            .workspace
+            .build_dir(&format!("standalone-{}-{}", name, version));
+        build_dir.purge().map_err(FailureError::compat)?;
+
+        let krate = Crate::local(path);
+        krate.fetch(&self.workspace).map_err(FailureError::compat)?;
+
+        let report = build_dir
+            .build(&self.toolchain, &krate, self.prepare_sandbox(&limits))
+            .run(|build| {
+                (|| -> Result<StandaloneBuildReport> {
+                    use docsrs_metadata::BuildTargets;
+
+                    let metadata = Metadata::from_crate_root(&build.host_source_dir())?;
+                    let BuildTargets {
+                        default_target,
+                        other_targets,
+                    } = metadata.targets(self.config.include_default_targets);
+
+                    let res =
+                        self.build_default_target(default_target, build, &limits, &metadata)?;
+                    let mut report = StandaloneBuildReport {
+                        name: name.clone(),
+                        version: version.clone(),
+                        rustc_version: self.rustc_version.clone(),
+                        default_target: default_target.to_string(),
+                        successful: res.result.successful,
+                        successful_targets: Vec::new(),
+                        failed_targets: Vec::new(),
+                        successful_profiles: Vec::new(),
+                        failed_profiles: Vec::new(),
+                        doc_coverage: res.doc_coverage,
+                        metadata_warnings: self.validate_metadata(&metadata),
+                    };
+
+                    std::fs::create_dir_all(output_dir)?;
+                    let build_log = report
+                        .metadata_warnings
+                        .iter()
+                        .map(|warning| format!("[WARN] docs.rs metadata: {}\n", warning))
+                        .collect::<String>()
+                        + &res.build_log;
+                    std::fs::write(output_dir.join("build-log.txt"), build_log)?;
+
+                    let has_docs = res.result.successful
+                        && build
+                            .host_target_dir()
+                            .join(default_target)
+                            .join("doc")
+                            .is_dir();
+                    if !has_docs {
+                        return Ok(report);
+                    }
+                    self.copy_docs(&build.host_target_dir(), output_dir, default_target, true)?;
+
+                    for target in other_targets.into_iter().take(limits.targets()) {
+                        self.build_target(
+                            target,
+                            build,
+                            &limits,
+                            output_dir,
+                            &mut report.successful_targets,
+                            &metadata,
+                        )?;
+                        if !report.successful_targets.iter().any(|t| t == target) {
+                            report.failed_targets.push(target.to_string());
+                        }
+                    }
+
+                    if !metadata.proc_macro {
+                        for profile in metadata.feature_profiles().take(limits.feature_profiles()) {
+                            self.build_feature_profile(
+                                profile,
+                                default_target,
+                                build,
+                                &limits,
+                                output_dir,
+                                &mut report.successful_profiles,
+                                &metadata,
+                            )?;
+                            if !report.successful_profiles.iter().any(|p| p == profile) {
+                                report.failed_profiles.push(profile.to_string());
+                            }
+                        }
+                    }
+
+                    Ok(report)
+                })()
+                .map_err(|e| failure::Error::from_boxed_compat(e.into()))
+            })
+            .map_err(|e| e.compat())?;
+
+        build_dir.purge().map_err(FailureError::compat)?;
+        krate
+            .purge_from_cache(&self.workspace)
+            .map_err(FailureError::compat)?;
+        Ok(report)
+    }
+
     pub fn build_package(
         &mut self,
         name: &str,
         version: &str,
         kind: PackageKind<'_>,
     ) -> Result<bool> {
-        let mut conn = self.db.get()?;
+        Ok(self
+            .build_queued_package(name, version, kind, None)?
+            .successful)
+    }
+
+    /// Build and publish the documentation of a queued crate, with the escalated limits the
+    /// queue retries it with after it exceeded its limits.
+    pub(crate) fn build_queued_package(
+        &mut self,
+        name: &str,
+        version: &str,
+        kind: PackageKind<'_>,
+        escalated_limits: Option<&Limits>,
+    ) -> Result<BuildOutcome> {
+        let mut conn = self.services()?.db.get()?;
 
         if !self.should_build(&mut conn, name, version)? {
-            return Ok(false);
+            return Ok(BuildOutcome::default());
         }
 
-        self.update_toolchain()?;
+        let _toolchain = self.update_toolchain_before_build()?;
+        let services = self.services()?;
 
         info!("building package {} {}", name, version);
 
         if is_blacklisted(&mut conn, name)? {
             info!("skipping build of {}, crate has been blacklisted", name);
-            return Ok(false);
+            return Ok(BuildOutcome::default());
         }
 
-        let limits = Limits::for_crate(&mut conn, name)?;
-        #[cfg(target_os = "linux")]
-        if !self.config.disable_memory_limit {
-            use anyhow::Context;
-            let mem_info = procfs::Meminfo::new().context("failed to read /proc/meminfo")?;
-            let available = mem_info
-                .mem_available
-                .expect("kernel version too old for determining memory limit");
+        let limits = match escalated_limits {
+            Some(limits) => limits.clone(),
+            None => Limits::for_crate(&mut conn, name)?,
+        };
+        let publisher = Publisher {
+            storage: &services.storage,
+            metrics: &services.metrics,
+            config: &services.config,
+            repository_stats_updater: &services.repository_stats_updater,
+            registry: Some(services.index.api()),
+        };
+        self.build_crate(
+            name,
+            version,
+            kind,
+            &limits,
+            |mut output, doc_dir, source_dir| {
+                output.result.escalated_limits = escalated_limits.cloned();
+                let successful =
+                    publisher.publish(&mut conn, name, version, &output, doc_dir, source_dir)?;
+                Ok(BuildOutcome {
+                    successful,
+                    retry_limits: limits.escalate(&output.result, &self.config),
+                })
+            },
+        )
+    }
+
+    /// Build the documentation of a crate leased from the build queue of another docs.rs
+    /// instance, without publishing it.
+    ///
+    /// The documentation is copied to `output_dir/doc` and the sources to `output_dir/source`,
+    /// so both can be uploaded together with the returned [`BuildOutput`].
+    pub(crate) fn build_leased(
+        &mut self,
+        lease: &Lease,
+        limits: &Limits,
+        output_dir: &Path,
+    ) -> Result<BuildOutput> {
+        let _toolchain = self.update_toolchain_before_build()?;
+        info!("building leased package {} {}", lease.name, lease.version);
+
+        let kind = lease
+            .registry
+            .as_deref()
+            .map_or(PackageKind::CratesIo, PackageKind::Registry);
+        self.build_crate(
+            &lease.name,
+            &lease.version,
+            kind,
+            limits,
+            |output, doc_dir, source_dir| {
+                copy_dir_all(doc_dir, output_dir.join("doc"))?;
+                copy_dir_all(source_dir, output_dir.join("source"))?;
+                Ok(output)
+            },
+        )
+    }
+
+    /// Build the documentation of the crate, then pass the output to `publish` together with
+    /// the directory containing the documentation and the one containing the sources.
+    ///
+    /// Both directories are removed once `publish` returns.
+    fn build_crate<R>(
+        &self,
+        name: &str,
+        version: &str,
+        kind: PackageKind<'_>,
+        limits: &Limits,
+        publish: impl FnOnce(BuildOutput, &Path, &Path) -> Result<R>,
+    ) -> Result<R> {
+        if let Some(available) = self.available_memory()? {
             if limits.memory() as u64 > available {
                 bail!("not enough memory to build {} {}: needed {} MiB, have {} MiB\nhelp: set DOCSRS_DISABLE_MEMORY_LIMIT=true to force a build",
                     name, version, limits.memory() / 1024 / 1024, available / 1024 / 1024
@@ -326,170 +650,22 @@ impl RustwideBuilder {
         krate.fetch(&self.workspace).map_err(FailureError::compat)?;
 
         let local_storage = tempfile::Builder::new()
-            .prefix(queue_builder::TEMPDIR_PREFIX)
+            .prefix(&self.tempdir_prefix)
             .tempdir()?;
 
-        let successful = build_dir
-            .build(&self.toolchain, &krate, self.prepare_sandbox(&limits))
+        let res = build_dir
+            .build(&self.toolchain, &krate, self.prepare_sandbox(limits))
             .run(|build| {
-                (|| -> Result<bool> {
-                    use docsrs_metadata::BuildTargets;
-
-                    let mut has_docs = false;
-                    let mut successful_targets = Vec::new();
-                    let metadata = Metadata::from_crate_root(&build.host_source_dir())?;
-                    let BuildTargets {
-                        default_target,
-                        other_targets,
-                    } = metadata.targets(self.config.include_default_targets);
-
-                    // Perform an initial build
-                    let mut res =
-                        self.execute_build(default_target, true, build, &limits, &metadata, false)?;
-
-                    // If the build fails with the lockfile given, try using only the dependencies listed in Cargo.toml.
-                    let cargo_lock = build.host_source_dir().join("Cargo.lock");
-                    if !res.result.successful && cargo_lock.exists() {
-                        info!("removing lockfile and reattempting build");
-                        std::fs::remove_file(cargo_lock)?;
-                        Command::new(&self.workspace, self.toolchain.cargo())
-                            .cd(build.host_source_dir())
-                            .args(&["generate-lockfile", "-Zno-index-update"])
-                            .run()?;
-                        Command::new(&self.workspace, self.toolchain.cargo())
-                            .cd(build.host_source_dir())
-                            .args(&["fetch", "--locked"])
-                            .run()?;
-                        res = self.execute_build(
-                            default_target,
-                            true,
-                            build,
-                            &limits,
-                            &metadata,
-                            false,
-                        )?;
-                    }
-
-                    if res.result.successful {
-                        if let Some(name) = res.cargo_metadata.root().library_name() {
-                            let host_target = build.host_target_dir();
-                            has_docs = host_target
-                                .join(default_target)
-                                .join("doc")
-                                .join(name)
-                                .is_dir();
-                        }
-                    }
-
-                    let mut algs = HashSet::new();
-                    if has_docs {
-                        debug!("adding documentation for the default target to the database");
-                        self.copy_docs(
-                            &build.host_target_dir(),
-                            local_storage.path(),
-                            default_target,
-                            true,
-                        )?;
-
-                        successful_targets.push(res.target.clone());
-
-                        // Then build the documentation for all the targets
-                        // Limit the number of targets so that no one can try to build all 200000 possible targets
-                        for target in other_targets.into_iter().take(limits.targets()) {
-                            debug!("building package {} {} for {}", name, version, target);
-                            self.build_target(
-                                target,
-                                build,
-                                &limits,
-                                local_storage.path(),
-                                &mut successful_targets,
-                                &metadata,
-                            )?;
-                        }
-                        let (_, new_alg) = add_path_into_remote_archive(
-                            &self.storage,
-                            &rustdoc_archive_path(name, version),
-                            local_storage.path(),
-                        )?;
-                        algs.insert(new_alg);
-                    };
-
-                    // Store the sources even if the build fails
-                    debug!("adding sources into database");
-                    let files_list = {
-                        let (files_list, new_alg) = add_path_into_remote_archive(
-                            &self.storage,
-                            &source_archive_path(name, version),
-                            build.host_source_dir(),
-                        )?;
-                        algs.insert(new_alg);
-                        files_list
-                    };
-
-                    let has_examples = build.host_source_dir().join("examples").is_dir();
-                    if res.result.successful {
-                        self.metrics.successful_builds.inc();
-                    } else if res.cargo_metadata.root().is_library() {
-                        self.metrics.failed_builds.inc();
-                    } else {
-                        self.metrics.non_library_builds.inc();
-                    }
-
-                    let release_data = match self.index.api().get_release_data(name, version) {
-                        Ok(data) => data,
-                        Err(err) => {
-                            warn!("{:#?}", err);
-                            ReleaseData::default()
-                        }
-                    };
-
-                    let cargo_metadata = res.cargo_metadata.root();
-                    let repository = self.get_repo(cargo_metadata)?;
-
-                    let release_id = add_package_into_database(
-                        &mut conn,
-                        cargo_metadata,
-                        &build.host_source_dir(),
-                        &res.result,
-                        &res.target,
-                        files_list,
-                        successful_targets,
-                        &release_data,
-                        has_docs,
-                        has_examples,
-                        algs,
-                        repository,
-                        true,
+                (|| -> Result<R> {
+                    let output = self.build_output(
+                        name,
+                        version,
+                        &krate,
+                        build,
+                        limits,
+                        local_storage.path(),
                     )?;
-
-                    if let Some(doc_coverage) = res.doc_coverage {
-                        add_doc_coverage(&mut conn, release_id, doc_coverage)?;
-                    }
-
-                    let build_id = add_build_into_database(&mut conn, release_id, &res.result)?;
-                    let build_log_path = format!("build-logs/{}/{}.txt", build_id, default_target);
-                    self.storage.store_one(build_log_path, res.build_log)?;
-
-                    // Some crates.io crate data is mutable, so we proactively update it during a release
-                    match self.index.api().get_crate_data(name) {
-                        Ok(crate_data) => {
-                            update_crate_data_in_database(&mut conn, name, &crate_data)?
-                        }
-                        Err(err) => warn!("{:#?}", err),
-                    }
-
-                    if res.result.successful {
-                        // delete eventually existing files from pre-archive storage.
-                        // we're doing this in the end so eventual problems in the build
-                        // won't lead to non-existing docs.
-                        for prefix in &["rustdoc", "sources"] {
-                            let prefix = format!("{}/{}/{}/", prefix, name, version);
-                            log::debug!("cleaning old storage folder {}", prefix);
-                            self.storage.delete_prefix(&prefix)?;
-                        }
-                    }
-
-                    Ok(res.result.successful)
+                    publish(output, local_storage.path(), &build.host_source_dir())
                 })()
                 .map_err(|e| failure::Error::from_boxed_compat(e.into()))
             })
@@ -500,7 +676,203 @@ impl RustwideBuilder {
             .purge_from_cache(&self.workspace)
             .map_err(FailureError::compat)?;
         local_storage.close()?;
-        Ok(successful)
+        Ok(res)
+    }
+
+    /// Build the documentation for the default target, the other targets and the feature
+    /// profiles, copying it to `local_storage`.
+    fn build_output(
+        &self,
+        name: &str,
+        version: &str,
+        krate: &Crate,
+        build: &Build,
+        limits: &Limits,
+        local_storage: &Path,
+    ) -> Result<BuildOutput> {
+        use docsrs_metadata::BuildTargets;
+
+        let mut has_docs = false;
+        let mut successful_targets = Vec::new();
+        let mut successful_profiles = Vec::new();
+        let metadata = Metadata::from_crate_root(&build.host_source_dir())?;
+        let BuildTargets {
+            default_target,
+            other_targets,
+        } = metadata.targets(self.config.include_default_targets);
+
+        let started = Instant::now();
+        let mut res = self.build_default_target(default_target, build, limits, &metadata)?;
+        let default_target_time = started.elapsed();
+
+        if res.result.successful {
+            if let Some(name) = res.cargo_metadata.root().library_name() {
+                let host_target = build.host_target_dir();
+                has_docs = host_target
+                    .join(default_target)
+                    .join("doc")
+                    .join(name)
+                    .is_dir();
+            }
+        }
+
+        let mut feature_items = Vec::new();
+        let mut undocumented = Vec::new();
+        if has_docs {
+            debug!("adding documentation for the default target to the database");
+            self.copy_docs(
+                &build.host_target_dir(),
+                local_storage,
+                default_target,
+                true,
+            )?;
+
+            if let Some(library_name) = res.cargo_metadata.root().library_name() {
+                let doc_dir = build.host_target_dir().join(default_target).join("doc");
+                feature_items = match collect_feature_items(&doc_dir, &library_name) {
+                    Ok(items) => items,
+                    Err(err) => {
+                        warn!("failed to collect feature gated items: {:#}", err);
+                        Vec::new()
+                    }
+                };
+
+                undocumented = match self.get_undocumented_items(
+                    default_target,
+                    build,
+                    &metadata,
+                    limits,
+                    limits.timeout().saturating_sub(default_target_time),
+                    &mut res.build_log,
+                    &library_name,
+                ) {
+                    Ok(items) => items,
+                    Err(err) => {
+                        warn!("failed to collect undocumented items: {:#}", err);
+                        Vec::new()
+                    }
+                };
+            }
+
+            successful_targets.push(res.target.clone());
+
+            // Then build the documentation for all the targets
+            // Limit the number of targets so that no one can try to build all 200000 possible targets
+            let other_targets = other_targets
+                .into_iter()
+                .take(limits.targets())
+                .collect::<Vec<_>>();
+            successful_targets.extend(self.build_other_targets(
+                name,
+                version,
+                krate,
+                &other_targets,
+                build,
+                limits,
+                local_storage,
+                &metadata,
+            )?);
+
+            // Proc-macros are only documented for the host, and their docs are
+            // moved around after the build, so they don't support feature profiles.
+            if !metadata.proc_macro {
+                for profile in metadata.feature_profiles().take(limits.feature_profiles()) {
+                    debug!(
+                        "building package {} {} with feature profile {}",
+                        name, version, profile
+                    );
+                    self.build_feature_profile(
+                        profile,
+                        default_target,
+                        build,
+                        limits,
+                        local_storage,
+                        &mut successful_profiles,
+                        &metadata,
+                    )?;
+                }
+            }
+        }
+
+        let FullBuildResult {
+            mut result,
+            target,
+            cargo_metadata,
+            doc_coverage,
+            file_coverage,
+            build_log,
+        } = res;
+        result.metadata_warnings = self.validate_metadata(&metadata);
+        let build_log = result
+            .metadata_warnings
+            .iter()
+            .map(|warning| format!("[WARN] docs.rs metadata: {}\n", warning))
+            .collect::<String>()
+            + &build_log;
+
+        Ok(BuildOutput {
+            result,
+            default_target: target,
+            package: cargo_metadata.into_root(),
+            has_docs,
+            has_examples: build.host_source_dir().join("examples").is_dir(),
+            successful_targets,
+            successful_profiles,
+            doc_coverage,
+            file_coverage,
+            undocumented,
+            feature_items,
+            build_log,
+        })
+    }
+
+    /// Build the documentation for the default target.
+    ///
+    /// If the build fails with the lockfile of the crate, it is retried with only the
+    /// dependencies listed in `Cargo.toml`.
+    fn build_default_target(
+        &self,
+        default_target: &str,
+        build: &Build,
+        limits: &Limits,
+        metadata: &Metadata,
+    ) -> Result<FullBuildResult> {
+        // Perform an initial build
+        let res = self.execute_build(default_target, true, build, limits, metadata, false)?;
+
+        // If the build fails with the lockfile given, try using only the dependencies listed in Cargo.toml.
+        let cargo_lock = build.host_source_dir().join("Cargo.lock");
+        if !res.result.successful && cargo_lock.exists() {
+            info!("removing lockfile and reattempting build");
+            std::fs::remove_file(cargo_lock)?;
+            Command::new(&self.workspace, self.toolchain.cargo())
+                .cd(build.host_source_dir())
+                .args(&["generate-lockfile", "-Zno-index-update"])
+                .run()?;
+            Command::new(&self.workspace, self.toolchain.cargo())
+                .cd(build.host_source_dir())
+                .args(&["fetch", "--locked"])
+                .run()?;
+            return self.execute_build(default_target, true, build, limits, metadata, false);
+        }
+
+        Ok(res)
+    }
+
+    /// Returns the problems with the docs.rs metadata of a crate, to show them in the build log.
+    fn validate_metadata(&self, metadata: &Metadata) -> Vec<String> {
+        let known_targets = match self.detect_target_list() {
+            Ok(targets) => Some(targets),
+            Err(err) => {
+                warn!("failed to detect the supported targets: {:#}", err);
+                None
+            }
+        };
+        metadata
+            .validate(known_targets.as_deref())
+            .iter()
+            .map(ToString::to_string)
+            .collect()
     }
 
     fn build_target(
@@ -526,59 +898,266 @@ impl RustwideBuilder {
         Ok(())
     }
 
+    /// Build the documentation for the targets other than the default one, returning the
+    /// targets which built successfully.
+    ///
+    /// When the CPU and memory limits leave room for it, the targets are built in parallel, each
+    /// in its own build directory: cargo locks the whole target directory while building.
+    #[allow(clippy::too_many_arguments)]
+    fn build_other_targets(
+        &self,
+        name: &str,
+        version: &str,
+        krate: &Crate,
+        targets: &[&str],
+        build: &Build,
+        limits: &Limits,
+        local_storage: &Path,
+        metadata: &Metadata,
+    ) -> Result<Vec<String>> {
+        let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+        let parallel = parallel_builds(
+            cpus,
+            self.config.build_cpu_limit,
+            self.available_memory()?,
+            limits.memory(),
+            targets.len(),
+        );
+
+        let mut successful_targets = Vec::new();
+        if parallel <= 1 {
+            for target in targets {
+                debug!("building package {} {} for {}", name, version, target);
+                self.build_target(
+                    target,
+                    build,
+                    limits,
+                    local_storage,
+                    &mut successful_targets,
+                    metadata,
+                )?;
+            }
+            return Ok(successful_targets);
+        }
+
+        info!(
+            "building package {} {} for {} targets, {} at once",
+            name,
+            version,
+            targets.len(),
+            parallel
+        );
+        // Build with the same lockfile as the default target, which might have been regenerated.
+        let lockfile = build.host_source_dir().join("Cargo.lock");
+
+        let remaining = Mutex::new(targets.iter());
+        let successful = Mutex::new(HashSet::new());
+        std::thread::scope(|scope| -> Result<()> {
+            let workers = (0..parallel)
+                .map(|_| {
+                    scope.spawn(|| -> Result<()> {
+                        loop {
+                            let target = match remaining.lock().unwrap().next() {
+                                Some(target) => *target,
+                                None => return Ok(()),
+                            };
+                            debug!("building package {} {} for {}", name, version, target);
+                            let mut build_dir = self
+                                .workspace
+                                .build_dir(&format!("{}-{}-{}", name, version, target));
+                            if self.build_target_in(
+                                &mut build_dir,
+                                krate,
+                                target,
+                                &lockfile,
+                                limits,
+                                local_storage,
+                                metadata,
+                            )? {
+                                successful.lock().unwrap().insert(target);
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for worker in workers {
+                match worker.join() {
+                    Ok(result) => result?,
+                    Err(panic) => std::panic::resume_unwind(panic),
+                }
+            }
+            Ok(())
+        })?;
+
+        // keep the order of the targets from the metadata
+        let successful = successful.into_inner().unwrap();
+        successful_targets.extend(
+            targets
+                .iter()
+                .filter(|target| successful.contains(*target))
+                .map(|target| target.to_string()),
+        );
+        Ok(successful_targets)
+    }
+
+    /// Build the documentation for a target in a separate build directory, returning whether
+    /// it was successful.
+    #[allow(clippy::too_many_arguments)]
+    fn build_target_in(
+        &self,
+        build_dir: &mut BuildDirectory,
+        krate: &Crate,
+        target: &str,
+        lockfile: &Path,
+        limits: &Limits,
+        local_storage: &Path,
+        metadata: &Metadata,
+    ) -> Result<bool> {
+        build_dir.purge().map_err(FailureError::compat)?;
+        let successful = build_dir
+            .build(&self.toolchain, krate, self.prepare_sandbox(limits))
+            .run(|build| {
+                (|| -> Result<bool> {
+                    if lockfile.exists() {
+                        std::fs::copy(lockfile, build.host_source_dir().join("Cargo.lock"))?;
+                    }
+                    let mut successful_targets = Vec::new();
+                    self.build_target(
+                        target,
+                        build,
+                        limits,
+                        local_storage,
+                        &mut successful_targets,
+                        metadata,
+                    )?;
+                    Ok(!successful_targets.is_empty())
+                })()
+                .map_err(|e| failure::Error::from_boxed_compat(e.into()))
+            })
+            .map_err(|e| e.compat())?;
+        build_dir.purge().map_err(FailureError::compat)?;
+        Ok(successful)
+    }
+
+    /// The memory available on the host for new builds, if it's limited.
+    fn available_memory(&self) -> Result<Option<u64>> {
+        #[cfg(target_os = "linux")]
+        if !self.config.disable_memory_limit {
+            use anyhow::Context;
+            let mem_info = procfs::Meminfo::new().context("failed to read /proc/meminfo")?;
+            let available = mem_info
+                .mem_available
+                .expect("kernel version too old for determining memory limit");
+            return Ok(Some(available));
+        }
+        Ok(None)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn build_feature_profile(
+        &self,
+        profile: &str,
+        target: &str,
+        build: &Build,
+        limits: &Limits,
+        local_storage: &Path,
+        successful_profiles: &mut Vec<String>,
+        metadata: &Metadata,
+    ) -> Result<()> {
+        let profile_metadata = match metadata.feature_profile(profile) {
+            Some(profile_metadata) => profile_metadata,
+            None => return Ok(()),
+        };
+
+        let profile_res =
+            self.execute_build(target, true, build, limits, &profile_metadata, false)?;
+        let source = build.host_target_dir().join(target).join("doc");
+        if profile_res.result.successful && source.is_dir() {
+            let dest = local_storage.join(feature_profile_dir(profile));
+            debug!(
+                "adding documentation for feature profile {} to the database",
+                profile
+            );
+            info!("copy {} to {}", source.display(), dest.display());
+            copy_dir_all(source, dest)?;
+            successful_profiles.push(profile.to_string());
+        }
+        Ok(())
+    }
+
     fn get_coverage(
         &self,
         target: &str,
         build: &Build,
         metadata: &Metadata,
         limits: &Limits,
-    ) -> Result<Option<DocCoverage>> {
+    ) -> Result<Vec<FileCoverage>> {
         let rustdoc_flags = vec![
             "--output-format".to_string(),
             "json".to_string(),
             "--show-coverage".to_string(),
         ];
 
-        #[derive(serde::Deserialize)]
-        struct FileCoverage {
-            total: i32,
-            with_docs: i32,
-            total_examples: i32,
-            with_examples: i32,
-        }
-
-        let mut coverage = DocCoverage {
-            total_items: 0,
-            documented_items: 0,
-            total_items_needing_examples: 0,
-            items_with_examples: 0,
-        };
-
+        let mut files = Vec::new();
         self.prepare_command(build, target, metadata, limits, rustdoc_flags)?
             .process_lines(&mut |line, _| {
-                if line.starts_with('{') && line.ends_with('}') {
-                    let parsed = match serde_json::from_str::<HashMap<String, FileCoverage>>(line) {
-                        Ok(parsed) => parsed,
-                        Err(_) => return,
-                    };
-                    for file in parsed.values() {
-                        coverage.total_items += file.total;
-                        coverage.documented_items += file.with_docs;
-                        coverage.total_items_needing_examples += file.total_examples;
-                        coverage.items_with_examples += file.with_examples;
-                    }
+                if let Some(parsed) = parse_coverage_line(line) {
+                    files.extend(parsed);
                 }
             })
             .log_output(false)
             .run()?;
 
-        Ok(
-            if coverage.total_items == 0 && coverage.documented_items == 0 {
-                None
-            } else {
-                Some(coverage)
-            },
-        )
+        files.sort_by(|a, b| a.file.cmp(&b.file));
+        Ok(files)
+    }
+
+    /// Build the rustdoc JSON output of the crate to find its undocumented public items.
+    ///
+    /// This has to run after the documentation was copied out of the target directory, as the
+    /// JSON build can delete it. The JSON build only gets the `timeout` left over from the build
+    /// of the default target, and its output is appended to `build_log` within the same size
+    /// limit.
+    #[allow(clippy::too_many_arguments)]
+    fn get_undocumented_items(
+        &self,
+        target: &str,
+        build: &Build,
+        metadata: &Metadata,
+        limits: &Limits,
+        timeout: Duration,
+        build_log: &mut String,
+        library_name: &str,
+    ) -> Result<Vec<UndocumentedItem>> {
+        if timeout.is_zero() {
+            bail!("no time left to build the rustdoc JSON output");
+        }
+
+        let mut storage = LogStorage::new(LevelFilter::Info);
+        storage.set_max_size(limits.max_log_size().saturating_sub(build_log.len()));
+
+        let rustdoc_flags = vec!["--output-format".to_string(), "json".to_string()];
+        let result = logging::capture(&storage, || {
+            info!("building the rustdoc JSON output to collect undocumented items");
+            self.prepare_command(build, target, metadata, limits, rustdoc_flags)
+                .and_then(|command| command.timeout(Some(timeout)).run().map_err(Error::from))
+        });
+        build_log.push_str(&storage.to_string());
+        result?;
+
+        // proc-macros are built without `--target`, see `prepare_command`
+        let target_dir = build.host_target_dir();
+        let json_path = if metadata.proc_macro {
+            target_dir.join("doc")
+        } else {
+            target_dir.join(target).join("doc")
+        }
+        .join(format!("{}.json", library_name));
+
+        let items = undocumented_items(&std::fs::read(&json_path)?)?;
+        std::fs::remove_file(json_path)?;
+        Ok(items)
     }
 
     fn execute_build(
@@ -610,20 +1189,25 @@ impl RustwideBuilder {
         // we have to run coverage before the doc-build because currently it
         // deletes the doc-target folder.
         // https://github.com/rust-lang/cargo/issues/9447
-        let doc_coverage = match self.get_coverage(target, build, metadata, limits) {
+        let file_coverage = match self.get_coverage(target, build, metadata, limits) {
             Ok(cov) => cov,
             Err(err) => {
                 log::info!("error when trying to get coverage: {}", err);
                 log::info!("continuing anyways.");
-                None
+                Vec::new()
             }
         };
 
-        let successful = logging::capture(&storage, || {
+        let result = logging::capture(&storage, || {
             self.prepare_command(build, target, metadata, limits, rustdoc_flags)
                 .and_then(|command| command.run().map_err(Error::from))
-                .is_ok()
         });
+        let successful = result.is_ok();
+        let exceeded_limit = match result.as_ref().map_err(|err| err.downcast_ref()) {
+            Err(Some(CommandError::SandboxOOM)) => Some(ExceededLimit::Memory),
+            Err(Some(CommandError::Timeout(_))) => Some(ExceededLimit::Timeout),
+            _ => None,
+        };
 
         // For proc-macros, cargo will put the output in `target/doc`.
         // Move it to the target-specific directory for consistency with other builds.
@@ -647,8 +1231,12 @@ impl RustwideBuilder {
                 rustc_version: self.rustc_version.clone(),
                 docsrs_version: format!("docsrs {}", crate::BUILD_VERSION),
                 successful,
+                metadata_warnings: Vec::new(),
+                exceeded_limit,
+                escalated_limits: None,
             },
-            doc_coverage,
+            doc_coverage: DocCoverage::from_files(&file_coverage),
+            file_coverage,
             cargo_metadata,
             build_log: storage.to_string(),
             target: target.to_string(),
@@ -665,6 +1253,9 @@ impl RustwideBuilder {
     ) -> Result<Command<'ws, 'pl>> {
         // If the explicit target is not a tier one target, we need to install it.
         if !docsrs_metadata::DEFAULT_TARGETS.contains(&target) {
+            // rustup can't install targets concurrently, and targets of parallel builds share
+            // the toolchain.
+            let _guard = TARGET_INSTALL_LOCK.lock().unwrap();
             // This is a no-op if the target is already installed.
             self.toolchain
                 .add_target(&self.workspace, target)
@@ -767,23 +1358,122 @@ impl RustwideBuilder {
             Ok(true)
         }
     }
+}
 
-    fn get_repo(&self, metadata: &MetadataPackage) -> Result<Option<i32>> {
-        self.repository_stats_updater
-            .load_repository(metadata)
-            .map_err(Into::into)
+fn retry<T>(mut f: impl FnMut() -> Result<T>, max_attempts: u32) -> Result<T> {
+    for attempt in 1.. {
+        match f() {
+            Ok(result) => return Ok(result),
+            Err(err) => {
+                if attempt > max_attempts {
+                    return Err(err);
+                } else {
+                    let sleep_for = 2u32.pow(attempt);
+                    log::warn!(
+                        "got error on attempt {}, will try again after {}s:\n{:?}",
+                        attempt,
+                        sleep_for,
+                        err
+                    );
+                    std::thread::sleep(Duration::from_secs(sleep_for as u64));
+                }
+            }
+        }
+    }
+    unreachable!()
+}
+
+/// The outcome of [`RustwideBuilder::build_standalone`].
+pub struct StandaloneBuildReport {
+    name: String,
+    version: String,
+    rustc_version: String,
+    default_target: String,
+    successful: bool,
+    successful_targets: Vec<String>,
+    failed_targets: Vec<String>,
+    successful_profiles: Vec<String>,
+    failed_profiles: Vec<String>,
+    doc_coverage: Option<DocCoverage>,
+    metadata_warnings: Vec<String>,
+}
+
+impl StandaloneBuildReport {
+    /// Whether the documentation for the default target was built.
+    pub fn successful(&self) -> bool {
+        self.successful
+    }
+}
+
+impl std::fmt::Display for StandaloneBuildReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = |successful| if successful { "ok" } else { "failed" };
+
+        writeln!(f, "{} {} ({})", self.name, self.version, self.rustc_version)?;
+        writeln!(
+            f,
+            "  default target {}: {}",
+            self.default_target,
+            status(self.successful)
+        )?;
+        for target in &self.successful_targets {
+            writeln!(f, "  target {}: {}", target, status(true))?;
+        }
+        for target in &self.failed_targets {
+            writeln!(f, "  target {}: {}", target, status(false))?;
+        }
+        for profile in &self.successful_profiles {
+            writeln!(f, "  feature profile {}: {}", profile, status(true))?;
+        }
+        for profile in &self.failed_profiles {
+            writeln!(f, "  feature profile {}: {}", profile, status(false))?;
+        }
+        if let Some(coverage) = &self.doc_coverage {
+            writeln!(
+                f,
+                "  coverage: {} of {} items documented, {} of {} items with examples",
+                coverage.documented_items,
+                coverage.total_items,
+                coverage.items_with_examples,
+                coverage.total_items_needing_examples,
+            )?;
+        }
+        for warning in &self.metadata_warnings {
+            writeln!(f, "  warning: {}", warning)?;
+        }
+        Ok(())
     }
 }
 
+/// The outcome of building the documentation of a crate, with everything needed to publish it
+/// except for the documentation and the sources themselves.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct BuildOutput {
+    pub(crate) result: BuildResult,
+    pub(crate) default_target: String,
+    pub(crate) package: MetadataPackage,
+    pub(crate) has_docs: bool,
+    pub(crate) has_examples: bool,
+    pub(crate) successful_targets: Vec<String>,
+    pub(crate) successful_profiles: Vec<String>,
+    pub(crate) doc_coverage: Option<DocCoverage>,
+    pub(crate) file_coverage: Vec<FileCoverage>,
+    pub(crate) undocumented: Vec<UndocumentedItem>,
+    pub(crate) feature_items: Vec<FeatureItem>,
+    /// The log of the build for the default target, including the metadata warnings.
+    pub(crate) build_log: String,
+}
+
 struct FullBuildResult {
     result: BuildResult,
     target: String,
     cargo_metadata: CargoMetadata,
     doc_coverage: Option<DocCoverage>,
+    file_coverage: Vec<FileCoverage>,
     build_log: String,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub(crate) struct DocCoverage {
     /// The total items that could be documented in the current crate, used to calculate
     /// documentation coverage.
@@ -797,15 +1487,58 @@ pub(crate) struct DocCoverage {
     pub(crate) items_with_examples: i32,
 }
 
+impl DocCoverage {
+    /// Sum up the coverage of all files, returns `None` if nothing could be documented.
+    pub(crate) fn from_files(files: &[FileCoverage]) -> Option<Self> {
+        let coverage = files.iter().fold(
+            DocCoverage {
+                total_items: 0,
+                documented_items: 0,
+                total_items_needing_examples: 0,
+                items_with_examples: 0,
+            },
+            |mut coverage, file| {
+                coverage.total_items += file.total_items;
+                coverage.documented_items += file.documented_items;
+                coverage.total_items_needing_examples += file.total_items_needing_examples;
+                coverage.items_with_examples += file.items_with_examples;
+                coverage
+            },
+        );
+
+        if coverage.total_items == 0 && coverage.documented_items == 0 {
+            None
+        } else {
+            Some(coverage)
+        }
+    }
+}
+
+/// The outcome of [`RustwideBuilder::build_queued_package`].
+#[derive(Debug, Default)]
+pub(crate) struct BuildOutcome {
+    pub(crate) successful: bool,
+    /// The limits to retry the build with, if it exceeded its memory or time limit.
+    pub(crate) retry_limits: Option<Limits>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub(crate) struct BuildResult {
     pub(crate) rustc_version: String,
     pub(crate) docsrs_version: String,
     pub(crate) successful: bool,
+    /// Problems with the `[package.metadata.docs.rs]` settings of the crate.
+    pub(crate) metadata_warnings: Vec<String>,
+    /// The limit the build was killed for exceeding.
+    pub(crate) exceeded_limit: Option<ExceededLimit>,
+    /// The limits the build was retried with, after the previous build exceeded its limits.
+    pub(crate) escalated_limits: Option<Limits>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::{rustdoc_archive_path, source_archive_path};
     use crate::test::{assert_redirect, assert_success, wrapper};
     use serde_json::Value;
 
@@ -1048,14 +1781,15 @@ mod tests {
     #[ignore]
     fn test_locked_fails_unlocked_needs_new_deps() {
         wrapper(|env| {
-            env.override_config(|cfg| cfg.include_default_targets = false);
+            env.override_config(|cfg| cfg.build.include_default_targets = false);
 
             // if the corrected dependency of the crate was already downloaded we need to remove it
-            let crate_file = env.config().rustwide_workspace.join(
+            let crate_file = env.config().build.rustwide_workspace.join(
                 "cargo-home/registry/cache/github.com-1ecc6299db9ec823/rand_core-0.5.1.crate",
             );
             let src_dir = env
                 .config()
+                .build
                 .rustwide_workspace
                 .join("cargo-home/registry/src/github.com-1ecc6299db9ec823/rand_core-0.5.1");
 
@@ -1095,4 +1829,51 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    #[ignore]
+    fn test_standalone_build() {
+        wrapper(|env| {
+            env.override_config(|cfg| cfg.build.include_default_targets = false);
+
+            let output = tempfile::tempdir()?;
+            let mut builder = RustwideBuilder::init_standalone(env.config().build.clone()).unwrap();
+            let report = builder.build_standalone(Path::new("crates/metadata"), output.path())?;
+            assert!(report.successful());
+
+            assert!(output.path().join("build-log.txt").is_file());
+            assert!(output
+                .path()
+                .join("docsrs_metadata")
+                .join("index.html")
+                .is_file());
+
+            // nothing is published
+            let storage = env.storage();
+            assert!(!storage.exists(&rustdoc_archive_path("docsrs-metadata", "0.1.0"))?);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_parallel_builds() {
+        const GIB: usize = 1024 * 1024 * 1024;
+
+        // without a CPU limit, every build uses all CPUs
+        assert_eq!(parallel_builds(16, None, None, 3 * GIB, 10), 1);
+        assert_eq!(parallel_builds(16, Some(4), None, 3 * GIB, 10), 4);
+        assert_eq!(parallel_builds(16, Some(4), None, 3 * GIB, 2), 2);
+        // limited by the available memory
+        assert_eq!(
+            parallel_builds(16, Some(2), Some(10 * GIB as u64), 3 * GIB, 10),
+            3
+        );
+        // there's always at least one build
+        assert_eq!(
+            parallel_builds(2, Some(4), Some(GIB as u64), 3 * GIB, 10),
+            1
+        );
+        assert_eq!(parallel_builds(16, Some(4), None, 3 * GIB, 0), 1);
+    }
 }
@@ -6,6 +6,7 @@ use url::Url;
 use self::api::Api;
 use crate::error::Result;
 use crate::utils::report_error;
+use crate::Config;
 
 pub(crate) mod api;
 
@@ -38,6 +39,17 @@ fn load_config(repo: &git2::Repository) -> Result<IndexConfig> {
 }
 
 impl Index {
+    /// Opens the index configured with `REGISTRY_INDEX_PATH` and `REGISTRY_URL`, cloning it if
+    /// it doesn't exist yet.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let path = config.registry_index_path.clone();
+        if let Some(registry_url) = config.registry_url.clone() {
+            Index::from_url(path, registry_url)
+        } else {
+            Index::new(path)
+        }
+    }
+
     pub fn from_url(path: PathBuf, repository_url: String) -> Result<Self> {
         let url = repository_url.clone();
         let diff = crates_index_diff::Index::from_path_or_cloned_with_options(
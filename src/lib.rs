@@ -3,9 +3,10 @@
 #![allow(clippy::cognitive_complexity)]
 
 pub use self::build_queue::BuildQueue;
-pub use self::config::Config;
+pub use self::config::{BuildConfig, Config, RemoteBuilderConfig};
 pub use self::context::Context;
 pub use self::docbuilder::PackageKind;
+pub use self::docbuilder::RemoteBuilder;
 pub use self::docbuilder::RustwideBuilder;
 pub use self::index::Index;
 pub use self::metrics::Metrics;
@@ -13,6 +14,7 @@ pub use self::storage::Storage;
 pub use self::web::Server;
 
 mod build_queue;
+mod cdn;
 mod config;
 mod context;
 pub mod db;
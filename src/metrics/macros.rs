@@ -21,6 +21,7 @@ macro_rules! metrics {
                 $metric_vis $metric: $ty,
             )*
             pub(crate) recently_accessed_releases: RecentlyAccessedReleases,
+            storage_usage_gathered: std::sync::Mutex<Option<std::time::Instant>>,
         }
         impl $name {
             $vis fn new() -> Result<Self, prometheus::Error> {
@@ -38,6 +39,7 @@ macro_rules! metrics {
                 Ok(Self {
                     registry,
                     recently_accessed_releases: RecentlyAccessedReleases::new(),
+                    storage_usage_gathered: std::sync::Mutex::new(None),
                     $(
                         $(#[$meta])*
                         $metric,
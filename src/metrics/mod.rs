@@ -3,6 +3,7 @@ mod macros;
 
 use self::macros::MetricFromOpts;
 use crate::db::Pool;
+use crate::storage::storage_usage_totals;
 use crate::target::TargetAtom;
 use crate::BuildQueue;
 use anyhow::Error;
@@ -16,6 +17,9 @@ load_metric_type!(IntCounterVec as vec);
 load_metric_type!(IntGaugeVec as vec);
 load_metric_type!(HistogramVec as vec);
 
+/// How long the storage usage gauges are kept before summing up the recorded usage again.
+const STORAGE_USAGE_REFRESH: Duration = Duration::from_secs(10 * 60);
+
 metrics! {
     pub struct Metrics {
         /// Number of crates in the build queue
@@ -71,6 +75,30 @@ metrics! {
         /// Number of files uploaded to the storage backend
         pub(crate) uploaded_files_total: IntCounter,
 
+        /// Lookups in the in-process page caches that found an entry
+        pub(crate) page_cache_hits: IntCounterVec["cache"],
+        /// Lookups in the in-process page caches that didn't find an entry
+        pub(crate) page_cache_misses: IntCounterVec["cache"],
+        /// The size of the rendered rustdoc pages in the in-process cache, in bytes
+        pub(crate) rustdoc_html_cache_bytes: IntGauge,
+
+        /// Lookups of archive indexes that found them in the local cache
+        pub(crate) archive_index_cache_hits: IntCounter,
+        /// Lookups of archive indexes that had to fetch them from the storage backend
+        pub(crate) archive_index_cache_misses: IntCounter,
+        /// Number of archive indexes deleted from the local cache to stay within its size
+        pub(crate) archive_index_cache_evictions: IntCounter,
+        /// The size of the archive indexes in the local cache, in bytes
+        pub(crate) archive_index_cache_bytes: IntGauge,
+
+        /// The recorded size of the files of all releases in the storage backends, in bytes
+        storage_usage_compressed_bytes: IntGaugeVec["backend", "kind"],
+        /// The recorded size of the files of all releases after decompressing them, in bytes
+        storage_usage_uncompressed_bytes: IntGaugeVec["backend", "kind"],
+
+        /// Number of paths invalidated in the CDN
+        pub(crate) cdn_invalidated_paths: IntCounter,
+
         /// The number of attempted files that failed due to a memory limit
         pub(crate) html_rewrite_ooms: IntCounter,
 
@@ -165,11 +193,33 @@ impl Metrics {
             .set(queue.prioritized_count()? as i64);
         self.failed_crates_count.set(queue.failed_count()? as i64);
 
+        self.gather_storage_usage(pool)?;
         self.recently_accessed_releases.gather(self);
         self.gather_system_performance();
         Ok(self.registry.gather())
     }
 
+    /// Sums up the recorded storage usage at most every [`STORAGE_USAGE_REFRESH`], it scans the
+    /// whole `storage_usage` table.
+    fn gather_storage_usage(&self, pool: &Pool) -> Result<(), Error> {
+        let mut gathered = self.storage_usage_gathered.lock().unwrap();
+        if gathered.map_or(false, |at| at.elapsed() < STORAGE_USAGE_REFRESH) {
+            return Ok(());
+        }
+
+        for total in storage_usage_totals(&mut *pool.get()?)? {
+            let labels = [total.backend.as_str(), total.kind.as_str()];
+            self.storage_usage_compressed_bytes
+                .with_label_values(&labels)
+                .set(total.size.compressed as i64);
+            self.storage_usage_uncompressed_bytes
+                .with_label_values(&labels)
+                .set(total.size.uncompressed as i64);
+        }
+        *gathered = Some(Instant::now());
+        Ok(())
+    }
+
     #[cfg(not(target_os = "linux"))]
     fn gather_system_performance(&self) {}
 
@@ -0,0 +1,254 @@
+use crate::error::Result;
+use chrono::{DateTime, Utc};
+use log::warn;
+use reqwest::{
+    blocking::Client as HttpClient,
+    header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT},
+    StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::repositories::{
+    FetchRepositoriesResult, RateLimitReached, Repository, RepositoryForge, RepositoryName,
+    APP_USER_AGENT,
+};
+
+/// Collects repository stats from Bitbucket Cloud.
+///
+/// Bitbucket has no stars, so the amount of watchers is stored instead.
+pub struct Bitbucket {
+    client: HttpClient,
+    host: String,
+}
+
+impl Bitbucket {
+    pub fn new(host: &str, access_token: &Option<String>) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static(APP_USER_AGENT));
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+
+        if let Some(token) = access_token {
+            headers.insert(
+                AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {}", token))?,
+            );
+        } else {
+            warn!(
+                "will try to retrieve `{}` stats without token since none was provided",
+                host
+            );
+        }
+
+        let client = HttpClient::builder().default_headers(headers).build()?;
+        Ok(Bitbucket {
+            client,
+            host: host.to_string(),
+        })
+    }
+}
+
+impl RepositoryForge for Bitbucket {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn icon(&self) -> &'static str {
+        "bitbucket"
+    }
+
+    /// Every repository needs several requests, so this is only the amount of repositories
+    /// fetched before the results are stored.
+    fn chunk_size(&self) -> usize {
+        25
+    }
+
+    fn fetch_repository(&self, name: &RepositoryName) -> Result<Option<Repository>> {
+        self.fetch(&format!("{}/{}", name.owner, name.repo))
+    }
+
+    fn fetch_repositories(&self, ids: &[String]) -> Result<FetchRepositoriesResult> {
+        let mut ret = FetchRepositoriesResult::default();
+        for id in ids {
+            // The IDs are the UUIDs of the repositories, which can be used in place of the
+            // repository slug when the workspace is replaced by `{}`.
+            match self.fetch(&encode_braces(&format!("{{}}/{}", id))) {
+                Ok(Some(repo)) => {
+                    ret.present.insert(repo.id.clone(), repo);
+                }
+                Ok(None) => ret.missing.push(id.clone()),
+                // Keep what was already fetched, the next chunk will hit the rate limit again.
+                Err(err) if err.is::<RateLimitReached>() && !ret.present.is_empty() => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(ret)
+    }
+}
+
+impl Bitbucket {
+    /// Fetches a repository and the counts of its watchers, forks and open issues.
+    fn fetch(&self, path: &str) -> Result<Option<Repository>> {
+        let repo: BitbucketRepository = match self.get(path, "")? {
+            Some(repo) => repo,
+            None => return Ok(None),
+        };
+        let count = |endpoint: &str| -> Result<i64> {
+            let page: Option<BitbucketPage> = self.get(&format!("{}/{}", path, endpoint), "")?;
+            Ok(page.and_then(|page| page.size).unwrap_or(0))
+        };
+        let stars = count("watchers")?;
+        let forks = count("forks")?;
+        let issues = if repo.has_issues {
+            let page: Option<BitbucketPage> = self.get(
+                &format!("{}/issues", path),
+                "?q=state%3D%22new%22%20OR%20state%3D%22open%22",
+            )?;
+            page.and_then(|page| page.size).unwrap_or(0)
+        } else {
+            0
+        };
+
+        Ok(Some(Repository {
+            id: repo.uuid,
+            name_with_owner: repo.full_name,
+            description: repo.description.filter(|d| !d.is_empty()),
+            last_activity_at: repo.updated_on,
+            stars,
+            forks,
+            issues,
+        }))
+    }
+
+    /// Sends a request to the REST API, returning `None` if the resource doesn't exist.
+    fn get<T: DeserializeOwned>(&self, path: &str, query: &str) -> Result<Option<T>> {
+        #[cfg(not(test))]
+        let url = format!(
+            "https://api.{}/2.0/repositories/{}{}",
+            self.host, path, query
+        );
+        #[cfg(test)]
+        let url = format!(
+            "{}/2.0/repositories/{}{}",
+            mockito::server_url(),
+            path,
+            query
+        );
+
+        let res = self.client.get(url).send()?;
+        match res.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            StatusCode::TOO_MANY_REQUESTS => Err(RateLimitReached.into()),
+            _ => Ok(Some(res.error_for_status()?.json()?)),
+        }
+    }
+}
+
+fn encode_braces(path: &str) -> String {
+    path.replace('{', "%7B").replace('}', "%7D")
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketRepository {
+    uuid: String,
+    full_name: String,
+    description: Option<String>,
+    updated_on: Option<DateTime<Utc>>,
+    #[serde(default)]
+    has_issues: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketPage {
+    size: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bitbucket;
+    use crate::repositories::updater::{repository_name, RepositoryForge};
+    use crate::repositories::RateLimitReached;
+    use mockito::{mock, Matcher};
+
+    #[test]
+    fn test_rate_limit() {
+        let updater = Bitbucket::new("bitbucket.org", &None).expect("Bitbucket::new failed");
+
+        let _m1 = mock("GET", "/2.0/repositories/foo/bar")
+            .with_status(429)
+            .create();
+        let _m2 = mock("GET", "/2.0/repositories/%7B%7D/%7Bdead%7D")
+            .with_status(429)
+            .create();
+
+        match updater.fetch_repository(
+            &repository_name("https://bitbucket.org/foo/bar").expect("repository_name failed"),
+        ) {
+            Err(e) if e.downcast_ref::<RateLimitReached>().is_some() => {}
+            x => panic!("Expected Err(RateLimitReached), found: {:?}", x),
+        }
+        match updater.fetch_repositories(&["{dead}".to_string()]) {
+            Err(e) if e.downcast_ref::<RateLimitReached>().is_some() => {}
+            x => panic!("Expected Err(RateLimitReached), found: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn not_found() {
+        let updater = Bitbucket::new("bitbucket.org", &None).expect("Bitbucket::new failed");
+
+        let _m1 = mock("GET", "/2.0/repositories/%7B%7D/%7Bdead%7D")
+            .with_status(404)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"type": "error", "error": {"message": "Repository not found"}}"#)
+            .create();
+
+        match updater.fetch_repositories(&["{dead}".to_string()]) {
+            Ok(res) => {
+                assert_eq!(res.missing, vec!["{dead}".to_string()]);
+                assert_eq!(res.present.len(), 0);
+            }
+            x => panic!("Failed: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn get_repository_info() {
+        let updater = Bitbucket::new("bitbucket.org", &None).expect("Bitbucket::new failed");
+
+        let _m1 = mock("GET", "/2.0/repositories/foo/bar")
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"uuid": "{c0ffee}", "full_name": "foo/bar", "description": "this is",
+                "updated_on": "2022-03-04T10:20:30.123456+00:00", "has_issues": true}"#,
+            )
+            .create();
+        let _m2 = mock("GET", "/2.0/repositories/foo/bar/watchers")
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"size": 10, "values": []}"#)
+            .create();
+        let _m3 = mock("GET", "/2.0/repositories/foo/bar/forks")
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"size": 11, "values": []}"#)
+            .create();
+        let _m4 = mock("GET", "/2.0/repositories/foo/bar/issues")
+            .match_query(Matcher::Regex("state".into()))
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"size": 12, "values": []}"#)
+            .create();
+
+        let repo = updater
+            .fetch_repository(
+                &repository_name("https://bitbucket.org/foo/bar").expect("repository_name failed"),
+            )
+            .expect("fetch_repository failed")
+            .unwrap();
+
+        assert_eq!(repo.id, "{c0ffee}");
+        assert_eq!(repo.name_with_owner, "foo/bar");
+        assert_eq!(repo.description, Some("this is".to_owned()));
+        assert!(repo.last_activity_at.is_some());
+        assert_eq!(repo.stars, 10);
+        assert_eq!(repo.forks, 11);
+        assert_eq!(repo.issues, 12);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::error::Result;
+use chrono::{DateTime, Utc};
+use log::warn;
+use reqwest::{
+    blocking::Client as HttpClient,
+    header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT},
+    StatusCode,
+};
+use serde::Deserialize;
+
+use crate::repositories::{
+    FetchRepositoriesResult, RateLimitReached, Repository, RepositoryForge, RepositoryName,
+    APP_USER_AGENT,
+};
+
+/// Collects repository stats from a Gitea or Forgejo instance, like `codeberg.org`.
+pub struct Gitea {
+    client: HttpClient,
+    host: String,
+}
+
+impl Gitea {
+    pub fn new(host: &str, access_token: &Option<String>) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static(APP_USER_AGENT));
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+
+        if let Some(token) = access_token {
+            headers.insert(
+                AUTHORIZATION,
+                HeaderValue::from_str(&format!("token {}", token))?,
+            );
+        } else {
+            warn!(
+                "will try to retrieve `{}` stats without token since none was provided",
+                host
+            );
+        }
+
+        let client = HttpClient::builder().default_headers(headers).build()?;
+        Ok(Gitea {
+            client,
+            host: host.to_string(),
+        })
+    }
+}
+
+impl RepositoryForge for Gitea {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn icon(&self) -> &'static str {
+        "git-alt"
+    }
+
+    /// The REST API only returns one repository per request, so this is only the amount of
+    /// requests made before the results are stored.
+    fn chunk_size(&self) -> usize {
+        50
+    }
+
+    fn fetch_repository(&self, name: &RepositoryName) -> Result<Option<Repository>> {
+        self.get(&format!("repos/{}/{}", name.owner, name.repo))
+    }
+
+    fn fetch_repositories(&self, ids: &[String]) -> Result<FetchRepositoriesResult> {
+        let mut ret = FetchRepositoriesResult::default();
+        for id in ids {
+            match self.get(&format!("repositories/{}", id)) {
+                Ok(Some(repo)) => {
+                    ret.present.insert(repo.id.clone(), repo);
+                }
+                Ok(None) => ret.missing.push(id.clone()),
+                // Keep what was already fetched, the next chunk will hit the rate limit again.
+                Err(err) if err.is::<RateLimitReached>() && !ret.present.is_empty() => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(ret)
+    }
+}
+
+impl Gitea {
+    /// Fetches a repository from the REST API, returning `None` if it doesn't exist.
+    fn get(&self, path: &str) -> Result<Option<Repository>> {
+        #[cfg(not(test))]
+        let url = format!("https://{}/api/v1/{}", self.host, path);
+        #[cfg(test)]
+        let url = format!("{}/api/v1/{}", mockito::server_url(), path);
+
+        let res = self.client.get(url).send()?;
+        match res.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            StatusCode::TOO_MANY_REQUESTS => return Err(RateLimitReached.into()),
+            _ => {}
+        }
+        let repo: GiteaRepository = res.error_for_status()?.json()?;
+
+        Ok(Some(Repository {
+            id: repo.id.to_string(),
+            name_with_owner: repo.full_name,
+            description: repo.description.filter(|d| !d.is_empty()),
+            last_activity_at: repo.updated_at,
+            stars: repo.stars_count,
+            forks: repo.forks_count,
+            issues: repo.open_issues_count,
+        }))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaRepository {
+    id: i64,
+    full_name: String,
+    description: Option<String>,
+    updated_at: Option<DateTime<Utc>>,
+    stars_count: i64,
+    forks_count: i64,
+    open_issues_count: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Gitea;
+    use crate::repositories::updater::{repository_name, RepositoryForge};
+    use crate::repositories::RateLimitReached;
+    use mockito::mock;
+
+    #[test]
+    fn test_rate_limit() {
+        let updater = Gitea::new("codeberg.org", &None).expect("Gitea::new failed");
+
+        let _m1 = mock("GET", "/api/v1/repos/foo/bar")
+            .with_status(429)
+            .create();
+        let _m2 = mock("GET", "/api/v1/repositories/1")
+            .with_status(429)
+            .create();
+
+        match updater.fetch_repository(
+            &repository_name("https://codeberg.org/foo/bar").expect("repository_name failed"),
+        ) {
+            Err(e) if e.downcast_ref::<RateLimitReached>().is_some() => {}
+            x => panic!("Expected Err(RateLimitReached), found: {:?}", x),
+        }
+        match updater.fetch_repositories(&["1".to_string()]) {
+            Err(e) if e.downcast_ref::<RateLimitReached>().is_some() => {}
+            x => panic!("Expected Err(RateLimitReached), found: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn not_found() {
+        let updater = Gitea::new("codeberg.org", &None).expect("Gitea::new failed");
+
+        let _m1 = mock("GET", "/api/v1/repositories/1")
+            .with_status(404)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"errors": null, "message": "The target couldn't be found."}"#)
+            .create();
+        let _m2 = mock("GET", "/api/v1/repositories/2")
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"id": 2, "full_name": "foo/baz", "description": "", "stars_count": 1,
+                "forks_count": 2, "open_issues_count": 3}"#,
+            )
+            .create();
+
+        match updater.fetch_repositories(&["1".to_string(), "2".to_string()]) {
+            Ok(res) => {
+                assert_eq!(res.missing, vec!["1".to_string()]);
+                assert_eq!(res.present.len(), 1);
+                assert_eq!(res.present["2"].name_with_owner, "foo/baz");
+                assert_eq!(res.present["2"].description, None);
+            }
+            x => panic!("Failed: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn get_repository_info() {
+        let updater = Gitea::new("codeberg.org", &None).expect("Gitea::new failed");
+
+        let _m1 = mock("GET", "/api/v1/repos/foo/bar")
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"id": 42, "full_name": "foo/bar", "description": "this is",
+                "updated_at": "2022-03-04T10:20:30+01:00", "stars_count": 10,
+                "forks_count": 11, "open_issues_count": 12}"#,
+            )
+            .create();
+
+        let repo = updater
+            .fetch_repository(
+                &repository_name("https://codeberg.org/foo/bar").expect("repository_name failed"),
+            )
+            .expect("fetch_repository failed")
+            .unwrap();
+
+        assert_eq!(repo.id, "42");
+        assert_eq!(repo.name_with_owner, "foo/bar");
+        assert_eq!(repo.description, Some("this is".to_owned()));
+        assert!(repo.last_activity_at.is_some());
+        assert_eq!(repo.stars, 10);
+        assert_eq!(repo.forks, 11);
+        assert_eq!(repo.issues, 12);
+    }
+}
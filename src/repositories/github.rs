@@ -75,7 +75,7 @@ impl GitHub {
 }
 
 impl RepositoryForge for GitHub {
-    fn host(&self) -> &'static str {
+    fn host(&self) -> &str {
         "github.com"
     }
 
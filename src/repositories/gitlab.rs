@@ -42,11 +42,11 @@ const GRAPHQL_SINGLE: &str = "query($fullPath: ID!) {
 
 pub struct GitLab {
     client: HttpClient,
-    host: &'static str,
+    host: String,
 }
 
 impl GitLab {
-    pub fn new(host: &'static str, access_token: &Option<String>) -> Result<Self> {
+    pub fn new(host: &str, access_token: &Option<String>) -> Result<Self> {
         let mut headers = HeaderMap::new();
         headers.insert(USER_AGENT, HeaderValue::from_static(APP_USER_AGENT));
         headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
@@ -64,13 +64,16 @@ impl GitLab {
         }
 
         let client = HttpClient::builder().default_headers(headers).build()?;
-        Ok(GitLab { client, host })
+        Ok(GitLab {
+            client,
+            host: host.to_string(),
+        })
     }
 }
 
 impl RepositoryForge for GitLab {
-    fn host(&self) -> &'static str {
-        self.host
+    fn host(&self) -> &str {
+        &self.host
     }
 
     fn icon(&self) -> &'static str {
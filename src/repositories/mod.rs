@@ -1,3 +1,8 @@
+use crate::error::Result;
+use std::str::FromStr;
+
+pub use self::bitbucket::Bitbucket;
+pub use self::gitea::Gitea;
 pub use self::github::GitHub;
 pub use self::gitlab::GitLab;
 pub(crate) use self::updater::RepositoryName;
@@ -15,6 +20,125 @@ pub const APP_USER_AGENT: &str = concat!(
 #[error("rate limit reached")]
 struct RateLimitReached;
 
+/// The software running on a forge, which decides the API used to query it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForgeKind {
+    GitLab,
+    /// Gitea and its fork Forgejo, which share the same API.
+    Gitea,
+    Bitbucket,
+}
+
+impl FromStr for ForgeKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "gitlab" => ForgeKind::GitLab,
+            "gitea" | "forgejo" => ForgeKind::Gitea,
+            "bitbucket" => ForgeKind::Bitbucket,
+            _ => anyhow::bail!("unknown forge kind `{}`", s),
+        })
+    }
+}
+
+/// A forge we collect repository stats from, as configured through `DOCSRS_REPOSITORY_FORGES`.
+///
+/// GitHub is not part of the list since it is only enabled by `DOCSRS_GITHUB_ACCESSTOKEN`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForgeConfig {
+    pub kind: ForgeKind,
+    pub host: String,
+    pub access_token: Option<String>,
+}
+
+impl ForgeConfig {
+    pub fn new(kind: ForgeKind, host: impl Into<String>, access_token: Option<String>) -> Self {
+        Self {
+            kind,
+            host: host.into(),
+            access_token,
+        }
+    }
+
+    /// The environment variable holding the access token of the forge, for example
+    /// `DOCSRS_FORGE_ACCESSTOKEN_CODEBERG_ORG` for `codeberg.org`.
+    pub(crate) fn access_token_var(host: &str) -> String {
+        let host: String = host
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("DOCSRS_FORGE_ACCESSTOKEN_{}", host)
+    }
+
+    pub(crate) fn create_forge(&self) -> Result<Box<dyn RepositoryForge + Send + Sync>> {
+        Ok(match self.kind {
+            ForgeKind::GitLab => Box::new(GitLab::new(&self.host, &self.access_token)?),
+            ForgeKind::Gitea => Box::new(Gitea::new(&self.host, &self.access_token)?),
+            ForgeKind::Bitbucket => Box::new(Bitbucket::new(&self.host, &self.access_token)?),
+        })
+    }
+}
+
+/// Parses a comma-separated list of `kind:host` entries, like `gitlab:gitlab.com,gitea:codeberg.org`.
+///
+/// The access tokens are filled in by the caller.
+pub(crate) fn parse_forge_list(list: &str) -> Result<Vec<ForgeConfig>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (kind, host) = entry.split_once(':').ok_or_else(|| {
+                anyhow::anyhow!("invalid forge `{}`, expected `kind:host`", entry)
+            })?;
+            let host = host.trim();
+            if host.is_empty() || host.contains('/') {
+                anyhow::bail!("invalid host `{}` for forge `{}`", host, entry);
+            }
+            Ok(ForgeConfig::new(kind.trim().parse()?, host, None))
+        })
+        .collect()
+}
+
+mod bitbucket;
+mod gitea;
 mod github;
 mod gitlab;
 mod updater;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forge_list() {
+        assert_eq!(
+            parse_forge_list("gitlab:gitlab.com, forgejo:codeberg.org,Bitbucket:bitbucket.org,")
+                .unwrap(),
+            vec![
+                ForgeConfig::new(ForgeKind::GitLab, "gitlab.com", None),
+                ForgeConfig::new(ForgeKind::Gitea, "codeberg.org", None),
+                ForgeConfig::new(ForgeKind::Bitbucket, "bitbucket.org", None),
+            ]
+        );
+        assert_eq!(parse_forge_list("").unwrap(), vec![]);
+        assert!(parse_forge_list("gitlab.com").is_err());
+        assert!(parse_forge_list("svn:example.com").is_err());
+        assert!(parse_forge_list("gitea:").is_err());
+        assert!(parse_forge_list("gitea:example.com/git").is_err());
+    }
+
+    #[test]
+    fn access_token_var() {
+        assert_eq!(
+            ForgeConfig::access_token_var("gitlab.gnome.org"),
+            "DOCSRS_FORGE_ACCESSTOKEN_GITLAB_GNOME_ORG"
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use crate::error::Result;
-use crate::repositories::{GitHub, GitLab, RateLimitReached};
+use crate::repositories::{GitHub, RateLimitReached};
 use crate::utils::MetadataPackage;
 use crate::{db::Pool, Config};
 use chrono::{DateTime, Utc};
@@ -13,7 +13,7 @@ use std::fmt;
 pub trait RepositoryForge {
     /// Result used both as the `host` column in the DB and to match repository URLs during
     /// backfill.
-    fn host(&self) -> &'static str;
+    fn host(&self) -> &str;
 
     /// FontAwesome icon used in the front-end.
     fn icon(&self) -> &'static str;
@@ -70,11 +70,14 @@ impl RepositoryStatsUpdater {
         if let Ok(Some(updater)) = GitHub::new(config) {
             updaters.push(Box::new(updater));
         }
-        if let Ok(updater) = GitLab::new("gitlab.com", &config.gitlab_accesstoken) {
-            updaters.push(Box::new(updater));
-        }
-        if let Ok(updater) = GitLab::new("gitlab.freedesktop.org", &None) {
-            updaters.push(Box::new(updater));
+        for forge in &config.repository_forges {
+            match forge.create_forge() {
+                Ok(updater) => updaters.push(updater),
+                Err(err) => warn!(
+                    "failed to set up the `{}` repository stats updater: {}",
+                    forge.host, err
+                ),
+            }
         }
         Self { updaters, pool }
     }
@@ -313,6 +316,7 @@ pub struct RepositoryName<'a> {
 mod test {
     use super::*;
     use crate::context::Context;
+    use crate::repositories::{ForgeConfig, ForgeKind};
 
     #[test]
     fn test_repository_name() {
@@ -408,6 +412,26 @@ mod test {
                 updater.get_icon_name("a.gitlab.freedesktop.org"),
                 "code-branch"
             );
+            assert_eq!(updater.get_icon_name("codeberg.org"), "code-branch");
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_configured_forges() {
+        crate::test::wrapper(|env| {
+            let mut config = env.base_config();
+            config.repository_forges = vec![
+                ForgeConfig::new(ForgeKind::Gitea, "codeberg.org", None),
+                ForgeConfig::new(ForgeKind::Bitbucket, "bitbucket.org", None),
+                ForgeConfig::new(ForgeKind::GitLab, "gitlab.gnome.org", None),
+            ];
+            let updater = RepositoryStatsUpdater::new(&config, env.pool()?);
+
+            assert_eq!(updater.get_icon_name("codeberg.org"), "git-alt");
+            assert_eq!(updater.get_icon_name("bitbucket.org"), "bitbucket");
+            assert_eq!(updater.get_icon_name("gitlab.gnome.org"), "gitlab");
+            assert_eq!(updater.get_icon_name("gitlab.com"), "code-branch");
             Ok(())
         });
     }
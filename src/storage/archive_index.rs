@@ -1,16 +1,47 @@
+//! The indexes of the rustdoc and source archives, mapping the path of every file to its byte
+//! range and compression inside the archive.
+//!
+//! Indexes are written in a binary format with the paths sorted, so files are found with a binary
+//! search and the entries of a directory are next to each other:
+//!
+//! ```text
+//! magic       b"docs.rs archive index\0"
+//! version     u32
+//! count       u32
+//! offsets     count * u32, the offset of each entry after the offsets, sorted by path
+//! entries     count * (path length: u32, path: [u8], range start: u64, range end: u64,
+//!                      compression: u8)
+//! ```
+//!
+//! All integers are little endian. Older indexes are a CBOR map, which can only be searched by
+//! walking through all of it. They're still read, and written until
+//! `DOCSRS_SORTED_ARCHIVE_INDEXES` is enabled, so web servers which can't read the sorted format
+//! yet keep working during a deploy.
+//!
+//! Content-addressed releases don't have an archive, their index is a manifest with version 3
+//! and the same layout, but every entry contains the SHA-256 hash of the file instead of its
+//! range and compression: `(path length: u32, path: [u8], hash: [u8; 32])`.
+
 use crate::error::Result;
 use crate::storage::{compression::CompressionAlgorithm, FileRange};
-use anyhow::{bail, Context as _};
+use anyhow::{anyhow, bail, Context as _};
 use memmap2::MmapOptions;
 use serde::de::DeserializeSeed;
 use serde::de::{IgnoredAny, MapAccess, Visitor};
 use serde::{Deserialize, Deserializer, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::{TryFrom, TryInto};
 use std::fmt;
-use std::path::Path;
 use std::{fs, io};
 
-#[derive(Deserialize, Serialize)]
+const MAGIC: &[u8] = b"docs.rs archive index\0";
+const VERSION: u32 = 2;
+const MANIFEST_VERSION: u32 = 3;
+
+/// The SHA-256 hash of the content of a file.
+pub(crate) type ContentHash = [u8; 32];
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub(crate) struct FileInfo {
     range: FileRange,
     compression: CompressionAlgorithm,
@@ -25,19 +56,39 @@ impl FileInfo {
     }
 }
 
-#[derive(Serialize)]
-struct Index {
+/// Where the content of a file is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Entry {
+    /// A range of the archive the index belongs to.
+    Archived(FileInfo),
+    /// A content-addressed blob, see [`super::dedup::blob_path`].
+    Blob(ContentHash),
+}
+
+/// A file or directory directly inside a directory of an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DirEntry {
+    pub(crate) name: String,
+    pub(crate) is_dir: bool,
+}
+
+/// An index in the legacy format.
+#[derive(Deserialize, Serialize)]
+struct CborIndex {
     files: HashMap<String, FileInfo>,
 }
 
+/// Writes the index of the archive, in the sorted format if `sorted` is set and in the legacy
+/// one otherwise.
 pub(crate) fn create<R: io::Read + io::Seek, W: io::Write>(
     zipfile: &mut R,
     writer: &mut W,
+    sorted: bool,
 ) -> Result<()> {
     let mut archive = zip::ZipArchive::new(zipfile)?;
 
     // get file locations
-    let mut files: HashMap<String, FileInfo> = HashMap::with_capacity(archive.len());
+    let mut files = BTreeMap::new();
     for i in 0..archive.len() {
         let zf = archive.by_index(i)?;
 
@@ -45,18 +96,283 @@ pub(crate) fn create<R: io::Read + io::Seek, W: io::Write>(
             zf.name().to_string(),
             FileInfo {
                 range: FileRange::new(zf.data_start(), zf.data_start() + zf.compressed_size() - 1),
-                compression: match zf.compression() {
-                    zip::CompressionMethod::Bzip2 => CompressionAlgorithm::Bzip2,
-                    c => bail!("unsupported compression algorithm {} in zip-file", c),
+                compression: match CompressionAlgorithm::from_zip_method(zf.compression()) {
+                    Some(alg) => alg,
+                    None => bail!(
+                        "unsupported compression algorithm {} in zip-file",
+                        zf.compression()
+                    ),
                 },
             },
         );
     }
 
-    serde_cbor::to_writer(writer, &Index { files }).context("serialization error")
+    if sorted {
+        write_index(&files, writer)
+    } else {
+        let files = files.into_iter().collect();
+        serde_cbor::to_writer(writer, &CborIndex { files }).context("serialization error")
+    }
+}
+
+/// Writes the manifest of a content-addressed release.
+pub(crate) fn create_manifest<W: io::Write>(
+    files: &BTreeMap<String, ContentHash>,
+    writer: &mut W,
+) -> Result<()> {
+    write_sorted(
+        MANIFEST_VERSION,
+        files.len(),
+        files
+            .iter()
+            .map(|(path, hash)| (path.as_str(), hash.to_vec())),
+        writer,
+    )
+}
+
+fn write_index<W: io::Write>(files: &BTreeMap<String, FileInfo>, writer: &mut W) -> Result<()> {
+    write_sorted(
+        VERSION,
+        files.len(),
+        files.iter().map(|(path, info)| {
+            let mut data = Vec::with_capacity(17);
+            data.extend_from_slice(&info.range.start().to_le_bytes());
+            data.extend_from_slice(&info.range.end().to_le_bytes());
+            data.push(info.compression as u8);
+            (path.as_str(), data)
+        }),
+        writer,
+    )
+}
+
+/// Writes `count` entries, each a path followed by `data`, which have to be sorted by path.
+fn write_sorted<'a, W: io::Write>(
+    version: u32,
+    count: usize,
+    files: impl Iterator<Item = (&'a str, Vec<u8>)>,
+    writer: &mut W,
+) -> Result<()> {
+    let mut offsets = Vec::with_capacity(count * 4);
+    let mut entries = Vec::new();
+    for (path, data) in files {
+        offsets.extend_from_slice(&u32::try_from(entries.len())?.to_le_bytes());
+        entries.extend_from_slice(&u32::try_from(path.len())?.to_le_bytes());
+        entries.extend_from_slice(path.as_bytes());
+        entries.extend_from_slice(&data);
+    }
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&version.to_le_bytes())?;
+    writer.write_all(&u32::try_from(count)?.to_le_bytes())?;
+    writer.write_all(&offsets)?;
+    writer.write_all(&entries)?;
+    Ok(())
+}
+
+/// Rewrites an index in the legacy format in the current one, returns `None` if it's in the
+/// current format already or a manifest.
+pub(crate) fn convert(bytes: &[u8]) -> Result<Option<Vec<u8>>> {
+    if bytes.starts_with(MAGIC) {
+        return Ok(None);
+    }
+
+    let index: CborIndex = serde_cbor::from_slice(bytes).context("invalid legacy index")?;
+    let mut converted = Vec::new();
+    write_index(&index.files.into_iter().collect(), &mut converted)?;
+    Ok(Some(converted))
+}
+
+pub(crate) fn find_in_slice(bytes: &[u8], search_for: &str) -> Result<Option<Entry>> {
+    match SortedIndex::parse(bytes)? {
+        Some(index) => index.find(search_for),
+        None => Ok(find_in_cbor(bytes, search_for)?.map(Entry::Archived)),
+    }
+}
+
+/// Lists the hashes of all files of a manifest, or returns `None` for the index of an archive.
+pub(crate) fn blobs_in_slice(bytes: &[u8]) -> Result<Option<Vec<ContentHash>>> {
+    match SortedIndex::parse(bytes)? {
+        Some(index) if index.version == MANIFEST_VERSION => (0..index.count)
+            .map(|i| index.blob(i))
+            .collect::<Result<_>>()
+            .map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Lists the files and directories directly inside `dir`, which is either empty for the root of
+/// the archive or ends with a `/`, sorted by name.
+pub(crate) fn list_dir_in_slice(bytes: &[u8], dir: &str) -> Result<Vec<DirEntry>> {
+    let mut entries = Vec::new();
+    let mut push = |path: &str| {
+        let rest = &path[dir.len()..];
+        let (name, is_dir) = match rest.split_once('/') {
+            Some((name, _)) => (name, true),
+            None => (rest, false),
+        };
+        // the files of a subdirectory are next to each other
+        if entries
+            .last()
+            .map_or(true, |last: &DirEntry| last.name != name)
+        {
+            entries.push(DirEntry {
+                name: name.to_owned(),
+                is_dir,
+            });
+        }
+    };
+
+    match SortedIndex::parse(bytes)? {
+        Some(index) => {
+            for i in index.lower_bound(dir)?..index.count {
+                let path = index.path(i)?;
+                if !path.starts_with(dir) {
+                    break;
+                }
+                push(path);
+            }
+        }
+        None => {
+            let index: CborIndex = serde_cbor::from_slice(bytes).context("invalid legacy index")?;
+            let mut paths: Vec<_> = index
+                .files
+                .keys()
+                .filter(|path| path.starts_with(dir))
+                .collect();
+            paths.sort();
+            for path in paths {
+                push(path);
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Lists the paths of all entries, sorted.
+pub(crate) fn paths_in_slice(bytes: &[u8]) -> Result<Vec<String>> {
+    match SortedIndex::parse(bytes)? {
+        Some(index) => (0..index.count)
+            .map(|i| Ok(index.path(i)?.to_owned()))
+            .collect(),
+        None => {
+            let index: CborIndex = serde_cbor::from_slice(bytes).context("invalid legacy index")?;
+            let mut paths: Vec<_> = index.files.into_keys().collect();
+            paths.sort();
+            Ok(paths)
+        }
+    }
+}
+
+/// An index in the current format.
+struct SortedIndex<'a> {
+    version: u32,
+    count: usize,
+    offsets: &'a [u8],
+    entries: &'a [u8],
+}
+
+impl<'a> SortedIndex<'a> {
+    /// Returns `None` for an index in the legacy format.
+    fn parse(bytes: &'a [u8]) -> Result<Option<Self>> {
+        let rest = match bytes.strip_prefix(MAGIC) {
+            Some(rest) => rest,
+            None => return Ok(None),
+        };
+
+        let version = read_u32(rest, 0)?;
+        if version != VERSION && version != MANIFEST_VERSION {
+            bail!("unsupported archive index version {}", version);
+        }
+        let count = read_u32(rest, 4)? as usize;
+        let entries_start = 8 + count * 4;
+        Ok(Some(Self {
+            version,
+            count,
+            offsets: slice(rest, 8, count * 4)?,
+            entries: rest.get(entries_start..).ok_or_else(corrupt)?,
+        }))
+    }
+
+    fn find(&self, search_for: &str) -> Result<Option<Entry>> {
+        let i = self.lower_bound(search_for)?;
+        if i == self.count || self.path(i)? != search_for {
+            return Ok(None);
+        }
+        if self.version == MANIFEST_VERSION {
+            return Ok(Some(Entry::Blob(self.blob(i)?)));
+        }
+
+        let info = self.data_offset(i)?;
+        let compression = *self.entries.get(info + 16).ok_or_else(corrupt)?;
+        Ok(Some(Entry::Archived(FileInfo {
+            range: FileRange::new(
+                read_u64(self.entries, info)?,
+                read_u64(self.entries, info + 8)?,
+            ),
+            compression: i32::from(compression)
+                .try_into()
+                .map_err(|alg| anyhow!("unknown compression algorithm {} in index", alg))?,
+        })))
+    }
+
+    /// The hash of the file at position `i` of a manifest.
+    fn blob(&self, i: usize) -> Result<ContentHash> {
+        Ok(slice(self.entries, self.data_offset(i)?, 32)?.try_into()?)
+    }
+
+    /// The position of the first path that isn't less than `search_for`.
+    fn lower_bound(&self, search_for: &str) -> Result<usize> {
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.path(mid)? < search_for {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        Ok(low)
+    }
+
+    fn path(&self, i: usize) -> Result<&'a str> {
+        let offset = self.entry_offset(i)?;
+        let len = read_u32(self.entries, offset)? as usize;
+        Ok(std::str::from_utf8(slice(self.entries, offset + 4, len)?)?)
+    }
+
+    fn entry_offset(&self, i: usize) -> Result<usize> {
+        Ok(read_u32(self.offsets, i * 4)? as usize)
+    }
+
+    /// The offset of the data following the path at position `i`.
+    fn data_offset(&self, i: usize) -> Result<usize> {
+        let offset = self.entry_offset(i)?;
+        Ok(offset + 4 + read_u32(self.entries, offset)? as usize)
+    }
 }
 
-pub(crate) fn find_in_slice(bytes: &[u8], search_for: &str) -> Result<Option<FileInfo>> {
+fn corrupt() -> anyhow::Error {
+    anyhow!("corrupt archive index")
+}
+
+fn slice(bytes: &[u8], start: usize, len: usize) -> Result<&[u8]> {
+    start
+        .checked_add(len)
+        .and_then(|end| bytes.get(start..end))
+        .ok_or_else(corrupt)
+}
+
+fn read_u32(bytes: &[u8], start: usize) -> Result<u32> {
+    Ok(u32::from_le_bytes(slice(bytes, start, 4)?.try_into()?))
+}
+
+fn read_u64(bytes: &[u8], start: usize) -> Result<u64> {
+    Ok(u64::from_le_bytes(slice(bytes, start, 8)?.try_into()?))
+}
+
+/// Finds a file in an index in the legacy format, a CBOR map walked until the path is found.
+fn find_in_cbor(bytes: &[u8], search_for: &str) -> Result<Option<FileInfo>> {
     let mut deserializer = serde_cbor::Deserializer::from_slice(bytes);
 
     /// This visitor will just find the `files` element in the top-level map.
@@ -155,18 +471,20 @@ pub(crate) fn find_in_slice(bytes: &[u8], search_for: &str) -> Result<Option<Fil
     .deserialize(&mut deserializer)?)
 }
 
-pub(crate) fn find_in_file<P: AsRef<Path>>(
-    archive_index_path: P,
-    search_for: &str,
-) -> Result<Option<FileInfo>> {
-    let file = fs::File::open(archive_index_path).context("could not open file")?;
-    let mmap = unsafe {
+fn map_file(file: &fs::File) -> Result<memmap2::Mmap> {
+    Ok(unsafe {
         MmapOptions::new()
-            .map(&file)
+            .map(file)
             .context("could not create memory map")?
-    };
+    })
+}
+
+pub(crate) fn find_in_file(file: &fs::File, search_for: &str) -> Result<Option<Entry>> {
+    find_in_slice(&map_file(file)?, search_for)
+}
 
-    find_in_slice(&mmap, search_for)
+pub(crate) fn list_dir_in_file(file: &fs::File, dir: &str) -> Result<Vec<DirEntry>> {
+    list_dir_in_slice(&map_file(file)?, dir)
 }
 
 #[cfg(test)]
@@ -175,6 +493,13 @@ mod tests {
     use std::io::Write;
     use zip::write::FileOptions;
 
+    fn archived(entry: Option<Entry>) -> FileInfo {
+        match entry {
+            Some(Entry::Archived(info)) => info,
+            other => panic!("expected an archived file, got {:?}", other),
+        }
+    }
+
     #[test]
     fn index_create_save_load() {
         let mut tf = tempfile::tempfile().unwrap();
@@ -191,13 +516,186 @@ mod tests {
         archive.write_all(&objectcontent).unwrap();
         tf = archive.finish().unwrap();
 
+        for sorted in [true, false] {
+            let mut buf = Vec::new();
+            create(&mut tf, &mut buf, sorted).unwrap();
+            assert_eq!(convert(&buf).unwrap().is_none(), sorted);
+
+            let fi = archived(find_in_slice(&buf, "testfile1").unwrap());
+            assert_eq!(fi.range, FileRange::new(39, 459));
+            assert_eq!(fi.compression, CompressionAlgorithm::Bzip2);
+
+            assert!(find_in_slice(&buf, "some_other_file").unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn index_zstd_entries() {
+        let mut archive = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        archive
+            .start_file(
+                "testfile1",
+                FileOptions::default().compression_method(zip::CompressionMethod::Zstd),
+            )
+            .unwrap();
+        archive.write_all(b"some content").unwrap();
+        let mut zipfile = archive.finish().unwrap();
+
         let mut buf = Vec::new();
-        create(&mut tf, &mut buf).unwrap();
+        create(&mut zipfile, &mut buf, true).unwrap();
+
+        let fi = archived(find_in_slice(&buf, "testfile1").unwrap());
+        assert_eq!(fi.compression, CompressionAlgorithm::Zstd);
+
+        let content = &zipfile.get_ref()[*fi.range.start() as usize..=*fi.range.end() as usize];
+        assert_eq!(
+            crate::storage::decompress(content, fi.compression, usize::MAX).unwrap(),
+            b"some content"
+        );
+    }
+
+    fn index_of(paths: &[&str]) -> Vec<u8> {
+        let mut archive = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        for path in paths {
+            archive
+                .start_file(
+                    *path,
+                    FileOptions::default().compression_method(zip::CompressionMethod::Zstd),
+                )
+                .unwrap();
+            archive.write_all(path.as_bytes()).unwrap();
+        }
+        let mut zipfile = archive.finish().unwrap();
+
+        let mut buf = Vec::new();
+        create(&mut zipfile, &mut buf, true).unwrap();
+        buf
+    }
 
-        let fi = find_in_slice(&buf, "testfile1").unwrap().unwrap();
-        assert_eq!(fi.range, FileRange::new(39, 459));
-        assert_eq!(fi.compression, CompressionAlgorithm::Bzip2);
+    #[test]
+    fn find_in_many_files() {
+        let paths: Vec<_> = (0..1000)
+            .map(|i| format!("dir{}/file{}.html", i % 7, i))
+            .collect();
+        let index = index_of(&paths.iter().map(|p| p.as_str()).collect::<Vec<_>>());
+
+        for path in &paths {
+            assert!(find_in_slice(&index, path).unwrap().is_some(), "{}", path);
+        }
+        for missing in ["", "dir0", "dir0/", "dir9/file1.html", "zzz"] {
+            assert!(
+                find_in_slice(&index, missing).unwrap().is_none(),
+                "{}",
+                missing
+            );
+        }
+    }
+
+    #[test]
+    fn list_dirs() {
+        let index = index_of(&["a.txt", "a/b.txt", "a/c/d.txt", "a/c/e.txt", "a-b/f.txt"]);
+        let entry = |name: &str, is_dir| DirEntry {
+            name: name.into(),
+            is_dir,
+        };
+
+        assert_eq!(
+            list_dir_in_slice(&index, "").unwrap(),
+            vec![entry("a-b", true), entry("a.txt", false), entry("a", true)]
+        );
+        assert_eq!(
+            list_dir_in_slice(&index, "a/").unwrap(),
+            vec![entry("b.txt", false), entry("c", true)]
+        );
+        assert!(list_dir_in_slice(&index, "b/").unwrap().is_empty());
+
+        assert_eq!(
+            paths_in_slice(&index).unwrap(),
+            ["a-b/f.txt", "a.txt", "a/b.txt", "a/c/d.txt", "a/c/e.txt"]
+        );
+    }
+
+    #[test]
+    fn legacy_indexes_are_read_and_converted() {
+        #[derive(Serialize)]
+        struct LegacyIndex {
+            files: HashMap<String, FileInfo>,
+        }
+
+        let info = FileInfo {
+            range: FileRange::new(10, 20),
+            compression: CompressionAlgorithm::Bzip2,
+        };
+        let mut files = HashMap::new();
+        files.insert("src/lib.rs".to_string(), info.clone());
+        files.insert("Cargo.toml".to_string(), info.clone());
+        let legacy = serde_cbor::to_vec(&LegacyIndex { files }).unwrap();
+
+        assert_eq!(
+            find_in_slice(&legacy, "src/lib.rs").unwrap(),
+            Some(Entry::Archived(info.clone()))
+        );
+        assert_eq!(find_in_slice(&legacy, "src/main.rs").unwrap(), None);
+        assert_eq!(
+            list_dir_in_slice(&legacy, "").unwrap(),
+            vec![
+                DirEntry {
+                    name: "Cargo.toml".into(),
+                    is_dir: false
+                },
+                DirEntry {
+                    name: "src".into(),
+                    is_dir: true
+                },
+            ]
+        );
+
+        assert_eq!(
+            paths_in_slice(&legacy).unwrap(),
+            ["Cargo.toml", "src/lib.rs"]
+        );
+
+        let converted = convert(&legacy).unwrap().unwrap();
+        assert!(converted.starts_with(MAGIC));
+        assert_eq!(
+            find_in_slice(&converted, "src/lib.rs").unwrap(),
+            Some(Entry::Archived(info))
+        );
+        assert!(convert(&converted).unwrap().is_none());
+        assert_eq!(blobs_in_slice(&converted).unwrap(), None);
+    }
 
-        assert!(find_in_slice(&buf, "some_other_file").unwrap().is_none());
+    #[test]
+    fn manifests() {
+        let mut files = BTreeMap::new();
+        files.insert("a/b.html".to_string(), [1; 32]);
+        files.insert("a.html".to_string(), [2; 32]);
+        files.insert("c.js".to_string(), [1; 32]);
+        let mut manifest = Vec::new();
+        create_manifest(&files, &mut manifest).unwrap();
+
+        assert_eq!(
+            find_in_slice(&manifest, "a/b.html").unwrap(),
+            Some(Entry::Blob([1; 32]))
+        );
+        assert_eq!(find_in_slice(&manifest, "b.html").unwrap(), None);
+        assert_eq!(
+            paths_in_slice(&manifest).unwrap(),
+            ["a.html", "a/b.html", "c.js"]
+        );
+        assert_eq!(
+            blobs_in_slice(&manifest).unwrap(),
+            Some(vec![[2; 32], [1; 32], [1; 32]])
+        );
+        // manifests are in the current format
+        assert!(convert(&manifest).unwrap().is_none());
+    }
+
+    #[test]
+    fn corrupt_indexes_are_rejected() {
+        let index = index_of(&["a.txt", "b.txt"]);
+        for len in [MAGIC.len() + 2, MAGIC.len() + 10, index.len() - 5] {
+            assert!(find_in_slice(&index[..len], "b.txt").is_err(), "{}", len);
+        }
     }
 }
@@ -0,0 +1,210 @@
+//! Moving releases with every rustdoc and source file stored separately into archives.
+//!
+//! A release is only switched to archive storage once both archives were stored and verified,
+//! and the separate files are deleted afterwards, so an interrupted job continues with the
+//! remaining releases when it's started again.
+
+use super::{rustdoc_archive_path, source_archive_path, ReleaseBatchOptions, ReleaseBatches};
+use crate::error::Result;
+use crate::utils::report_error;
+use crate::Storage;
+use anyhow::Context as _;
+use log::info;
+use postgres::Client;
+use std::fmt;
+
+/// The outcome of a migration run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ArchiveMigrationReport {
+    pub releases: usize,
+    pub archives: usize,
+    pub files: usize,
+    pub failed: usize,
+}
+
+impl fmt::Display for ArchiveMigrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "packed {} files into {} archives for {} releases, {} releases failed",
+            self.files, self.archives, self.releases, self.failed
+        )
+    }
+}
+
+/// Packs the separately stored files of all releases without archive storage into archives,
+/// one release after another.
+pub fn migrate_to_archive_storage(
+    conn: &mut Client,
+    storage: &Storage,
+    options: &ReleaseBatchOptions,
+) -> Result<ArchiveMigrationReport> {
+    let mut report = ArchiveMigrationReport::default();
+    let mut releases = ReleaseBatches::new(
+        "SELECT releases.id, crates.name, releases.version
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE
+            NOT releases.archive_storage AND
+            releases.id > $1
+         ORDER BY releases.id
+         LIMIT $2",
+        Vec::new(),
+        options,
+    );
+
+    while let Some(row) = releases.next(conn)? {
+        let id: i32 = row.get("id");
+        let name: String = row.get("name");
+        let version: String = row.get("version");
+
+        match migrate_release(conn, storage, id, &name, &version)
+            .with_context(|| format!("failed to migrate {} {}", name, version))
+        {
+            Ok((archives, files)) => {
+                info!("migrated {} {} to archive storage", name, version);
+                report.releases += 1;
+                report.archives += archives;
+                report.files += files;
+            }
+            Err(err) => {
+                report.failed += 1;
+                report_error(&err);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Returns how many archives and files were stored.
+fn migrate_release(
+    conn: &mut Client,
+    storage: &Storage,
+    release_id: i32,
+    name: &str,
+    version: &str,
+) -> Result<(usize, usize)> {
+    let prefixes = [
+        format!("rustdoc/{}/{}/", name, version),
+        format!("sources/{}/{}/", name, version),
+    ];
+
+    let mut archives = 0;
+    let mut files = 0;
+    let mut algorithm = None;
+    for (prefix, archive_path) in prefixes.iter().zip([
+        rustdoc_archive_path(name, version),
+        source_archive_path(name, version),
+    ]) {
+        // releases without documentation only have source files
+        if let Some((count, alg)) = storage.pack_into_archive(prefix, &archive_path)? {
+            archives += 1;
+            files += count;
+            algorithm = Some(alg);
+        }
+    }
+
+    let mut transaction = conn.transaction()?;
+    transaction.execute(
+        "UPDATE releases SET archive_storage = TRUE WHERE id = $1;",
+        &[&release_id],
+    )?;
+    if let Some(alg) = algorithm {
+        transaction.execute(
+            "DELETE FROM compression_rels WHERE release = $1;",
+            &[&release_id],
+        )?;
+        transaction.execute(
+            "INSERT INTO compression_rels (release, algorithm) VALUES ($1, $2);",
+            &[&release_id, &(alg as i32)],
+        )?;
+    }
+    transaction.commit()?;
+
+    for prefix in &prefixes {
+        storage.delete_prefix(prefix)?;
+    }
+
+    Ok((archives, files))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    fn archive_storage(env: &crate::test::TestEnvironment, name: &str) -> Result<bool> {
+        Ok(env
+            .db()
+            .conn()
+            .query_one(
+                "SELECT releases.archive_storage
+                 FROM releases
+                 INNER JOIN crates ON crates.id = releases.crate_id
+                 WHERE crates.name = $1",
+                &[&name],
+            )?
+            .get(0))
+    }
+
+    #[test]
+    fn releases_are_migrated_once() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .rustdoc_file_with("foo/index.html", b"<html>foo</html>")
+                .source_file("src/lib.rs", b"//! foo")
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("0.1.0")
+                .source_file("src/lib.rs", b"//! bar")
+                .create()?;
+            env.fake_release()
+                .name("baz")
+                .version("0.1.0")
+                .archive_storage(true)
+                .create()?;
+
+            let storage = env.storage();
+            assert!(storage.exists("rustdoc/foo/0.1.0/foo/index.html")?);
+
+            let options = ReleaseBatchOptions {
+                limit: Some(1),
+                ..Default::default()
+            };
+            let mut conn = env.db().conn();
+
+            let report = migrate_to_archive_storage(&mut conn, &storage, &options)?;
+            assert_eq!((report.releases, report.archives, report.failed), (1, 2, 0));
+            assert!(archive_storage(env, "foo")?);
+            assert!(!archive_storage(env, "bar")?);
+            assert!(storage.list_prefix("rustdoc/foo/0.1.0/")?.is_empty());
+            assert!(storage.list_prefix("sources/foo/0.1.0/")?.is_empty());
+
+            let file =
+                storage.get_from_archive("rustdoc/foo/0.1.0.zip", "foo/index.html", 1024, None)?;
+            assert_eq!(file.content, b"<html>foo</html>");
+            let web = env.frontend();
+            for path in [
+                "/foo/0.1.0/foo/index.html",
+                "/crate/foo/0.1.0/source/src/lib.rs",
+            ] {
+                assert!(web.get(path).send()?.status().is_success(), "{}", path);
+            }
+
+            // the next run continues with the remaining releases
+            let options = ReleaseBatchOptions::default();
+            let report = migrate_to_archive_storage(&mut conn, &storage, &options)?;
+            assert_eq!((report.releases, report.failed), (1, 0));
+            assert!(archive_storage(env, "bar")?);
+
+            let report = migrate_to_archive_storage(&mut conn, &storage, &options)?;
+            assert_eq!(report, ArchiveMigrationReport::default());
+
+            Ok(())
+        })
+    }
+}
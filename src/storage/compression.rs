@@ -10,6 +10,10 @@ use std::{
 
 pub type CompressionAlgorithms = HashSet<CompressionAlgorithm>;
 
+#[derive(Debug, thiserror::Error)]
+#[error("invalid compression algorithm")]
+pub struct InvalidCompressionAlgorithm;
+
 macro_rules! enum_id {
     ($vis:vis enum $name:ident { $($variant:ident = $discriminant:expr,)* }) => {
         #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -31,11 +35,11 @@ macro_rules! enum_id {
         }
 
         impl std::str::FromStr for CompressionAlgorithm {
-            type Err = ();
+            type Err = InvalidCompressionAlgorithm;
             fn from_str(s: &str) -> Result<Self, Self::Err> {
                 match s {
                     $(stringify!($variant) => Ok(Self::$variant),)*
-                    _ => Err(()),
+                    _ => Err(InvalidCompressionAlgorithm),
                 }
             }
         }
@@ -65,6 +69,25 @@ impl Default for CompressionAlgorithm {
     }
 }
 
+impl CompressionAlgorithm {
+    /// The compression method of zip entries compressed with this algorithm.
+    pub(crate) fn zip_method(self) -> zip::CompressionMethod {
+        match self {
+            CompressionAlgorithm::Zstd => zip::CompressionMethod::Zstd,
+            CompressionAlgorithm::Bzip2 => zip::CompressionMethod::Bzip2,
+        }
+    }
+
+    /// The algorithm compressing zip entries with the given method, if it's supported.
+    pub(crate) fn from_zip_method(method: zip::CompressionMethod) -> Option<Self> {
+        match method {
+            zip::CompressionMethod::Zstd => Some(CompressionAlgorithm::Zstd),
+            zip::CompressionMethod::Bzip2 => Some(CompressionAlgorithm::Bzip2),
+            _ => None,
+        }
+    }
+}
+
 // public for benchmarking
 pub fn compress(content: impl Read, algorithm: CompressionAlgorithm) -> Result<Vec<u8>, Error> {
     match algorithm {
@@ -1,10 +1,11 @@
-use super::{Blob, FileRange, StorageTransaction};
+use super::{read_chunk, Blob, FileRange, StorageTransaction, StoredObject, UPLOAD_CHUNK_SIZE};
 use crate::db::Pool;
 use crate::error::Result;
 use crate::Metrics;
 use postgres::Transaction;
-use std::{convert::TryFrom, sync::Arc};
+use std::{convert::TryFrom, io::Read, sync::Arc};
 
+#[derive(Clone)]
 pub(crate) struct DatabaseBackend {
     pool: Pool,
     metrics: Arc<Metrics>,
@@ -21,6 +22,52 @@ impl DatabaseBackend {
         Ok(conn.query(query, &[&path])?[0].get(0))
     }
 
+    pub(super) async fn exists_async(&self, path: &str) -> Result<bool> {
+        let backend = self.clone();
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || backend.exists(&path)).await?
+    }
+
+    pub(super) fn list_prefix(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut conn = self.pool.get()?;
+        Ok(conn
+            .query(
+                "SELECT path FROM files WHERE path LIKE $1 ORDER BY path;",
+                &[&like_prefix(prefix)],
+            )?
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect())
+    }
+
+    pub(super) fn list_page(
+        &self,
+        prefix: &str,
+        start_after: &str,
+        limit: usize,
+    ) -> Result<Vec<StoredObject>> {
+        let mut conn = self.pool.get()?;
+        Ok(conn
+            .query(
+                &format!(
+                    "SELECT path, size, date_updated
+                     FROM files {}
+                     WHERE path LIKE $1 AND path > $2
+                     ORDER BY path
+                     LIMIT $3;",
+                    JOIN_SIZE
+                ),
+                &[&like_prefix(prefix), &start_after, &(limit as i64)],
+            )?
+            .into_iter()
+            .map(|row| StoredObject {
+                path: row.get("path"),
+                size: row.get::<_, i64>("size") as u64,
+                date_updated: row.get("date_updated"),
+            })
+            .collect())
+    }
+
     pub(super) fn get(
         &self,
         path: &str,
@@ -31,7 +78,9 @@ impl DatabaseBackend {
         // https://www.postgresql.org/message-id/162867790712200946i7ba8eb92v908ac595c0c35aee%40mail.gmail.com
         let max_size = max_size.min(std::i32::MAX as usize) as i32;
 
-        let rows = if let Some(r) = range {
+        let mut conn = self.pool.get()?;
+        let chunk_size = UPLOAD_CHUNK_SIZE as u64;
+        let row = if let Some(r) = &range {
             // when we only want to get a range we can validate already if the range is small enough
             if (r.end() - r.start() + 1) > max_size as u64 {
                 return Err(std::io::Error::new(
@@ -40,9 +89,10 @@ impl DatabaseBackend {
                 )
                 .into());
             }
-            let range_start = i32::try_from(*r.start())?;
+            // the part of the range in the first chunk, which is empty if the range starts after it
+            let range_start = i32::try_from((*r.start()).min(chunk_size))?;
 
-            self.pool.get()?.query(
+            conn.query_opt(
                 "SELECT
                      path, mime, date_updated, compression,
                      substring(content from $2 for $3) as content,
@@ -58,42 +108,82 @@ impl DatabaseBackend {
         } else {
             // The size limit is checked at the database level, to avoid receiving data altogether if
             // the limit is exceeded.
-            self.pool.get()?.query(
-                "SELECT
-                     path, mime, date_updated, compression,
-                     (CASE WHEN LENGTH(content) <= $2 THEN content ELSE NULL END) AS content,
-                     (LENGTH(content) > $2) AS is_too_big
-                 FROM files
-                 WHERE path = $1;",
-                &[&path, &(max_size)],
+            conn.query_opt(
+                &format!(
+                    "SELECT
+                         path, mime, date_updated, compression,
+                         (CASE WHEN size <= $2 THEN content ELSE NULL END) AS content,
+                         (size > $2) AS is_too_big
+                     FROM files {}
+                     WHERE path = $1;",
+                    JOIN_SIZE
+                ),
+                &[&path, &(max_size as i64)],
             )?
         };
 
-        if rows.is_empty() {
-            Err(super::PathNotFoundError.into())
-        } else {
-            let row = &rows[0];
+        let row = row.ok_or(super::PathNotFoundError)?;
+        if row.get("is_too_big") {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                crate::error::SizeLimitReached,
+            )
+            .into());
+        }
 
-            if row.get("is_too_big") {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    crate::error::SizeLimitReached,
-                )
-                .into());
+        // the other chunks of large files, see `store_file`
+        let mut content: Vec<u8> = row.get("content");
+        let (first_chunk, last_chunk) = match &range {
+            Some(r) => (
+                i32::try_from(r.start() / chunk_size)?.max(1),
+                i32::try_from(r.end() / chunk_size)?,
+            ),
+            None => (1, i32::MAX),
+        };
+        if last_chunk >= first_chunk {
+            for chunk in conn.query(
+                "SELECT chunk, content
+                 FROM file_chunks
+                 WHERE path = $1 AND chunk BETWEEN $2 AND $3
+                 ORDER BY chunk;",
+                &[&path, &first_chunk, &last_chunk],
+            )? {
+                let chunk_content: &[u8] = chunk.get("content");
+                match &range {
+                    Some(r) => {
+                        let offset = chunk.get::<_, i32>("chunk") as u64 * chunk_size;
+                        let end = ((r.end() + 1 - offset) as usize).min(chunk_content.len());
+                        let start = (r.start().saturating_sub(offset) as usize).min(end);
+                        content.extend_from_slice(&chunk_content[start..end]);
+                    }
+                    None => content.extend_from_slice(chunk_content),
+                }
             }
-
-            let compression = row.get::<_, Option<i32>>("compression").map(|i| {
-                i.try_into()
-                    .expect("invalid compression algorithm stored in database")
-            });
-            Ok(Blob {
-                path: row.get("path"),
-                mime: row.get("mime"),
-                date_updated: row.get("date_updated"),
-                content: row.get("content"),
-                compression,
-            })
         }
+
+        let compression = row.get::<_, Option<i32>>("compression").map(|i| {
+            i.try_into()
+                .expect("invalid compression algorithm stored in database")
+        });
+        Ok(Blob {
+            path: row.get("path"),
+            mime: row.get("mime"),
+            date_updated: row.get("date_updated"),
+            content,
+            compression,
+        })
+    }
+
+    /// Runs [`Self::get`] on the blocking thread pool, since the database client is synchronous.
+    pub(super) async fn get_async(
+        &self,
+        path: &str,
+        max_size: usize,
+        range: Option<FileRange>,
+    ) -> Result<Blob> {
+        let backend = self.clone();
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || backend.get(&path, max_size, range)).await?
     }
 
     pub(super) fn start_connection(&self) -> Result<DatabaseClient> {
@@ -125,13 +215,18 @@ pub(super) struct DatabaseStorageTransaction<'a> {
 
 impl<'a> StorageTransaction for DatabaseStorageTransaction<'a> {
     fn store_batch(&mut self, batch: Vec<Blob>) -> Result<()> {
+        // the chunks of large files stored at the same paths before
+        let paths: Vec<&str> = batch.iter().map(|blob| blob.path.as_str()).collect();
+        self.transaction
+            .execute("DELETE FROM file_chunks WHERE path = ANY($1);", &[&paths])?;
         for blob in batch {
             let compression = blob.compression.map(|alg| alg as i32);
             self.transaction.query(
                 "INSERT INTO files (path, mime, content, compression)
                  VALUES ($1, $2, $3, $4)
                  ON CONFLICT (path) DO UPDATE
-                    SET mime = EXCLUDED.mime, content = EXCLUDED.content, compression = EXCLUDED.compression",
+                    SET mime = EXCLUDED.mime, content = EXCLUDED.content,
+                        compression = EXCLUDED.compression, date_updated = NOW()",
                 &[&blob.path, &blob.mime, &blob.content, &compression],
             )?;
             self.metrics.uploaded_files_total.inc();
@@ -139,19 +234,73 @@ impl<'a> StorageTransaction for DatabaseStorageTransaction<'a> {
         Ok(())
     }
 
+    fn store_file(&mut self, path: &str, mime: &str, file: &mut dyn Read) -> Result<()> {
+        // values can't be streamed into a query, and a single value can be at most 1 GB, so only
+        // the first chunk is stored in `files` and every other chunk in a row of `file_chunks`
+        let mut chunk = read_chunk(file)?;
+        self.transaction.execute(
+            "INSERT INTO files (path, mime, content, compression)
+             VALUES ($1, $2, $3, NULL)
+             ON CONFLICT (path) DO UPDATE
+                SET mime = EXCLUDED.mime, content = EXCLUDED.content, compression = NULL,
+                    date_updated = NOW()",
+            &[&path, &mime, &chunk],
+        )?;
+        self.transaction
+            .execute("DELETE FROM file_chunks WHERE path = $1;", &[&path])?;
+        let mut number: i32 = 0;
+        while chunk.len() == UPLOAD_CHUNK_SIZE {
+            chunk = read_chunk(file)?;
+            number += 1;
+            if !chunk.is_empty() {
+                self.transaction.execute(
+                    "INSERT INTO file_chunks (path, chunk, content) VALUES ($1, $2, $3);",
+                    &[&path, &number, &chunk],
+                )?;
+            }
+        }
+        self.metrics.uploaded_files_total.inc();
+        Ok(())
+    }
+
     fn delete_prefix(&mut self, prefix: &str) -> Result<()> {
         self.transaction.execute(
             "DELETE FROM files WHERE path LIKE $1;",
-            &[&format!("{}%", prefix.replace('%', "\\%"))],
+            &[&like_prefix(prefix)],
         )?;
         Ok(())
     }
 
+    fn delete_paths(&mut self, paths: &[String]) -> Result<()> {
+        self.transaction
+            .execute("DELETE FROM files WHERE path = ANY($1);", &[&paths])?;
+        Ok(())
+    }
+
     fn complete(self: Box<Self>) -> Result<()> {
         self.transaction.commit()?;
         Ok(())
     }
 }
 
+/// Joins the total size of each file as `size`, including the chunks of large files stored
+/// separately, see `store_file`.
+const JOIN_SIZE: &str = "CROSS JOIN LATERAL (
+        SELECT
+            COALESCE(OCTET_LENGTH(files.content), 0)
+                + COALESCE(SUM(OCTET_LENGTH(file_chunks.content)), 0) AS size
+        FROM file_chunks
+        WHERE file_chunks.path = files.path
+    ) AS sizes";
+
+/// Builds a `LIKE` pattern matching all paths starting with `prefix`.
+fn like_prefix(prefix: &str) -> String {
+    let escaped = prefix
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("{}%", escaped)
+}
+
 // The tests for this module are in src/storage/mod.rs, as part of the backend tests. Please add
 // any test checking the public interface there.
@@ -0,0 +1,272 @@
+//! Content-addressed storage of the files of releases.
+//!
+//! Instead of an archive, every file of a release is stored as a blob named after the SHA-256
+//! hash of its content, so the files successive versions share, like static pages and unchanged
+//! modules, are only stored once. The index next to where the archive would be is a manifest
+//! mapping the paths to their hashes, see [`super::archive_index`], so these releases are read
+//! like any other archive.
+//!
+//! Blobs aren't deleted together with a release, other releases can still use them. The garbage
+//! collector deletes the blobs no manifest refers to anymore, see
+//! [`super::collect_garbage`].
+
+use super::archive_index::{self, ContentHash};
+use super::{
+    rustdoc_archive_path, source_archive_path, PathNotFoundError, ReleaseBatchOptions,
+    ReleaseBatches, LIST_PAGE_SIZE,
+};
+use crate::error::Result;
+use crate::Storage;
+use postgres::Client;
+use std::collections::HashMap;
+use std::fmt;
+
+pub(super) const BLOB_PREFIX: &str = "blobs/";
+
+/// The path of the blob of a file with this hash.
+pub(crate) fn blob_path(hash: &ContentHash) -> String {
+    let hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+    // the first byte spreads the blobs over directories
+    format!("{}{}/{}", BLOB_PREFIX, &hex[..2], hex)
+}
+
+/// The hash of the file in the blob at `path`, or `None` if it isn't the path of a blob.
+pub(crate) fn blob_hash(path: &str) -> Option<ContentHash> {
+    let (dir, hex) = path.strip_prefix(BLOB_PREFIX)?.split_once('/')?;
+    if hex.len() != 64 || !hex.starts_with(dir) || dir.len() != 2 {
+        return None;
+    }
+    let mut hash = [0; 32];
+    for (byte, digits) in hash.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// How much space content-addressed storage saves. All sizes are of the stored, compressed
+/// blobs.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DedupReport {
+    /// Releases with at least one manifest.
+    pub releases: usize,
+    /// Files in all manifests.
+    pub files: usize,
+    /// The total size of all files, as if every file was stored on its own.
+    pub file_bytes: u64,
+    /// Blobs used by at least one file.
+    pub blobs: usize,
+    pub blob_bytes: u64,
+    /// Blobs no manifest refers to anymore, for example after their releases were deleted.
+    pub unreferenced_blobs: usize,
+    pub unreferenced_bytes: u64,
+    /// Files whose blob doesn't exist.
+    pub missing: usize,
+}
+
+impl DedupReport {
+    pub fn saved_bytes(&self) -> u64 {
+        self.file_bytes.saturating_sub(self.blob_bytes)
+    }
+}
+
+impl fmt::Display for DedupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let saved_percent = if self.file_bytes == 0 {
+            0.0
+        } else {
+            self.saved_bytes() as f64 * 100.0 / self.file_bytes as f64
+        };
+        write!(
+            f,
+            "{} files of {} releases with {} bytes are stored in {} blobs with {} bytes, \
+             saving {} bytes ({:.1}%)\n\
+             {} blobs with {} bytes are unreferenced, {} files are missing their blob",
+            self.files,
+            self.releases,
+            self.file_bytes,
+            self.blobs,
+            self.blob_bytes,
+            self.saved_bytes(),
+            saved_percent,
+            self.unreferenced_blobs,
+            self.unreferenced_bytes,
+            self.missing
+        )
+    }
+}
+
+/// How often the manifests of all content-addressed releases refer to each blob.
+pub(super) struct BlobReferences {
+    /// Releases with at least one manifest.
+    pub(super) releases: usize,
+    /// How many files use each blob, by the hash of its content.
+    pub(super) files: HashMap<ContentHash, usize>,
+}
+
+/// Reads the manifests of all content-addressed releases from the storage backend, bypassing
+/// the local cache.
+///
+/// The index of every release with archive storage is read, to find the manifests. An index
+/// that can't be read fails the whole run, the blobs it refers to would count as unreferenced
+/// otherwise.
+pub(super) fn count_references(conn: &mut Client, storage: &Storage) -> Result<BlobReferences> {
+    let mut references = BlobReferences {
+        releases: 0,
+        files: HashMap::new(),
+    };
+    let options = ReleaseBatchOptions::default();
+    let mut releases = ReleaseBatches::new(
+        "SELECT releases.id, crates.name, releases.version
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE
+            releases.archive_storage AND
+            releases.id > $1
+         ORDER BY releases.id
+         LIMIT $2",
+        Vec::new(),
+        &options,
+    );
+    while let Some(row) = releases.next(conn)? {
+        let name: String = row.get("name");
+        let version: String = row.get("version");
+
+        let mut content_addressed = false;
+        for archive_path in [
+            rustdoc_archive_path(&name, &version),
+            source_archive_path(&name, &version),
+        ] {
+            let index = match storage.get_remote_index(&archive_path) {
+                Ok(index) => index,
+                // releases without documentation only have a source archive
+                Err(err) if err.is::<PathNotFoundError>() => continue,
+                Err(err) => return Err(err),
+            };
+            if let Some(hashes) = archive_index::blobs_in_slice(&index)? {
+                content_addressed = true;
+                for hash in hashes {
+                    *references.files.entry(hash).or_default() += 1;
+                }
+            }
+        }
+        if content_addressed {
+            references.releases += 1;
+        }
+    }
+    Ok(references)
+}
+
+/// Compares the size of all files of content-addressed releases with the size of the blobs
+/// actually stored for them.
+///
+/// Only the references are kept in memory, the blobs are listed page by page.
+pub fn dedup_report(conn: &mut Client, storage: &Storage) -> Result<DedupReport> {
+    let BlobReferences {
+        releases,
+        files: mut unseen,
+    } = count_references(conn, storage)?;
+    let mut report = DedupReport {
+        releases,
+        files: unseen.values().sum(),
+        ..DedupReport::default()
+    };
+
+    let mut start_after = String::new();
+    loop {
+        let page = storage.list_page(BLOB_PREFIX, &start_after, LIST_PAGE_SIZE)?;
+        start_after = match page.last() {
+            Some(object) => object.path.clone(),
+            None => break,
+        };
+
+        for object in page {
+            match blob_hash(&object.path).and_then(|hash| unseen.remove(&hash)) {
+                Some(files) => {
+                    report.blobs += 1;
+                    report.blob_bytes += object.size;
+                    report.file_bytes += files as u64 * object.size;
+                }
+                None => {
+                    report.unreferenced_blobs += 1;
+                    report.unreferenced_bytes += object.size;
+                }
+            }
+        }
+    }
+    report.missing = unseen.values().sum();
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn blob_paths() {
+        let mut hash = [0; 32];
+        hash[0] = 0xab;
+        hash[31] = 0x01;
+        assert_eq!(blob_path(&hash), format!("blobs/ab/ab{}01", "0".repeat(60)));
+        assert_eq!(blob_hash(&blob_path(&hash)), Some(hash));
+
+        for path in [
+            "blobs/ab/ab",
+            "blobs/cd/ab00000000000000000000000000000000000000000000000000000000000001",
+            "blobs/ab/ab0000000000000000000000000000000000000000000000000000000000000x",
+            "rustdoc/ab/ab00000000000000000000000000000000000000000000000000000000000001",
+        ] {
+            assert_eq!(blob_hash(path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn shared_files_are_stored_once() {
+        wrapper(|env| {
+            env.override_config(|config| config.content_addressed_storage = true);
+            for version in ["0.1.0", "0.2.0"] {
+                env.fake_release()
+                    .name("foo")
+                    .version(version)
+                    .archive_storage(true)
+                    .rustdoc_file_with("static.css", b"body {}")
+                    .rustdoc_file_with("foo/index.html", version.as_bytes())
+                    .create()?;
+            }
+            let storage = env.storage();
+            assert!(!storage.exists("rustdoc/foo/0.1.0.zip")?);
+            assert!(storage.exists("rustdoc/foo/0.1.0.zip.index")?);
+
+            let web = env.frontend();
+            for version in ["0.1.0", "0.2.0"] {
+                let archive_path = rustdoc_archive_path("foo", version);
+                let page = storage.get_from_archive(&archive_path, "foo/index.html", 1024, None)?;
+                assert_eq!(page.content, version.as_bytes());
+                let css = storage.get_from_archive(&archive_path, "static.css", 1024, None)?;
+                assert_eq!(
+                    (css.content.as_slice(), css.mime.as_str()),
+                    (&b"body {}"[..], "text/css")
+                );
+
+                let path = format!("/foo/{}/foo/index.html", version);
+                assert!(web.get(&path).send()?.status().is_success(), "{}", path);
+            }
+            assert_eq!(
+                crate::storage::archive_index::paths_in_slice(
+                    &storage.get_remote_index("rustdoc/foo/0.2.0.zip")?
+                )?,
+                ["foo/index.html", "static.css"]
+            );
+
+            let report = dedup_report(&mut env.db().conn(), &storage)?;
+            assert_eq!((report.releases, report.missing), (2, 0));
+            // the css file is shared, the index pages aren't
+            assert_eq!((report.files, report.blobs), (4, 3));
+            assert!(report.saved_bytes() > 0);
+            assert_eq!(report.unreferenced_blobs, 0);
+
+            Ok(())
+        })
+    }
+}
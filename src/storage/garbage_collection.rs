@@ -0,0 +1,456 @@
+//! Finding and deleting files in the storage backend that don't belong to any release or build.
+//!
+//! Only the prefixes written for releases and builds are scanned, the essential files stored at
+//! the root are never touched. Files updated recently are kept, so the files of running builds
+//! aren't collected before their release is added to the database.
+//!
+//! Content-addressed blobs are shared between releases, they're orphaned when none of the
+//! manifests of all releases refers to them. The manifests are only read once the first blob is
+//! listed, and the blobs listed after that are checked against them.
+
+use super::archive_index::ContentHash;
+use super::dedup::{blob_hash, count_references, BLOB_PREFIX};
+use super::{forget_blob_usage, LIST_PAGE_SIZE};
+use crate::error::Result;
+use crate::Storage;
+use chrono::Utc;
+use log::info;
+use postgres::Client;
+use std::collections::HashMap;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+/// How many orphaned files are deleted at once.
+const DELETE_BATCH_SIZE: usize = 1000;
+const PREFIXES: &[&str] = &["build-logs/", "rustdoc/", "sources/", BLOB_PREFIX];
+
+#[derive(Debug, Clone)]
+pub struct GarbageCollectionOptions {
+    /// Only report the orphaned files, without deleting anything.
+    pub dry_run: bool,
+    /// Files updated more recently are kept.
+    pub min_age: Duration,
+    /// How long to wait after each deleted batch, so the job doesn't slow down the builds.
+    pub pause: Duration,
+}
+
+/// The outcome of a garbage collection run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GarbageCollectionReport {
+    pub files: usize,
+    pub orphans: usize,
+    /// The total size of the orphaned files.
+    pub bytes: u64,
+    pub deleted: usize,
+    /// Locally cached indexes of orphaned archives.
+    pub local_indexes: usize,
+}
+
+impl fmt::Display for GarbageCollectionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "found {} orphaned files with {} bytes in {} files, deleted {} of them and {} local \
+             archive indexes",
+            self.orphans, self.bytes, self.files, self.deleted, self.local_indexes
+        )
+    }
+}
+
+/// What a file in the storage backend was stored for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Owner<'a> {
+    Build(i32),
+    Release {
+        name: &'a str,
+        version: &'a str,
+        /// Whether the file is an archive or its index, rather than a separately stored file.
+        archive: bool,
+    },
+    /// A content-addressed blob, used by every release whose manifest contains its hash.
+    Blob(ContentHash),
+}
+
+/// Returns `None` for paths that don't look like they were written for a release or build.
+fn owner(path: &str) -> Option<Owner<'_>> {
+    if path.starts_with(BLOB_PREFIX) {
+        return blob_hash(path).map(Owner::Blob);
+    }
+    if let Some(rest) = path.strip_prefix("build-logs/") {
+        let (id, _) = rest.split_once('/')?;
+        return id.parse().ok().map(Owner::Build);
+    }
+
+    let rest = path
+        .strip_prefix("rustdoc/")
+        .or_else(|| path.strip_prefix("sources/"))?;
+    let (name, rest) = rest.split_once('/')?;
+    let (version, archive) = match rest.split_once('/') {
+        Some((version, _)) => (version, false),
+        None => (
+            rest.strip_suffix(".zip.index")
+                .or_else(|| rest.strip_suffix(".zip"))?,
+            true,
+        ),
+    };
+    if name.is_empty() || version.is_empty() {
+        return None;
+    }
+    Some(Owner::Release {
+        name,
+        version,
+        archive,
+    })
+}
+
+/// Looks up the owners of files in the database.
+///
+/// Files are listed in the order of their paths, so the files of an owner come one after
+/// another and only the last lookup is remembered.
+struct Owners<'a> {
+    conn: &'a mut Client,
+    storage: &'a Storage,
+    last_build: Option<(i32, bool)>,
+    last_release: Option<(String, String, Option<bool>)>,
+    /// How many files of all manifests use each blob, read on the first lookup of a blob.
+    referenced_blobs: Option<HashMap<ContentHash, usize>>,
+}
+
+impl<'a> Owners<'a> {
+    fn new(conn: &'a mut Client, storage: &'a Storage) -> Self {
+        Self {
+            conn,
+            storage,
+            last_build: None,
+            last_release: None,
+            referenced_blobs: None,
+        }
+    }
+
+    fn is_orphan(&mut self, owner: Owner<'_>) -> Result<bool> {
+        Ok(match owner {
+            Owner::Build(id) => !self.build_exists(id)?,
+            // archives of releases with separately stored files are left over from interrupted
+            // builds or migrations, and the other way around
+            Owner::Release {
+                name,
+                version,
+                archive,
+            } => self.archive_storage(name, version)? != Some(archive),
+            Owner::Blob(hash) => !self.referenced_blobs()?.contains_key(&hash),
+        })
+    }
+
+    fn referenced_blobs(&mut self) -> Result<&HashMap<ContentHash, usize>> {
+        if self.referenced_blobs.is_none() {
+            let references = count_references(self.conn, self.storage)?;
+            info!(
+                "{} blobs are referenced by the manifests of {} releases",
+                references.files.len(),
+                references.releases
+            );
+            self.referenced_blobs = Some(references.files);
+        }
+        Ok(self.referenced_blobs.as_ref().unwrap())
+    }
+
+    fn build_exists(&mut self, id: i32) -> Result<bool> {
+        match self.last_build {
+            Some((last_id, exists)) if last_id == id => Ok(exists),
+            _ => {
+                let exists = self
+                    .conn
+                    .query_opt("SELECT id FROM builds WHERE id = $1", &[&id])?
+                    .is_some();
+                self.last_build = Some((id, exists));
+                Ok(exists)
+            }
+        }
+    }
+
+    /// Returns `None` if the release doesn't exist.
+    fn archive_storage(&mut self, name: &str, version: &str) -> Result<Option<bool>> {
+        match &self.last_release {
+            Some((last_name, last_version, archive_storage))
+                if last_name == name && last_version == version =>
+            {
+                Ok(*archive_storage)
+            }
+            _ => {
+                let archive_storage = self
+                    .conn
+                    .query_opt(
+                        "SELECT releases.archive_storage
+                         FROM releases
+                         INNER JOIN crates ON crates.id = releases.crate_id
+                         WHERE crates.name = $1 AND releases.version = $2",
+                        &[&name, &version],
+                    )?
+                    .map(|row| row.get(0));
+                self.last_release = Some((name.into(), version.into(), archive_storage));
+                Ok(archive_storage)
+            }
+        }
+    }
+}
+
+/// Finds the files not belonging to any release or build and deletes them, unless it's a dry
+/// run.
+pub fn collect_garbage(
+    conn: &mut Client,
+    storage: &Storage,
+    options: &GarbageCollectionOptions,
+) -> Result<GarbageCollectionReport> {
+    let mut report = GarbageCollectionReport::default();
+    let cutoff = Utc::now() - chrono::Duration::from_std(options.min_age)?;
+    let mut owners = Owners::new(conn, storage);
+    let mut orphans = Vec::new();
+
+    for prefix in PREFIXES {
+        let mut start_after = String::new();
+        loop {
+            let page = storage.list_page(prefix, &start_after, LIST_PAGE_SIZE)?;
+            start_after = match page.last() {
+                Some(object) => object.path.clone(),
+                None => break,
+            };
+
+            for object in page {
+                report.files += 1;
+                if object.date_updated > cutoff {
+                    continue;
+                }
+                let orphaned = match owner(&object.path) {
+                    Some(owner) => owners.is_orphan(owner)?,
+                    None => false,
+                };
+                if !orphaned {
+                    continue;
+                }
+
+                info!("{} is orphaned ({} bytes)", object.path, object.size);
+                report.orphans += 1;
+                report.bytes += object.size;
+                orphans.push(object.path);
+                if orphans.len() >= DELETE_BATCH_SIZE {
+                    report.deleted += delete_orphans(owners.conn, storage, &mut orphans, options)?;
+                }
+            }
+        }
+    }
+    report.deleted += delete_orphans(owners.conn, storage, &mut orphans, options)?;
+
+    for archive_path in storage.index_cache.archive_paths() {
+        let orphaned = match owner(&archive_path) {
+            Some(owner @ Owner::Release { archive: true, .. }) => owners.is_orphan(owner)?,
+            _ => false,
+        };
+        if orphaned {
+            info!("the local index of {} is orphaned", archive_path);
+            report.local_indexes += 1;
+            if !options.dry_run {
+                storage.index_cache.remove(&archive_path)?;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Returns how many files were deleted, and empties the list.
+fn delete_orphans(
+    conn: &mut Client,
+    storage: &Storage,
+    paths: &mut Vec<String>,
+    options: &GarbageCollectionOptions,
+) -> Result<usize> {
+    if options.dry_run || paths.is_empty() {
+        paths.clear();
+        return Ok(0);
+    }
+
+    storage.delete_paths(paths)?;
+    forget_blob_usage(conn, storage, paths)?;
+    let deleted = paths.len();
+    paths.clear();
+
+    if !options.pause.is_zero() {
+        thread::sleep(options.pause);
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::dedup::blob_path;
+    use crate::test::wrapper;
+
+    #[test]
+    fn owners_are_parsed_from_paths() {
+        let release = |name, version, archive| {
+            Some(Owner::Release {
+                name,
+                version,
+                archive,
+            })
+        };
+        assert_eq!(
+            owner("build-logs/42/x86_64-unknown-linux-gnu.txt"),
+            Some(Owner::Build(42))
+        );
+        assert_eq!(
+            owner("rustdoc/foo/0.1.0.zip"),
+            release("foo", "0.1.0", true)
+        );
+        assert_eq!(
+            owner("sources/foo/0.1.0.zip.index"),
+            release("foo", "0.1.0", true)
+        );
+        assert_eq!(
+            owner("rustdoc/foo/0.1.0/foo/index.html"),
+            release("foo", "0.1.0", false)
+        );
+        let hash = [0xab; 32];
+        assert_eq!(owner(&blob_path(&hash)), Some(Owner::Blob(hash)));
+
+        for path in [
+            "rustdoc-20220101-1.60.0.css",
+            "build-logs/latest.txt",
+            "rustdoc/foo",
+            "rustdoc/foo/0.1.0.tar",
+            "sources//0.1.0.zip",
+            "blobs/ab/abc",
+        ] {
+            assert_eq!(owner(path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn orphaned_files_are_collected() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .archive_storage(true)
+                .rustdoc_file_with("foo/index.html", b"<html>foo</html>")
+                .create()?;
+
+            let storage = env.storage();
+            let orphans = [
+                "build-logs/999999/x86_64-unknown-linux-gnu.txt",
+                "rustdoc/bar/0.1.0.zip",
+                "rustdoc/foo/0.1.0/foo/index.html",
+                "sources/bar/0.1.0/src/lib.rs",
+            ];
+            for path in orphans {
+                storage.store_one(path, b"orphan".to_vec())?;
+            }
+            storage.store_one("rustdoc-20220101-1.60.0.css", b"css".to_vec())?;
+            storage
+                .index_cache
+                .insert("rustdoc/bar/0.1.0.zip", b"index")?;
+
+            let mut conn = env.db().conn();
+            let options = GarbageCollectionOptions {
+                dry_run: true,
+                min_age: Duration::from_secs(3600),
+                pause: Duration::ZERO,
+            };
+
+            // new files could belong to running builds
+            let report = collect_garbage(&mut conn, &storage, &options)?;
+            assert_eq!(report.orphans, 0);
+
+            let options = GarbageCollectionOptions {
+                min_age: Duration::ZERO,
+                ..options
+            };
+            let report = collect_garbage(&mut conn, &storage, &options)?;
+            assert_eq!(
+                (report.orphans, report.deleted, report.local_indexes),
+                (4, 0, 1)
+            );
+            // files are stored compressed
+            let mut bytes = 0;
+            for path in orphans {
+                bytes += storage.list_page(path, "", 1)?[0].size;
+            }
+            assert_eq!(report.bytes, bytes);
+            assert!(storage.exists(orphans[0])?);
+            assert!(storage.index_cache.contains("rustdoc/bar/0.1.0.zip"));
+
+            let options = GarbageCollectionOptions {
+                dry_run: false,
+                ..options
+            };
+            let report = collect_garbage(&mut conn, &storage, &options)?;
+            assert_eq!(
+                (report.orphans, report.deleted, report.local_indexes),
+                (4, 4, 1)
+            );
+            for path in orphans {
+                assert!(!storage.exists(path)?, "{}", path);
+            }
+            assert!(!storage.index_cache.contains("rustdoc/bar/0.1.0.zip"));
+
+            // the files of the release and its build are kept
+            assert!(storage.exists("rustdoc/foo/0.1.0.zip")?);
+            assert!(storage.exists("sources/foo/0.1.0.zip.index")?);
+            assert!(storage.exists("rustdoc-20220101-1.60.0.css")?);
+            assert_eq!(storage.list_prefix("build-logs/")?.len(), 1);
+
+            let report = collect_garbage(&mut conn, &storage, &options)?;
+            assert_eq!(report.orphans, 0);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn unreferenced_blobs_are_collected() {
+        wrapper(|env| {
+            env.override_config(|config| config.content_addressed_storage = true);
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .archive_storage(true)
+                .rustdoc_file_with("foo/index.html", b"<html>foo</html>")
+                .source_file("src/lib.rs", b"//! foo")
+                .create()?;
+
+            let storage = env.storage();
+            let referenced = storage.list_prefix(BLOB_PREFIX)?;
+            assert!(!referenced.is_empty());
+            let orphan = blob_path(&[0xab; 32]);
+            storage.store_one(&orphan, b"orphan".to_vec())?;
+            // not a blob, so it's kept
+            storage.store_one("blobs/ab/abc", b"unknown".to_vec())?;
+
+            let mut conn = env.db().conn();
+            let options = GarbageCollectionOptions {
+                dry_run: false,
+                min_age: Duration::ZERO,
+                pause: Duration::ZERO,
+            };
+            let report = collect_garbage(&mut conn, &storage, &options)?;
+            assert_eq!((report.orphans, report.deleted), (1, 1));
+            assert!(!storage.exists(&orphan)?);
+            assert!(storage.exists("blobs/ab/abc")?);
+            for path in &referenced {
+                assert!(storage.exists(path)?, "{}", path);
+            }
+            let file =
+                storage.get_from_archive("sources/foo/0.1.0.zip", "src/lib.rs", 1024, None)?;
+            assert_eq!(file.content, b"//! foo");
+
+            // the blobs of deleted releases are orphaned
+            crate::db::delete_crate(&mut conn, &storage, &env.config(), "foo")?;
+            collect_garbage(&mut conn, &storage, &options)?;
+            assert_eq!(storage.list_prefix(BLOB_PREFIX)?, ["blobs/ab/abc"]);
+
+            Ok(())
+        })
+    }
+}
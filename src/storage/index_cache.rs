@@ -0,0 +1,340 @@
+//! The local cache of archive indexes.
+//!
+//! Indexes are fetched from the storage backend on the first lookup in their archive and kept on
+//! disk, within a size budget: the least recently used indexes are deleted when it's exceeded.
+//! The indexes cached by an earlier process are found by scanning the directory in the
+//! background, their recency is approximated by their modification time.
+//!
+//! Which indexes are cached and their size is only tracked in memory, so the budget holds per
+//! process. Processes sharing the directory, like the web server and a builder on the same
+//! machine, don't see each other's indexes until they're restarted, and together can use up to
+//! the budget times the number of processes.
+
+use super::get_file_list;
+use crate::error::Result;
+use crate::utils::report_error;
+use crate::Metrics;
+use anyhow::Context as _;
+use lru::LruCache;
+use path_slash::PathExt;
+use std::{
+    fs,
+    future::Future,
+    io::{self, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+use tokio::task::spawn_blocking;
+
+/// The prefix of the temporary files indexes are written to before they're moved into place.
+const TEMPFILE_PREFIX: &str = ".tmp";
+
+#[derive(Clone)]
+pub(super) struct ArchiveIndexCache {
+    root: PathBuf,
+    max_size: u64,
+    state: Arc<Mutex<State>>,
+    metrics: Arc<Metrics>,
+}
+
+struct State {
+    /// The size of the cached index of each archive path.
+    entries: LruCache<String, u64>,
+    size: u64,
+}
+
+impl ArchiveIndexCache {
+    /// Creates an empty cache, see [`Self::load_existing_in_background`] for the indexes
+    /// already in `root`.
+    pub(super) fn new(root: PathBuf, max_size: u64, metrics: Arc<Metrics>) -> Self {
+        Self {
+            root,
+            max_size,
+            state: Arc::new(Mutex::new(State {
+                entries: LruCache::unbounded(),
+                size: 0,
+            })),
+            metrics,
+        }
+    }
+
+    /// The path of the cached index of an archive.
+    pub(super) fn path(&self, archive_path: &str) -> PathBuf {
+        self.root.join(format!("{}.index", archive_path))
+    }
+
+    pub(super) fn contains(&self, archive_path: &str) -> bool {
+        self.state().entries.contains(archive_path)
+    }
+
+    /// Opens the cached index of an archive, fetching and caching it first if needed.
+    ///
+    /// The returned file stays readable even if the index is evicted in the meantime.
+    pub(super) fn open(
+        &self,
+        archive_path: &str,
+        fetch: impl FnOnce() -> Result<Vec<u8>>,
+    ) -> Result<fs::File> {
+        if let Some(file) = self.open_cached(archive_path)? {
+            return Ok(file);
+        }
+
+        self.metrics.archive_index_cache_misses.inc();
+        let content = fetch()?;
+        self.insert(archive_path, &content)
+    }
+
+    /// Like [`Self::open`], but fetching the index asynchronously and doing the file system
+    /// operations on the blocking thread pool.
+    pub(super) async fn open_async(
+        &self,
+        archive_path: &str,
+        fetch: impl Future<Output = Result<Vec<u8>>>,
+    ) -> Result<fs::File> {
+        let cache = self.clone();
+        let owned_archive_path = archive_path.to_owned();
+        if let Some(file) = spawn_blocking(move || cache.open_cached(&owned_archive_path)).await?? {
+            return Ok(file);
+        }
+
+        self.metrics.archive_index_cache_misses.inc();
+        let content = fetch.await?;
+        let cache = self.clone();
+        let archive_path = archive_path.to_owned();
+        spawn_blocking(move || cache.insert(&archive_path, &content)).await?
+    }
+
+    /// Opens the index of an archive if it's cached.
+    fn open_cached(&self, archive_path: &str) -> Result<Option<fs::File>> {
+        let cached = self.state().entries.get(archive_path).is_some();
+        if cached {
+            match fs::File::open(self.path(archive_path)) {
+                Ok(file) => {
+                    self.metrics.archive_index_cache_hits.inc();
+                    return Ok(Some(file));
+                }
+                // deleted by another process sharing the directory
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Caches the index of an archive, replacing any index cached for it before, and returns it
+    /// opened.
+    pub(super) fn insert(&self, archive_path: &str, content: &[u8]) -> Result<fs::File> {
+        let path = self.path(archive_path);
+        let parent = path.parent().expect("index path without parent");
+        fs::create_dir_all(parent)?;
+
+        // readers never see a partially written index, they either open the old or the new file
+        let mut tempfile = tempfile::Builder::new()
+            .prefix(TEMPFILE_PREFIX)
+            .tempfile_in(parent)?;
+        tempfile.write_all(content)?;
+        let mut file = tempfile.persist(&path)?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut state = self.state();
+        if let Some(old_size) = state
+            .entries
+            .put(archive_path.to_owned(), content.len() as u64)
+        {
+            state.size -= old_size;
+        }
+        state.size += content.len() as u64;
+        self.evict(&mut state)?;
+
+        Ok(file)
+    }
+
+    /// The archive paths of all cached indexes.
+    pub(super) fn archive_paths(&self) -> Vec<String> {
+        self.state()
+            .entries
+            .iter()
+            .map(|(archive_path, _)| archive_path.clone())
+            .collect()
+    }
+
+    /// Deletes the cached index of an archive.
+    pub(super) fn remove(&self, archive_path: &str) -> Result<()> {
+        let mut state = self.state();
+        if let Some(size) = state.entries.pop(archive_path) {
+            state.size -= size;
+            self.update_size(&state);
+        }
+        remove_file(&self.path(archive_path))
+    }
+
+    fn evict(&self, state: &mut State) -> Result<()> {
+        while state.size > self.max_size {
+            let (archive_path, size) = match state.entries.pop_lru() {
+                Some(entry) => entry,
+                None => break,
+            };
+            state.size -= size;
+            remove_file(&self.path(&archive_path))?;
+            self.metrics.archive_index_cache_evictions.inc();
+        }
+        self.update_size(state);
+        Ok(())
+    }
+
+    /// Adds the indexes cached by earlier processes in a background thread, so large caches
+    /// don't delay the startup. Until it's done, they're fetched again when they're used.
+    pub(super) fn load_existing_in_background(&self) -> JoinHandle<()> {
+        let cache = self.clone();
+        thread::spawn(move || {
+            if let Err(err) = cache.load_existing() {
+                report_error(&err.context("failed to load the cached archive indexes"));
+            }
+        })
+    }
+
+    /// Adds the indexes cached by earlier processes as less recently used than the indexes
+    /// cached by this one.
+    fn load_existing(&self) -> Result<()> {
+        if !self.root.is_dir() {
+            return Ok(());
+        }
+
+        let mut existing = Vec::new();
+        for path in get_file_list(&self.root)? {
+            let is_tempfile = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map_or(true, |name| name.starts_with(TEMPFILE_PREFIX));
+            let archive_path = match path.to_slash() {
+                Some(path) if !is_tempfile => match path.strip_suffix(".index") {
+                    Some(archive_path) => archive_path.to_owned(),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            let metadata = match fs::metadata(self.root.join(&path)) {
+                Ok(metadata) => metadata,
+                // evicted or replaced in the meantime
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err).context(path.display().to_string()),
+            };
+            existing.push((metadata.modified()?, archive_path, metadata.len()));
+        }
+        // newest first, every index is moved behind the ones added before
+        existing.sort_by(|a, b| b.cmp(a));
+
+        let mut state = self.state();
+        for (_, archive_path, size) in existing {
+            if state.entries.contains(&archive_path) {
+                continue;
+            }
+            state.entries.put(archive_path.clone(), size);
+            state.entries.demote(&archive_path);
+            state.size += size;
+        }
+        self.evict(&mut state)
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn update_size(&self, state: &State) {
+        self.metrics
+            .archive_index_cache_bytes
+            .set(state.size as i64);
+    }
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    fn read(mut file: fs::File) -> Vec<u8> {
+        let mut content = Vec::new();
+        io::Read::read_to_end(&mut file, &mut content).unwrap();
+        content
+    }
+
+    #[test]
+    fn least_recently_used_indexes_are_evicted() {
+        wrapper(|env| {
+            let dir = tempfile::tempdir()?;
+            let metrics = env.metrics();
+            let cache = ArchiveIndexCache::new(dir.path().into(), 10, metrics.clone());
+            let fetch = |content: &'static [u8]| move || Ok(content.to_vec());
+
+            assert_eq!(read(cache.open("a.zip", fetch(b"aaaa"))?), b"aaaa");
+            assert_eq!(read(cache.open("b.zip", fetch(b"bbbb"))?), b"bbbb");
+            // cached indexes aren't fetched again
+            assert_eq!(read(cache.open("a.zip", || unreachable!())?), b"aaaa");
+            assert_eq!(
+                (
+                    metrics.archive_index_cache_hits.get(),
+                    metrics.archive_index_cache_misses.get()
+                ),
+                (1, 2)
+            );
+
+            // "b" is the least recently used one
+            let file = cache.open("c.zip", fetch(b"cccc"))?;
+            assert_eq!(read(file), b"cccc");
+            assert!(cache.contains("a.zip"));
+            assert!(!cache.contains("b.zip"));
+            assert!(cache.contains("c.zip"));
+            assert_eq!(metrics.archive_index_cache_evictions.get(), 1);
+            assert_eq!(metrics.archive_index_cache_bytes.get(), 8);
+
+            // indexes larger than the whole cache are still returned
+            assert_eq!(read(cache.open("d.zip", fetch(&[b'd'; 20]))?), [b'd'; 20]);
+            assert!(!cache.contains("d.zip"));
+            assert_eq!(metrics.archive_index_cache_bytes.get(), 0);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn existing_indexes_are_loaded() {
+        wrapper(|env| {
+            let dir = tempfile::tempdir()?;
+            let cache = ArchiveIndexCache::new(dir.path().into(), 10, env.metrics());
+            cache.insert("rustdoc/foo/0.1.0.zip", b"foo")?;
+            cache.insert("rustdoc/bar/0.1.0.zip", b"bar")?;
+            // a temporary file left behind by a crashed process
+            fs::write(dir.path().join("rustdoc/foo/.tmp1234"), b"tmp")?;
+            drop(cache);
+
+            let cache = ArchiveIndexCache::new(dir.path().into(), 10, env.metrics());
+            // only the indexes this process knows about count as cached until the scan is done
+            assert!(!cache.contains("rustdoc/foo/0.1.0.zip"));
+            cache.insert("rustdoc/baz/0.1.0.zip", b"baz")?;
+            cache.load_existing_in_background().join().unwrap();
+
+            let file = cache.open("rustdoc/foo/0.1.0.zip", || unreachable!())?;
+            assert_eq!(read(file), b"foo");
+            assert_eq!(env.metrics().archive_index_cache_bytes.get(), 9);
+            // the existing indexes are older than the one added in the meantime
+            cache.insert("rustdoc/qux/0.1.0.zip", b"qux")?;
+            assert!(!cache.contains("rustdoc/bar/0.1.0.zip"));
+            assert!(cache.contains("rustdoc/baz/0.1.0.zip"));
+
+            cache.remove("rustdoc/foo/0.1.0.zip")?;
+            assert!(!cache.contains("rustdoc/foo/0.1.0.zip"));
+            assert!(!cache.path("rustdoc/foo/0.1.0.zip").exists());
+            assert_eq!(env.metrics().archive_index_cache_bytes.get(), 6);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,188 @@
+//! Converting the indexes of existing archives to the current index format.
+//!
+//! The format of an index is only known after fetching it, so releases are processed in the
+//! order of their ids and every report contains the last one, to continue after it when the job
+//! is started again.
+
+use super::{rustdoc_archive_path, source_archive_path, ReleaseBatchOptions, ReleaseBatches};
+use crate::error::Result;
+use crate::utils::report_error;
+use crate::Storage;
+use anyhow::Context as _;
+use log::info;
+use postgres::Client;
+use std::fmt;
+
+/// The outcome of a conversion run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct IndexConversionReport {
+    pub releases: usize,
+    pub converted: usize,
+    pub failed: usize,
+    /// The id of the last release that was processed.
+    pub last_release_id: Option<i32>,
+}
+
+impl fmt::Display for IndexConversionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "converted {} indexes of {} releases, {} releases failed",
+            self.converted, self.releases, self.failed
+        )?;
+        if let Some(id) = self.last_release_id {
+            write!(f, "\nlast release id: {}", id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts the indexes of all archives still in the legacy format, one release after another.
+pub fn convert_archive_indexes(
+    conn: &mut Client,
+    storage: &Storage,
+    options: &ReleaseBatchOptions,
+) -> Result<IndexConversionReport> {
+    let mut report = IndexConversionReport::default();
+    let mut releases = ReleaseBatches::new(
+        "SELECT releases.id, crates.name, releases.version
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE
+            releases.archive_storage AND
+            releases.id > $1
+         ORDER BY releases.id
+         LIMIT $2",
+        Vec::new(),
+        options,
+    );
+
+    while let Some(row) = releases.next(conn)? {
+        let name: String = row.get("name");
+        let version: String = row.get("version");
+
+        match convert_release(storage, &name, &version)
+            .with_context(|| format!("failed to convert the indexes of {} {}", name, version))
+        {
+            Ok(converted) => {
+                info!("converted {} indexes of {} {}", converted, name, version);
+                report.releases += 1;
+                report.converted += converted;
+            }
+            Err(err) => {
+                report.failed += 1;
+                report_error(&err);
+            }
+        }
+        report.last_release_id = Some(row.get("id"));
+    }
+
+    Ok(report)
+}
+
+fn convert_release(storage: &Storage, name: &str, version: &str) -> Result<usize> {
+    let mut converted = 0;
+    for archive_path in [
+        rustdoc_archive_path(name, version),
+        source_archive_path(name, version),
+    ] {
+        // releases without documentation only have a source archive
+        if !storage.exists(&archive_path)? {
+            continue;
+        }
+        if storage.convert_archive_index(&archive_path)? {
+            converted += 1;
+        }
+    }
+    Ok(converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{archive_index, compress, Blob, CompressionAlgorithm};
+    use crate::test::wrapper;
+    use chrono::Utc;
+    use std::collections::HashMap;
+
+    #[derive(serde::Serialize)]
+    struct LegacyIndex {
+        files: HashMap<String, archive_index::FileInfo>,
+    }
+
+    /// Replaces the index of the archive with one in the legacy format.
+    fn store_legacy_index(storage: &Storage, archive_path: &str) -> Result<Vec<u8>> {
+        let index = storage.get(&format!("{}.index", archive_path), usize::MAX)?;
+        let mut files = HashMap::new();
+        for entry in archive_index::list_dir_in_slice(&index.content, "")? {
+            if let Some(archive_index::Entry::Archived(info)) =
+                archive_index::find_in_slice(&index.content, &entry.name)?
+            {
+                files.insert(entry.name, info);
+            }
+        }
+        let legacy = serde_cbor::to_vec(&LegacyIndex { files })?;
+
+        storage.store_blobs(vec![Blob {
+            path: format!("{}.index", archive_path),
+            mime: "application/octet-stream".into(),
+            content: compress(&*legacy, CompressionAlgorithm::default())?,
+            compression: Some(CompressionAlgorithm::default()),
+            date_updated: Utc::now(),
+        }])?;
+        storage.index_cache.remove(archive_path)?;
+        Ok(legacy)
+    }
+
+    #[test]
+    fn legacy_indexes_are_converted() {
+        wrapper(|env| {
+            env.override_config(|config| config.sorted_archive_indexes = true);
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .archive_storage(true)
+                .source_file("lib.rs", b"//! foo")
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("0.1.0")
+                .archive_storage(true)
+                .create()?;
+
+            let storage = env.storage();
+            let legacy = store_legacy_index(&storage, "sources/foo/0.1.0.zip")?;
+
+            // legacy indexes are still read
+            let file = storage.get_from_archive("sources/foo/0.1.0.zip", "lib.rs", 1024, None)?;
+            assert_eq!(file.content, b"//! foo");
+
+            let options = ReleaseBatchOptions::default();
+            let mut conn = env.db().conn();
+            let report = convert_archive_indexes(&mut conn, &storage, &options)?;
+            assert_eq!(
+                (report.releases, report.converted, report.failed),
+                (2, 1, 0)
+            );
+
+            let index = storage.get("sources/foo/0.1.0.zip.index", usize::MAX)?;
+            assert_ne!(index.content, legacy);
+            assert!(archive_index::convert(&index.content)?.is_none());
+            let file = storage.get_from_archive("sources/foo/0.1.0.zip", "lib.rs", 1024, None)?;
+            assert_eq!(file.content, b"//! foo");
+
+            // later runs continue after the last release
+            let report = convert_archive_indexes(
+                &mut conn,
+                &storage,
+                &ReleaseBatchOptions {
+                    start_after: report.last_release_id.unwrap(),
+                    ..options
+                },
+            )?;
+            assert_eq!(report, IndexConversionReport::default());
+
+            Ok(())
+        })
+    }
+}
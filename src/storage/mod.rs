@@ -1,28 +1,67 @@
 mod archive_index;
+mod archive_migration;
 mod compression;
 mod database;
+mod dedup;
+mod garbage_collection;
+mod index_cache;
+mod index_conversion;
+mod recompress;
+mod release_batches;
 mod s3;
+mod usage;
+mod verify;
 
+pub(crate) use self::archive_index::DirEntry;
+pub use self::archive_migration::{migrate_to_archive_storage, ArchiveMigrationReport};
 pub use self::compression::{compress, decompress, CompressionAlgorithm, CompressionAlgorithms};
 use self::database::DatabaseBackend;
+pub use self::dedup::{dedup_report, DedupReport};
+pub use self::garbage_collection::{
+    collect_garbage, GarbageCollectionOptions, GarbageCollectionReport,
+};
+use self::index_cache::ArchiveIndexCache;
+pub use self::index_conversion::{convert_archive_indexes, IndexConversionReport};
+pub use self::recompress::{recompress_archives, RecompressOptions, RecompressReport};
+pub use self::release_batches::ReleaseBatchOptions;
+use self::release_batches::ReleaseBatches;
 use self::s3::S3Backend;
+pub(crate) use self::usage::{
+    add_storage_usage, forget_blob_usage, record_storage_usage, storage_usage_totals, UsageKind,
+};
+pub use self::usage::{
+    backfill_storage_usage, storage_usage_report, ArchiveUsage, CrateStorageUsage, StorageSize,
+    StorageUsageReport, StorageUsageTotal, UsageBackfillReport,
+};
+pub use self::verify::{verify_storage, VerifyOptions, VerifyReport};
 use crate::error::Result;
 use crate::web::metrics::RenderingTimesRecorder;
 use crate::{db::Pool, Config, Metrics};
-use anyhow::{anyhow, ensure};
+use anyhow::ensure;
+use archive_index::{ContentHash, Entry};
 use chrono::{DateTime, Utc};
+use log::info;
 use path_slash::PathExt;
+use sha2::{Digest, Sha256};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     ffi::OsStr,
     fmt, fs,
-    io::{self, Write},
+    io::{self, Read, Seek, SeekFrom, Write},
     ops::RangeInclusive,
     path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, Instant},
 };
+use tokio::task::spawn_blocking;
 
 const MAX_CONCURRENT_UPLOADS: usize = 1000;
+/// The size of the chunks large files are uploaded in, see [`StorageTransaction::store_file`].
+/// S3 requires at least 5 MiB for all but the last part of an upload.
+const UPLOAD_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+/// How many files the storage jobs list from the storage backend at once, see
+/// [`Storage::list_page`].
+const LIST_PAGE_SIZE: usize = 1000;
 
 type FileRange = RangeInclusive<u64>;
 
@@ -30,6 +69,12 @@ type FileRange = RangeInclusive<u64>;
 #[error("path not found")]
 pub(crate) struct PathNotFoundError;
 
+/// The entry the index points to can't be read from the archive, because the archive was replaced
+/// after the index was fetched or is corrupt.
+#[derive(Debug, thiserror::Error)]
+#[error("the archive doesn't match its index")]
+pub(crate) struct ArchiveIndexMismatchError;
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub(crate) struct Blob {
     pub(crate) path: String,
@@ -39,6 +84,14 @@ pub(crate) struct Blob {
     pub(crate) compression: Option<CompressionAlgorithm>,
 }
 
+/// A file in the storage backend, as it's listed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StoredObject {
+    pub(crate) path: String,
+    pub(crate) size: u64,
+    pub(crate) date_updated: DateTime<Utc>,
+}
+
 impl Blob {
     pub(crate) fn is_empty(&self) -> bool {
         self.mime == "application/x-empty"
@@ -109,13 +162,22 @@ enum StorageBackend {
 
 pub struct Storage {
     backend: StorageBackend,
+    index_cache: ArchiveIndexCache,
     config: Arc<Config>,
 }
 
 impl Storage {
     pub fn new(pool: Pool, metrics: Arc<Metrics>, config: Arc<Config>) -> Result<Self> {
+        let index_cache = ArchiveIndexCache::new(
+            config.local_archive_cache_path.clone(),
+            config.local_archive_cache_size,
+            metrics.clone(),
+        );
+        index_cache.load_existing_in_background();
+
         Ok(Storage {
             config: config.clone(),
+            index_cache,
             backend: match config.storage_backend {
                 StorageKind::Database => {
                     StorageBackend::Database(DatabaseBackend::new(pool, metrics))
@@ -125,6 +187,14 @@ impl Storage {
         })
     }
 
+    /// The name of the storage backend, as configured in `DOCSRS_STORAGE_BACKEND`.
+    pub(crate) fn backend_name(&self) -> &'static str {
+        match &self.backend {
+            StorageBackend::Database(_) => "database",
+            StorageBackend::S3(_) => "s3",
+        }
+    }
+
     pub(crate) fn exists(&self, path: &str) -> Result<bool> {
         match &self.backend {
             StorageBackend::Database(db) => db.exists(path),
@@ -132,6 +202,35 @@ impl Storage {
         }
     }
 
+    pub(crate) async fn exists_async(&self, path: &str) -> Result<bool> {
+        match &self.backend {
+            StorageBackend::Database(db) => db.exists_async(path).await,
+            StorageBackend::S3(s3) => s3.exists_async(path).await,
+        }
+    }
+
+    /// Lists the paths of all files starting with `prefix`.
+    pub(crate) fn list_prefix(&self, prefix: &str) -> Result<Vec<String>> {
+        match &self.backend {
+            StorageBackend::Database(db) => db.list_prefix(prefix),
+            StorageBackend::S3(s3) => s3.list_prefix(prefix),
+        }
+    }
+
+    /// Lists at most `limit` files starting with `prefix` whose path sorts after `start_after`,
+    /// in the order of their paths.
+    pub(crate) fn list_page(
+        &self,
+        prefix: &str,
+        start_after: &str,
+        limit: usize,
+    ) -> Result<Vec<StoredObject>> {
+        match &self.backend {
+            StorageBackend::Database(db) => db.list_page(prefix, start_after, limit),
+            StorageBackend::S3(s3) => s3.list_page(prefix, start_after, limit),
+        }
+    }
+
     fn max_file_size_for(&self, path: &str) -> usize {
         if path.ends_with(".html") {
             self.config.max_file_size_html
@@ -140,30 +239,32 @@ impl Storage {
         }
     }
 
-    pub(crate) fn fetch_rustdoc_file(
+    pub(crate) async fn fetch_rustdoc_file(
         &self,
         name: &str,
         version: &str,
         path: &str,
         archive_storage: bool,
-        fetch_time: &mut RenderingTimesRecorder,
+        fetch_time: &mut RenderingTimesRecorder<'_>,
     ) -> Result<Blob> {
         Ok(if archive_storage {
-            self.get_from_archive(
+            self.get_from_archive_async(
                 &rustdoc_archive_path(name, version),
                 path,
                 self.max_file_size_for(path),
                 Some(fetch_time),
-            )?
+            )
+            .await?
         } else {
             fetch_time.step("fetch from storage");
             // Add rustdoc prefix, name and version to the path for accessing the file stored in the database
             let remote_path = format!("rustdoc/{}/{}/{}", name, version, path);
-            self.get(&remote_path, self.max_file_size_for(path))?
+            self.get_async(&remote_path, self.max_file_size_for(path))
+                .await?
         })
     }
 
-    pub(crate) fn fetch_source_file(
+    pub(crate) async fn fetch_source_file(
         &self,
         name: &str,
         version: &str,
@@ -171,18 +272,21 @@ impl Storage {
         archive_storage: bool,
     ) -> Result<Blob> {
         Ok(if archive_storage {
-            self.get_from_archive(
+            self.get_from_archive_async(
                 &source_archive_path(name, version),
                 path,
                 self.max_file_size_for(path),
                 None,
-            )?
+            )
+            .await?
         } else {
             let remote_path = format!("sources/{}/{}/{}", name, version, path);
-            self.get(&remote_path, self.max_file_size_for(path))?
+            self.get_async(&remote_path, self.max_file_size_for(path))
+                .await?
         })
     }
 
+    #[cfg(test)]
     pub(crate) fn rustdoc_file_exists(
         &self,
         name: &str,
@@ -199,9 +303,44 @@ impl Storage {
         })
     }
 
+    pub(crate) async fn rustdoc_file_exists_async(
+        &self,
+        name: &str,
+        version: &str,
+        path: &str,
+        archive_storage: bool,
+    ) -> Result<bool> {
+        Ok(if archive_storage {
+            self.exists_in_archive_async(&rustdoc_archive_path(name, version), path)
+                .await?
+        } else {
+            // Add rustdoc prefix, name and version to the path for accessing the file stored in the database
+            let remote_path = format!("rustdoc/{}/{}/{}", name, version, path);
+            self.exists_async(&remote_path).await?
+        })
+    }
+
+    #[cfg(test)]
     pub(crate) fn exists_in_archive(&self, archive_path: &str, path: &str) -> Result<bool> {
-        match self.get_index_filename(archive_path) {
-            Ok(index_filename) => Ok(archive_index::find_in_file(index_filename, path)?.is_some()),
+        match self.open_index(archive_path) {
+            Ok(index) => Ok(archive_index::find_in_file(&index, path)?.is_some()),
+            Err(err) => {
+                if err.downcast_ref::<PathNotFoundError>().is_some() {
+                    Ok(false)
+                } else {
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    pub(crate) async fn exists_in_archive_async(
+        &self,
+        archive_path: &str,
+        path: &str,
+    ) -> Result<bool> {
+        match self.open_index_async(archive_path).await {
+            Ok(index) => Ok(find_in_file_async(index, path).await?.is_some()),
             Err(err) => {
                 if err.downcast_ref::<PathNotFoundError>().is_some() {
                     Ok(false)
@@ -212,16 +351,32 @@ impl Storage {
         }
     }
 
+    /// Lists the files and directories directly inside `dir` of the archive, see
+    /// [`archive_index::list_dir_in_slice`].
+    pub(crate) async fn list_archive_dir(
+        &self,
+        archive_path: &str,
+        dir: &str,
+    ) -> Result<Vec<DirEntry>> {
+        let index = self.open_index_async(archive_path).await?;
+        let dir = dir.to_owned();
+        spawn_blocking(move || archive_index::list_dir_in_file(&index, &dir)).await?
+    }
+
     pub(crate) fn get(&self, path: &str, max_size: usize) -> Result<Blob> {
-        let mut blob = match &self.backend {
+        let blob = match &self.backend {
             StorageBackend::Database(db) => db.get(path, max_size, None),
             StorageBackend::S3(s3) => s3.get(path, max_size, None),
         }?;
-        if let Some(alg) = blob.compression {
-            blob.content = decompress(blob.content.as_slice(), alg, max_size)?;
-            blob.compression = None;
-        }
-        Ok(blob)
+        decompress_blob(blob, max_size)
+    }
+
+    pub(crate) async fn get_async(&self, path: &str, max_size: usize) -> Result<Blob> {
+        let blob = match &self.backend {
+            StorageBackend::Database(db) => db.get_async(path, max_size, None).await,
+            StorageBackend::S3(s3) => s3.get_async(path, max_size, None).await,
+        }?;
+        decompress_blob(blob, max_size)
     }
 
     pub(super) fn get_range(
@@ -231,41 +386,57 @@ impl Storage {
         range: FileRange,
         compression: Option<CompressionAlgorithm>,
     ) -> Result<Blob> {
-        let mut blob = match &self.backend {
+        let len = range.end() - range.start() + 1;
+        let blob = match &self.backend {
             StorageBackend::Database(db) => db.get(path, max_size, Some(range)),
             StorageBackend::S3(s3) => s3.get(path, max_size, Some(range)),
         }?;
-        // `compression` represents the compression of the file-stream inside the archive.
-        // We don't compress the whole archive, so the encoding of the archive's blob is irrelevant
-        // here.
-        if let Some(alg) = compression {
-            blob.content = decompress(blob.content.as_slice(), alg, max_size)?;
-            blob.compression = None;
-        }
-        Ok(blob)
+        decompress_range(blob, len, max_size, compression)
     }
 
-    fn get_index_filename(&self, archive_path: &str) -> Result<PathBuf> {
-        // remote/folder/and/x.zip.index
-        let remote_index_path = format!("{}.index", archive_path);
-        let local_index_path = self
-            .config
-            .local_archive_cache_path
-            .join(&remote_index_path);
+    async fn get_range_async(
+        &self,
+        path: &str,
+        max_size: usize,
+        range: FileRange,
+        compression: Option<CompressionAlgorithm>,
+    ) -> Result<Blob> {
+        let len = range.end() - range.start() + 1;
+        let blob = match &self.backend {
+            StorageBackend::Database(db) => db.get_async(path, max_size, Some(range)).await,
+            StorageBackend::S3(s3) => s3.get_async(path, max_size, Some(range)).await,
+        }?;
+        decompress_range(blob, len, max_size, compression)
+    }
+
+    #[cfg(test)]
+    fn local_index_path(&self, archive_path: &str) -> PathBuf {
+        self.index_cache.path(archive_path)
+    }
 
-        if !local_index_path.exists() {
-            let index_content = self.get(&remote_index_path, std::usize::MAX)?.content;
+    /// Opens the index of the archive from the local cache, fetching it first if needed.
+    fn open_index(&self, archive_path: &str) -> Result<fs::File> {
+        self.index_cache
+            .open(archive_path, || self.get_remote_index(archive_path))
+    }
 
-            fs::create_dir_all(
-                local_index_path
-                    .parent()
-                    .ok_or_else(|| anyhow!("index path without parent"))?,
-            )?;
-            let mut file = fs::File::create(&local_index_path)?;
-            file.write_all(&index_content)?;
-        }
+    async fn open_index_async(&self, archive_path: &str) -> Result<fs::File> {
+        self.index_cache
+            .open_async(archive_path, async {
+                let remote_index_path = format!("{}.index", archive_path);
+                Ok(self
+                    .get_async(&remote_index_path, std::usize::MAX)
+                    .await?
+                    .content)
+            })
+            .await
+    }
 
-        Ok(local_index_path)
+    /// Fetches the index of the archive from the storage backend, bypassing the local cache.
+    pub(crate) fn get_remote_index(&self, archive_path: &str) -> Result<Vec<u8>> {
+        // remote/folder/and/x.zip.index
+        let remote_index_path = format!("{}.index", archive_path);
+        Ok(self.get(&remote_index_path, std::usize::MAX)?.content)
     }
 
     pub(crate) fn get_from_archive(
@@ -278,34 +449,147 @@ impl Storage {
         if let Some(ref mut t) = fetch_time {
             t.step("find path in index");
         }
-        let info = archive_index::find_in_file(self.get_index_filename(archive_path)?, path)?
+        let cached_index = self.index_cache.contains(archive_path);
+        let index = self.open_index(archive_path)?;
+        let entry = archive_index::find_in_file(&index, path)?.ok_or(PathNotFoundError)?;
+
+        match self.get_archive_entry(archive_path, path, entry, max_size, fetch_time) {
+            // The archive was rewritten or replaced by content-addressed blobs after its index
+            // was cached locally, so the cached index has to be replaced. Any other error is
+            // returned as is, a fresh index wouldn't help with it.
+            Err(err)
+                if cached_index
+                    && (err.is::<ArchiveIndexMismatchError>() || err.is::<PathNotFoundError>()) =>
+            {
+                self.index_cache.remove(archive_path)?;
+                self.get_from_archive(archive_path, path, max_size, None)
+            }
+            result => result,
+        }
+    }
+
+    pub(crate) async fn get_from_archive_async(
+        &self,
+        archive_path: &str,
+        path: &str,
+        max_size: usize,
+        mut fetch_time: Option<&mut RenderingTimesRecorder<'_>>,
+    ) -> Result<Blob> {
+        if let Some(ref mut t) = fetch_time {
+            t.step("find path in index");
+        }
+        let cached_index = self.index_cache.contains(archive_path);
+        let index = self.open_index_async(archive_path).await?;
+        let entry = find_in_file_async(index, path)
+            .await?
             .ok_or(PathNotFoundError)?;
 
-        if let Some(t) = fetch_time {
-            t.step("range request");
+        match self
+            .get_archive_entry_async(archive_path, path, entry, max_size, fetch_time)
+            .await
+        {
+            // Like in `get_from_archive`, a stale cached index is replaced once.
+            Err(err)
+                if cached_index
+                    && (err.is::<ArchiveIndexMismatchError>() || err.is::<PathNotFoundError>()) =>
+            {
+                self.index_cache.remove(archive_path)?;
+                let index = self.open_index_async(archive_path).await?;
+                let entry = find_in_file_async(index, path)
+                    .await?
+                    .ok_or(PathNotFoundError)?;
+                self.get_archive_entry_async(archive_path, path, entry, max_size, None)
+                    .await
+            }
+            result => result,
         }
-        let blob = self.get_range(
-            archive_path,
-            max_size,
-            info.range(),
-            Some(info.compression()),
-        )?;
-        assert_eq!(blob.compression, None);
+    }
 
-        Ok(Blob {
-            path: format!("{}/{}", archive_path, path),
-            mime: detect_mime(&path).into(),
-            date_updated: blob.date_updated,
-            content: blob.content,
-            compression: None,
-        })
+    /// Reads a file of the archive using `index` instead of the locally cached index.
+    pub(crate) fn get_from_archive_with_index(
+        &self,
+        archive_path: &str,
+        index: &[u8],
+        path: &str,
+        max_size: usize,
+    ) -> Result<Blob> {
+        let entry = archive_index::find_in_slice(index, path)?.ok_or(PathNotFoundError)?;
+        self.get_archive_entry(archive_path, path, entry, max_size, None)
+    }
+
+    fn get_archive_entry(
+        &self,
+        archive_path: &str,
+        path: &str,
+        entry: Entry,
+        max_size: usize,
+        fetch_time: Option<&mut RenderingTimesRecorder>,
+    ) -> Result<Blob> {
+        let blob = match entry {
+            Entry::Archived(info) => {
+                if let Some(t) = fetch_time {
+                    t.step("range request");
+                }
+                self.get_range(
+                    archive_path,
+                    max_size,
+                    info.range(),
+                    Some(info.compression()),
+                )?
+            }
+            Entry::Blob(hash) => {
+                if let Some(t) = fetch_time {
+                    t.step("fetch blob");
+                }
+                self.get(&dedup::blob_path(&hash), max_size)?
+            }
+        };
+        Ok(archive_entry_blob(archive_path, path, blob))
+    }
+
+    async fn get_archive_entry_async(
+        &self,
+        archive_path: &str,
+        path: &str,
+        entry: Entry,
+        max_size: usize,
+        fetch_time: Option<&mut RenderingTimesRecorder<'_>>,
+    ) -> Result<Blob> {
+        let blob = match entry {
+            Entry::Archived(info) => {
+                if let Some(t) = fetch_time {
+                    t.step("range request");
+                }
+                self.get_range_async(
+                    archive_path,
+                    max_size,
+                    info.range(),
+                    Some(info.compression()),
+                )
+                .await?
+            }
+            Entry::Blob(hash) => {
+                if let Some(t) = fetch_time {
+                    t.step("fetch blob");
+                }
+                self.get_async(&dedup::blob_path(&hash), max_size).await?
+            }
+        };
+        Ok(archive_entry_blob(archive_path, path, blob))
     }
 
+    /// Stores all files in `root_dir` as the archive at `archive_path`, or as content-addressed
+    /// blobs if that's enabled, returning the size of the files and how much space they take in
+    /// the storage backend.
     pub(crate) fn store_all_in_archive(
         &self,
         archive_path: &str,
         root_dir: &Path,
-    ) -> Result<(HashMap<PathBuf, String>, CompressionAlgorithm)> {
+    ) -> Result<(HashMap<PathBuf, String>, CompressionAlgorithm, ArchiveUsage)> {
+        if self.config.content_addressed_storage {
+            return self.store_all_content_addressed(archive_path, root_dir);
+        }
+
         let mut file_paths = HashMap::new();
 
         // We are only using the `zip` library to create the archives and the matching
@@ -317,65 +601,298 @@ impl Storage {
         // For decompression we are sharing the compression algorithms defined in
         // `storage::compression`. So every new algorithm to be used inside ZIP archives
         // also has to be added as supported algorithm for storage compression, together
-        // with a mapping in `storage::archive_index::Index::new_from_zip`.
+        // with a mapping in `CompressionAlgorithm::from_zip_method`.
 
-        let options =
-            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Bzip2);
+        let file_alg = self.config.archive_compression;
+        let options = zip::write::FileOptions::default().compression_method(file_alg.zip_method());
 
-        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        // the archive is written to a temporary file, the documentation can be larger than the
+        // memory of the build server
+        let mut zip = zip::ZipWriter::new(tempfile::tempfile()?);
+        let mut uncompressed = 0;
         for file_path in get_file_list(root_dir)? {
             let mut file = fs::File::open(root_dir.join(&file_path))?;
 
             zip.start_file(file_path.to_str().unwrap(), options)?;
-            io::copy(&mut file, &mut zip)?;
+            uncompressed += io::copy(&mut file, &mut zip)?;
 
             let mime = detect_mime(&file_path);
             file_paths.insert(file_path, mime.to_string());
         }
 
-        let mut zip_content = zip.finish()?.into_inner();
-        let mut index_content = vec![];
-        archive_index::create(&mut io::Cursor::new(&mut zip_content), &mut index_content)?;
-        let alg = CompressionAlgorithm::default();
-        let compressed_index_content = compress(&index_content[..], alg)?;
+        let compressed = self.store_archive(archive_path, zip.finish()?)?;
 
-        let remote_index_path = format!("{}.index", &archive_path);
+        Ok((
+            file_paths,
+            file_alg,
+            ArchiveUsage {
+                size: StorageSize {
+                    compressed,
+                    uncompressed,
+                },
+                blobs: Vec::new(),
+            },
+        ))
+    }
 
-        // additionally store the index in the local cache, so it's directly available
-        let local_index_path = self
-            .config
-            .local_archive_cache_path
-            .join(&remote_index_path);
-        if local_index_path.exists() {
-            fs::remove_file(&local_index_path)?;
-        }
-        fs::create_dir_all(local_index_path.parent().unwrap())?;
-        let mut local_index_file = fs::File::create(&local_index_path)?;
-        local_index_file.write_all(&index_content)?;
+    /// Stores every file in `root_dir` as a blob named after the hash of its content, and a
+    /// manifest of them in place of the index of the archive at `archive_path`.
+    ///
+    /// The size of the release is the size of its manifest, the blobs are listed separately since
+    /// other releases can share them.
+    fn store_all_content_addressed(
+        &self,
+        archive_path: &str,
+        root_dir: &Path,
+    ) -> Result<(HashMap<PathBuf, String>, CompressionAlgorithm, ArchiveUsage)> {
+        let alg = self.config.archive_compression;
+        let mut file_paths = HashMap::new();
+        let mut manifest = BTreeMap::new();
+        // the blobs already stored for this release, for files with the same content
+        let mut stored = HashSet::new();
+        let mut usage = ArchiveUsage::default();
+
+        // the files are read while they're uploaded, instead of all of them at once
+        let mut new_blob = |file_path: PathBuf| -> Result<Option<Blob>> {
+            let content = fs::read(root_dir.join(&file_path))?;
+            let hash: ContentHash = Sha256::digest(&content).into();
+            manifest.insert(file_path.to_str().unwrap().to_owned(), hash);
+            let mime = detect_mime(&file_path);
+            file_paths.insert(file_path, mime.to_string());
 
-        self.store_inner(
-            vec![
-                Blob {
-                    path: archive_path.to_string(),
-                    mime: "application/zip".to_owned(),
-                    content: zip_content,
-                    compression: None,
-                    date_updated: Utc::now(),
-                },
-                Blob {
-                    path: remote_index_path,
-                    mime: "application/octet-stream".to_owned(),
-                    content: compressed_index_content,
-                    compression: Some(alg),
-                    date_updated: Utc::now(),
+            let blob_path = dedup::blob_path(&hash);
+            if !stored.insert(blob_path.clone()) {
+                return Ok(None);
+            }
+            // blobs are put even if another release already stored them, instead of checking
+            // for each of them first: storing a blob again is harmless, and refreshing its date
+            // keeps the garbage collection from deleting it before the manifest is stored
+            let uncompressed = content.len() as u64;
+            let content = compress(&*content, alg)?;
+            usage.blobs.push((
+                blob_path.clone(),
+                StorageSize {
+                    compressed: content.len() as u64,
+                    uncompressed,
                 },
-            ]
-            .into_iter()
-            .map(Ok),
+            ));
+            Ok(Some(Blob {
+                path: blob_path,
+                mime: "application/octet-stream".to_owned(),
+                content,
+                compression: Some(alg),
+                date_updated: Utc::now(),
+            }))
+        };
+        // the blobs have to be stored before the manifest referencing them
+        self.store_inner(
+            get_file_list(root_dir)?
+                .into_iter()
+                .filter_map(|file_path| new_blob(file_path).transpose()),
+        )?;
+        info!(
+            "storing {} files of {} in {} blobs",
+            file_paths.len(),
+            archive_path,
+            usage.blobs.len()
+        );
+
+        let mut manifest_content = Vec::new();
+        archive_index::create_manifest(&manifest, &mut manifest_content)?;
+        let manifest_blob = index_blob(archive_path, &manifest_content)?;
+        usage.size = StorageSize {
+            compressed: manifest_blob.content.len() as u64,
+            uncompressed: manifest_content.len() as u64,
+        };
+        self.store_inner(std::iter::once(Ok(manifest_blob)))?;
+        self.index_cache.insert(archive_path, &manifest_content)?;
+        // an archive of an earlier build of the release isn't used anymore
+        self.delete_paths(&[archive_path.to_owned()])?;
+
+        Ok((file_paths, alg, usage))
+    }
+
+    /// Packs the files stored separately under `prefix` into an archive at `archive_path`, the
+    /// same way `store_all_in_archive` stores a fresh build, returning how many files were
+    /// packed and the algorithm used inside the archive, or `None` if there are no files under
+    /// `prefix`.
+    ///
+    /// Every file is read back from the stored archive and compared to the original. The original
+    /// files are kept, deleting them is up to the caller once the release uses the archive.
+    pub(crate) fn pack_into_archive(
+        &self,
+        prefix: &str,
+        archive_path: &str,
+    ) -> Result<Option<(usize, CompressionAlgorithm)>> {
+        let paths = self.list_prefix(prefix)?;
+        if paths.is_empty() {
+            return Ok(None);
+        }
+
+        let dir = tempfile::tempdir()?;
+        for path in &paths {
+            let local_path = dir.path().join(&path[prefix.len()..]);
+            fs::create_dir_all(local_path.parent().unwrap())?;
+            fs::write(local_path, self.get(path, std::usize::MAX)?.content)?;
+        }
+
+        let (file_paths, alg, _) = self.store_all_in_archive(archive_path, dir.path())?;
+        ensure!(
+            file_paths.len() == paths.len(),
+            "packed {} of {} files into {}",
+            file_paths.len(),
+            paths.len(),
+            archive_path
+        );
+
+        for file_path in file_paths.keys() {
+            let path = file_path.to_str().unwrap();
+            let original = fs::read(dir.path().join(file_path))?;
+            let archived = self.get_from_archive(archive_path, path, std::usize::MAX, None)?;
+            ensure!(
+                archived.content == original,
+                "{} in {} differs from the original file",
+                path,
+                archive_path
+            );
+        }
+
+        Ok(Some((paths.len(), alg)))
+    }
+
+    /// Rewrites the archive with all entries compressed with `alg`, returning its size and how
+    /// long decompressing all entries took before and after, or `None` if the archive only
+    /// contains entries compressed with `alg` already.
+    ///
+    /// The archive and its index are replaced in one transaction on the database backend. On S3
+    /// they're two separate uploads, so until both are done readers can see the new archive
+    /// with the old index. Reading an entry then fails with an [`ArchiveIndexMismatchError`]
+    /// instead of returning the wrong content, and readers using a cached copy of the old index
+    /// fetch the new one when that happens.
+    pub(crate) fn recompress_archive(
+        &self,
+        archive_path: &str,
+        alg: CompressionAlgorithm,
+    ) -> Result<Option<RecompressedArchive>> {
+        let archive_file = self.download_to_tempfile(archive_path)?;
+        let size_before = archive_file.metadata()?.len();
+        let mut archive = zip::ZipArchive::new(io::BufReader::new(archive_file))?;
+
+        let mut needs_recompression = false;
+        for index in 0..archive.len() {
+            if archive.by_index_raw(index)?.compression() != alg.zip_method() {
+                needs_recompression = true;
+                break;
+            }
+        }
+        if !needs_recompression {
+            return Ok(None);
+        }
+
+        let options = zip::write::FileOptions::default().compression_method(alg.zip_method());
+        let mut zip = zip::ZipWriter::new(tempfile::tempfile()?);
+        let mut buffer = Vec::new();
+        let mut time_before = Duration::ZERO;
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            let name = entry.name().to_owned();
+            if entry.is_dir() {
+                zip.add_directory(name, options)?;
+                continue;
+            }
+
+            buffer.clear();
+            let start = Instant::now();
+            entry.read_to_end(&mut buffer)?;
+            time_before += start.elapsed();
+
+            zip.start_file(name, options)?;
+            zip.write_all(&buffer)?;
+        }
+        let new_file = zip.finish()?;
+
+        // reading every entry again also verifies their checksums before replacing the archive
+        let mut new_archive = zip::ZipArchive::new(io::BufReader::new(&new_file))?;
+        let start = Instant::now();
+        for index in 0..new_archive.len() {
+            io::copy(&mut new_archive.by_index(index)?, &mut io::sink())?;
+        }
+        let time_after = start.elapsed();
+
+        let stats = RecompressedArchive {
+            size_before,
+            size_after: new_file.metadata()?.len(),
+            time_before,
+            time_after,
+        };
+        self.store_archive(archive_path, new_file)?;
+        Ok(Some(stats))
+    }
+
+    /// Downloads the uncompressed file at `path` into a temporary file, in chunks of
+    /// [`UPLOAD_CHUNK_SIZE`] instead of loading it into memory.
+    fn download_to_tempfile(&self, path: &str) -> Result<fs::File> {
+        let size = match self.list_page(path, "", 1)?.pop() {
+            Some(object) if object.path == path => object.size,
+            _ => return Err(PathNotFoundError.into()),
+        };
+
+        let mut file = tempfile::tempfile()?;
+        let mut start = 0;
+        while start < size {
+            let end = (start + UPLOAD_CHUNK_SIZE as u64).min(size) - 1;
+            let chunk = self.get_range(path, UPLOAD_CHUNK_SIZE, start..=end, None)?;
+            file.write_all(&chunk.content)?;
+            start = end + 1;
+        }
+        file.seek(SeekFrom::Start(0))?;
+        Ok(file)
+    }
+
+    /// Stores the archive together with its index, which is also added to the local cache,
+    /// returning the size of both in the storage backend.
+    fn store_archive(&self, archive_path: &str, mut zip_file: fs::File) -> Result<u64> {
+        let mut index_content = vec![];
+        archive_index::create(
+            &mut zip_file,
+            &mut index_content,
+            self.config.sorted_archive_indexes,
         )?;
+        let index_blob = index_blob(archive_path, &index_content)?;
+        let size = zip_file.metadata()?.len() + index_blob.content.len() as u64;
+
+        // additionally store the index in the local cache, so it's directly available
+        self.index_cache.insert(archive_path, &index_content)?;
+
+        zip_file.seek(SeekFrom::Start(0))?;
+        self.transaction(|trans| {
+            trans.store_file(
+                archive_path,
+                "application/zip",
+                &mut io::BufReader::new(zip_file),
+            )?;
+            trans.store_batch(vec![index_blob])
+        })?;
+        Ok(size)
+    }
+
+    /// Rewrites the index of the archive in the current format, returning `false` if it's in
+    /// the current format already.
+    pub(crate) fn convert_archive_index(&self, archive_path: &str) -> Result<bool> {
+        ensure!(
+            self.config.sorted_archive_indexes,
+            "sorted archive indexes aren't enabled, web servers might not be able to read them"
+        );
+        let remote_index_path = format!("{}.index", archive_path);
+        let index_content = self.get(&remote_index_path, std::usize::MAX)?.content;
+        let converted = match archive_index::convert(&index_content)? {
+            Some(converted) => converted,
+            None => return Ok(false),
+        };
 
-        let file_alg = CompressionAlgorithm::Bzip2;
-        Ok((file_paths, file_alg))
+        self.store_inner(std::iter::once(index_blob(archive_path, &converted)))?;
+        self.index_cache.insert(archive_path, &converted)?;
+        Ok(true)
     }
 
     fn transaction<T, F>(&self, f: F) -> Result<T>
@@ -446,16 +963,21 @@ impl Storage {
     }
 
     // Store file into the backend at the given path (also used to detect mime type), returns the
-    // chosen compression algorithm
+    // chosen compression algorithm and the size of the file before and after compression
     pub(crate) fn store_one(
         &self,
         path: impl Into<String>,
         content: impl Into<Vec<u8>>,
-    ) -> Result<CompressionAlgorithm> {
+    ) -> Result<(CompressionAlgorithm, StorageSize)> {
         let path = path.into();
         let content = content.into();
         let alg = CompressionAlgorithm::default();
+        let uncompressed = content.len() as u64;
         let content = compress(&*content, alg)?;
+        let size = StorageSize {
+            compressed: content.len() as u64,
+            uncompressed,
+        };
         let mime = detect_mime(&path).to_owned();
 
         self.store_inner(std::iter::once(Ok(Blob {
@@ -467,7 +989,7 @@ impl Storage {
             date_updated: Utc::now(),
         })))?;
 
-        Ok(alg)
+        Ok((alg, size))
     }
 
     fn store_inner(&self, blobs: impl IntoIterator<Item = Result<Blob>>) -> Result<()> {
@@ -491,6 +1013,10 @@ impl Storage {
         self.transaction(|trans| trans.delete_prefix(prefix))
     }
 
+    pub(crate) fn delete_paths(&self, paths: &[String]) -> Result<()> {
+        self.transaction(|trans| trans.delete_paths(paths))
+    }
+
     // We're using `&self` instead of consuming `self` or creating a Drop impl because during tests
     // we leak the web server, and Drop isn't executed in that case (since the leaked web server
     // still holds a reference to the storage).
@@ -514,11 +1040,24 @@ impl std::fmt::Debug for Storage {
 
 trait StorageTransaction {
     fn store_batch(&mut self, batch: Vec<Blob>) -> Result<()>;
+    /// Stores the rest of `file` uncompressed, reading and uploading it in chunks of
+    /// [`UPLOAD_CHUNK_SIZE`] instead of loading it into memory.
+    fn store_file(&mut self, path: &str, mime: &str, file: &mut dyn Read) -> Result<()>;
     fn delete_prefix(&mut self, prefix: &str) -> Result<()>;
+    fn delete_paths(&mut self, paths: &[String]) -> Result<()>;
     fn complete(self: Box<Self>) -> Result<()>;
 }
 
-fn detect_mime(file_path: impl AsRef<Path>) -> &'static str {
+/// Reads the next chunk of at most [`UPLOAD_CHUNK_SIZE`] bytes, which is only shorter at the end
+/// of the file.
+fn read_chunk(file: &mut dyn Read) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::new();
+    file.take(UPLOAD_CHUNK_SIZE as u64)
+        .read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+pub(crate) fn detect_mime(file_path: impl AsRef<Path>) -> &'static str {
     let mime = mime_guess::from_path(file_path.as_ref())
         .first_raw()
         .unwrap_or("text/plain");
@@ -540,6 +1079,90 @@ fn detect_mime(file_path: impl AsRef<Path>) -> &'static str {
     }
 }
 
+/// The size of an archive, and how long decompressing all its entries took, before and after it
+/// was recompressed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RecompressedArchive {
+    pub size_before: u64,
+    pub size_after: u64,
+    pub time_before: Duration,
+    pub time_after: Duration,
+}
+
+/// Decompresses a blob read from the storage backend.
+fn decompress_blob(mut blob: Blob, max_size: usize) -> Result<Blob> {
+    if let Some(alg) = blob.compression {
+        blob.content = decompress(blob.content.as_slice(), alg, max_size)?;
+        blob.compression = None;
+    }
+    Ok(blob)
+}
+
+/// Checks and decompresses a range of `len` bytes read from an archive.
+///
+/// `compression` represents the compression of the file-stream inside the archive.
+/// We don't compress the whole archive, so the encoding of the archive's blob is irrelevant
+/// here.
+fn decompress_range(
+    mut blob: Blob,
+    len: u64,
+    max_size: usize,
+    compression: Option<CompressionAlgorithm>,
+) -> Result<Blob> {
+    // the range ends after the end of the archive
+    ensure!(blob.content.len() as u64 == len, ArchiveIndexMismatchError);
+
+    if let Some(alg) = compression {
+        blob.content = decompress(blob.content.as_slice(), alg, max_size).map_err(|err| {
+            if is_size_limit_reached(&err) {
+                err
+            } else {
+                err.context(ArchiveIndexMismatchError)
+            }
+        })?;
+        blob.compression = None;
+    }
+    Ok(blob)
+}
+
+/// The blob of a file in an archive, read from its range or its content-addressed blob.
+fn archive_entry_blob(archive_path: &str, path: &str, blob: Blob) -> Blob {
+    assert_eq!(blob.compression, None);
+    Blob {
+        path: format!("{}/{}", archive_path, path),
+        mime: detect_mime(path).into(),
+        date_updated: blob.date_updated,
+        content: blob.content,
+        compression: None,
+    }
+}
+
+/// Looks up a path in an opened index on the blocking thread pool.
+async fn find_in_file_async(index: fs::File, path: &str) -> Result<Option<Entry>> {
+    let path = path.to_owned();
+    spawn_blocking(move || archive_index::find_in_file(&index, &path)).await?
+}
+
+/// Whether the error was caused by a file exceeding the maximum size it was read with.
+fn is_size_limit_reached(err: &anyhow::Error) -> bool {
+    err.is::<crate::error::SizeLimitReached>()
+        || err
+            .downcast_ref::<io::Error>()
+            .and_then(|err| err.get_ref())
+            .map_or(false, |err| err.is::<crate::error::SizeLimitReached>())
+}
+
+fn index_blob(archive_path: &str, index_content: &[u8]) -> Result<Blob> {
+    let alg = CompressionAlgorithm::default();
+    Ok(Blob {
+        path: format!("{}.index", archive_path),
+        mime: "application/octet-stream".to_owned(),
+        content: compress(index_content, alg)?,
+        compression: Some(alg),
+        date_updated: Utc::now(),
+    })
+}
+
 pub(crate) fn rustdoc_archive_path(name: &str, version: &str) -> String {
     format!("rustdoc/{0}/{1}.zip", name, version)
 }
@@ -548,6 +1171,11 @@ pub(crate) fn source_archive_path(name: &str, version: &str) -> String {
     format!("sources/{0}/{1}.zip", name, version)
 }
 
+/// The directory inside the rustdoc archive holding the docs of a feature profile.
+pub(crate) fn feature_profile_dir(profile: &str) -> String {
+    format!("profile-{}", profile)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -583,6 +1211,42 @@ mod test {
         let detected_mime = detect_mime(Path::new(&path));
         assert_eq!(detected_mime, expected_mime);
     }
+
+    #[test]
+    fn zstd_archives() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.archive_compression = CompressionAlgorithm::Zstd;
+            });
+            let storage = env.storage();
+
+            let dir = tempfile::tempdir()?;
+            fs::write(dir.path().join("index.html"), "<html>foo</html>")?;
+            let (_, alg, _) = storage.store_all_in_archive("rustdoc/foo/0.1.0.zip", dir.path())?;
+            assert_eq!(alg, CompressionAlgorithm::Zstd);
+
+            let entry = archive_index::find_in_file(
+                &storage.open_index("rustdoc/foo/0.1.0.zip")?,
+                "index.html",
+            )?;
+            match entry {
+                Some(Entry::Archived(info)) => {
+                    assert_eq!(info.compression(), CompressionAlgorithm::Zstd)
+                }
+                other => panic!("expected an archived file, got {:?}", other),
+            }
+
+            let file = storage.get_from_archive(
+                "rustdoc/foo/0.1.0.zip",
+                "index.html",
+                std::usize::MAX,
+                None,
+            )?;
+            assert_eq!(file.content, b"<html>foo</html>");
+
+            Ok(())
+        });
+    }
 }
 
 /// Backend tests are a set of tests executed on all the supported storage backends. They ensure
@@ -612,6 +1276,18 @@ mod backend_tests {
         Ok(())
     }
 
+    fn test_store_again_updates_date(storage: &Storage) -> Result<()> {
+        storage.store_one("path/to/file.txt", "Hello world!")?;
+        let first = storage.get("path/to/file.txt", std::usize::MAX)?;
+        // S3 only keeps the modification time in seconds
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        storage.store_one("path/to/file.txt", "Hello world!")?;
+        let second = storage.get("path/to/file.txt", std::usize::MAX)?;
+        assert!(second.date_updated > first.date_updated);
+
+        Ok(())
+    }
+
     fn test_get_object(storage: &Storage) -> Result<()> {
         let blob = Blob {
             path: "foo/bar.txt".into(),
@@ -767,12 +1443,22 @@ mod backend_tests {
 
         assert!(!local_index_location.exists());
 
-        let (stored_files, compression_alg) =
+        let (stored_files, compression_alg, usage) =
             storage.store_all_in_archive("folder/test.zip", dir.path())?;
 
         assert!(local_index_location.exists());
         assert!(storage.exists("folder/test.zip.index")?);
 
+        // the size of the archive and its index
+        let stored = storage.list_page("folder/test.zip", "", 10)?;
+        assert_eq!(stored.len(), 2);
+        assert_eq!(
+            usage.size.compressed,
+            stored.iter().map(|object| object.size).sum::<u64>()
+        );
+        assert_eq!(usage.size.uncompressed, 8);
+        assert!(usage.blobs.is_empty());
+
         assert_eq!(compression_alg, CompressionAlgorithm::Bzip2);
         assert_eq!(stored_files.len(), files.len());
         for name in &files {
@@ -816,6 +1502,43 @@ mod backend_tests {
         Ok(())
     }
 
+    fn test_archive_index_mismatch(storage: &Storage) -> Result<()> {
+        let store = |content: &[u8]| -> Result<()> {
+            let dir = tempfile::Builder::new()
+                .prefix("docs.rs-index-mismatch-test")
+                .tempdir()?;
+            fs::create_dir(dir.path().join("src"))?;
+            fs::write(dir.path().join("Cargo.toml"), content)?;
+            fs::write(dir.path().join("src/main.rs"), content)?;
+            storage.store_all_in_archive("folder/test.zip", dir.path())?;
+            Ok(())
+        };
+        let get = |path| storage.get_from_archive("folder/test.zip", path, std::usize::MAX, None);
+
+        store(b"data")?;
+        let old_index = fs::read(storage.local_index_path("folder/test.zip"))?;
+        let new_content = "different data ".repeat(100);
+        store(new_content.as_bytes())?;
+
+        // a stale cached index is replaced
+        storage.index_cache.insert("folder/test.zip", &old_index)?;
+        assert_eq!(get("Cargo.toml")?.content, new_content.as_bytes());
+        storage.index_cache.insert("folder/test.zip", &old_index)?;
+        assert_eq!(get("src/main.rs")?.content, new_content.as_bytes());
+
+        // a corrupt archive isn't hidden behind the fresh index
+        let archive = storage.get("folder/test.zip", std::usize::MAX)?;
+        storage.store_blobs(vec![Blob {
+            content: vec![0; archive.content.len()],
+            ..archive
+        }])?;
+        for path in ["Cargo.toml", "src/main.rs"] {
+            assert!(get(path).unwrap_err().is::<ArchiveIndexMismatchError>());
+        }
+
+        Ok(())
+    }
+
     fn test_store_all(storage: &Storage, metrics: &Metrics) -> Result<()> {
         let dir = tempfile::Builder::new()
             .prefix("docs.rs-upload-test")
@@ -920,6 +1643,112 @@ mod backend_tests {
         )
     }
 
+    fn test_delete_like_wildcards(storage: &Storage) -> Result<()> {
+        // "_" matches any single character and "\\" escapes the next one in PostgreSQL's patterns,
+        // both have to be matched literally in a prefix.
+        test_deletion(
+            storage,
+            "foo_bar\\/",
+            &["foo_bar\\/baz.txt", "fooxbar\\/baz.txt", "foo_bar/baz.txt"],
+            &["fooxbar\\/baz.txt", "foo_bar/baz.txt"],
+            &["foo_bar\\/baz.txt"],
+        )
+    }
+
+    fn test_list_prefix(storage: &Storage) -> Result<()> {
+        storage.store_blobs(
+            [
+                "foo_bar/a.txt",
+                "foo_bar/b/c.txt",
+                "foo-bar/a.txt",
+                "foo%bar/a.txt",
+            ]
+            .iter()
+            .map(|path| Blob {
+                path: (*path).to_string(),
+                content: b"foo\n".to_vec(),
+                compression: None,
+                mime: "text/plain".into(),
+                date_updated: Utc::now(),
+            })
+            .collect(),
+        )?;
+
+        // "_" and "%" in the prefix only match themselves
+        let mut paths = storage.list_prefix("foo_bar/")?;
+        paths.sort();
+        assert_eq!(paths, vec!["foo_bar/a.txt", "foo_bar/b/c.txt"]);
+        assert_eq!(storage.list_prefix("foo%")?, vec!["foo%bar/a.txt"]);
+        assert!(storage.list_prefix("bar/")?.is_empty());
+
+        Ok(())
+    }
+
+    fn test_store_file(storage: &Storage) -> Result<()> {
+        // three chunks, the last one is shorter
+        let content: Vec<u8> = (0..UPLOAD_CHUNK_SIZE * 2 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        storage.transaction(|trans| {
+            trans.store_file("big.zip", "application/zip", &mut content.as_slice())
+        })?;
+        let blob = storage.get("big.zip", usize::MAX)?;
+        assert_eq!(blob.mime, "application/zip");
+        assert!(blob.content == content);
+        assert_eq!(
+            storage.list_page("big.zip", "", 1)?[0].size,
+            content.len() as u64
+        );
+        assert!(storage.get("big.zip", content.len() - 1).is_err());
+
+        // ranges within a chunk and across chunks
+        for range in [
+            10..=20,
+            UPLOAD_CHUNK_SIZE as u64 - 5..=UPLOAD_CHUNK_SIZE as u64 + 5,
+            5..=UPLOAD_CHUNK_SIZE as u64 * 2 + 5,
+            UPLOAD_CHUNK_SIZE as u64 * 2 + 10..=UPLOAD_CHUNK_SIZE as u64 * 2 + 99,
+        ] {
+            let blob = storage.get_range("big.zip", usize::MAX, range.clone(), None)?;
+            assert!(blob.content == content[*range.start() as usize..=*range.end() as usize]);
+        }
+
+        // existing files are replaced, and files can be empty
+        storage.transaction(|trans| trans.store_file("big.zip", "text/plain", &mut &b""[..]))?;
+        let blob = storage.get("big.zip", usize::MAX)?;
+        assert_eq!((blob.mime.as_str(), blob.content.len()), ("text/plain", 0));
+
+        Ok(())
+    }
+
+    fn test_list_page_and_delete_paths(storage: &Storage) -> Result<()> {
+        storage.store_blobs(
+            ["a/1.txt", "a/2.txt", "a/3.txt", "b/1.txt"]
+                .iter()
+                .map(|path| Blob {
+                    path: (*path).to_string(),
+                    content: b"foo\n".to_vec(),
+                    compression: None,
+                    mime: "text/plain".into(),
+                    date_updated: Utc::now(),
+                })
+                .collect(),
+        )?;
+
+        let page = storage.list_page("a/", "", 2)?;
+        let paths: Vec<_> = page.iter().map(|object| object.path.as_str()).collect();
+        assert_eq!(paths, ["a/1.txt", "a/2.txt"]);
+        assert!(page.iter().all(|object| object.size == 4));
+        let page = storage.list_page("a/", "a/2.txt", 2)?;
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].path, "a/3.txt");
+
+        storage.delete_paths(&["a/1.txt".into(), "a/3.txt".into(), "c/1.txt".into()])?;
+        assert_eq!(storage.list_prefix("a/")?, ["a/2.txt"]);
+        assert!(storage.exists("b/1.txt")?);
+
+        Ok(())
+    }
+
     fn test_deletion(
         storage: &Storage,
         prefix: &str,
@@ -1013,13 +1842,19 @@ mod backend_tests {
         tests {
             test_batched_uploads,
             test_exists,
+            test_store_again_updates_date,
             test_get_object,
             test_get_range,
             test_get_too_big,
             test_delete_prefix,
             test_delete_prefix_without_matches,
             test_delete_percent,
+            test_delete_like_wildcards,
+            test_list_prefix,
+            test_list_page_and_delete_paths,
+            test_store_file,
             test_exists_without_remote_archive,
+            test_archive_index_mismatch,
         }
 
         tests_with_metrics {
@@ -0,0 +1,324 @@
+//! Recompressing the existing rustdoc and source archives with another algorithm.
+//!
+//! Releases are picked by their `compression_rels`, which are updated once all archives of a
+//! release were rewritten, so an interrupted job continues with the remaining releases when
+//! it's started again.
+//!
+//! Content-addressed releases have no archives, they're skipped and keep their algorithm.
+
+use super::{
+    archive_index, rustdoc_archive_path, source_archive_path, CompressionAlgorithm,
+    PathNotFoundError, RecompressedArchive, ReleaseBatchOptions, ReleaseBatches,
+};
+use crate::error::Result;
+use crate::utils::report_error;
+use crate::Storage;
+use anyhow::Context as _;
+use log::info;
+use postgres::Client;
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub struct RecompressOptions {
+    /// The algorithm the archives are recompressed with.
+    pub algorithm: CompressionAlgorithm,
+    pub releases: ReleaseBatchOptions,
+}
+
+/// The savings of a recompression run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RecompressReport {
+    pub releases: usize,
+    pub archives: usize,
+    /// Content-addressed releases, which have no archives to recompress.
+    pub skipped: usize,
+    pub failed: usize,
+    pub totals: RecompressedArchive,
+}
+
+impl fmt::Display for RecompressReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "recompressed {} archives of {} releases, skipped {} content-addressed releases, \
+             {} releases failed",
+            self.archives, self.releases, self.skipped, self.failed
+        )?;
+
+        let totals = &self.totals;
+        let saved = totals.size_before as i64 - totals.size_after as i64;
+        writeln!(
+            f,
+            "size: {} bytes -> {} bytes ({} bytes saved, {:.1}%)",
+            totals.size_before,
+            totals.size_after,
+            saved,
+            percentage(saved as f64, totals.size_before as f64),
+        )?;
+        write!(
+            f,
+            "decompressing all entries: {:.3}s -> {:.3}s ({:.1}% faster)",
+            totals.time_before.as_secs_f64(),
+            totals.time_after.as_secs_f64(),
+            percentage(
+                totals.time_before.as_secs_f64() - totals.time_after.as_secs_f64(),
+                totals.time_before.as_secs_f64()
+            ),
+        )
+    }
+}
+
+fn percentage(part: f64, total: f64) -> f64 {
+    if total == 0.0 {
+        0.0
+    } else {
+        part / total * 100.0
+    }
+}
+
+/// Recompresses the archives of all releases using another algorithm than
+/// `options.algorithm`, one release after another.
+pub fn recompress_archives(
+    conn: &mut Client,
+    storage: &Storage,
+    options: &RecompressOptions,
+) -> Result<RecompressReport> {
+    let mut report = RecompressReport::default();
+    let algorithm = options.algorithm as i32;
+    let mut releases = ReleaseBatches::new(
+        "SELECT releases.id, crates.name, releases.version
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE
+            releases.archive_storage AND
+            releases.id > $1 AND
+            EXISTS (
+                SELECT 1
+                FROM compression_rels
+                WHERE
+                    compression_rels.release = releases.id AND
+                    compression_rels.algorithm IS DISTINCT FROM $3
+            )
+         ORDER BY releases.id
+         LIMIT $2",
+        vec![&algorithm],
+        &options.releases,
+    );
+
+    while let Some(row) = releases.next(conn)? {
+        let id: i32 = row.get("id");
+        let name: String = row.get("name");
+        let version: String = row.get("version");
+
+        match recompress_release(conn, storage, id, &name, &version, options.algorithm)
+            .with_context(|| format!("failed to recompress {} {}", name, version))
+        {
+            Ok(None) => {
+                info!("skipped {} {}, it's content-addressed", name, version);
+                report.skipped += 1;
+            }
+            Ok(Some(archives)) => {
+                info!("recompressed {} {}", name, version);
+                report.releases += 1;
+                for archive in archives {
+                    report.archives += 1;
+                    report.totals.size_before += archive.size_before;
+                    report.totals.size_after += archive.size_after;
+                    report.totals.time_before += archive.time_before;
+                    report.totals.time_after += archive.time_after;
+                }
+            }
+            Err(err) => {
+                report.failed += 1;
+                report_error(&err);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Recompresses the archives of a release, or returns `None` without changing anything if the
+/// release is content-addressed.
+fn recompress_release(
+    conn: &mut Client,
+    storage: &Storage,
+    release_id: i32,
+    name: &str,
+    version: &str,
+    algorithm: CompressionAlgorithm,
+) -> Result<Option<Vec<RecompressedArchive>>> {
+    let mut archive_paths = Vec::new();
+    for archive_path in [
+        rustdoc_archive_path(name, version),
+        source_archive_path(name, version),
+    ] {
+        let index = match storage.get_remote_index(&archive_path) {
+            Ok(index) => index,
+            // releases without documentation only have a source archive
+            Err(err) if err.is::<PathNotFoundError>() => continue,
+            Err(err) => return Err(err),
+        };
+        if archive_index::blobs_in_slice(&index)?.is_some() {
+            return Ok(None);
+        }
+        archive_paths.push(archive_path);
+    }
+
+    let mut archives = Vec::new();
+    for archive_path in archive_paths {
+        archives.extend(storage.recompress_archive(&archive_path, algorithm)?);
+    }
+
+    let mut transaction = conn.transaction()?;
+    transaction.execute(
+        "DELETE FROM compression_rels WHERE release = $1 AND algorithm IS DISTINCT FROM $2;",
+        &[&release_id, &(algorithm as i32)],
+    )?;
+    transaction.execute(
+        "INSERT INTO compression_rels (release, algorithm)
+         VALUES ($1, $2)
+         ON CONFLICT DO NOTHING;",
+        &[&release_id, &(algorithm as i32)],
+    )?;
+    transaction.commit()?;
+
+    Ok(Some(archives))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    fn algorithms(env: &crate::test::TestEnvironment, name: &str) -> Result<Vec<i32>> {
+        Ok(env
+            .db()
+            .conn()
+            .query(
+                "SELECT compression_rels.algorithm
+                 FROM compression_rels
+                 INNER JOIN releases ON releases.id = compression_rels.release
+                 INNER JOIN crates ON crates.id = releases.crate_id
+                 WHERE crates.name = $1",
+                &[&name],
+            )?
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect())
+    }
+
+    #[test]
+    fn archives_are_recompressed_once() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .archive_storage(true)
+                .rustdoc_file_with("foo/index.html", b"<html>foo</html>")
+                .source_file("src/lib.rs", b"//! foo")
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("0.1.0")
+                .archive_storage(true)
+                .create()?;
+            // releases with the files stored separately aren't recompressed
+            env.fake_release().name("baz").version("0.1.0").create()?;
+            // neither are content-addressed releases
+            env.fake_release()
+                .name("qux")
+                .version("0.1.0")
+                .archive_storage(true)
+                .create()?;
+            let storage = env.storage();
+            let dir = tempfile::tempdir()?;
+            std::fs::write(dir.path().join("lib.rs"), "//! qux")?;
+            for archive_path in ["rustdoc/qux/0.1.0.zip", "sources/qux/0.1.0.zip"] {
+                storage.store_all_content_addressed(archive_path, dir.path())?;
+            }
+            assert_eq!(
+                algorithms(env, "foo")?,
+                vec![CompressionAlgorithm::Bzip2 as i32]
+            );
+
+            let options = RecompressOptions {
+                algorithm: CompressionAlgorithm::Zstd,
+                releases: ReleaseBatchOptions {
+                    limit: Some(1),
+                    ..Default::default()
+                },
+            };
+            let mut conn = env.db().conn();
+
+            let report = recompress_archives(&mut conn, &storage, &options)?;
+            assert_eq!((report.releases, report.archives, report.failed), (1, 2, 0));
+            assert!(report.totals.size_after > 0);
+            assert_eq!(
+                algorithms(env, "foo")?,
+                vec![CompressionAlgorithm::Zstd as i32]
+            );
+            assert_eq!(
+                algorithms(env, "bar")?,
+                vec![CompressionAlgorithm::Bzip2 as i32]
+            );
+
+            let file =
+                storage.get_from_archive("rustdoc/foo/0.1.0.zip", "foo/index.html", 1024, None)?;
+            assert_eq!(file.content, b"<html>foo</html>");
+
+            // the next run continues with the remaining releases
+            let options = RecompressOptions {
+                releases: ReleaseBatchOptions::default(),
+                ..options
+            };
+            let report = recompress_archives(&mut conn, &storage, &options)?;
+            assert_eq!((report.releases, report.skipped, report.failed), (1, 1, 0));
+            assert_eq!(
+                algorithms(env, "bar")?,
+                vec![CompressionAlgorithm::Zstd as i32]
+            );
+            assert_eq!(
+                algorithms(env, "qux")?,
+                vec![CompressionAlgorithm::Bzip2 as i32]
+            );
+
+            let report = recompress_archives(&mut conn, &storage, &options)?;
+            assert_eq!(
+                report,
+                RecompressReport {
+                    skipped: 1,
+                    ..RecompressReport::default()
+                }
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn stale_local_indexes_are_replaced() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .archive_storage(true)
+                .rustdoc_file_with("foo/index.html", b"<html>foo</html>")
+                .create()?;
+
+            let storage = env.storage();
+            let index_path = storage.local_index_path("rustdoc/foo/0.1.0.zip");
+            let stale_index = std::fs::read(&index_path)?;
+
+            storage.recompress_archive("rustdoc/foo/0.1.0.zip", CompressionAlgorithm::Zstd)?;
+            // another web server still has the index of the bzip2 archive in its cache
+            std::fs::write(&index_path, stale_index)?;
+
+            let file =
+                storage.get_from_archive("rustdoc/foo/0.1.0.zip", "foo/index.html", 1024, None)?;
+            assert_eq!(file.content, b"<html>foo</html>");
+
+            Ok(())
+        })
+    }
+}
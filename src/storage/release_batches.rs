@@ -0,0 +1,131 @@
+//! Walking over releases one after another, for the storage jobs processing all releases.
+//!
+//! Releases are loaded from the database in batches ordered by their id, so a job can be stopped
+//! and continued after the last release it processed.
+
+use crate::error::Result;
+use postgres::{types::ToSql, Client, Row};
+use std::{collections::VecDeque, thread, time::Duration};
+
+/// How many releases are loaded from the database at once.
+const BATCH_SIZE: i64 = 100;
+
+/// Which releases a job processes, and how fast.
+#[derive(Debug, Default, Clone)]
+pub struct ReleaseBatchOptions {
+    /// Only releases with a higher id are processed.
+    pub start_after: i32,
+    /// The most releases to process in this run.
+    pub limit: Option<usize>,
+    /// How long to wait after each release, so the job doesn't slow down the builds.
+    pub pause: Duration,
+}
+
+/// The releases selected by a query, loaded in batches.
+///
+/// The query gets the id to continue after as `$1` and the batch size as `$2`, followed by
+/// `params`, and has to return the releases ordered by their `id` column.
+pub(super) struct ReleaseBatches<'a> {
+    query: &'a str,
+    params: Vec<&'a (dyn ToSql + Sync)>,
+    options: &'a ReleaseBatchOptions,
+    last_id: i32,
+    batch: VecDeque<Row>,
+    returned: usize,
+}
+
+impl<'a> ReleaseBatches<'a> {
+    pub(super) fn new(
+        query: &'a str,
+        params: Vec<&'a (dyn ToSql + Sync)>,
+        options: &'a ReleaseBatchOptions,
+    ) -> Self {
+        Self {
+            query,
+            params,
+            options,
+            last_id: options.start_after,
+            batch: VecDeque::new(),
+            returned: 0,
+        }
+    }
+
+    /// Returns the next release, or `None` if all releases or `limit` releases were returned.
+    ///
+    /// Waits for `pause` first, unless it's the first release.
+    pub(super) fn next(&mut self, conn: &mut Client) -> Result<Option<Row>> {
+        if self
+            .options
+            .limit
+            .map_or(false, |limit| self.returned >= limit)
+        {
+            return Ok(None);
+        }
+
+        if self.returned > 0 && !self.options.pause.is_zero() {
+            thread::sleep(self.options.pause);
+        }
+
+        if self.batch.is_empty() {
+            let last_id = self.last_id;
+            let mut params: Vec<&(dyn ToSql + Sync)> = vec![&last_id, &BATCH_SIZE];
+            params.extend(&self.params);
+            self.batch = conn.query(self.query, &params)?.into();
+        }
+
+        let row = match self.batch.pop_front() {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        self.last_id = row.get("id");
+        self.returned += 1;
+        Ok(Some(row))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    #[test]
+    fn releases_are_loaded_in_batches() {
+        wrapper(|env| {
+            let mut ids = Vec::new();
+            for i in 0..(BATCH_SIZE + 5) {
+                ids.push(
+                    env.fake_release()
+                        .name("foo")
+                        .version(&format!("0.1.{}", i))
+                        .create()?,
+                );
+            }
+            let mut conn = env.db().conn();
+
+            let query =
+                "SELECT id FROM releases WHERE id > $1 AND id % $3 = 0 ORDER BY id LIMIT $2";
+            let walk = |options: &ReleaseBatchOptions, conn: &mut Client| -> Result<Vec<i32>> {
+                let mut releases = ReleaseBatches::new(query, vec![&1], options);
+                let mut ids = Vec::new();
+                while let Some(row) = releases.next(conn)? {
+                    ids.push(row.get("id"));
+                }
+                Ok(ids)
+            };
+
+            assert_eq!(walk(&ReleaseBatchOptions::default(), &mut conn)?, ids);
+
+            let options = ReleaseBatchOptions {
+                start_after: ids[2],
+                limit: Some(BATCH_SIZE as usize),
+                pause: Duration::ZERO,
+            };
+            assert_eq!(
+                walk(&options, &mut conn)?,
+                ids[3..3 + BATCH_SIZE as usize].to_vec()
+            );
+
+            Ok(())
+        })
+    }
+}
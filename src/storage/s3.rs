@@ -1,9 +1,9 @@
-use super::{Blob, FileRange, StorageTransaction};
+use super::{read_chunk, Blob, FileRange, StorageTransaction, StoredObject, UPLOAD_CHUNK_SIZE};
 use crate::{Config, Metrics};
 use anyhow::{Context, Error};
 use aws_sdk_s3::{
     error,
-    model::{Delete, ObjectIdentifier},
+    model::{CompletedMultipartUpload, CompletedPart, Delete, ObjectIdentifier},
     types::SdkError,
     Client, Endpoint, Region, RetryConfig,
 };
@@ -13,7 +13,10 @@ use futures_util::{
     future::TryFutureExt,
     stream::{FuturesUnordered, StreamExt},
 };
-use std::{io::Write, sync::Arc};
+use std::{
+    io::{Read, Write},
+    sync::Arc,
+};
 use tokio::runtime::Runtime;
 
 pub(super) struct S3Backend {
@@ -67,24 +70,90 @@ impl S3Backend {
     }
 
     pub(super) fn exists(&self, path: &str) -> Result<bool, Error> {
+        self.runtime.block_on(self.exists_async(path))
+    }
+
+    pub(super) async fn exists_async(&self, path: &str) -> Result<bool, Error> {
+        match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(path)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError { err, raw })
+                if (matches!(err.kind, error::HeadObjectErrorKind::NotFound(_))
+                    || raw.http().status() == http::StatusCode::NOT_FOUND) =>
+            {
+                Ok(false)
+            }
+            Err(other) => Err(other.into()),
+        }
+    }
+
+    pub(super) fn list_prefix(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        self.runtime.block_on(async {
+            let mut paths = Vec::new();
+            let mut continuation_token = None;
+            loop {
+                let list = self
+                    .client
+                    .list_objects_v2()
+                    .bucket(&self.bucket)
+                    .prefix(prefix)
+                    .set_continuation_token(continuation_token)
+                    .send()
+                    .await?;
+
+                paths.extend(
+                    list.contents
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|obj| obj.key),
+                );
+
+                continuation_token = list.next_continuation_token;
+                if continuation_token.is_none() {
+                    return Ok(paths);
+                }
+            }
+        })
+    }
+
+    pub(super) fn list_page(
+        &self,
+        prefix: &str,
+        start_after: &str,
+        limit: usize,
+    ) -> Result<Vec<StoredObject>, Error> {
         self.runtime.block_on(async {
-            match self
+            let list = self
                 .client
-                .head_object()
+                .list_objects_v2()
                 .bucket(&self.bucket)
-                .key(path)
+                .prefix(prefix)
+                .start_after(start_after)
+                .max_keys(limit.try_into().unwrap_or(i32::MAX))
                 .send()
-                .await
-            {
-                Ok(_) => Ok(true),
-                Err(SdkError::ServiceError { err, raw })
-                    if (matches!(err.kind, error::HeadObjectErrorKind::NotFound(_))
-                        || raw.http().status() == http::StatusCode::NOT_FOUND) =>
-                {
-                    Ok(false)
-                }
-                Err(other) => Err(other.into()),
-            }
+                .await?;
+
+            Ok(list
+                .contents
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|obj| {
+                    Some(StoredObject {
+                        path: obj.key?,
+                        size: obj.size.try_into().unwrap_or(0),
+                        date_updated: obj
+                            .last_modified
+                            .map(|dt| dt.to_chrono_utc())
+                            .unwrap_or_else(Utc::now),
+                    })
+                })
+                .collect())
         })
     }
 
@@ -94,50 +163,63 @@ impl S3Backend {
         max_size: usize,
         range: Option<FileRange>,
     ) -> Result<Blob, Error> {
-        self.runtime.block_on(async {
-            let res = self
-                .client
-                .get_object()
-                .bucket(&self.bucket)
-                .key(path)
-                .set_range(range.map(|r| format!("bytes={}-{}", r.start(), r.end())))
-                .send()
-                .map_err(|err| match err {
-                    SdkError::ServiceError { err, raw }
-                        if (matches!(err.kind, error::GetObjectErrorKind::NoSuchKey(_))
-                            || raw.http().status() == http::StatusCode::NOT_FOUND) =>
-                    {
-                        super::PathNotFoundError.into()
-                    }
-                    err => Error::from(err),
-                })
-                .await?;
+        self.runtime.block_on(self.get_async(path, max_size, range))
+    }
+
+    pub(super) async fn get_async(
+        &self,
+        path: &str,
+        max_size: usize,
+        range: Option<FileRange>,
+    ) -> Result<Blob, Error> {
+        let res = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(path)
+            .set_range(range.map(|r| format!("bytes={}-{}", r.start(), r.end())))
+            .send()
+            .map_err(|err| match err {
+                SdkError::ServiceError { err, raw }
+                    if (matches!(err.kind, error::GetObjectErrorKind::NoSuchKey(_))
+                        || raw.http().status() == http::StatusCode::NOT_FOUND) =>
+                {
+                    super::PathNotFoundError.into()
+                }
+                // ranges are only requested for the entries listed in an archive's index
+                SdkError::ServiceError { raw, .. }
+                    if raw.http().status() == http::StatusCode::RANGE_NOT_SATISFIABLE =>
+                {
+                    super::ArchiveIndexMismatchError.into()
+                }
+                err => Error::from(err),
+            })
+            .await?;
 
-            let mut content = crate::utils::sized_buffer::SizedBuffer::new(max_size);
-            content.reserve(res.content_length.try_into().ok().unwrap_or(0));
+        let mut content = crate::utils::sized_buffer::SizedBuffer::new(max_size);
+        content.reserve(res.content_length.try_into().ok().unwrap_or(0));
 
-            let mut body = res.body;
+        let mut body = res.body;
 
-            while let Some(data) = body.next().await.transpose()? {
-                content.write_all(data.as_ref())?;
-            }
+        while let Some(data) = body.next().await.transpose()? {
+            content.write_all(data.as_ref())?;
+        }
 
-            let date_updated = res
-                .last_modified
-                // This is a bug from AWS, it should always have a modified date of when it was created if nothing else.
-                // Workaround it by passing now as the modification time, since the exact time doesn't really matter.
-                .map(|dt| dt.to_chrono_utc())
-                .unwrap_or_else(Utc::now);
-
-            let compression = res.content_encoding.and_then(|s| s.parse().ok());
-
-            Ok(Blob {
-                path: path.into(),
-                mime: res.content_type.unwrap(),
-                date_updated,
-                content: content.into_inner(),
-                compression,
-            })
+        let date_updated = res
+            .last_modified
+            // This is a bug from AWS, it should always have a modified date of when it was created if nothing else.
+            // Workaround it by passing now as the modification time, since the exact time doesn't really matter.
+            .map(|dt| dt.to_chrono_utc())
+            .unwrap_or_else(Utc::now);
+
+        let compression = res.content_encoding.and_then(|s| s.parse().ok());
+
+        Ok(Blob {
+            path: path.into(),
+            mime: res.content_type.unwrap(),
+            date_updated,
+            content: content.into_inner(),
+            compression,
         })
     }
 
@@ -215,6 +297,95 @@ impl<'a> StorageTransaction for S3StorageTransaction<'a> {
         })
     }
 
+    fn store_file(&mut self, path: &str, mime: &str, file: &mut dyn Read) -> Result<(), Error> {
+        self.s3.runtime.block_on(async {
+            let upload_id = self
+                .s3
+                .client
+                .create_multipart_upload()
+                .bucket(&self.s3.bucket)
+                .key(path)
+                .content_type(mime)
+                .send()
+                .await?
+                .upload_id
+                .context("no upload id for multipart upload")?;
+
+            let upload = async {
+                let mut parts = Vec::new();
+                loop {
+                    let chunk = read_chunk(file)?;
+                    let last = chunk.len() < UPLOAD_CHUNK_SIZE;
+                    // every part but the last has to be at least 5 MiB, and there has to be one
+                    if chunk.is_empty() && !parts.is_empty() {
+                        break;
+                    }
+
+                    let part_number = i32::try_from(parts.len() + 1)?;
+                    let part = self
+                        .s3
+                        .client
+                        .upload_part()
+                        .bucket(&self.s3.bucket)
+                        .key(path)
+                        .upload_id(&upload_id)
+                        .part_number(part_number)
+                        .body(chunk.into())
+                        .send()
+                        .await?;
+                    parts.push(
+                        CompletedPart::builder()
+                            .set_e_tag(part.e_tag)
+                            .part_number(part_number)
+                            .build(),
+                    );
+                    if last {
+                        break;
+                    }
+                }
+
+                self.s3
+                    .client
+                    .complete_multipart_upload()
+                    .bucket(&self.s3.bucket)
+                    .key(path)
+                    .upload_id(&upload_id)
+                    .multipart_upload(
+                        CompletedMultipartUpload::builder()
+                            .set_parts(Some(parts))
+                            .build(),
+                    )
+                    .send()
+                    .await?;
+                Ok::<_, Error>(())
+            }
+            .await;
+
+            if upload.is_err() {
+                // otherwise the uploaded parts are kept, and billed
+                if let Err(err) = self
+                    .s3
+                    .client
+                    .abort_multipart_upload()
+                    .bucket(&self.s3.bucket)
+                    .key(path)
+                    .upload_id(&upload_id)
+                    .send()
+                    .await
+                {
+                    log::warn!(
+                        "Failed to abort the multipart upload of {}: {:?}",
+                        path,
+                        err
+                    );
+                }
+            } else {
+                self.s3.metrics.uploaded_files_total.inc();
+            }
+            upload
+        })
+    }
+
     fn delete_prefix(&mut self, prefix: &str) -> Result<(), Error> {
         self.s3.runtime.block_on(async {
             let mut continuation_token = None;
@@ -269,6 +440,40 @@ impl<'a> StorageTransaction for S3StorageTransaction<'a> {
         })
     }
 
+    fn delete_paths(&mut self, paths: &[String]) -> Result<(), Error> {
+        self.s3.runtime.block_on(async {
+            // S3 deletes at most 1000 objects per request
+            for chunk in paths.chunks(1000) {
+                let to_delete = Delete::builder()
+                    .set_objects(Some(
+                        chunk
+                            .iter()
+                            .map(|path| ObjectIdentifier::builder().key(path).build())
+                            .collect(),
+                    ))
+                    .build();
+
+                let resp = self
+                    .s3
+                    .client
+                    .delete_objects()
+                    .bucket(&self.s3.bucket)
+                    .delete(to_delete)
+                    .send()
+                    .await?;
+
+                if let Some(errs) = resp.errors {
+                    for err in &errs {
+                        log::error!("error deleting file from s3: {:?}", err);
+                    }
+
+                    anyhow::bail!("deleting from s3 failed");
+                }
+            }
+            Ok(())
+        })
+    }
+
     fn complete(self: Box<Self>) -> Result<(), Error> {
         Ok(())
     }
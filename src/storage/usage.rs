@@ -0,0 +1,806 @@
+//! Accounting for the space the files of releases take in the storage backend.
+//!
+//! The size of the rustdoc and source archives and of the build logs of a release is recorded in
+//! the `storage_usage` table when they are stored, together with the backend they are stored in.
+//! The blobs of content-addressed releases can be shared by many releases, so they're recorded
+//! once each in the `blob_usage` table instead, and only count towards the totals.
+//!
+//! Releases stored before are backfilled from the sizes listed by the backend, and the central
+//! directories of their archives. Files stored separately are compressed without recording their
+//! original size, so the stored size is backfilled as their uncompressed size too.
+
+use super::{
+    archive_index, dedup, rustdoc_archive_path, source_archive_path, PathNotFoundError,
+    ReleaseBatchOptions, ReleaseBatches, LIST_PAGE_SIZE,
+};
+use crate::error::Result;
+use crate::utils::report_error;
+use crate::Storage;
+use anyhow::{bail, Context as _};
+use log::info;
+use postgres::{Client, GenericClient};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::AddAssign;
+
+/// The size of stored files, as they are stored in the backend and after decompressing them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct StorageSize {
+    pub compressed: u64,
+    pub uncompressed: u64,
+}
+
+impl AddAssign for StorageSize {
+    fn add_assign(&mut self, other: Self) {
+        self.compressed += other.compressed;
+        self.uncompressed += other.uncompressed;
+    }
+}
+
+/// The space the archive of a release takes in the storage backend.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ArchiveUsage {
+    /// The archive and its index, or the manifest of a content-addressed release.
+    pub size: StorageSize,
+    /// The blobs of a content-addressed release by their path, which other releases can share.
+    pub blobs: Vec<(String, StorageSize)>,
+}
+
+/// The kind the blobs are summed up as in the totals.
+const BLOBS_KIND: &str = "blobs";
+
+/// What the stored files of a release are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UsageKind {
+    Rustdoc,
+    Sources,
+    BuildLogs,
+}
+
+impl UsageKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            UsageKind::Rustdoc => "rustdoc",
+            UsageKind::Sources => "sources",
+            UsageKind::BuildLogs => "build-logs",
+        }
+    }
+}
+
+/// Records the size of the archive of a release, replacing the size recorded before, and the
+/// blobs of a content-addressed release that weren't recorded for another release yet.
+pub(crate) fn record_storage_usage(
+    conn: &mut impl GenericClient,
+    storage: &Storage,
+    release_id: i32,
+    kind: UsageKind,
+    usage: &ArchiveUsage,
+) -> Result<()> {
+    let size = usage.size;
+    conn.execute(
+        "INSERT INTO storage_usage (release_id, kind, backend, compressed, uncompressed)
+         VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (release_id, kind) DO UPDATE
+         SET
+            backend = EXCLUDED.backend,
+            compressed = EXCLUDED.compressed,
+            uncompressed = EXCLUDED.uncompressed,
+            updated = CURRENT_TIMESTAMP",
+        &[
+            &release_id,
+            &kind.as_str(),
+            &storage.backend_name(),
+            &(size.compressed as i64),
+            &(size.uncompressed as i64),
+        ],
+    )?;
+
+    if !usage.blobs.is_empty() {
+        let paths: Vec<&str> = usage.blobs.iter().map(|(path, _)| path.as_str()).collect();
+        let (compressed, uncompressed): (Vec<i64>, Vec<i64>) = usage
+            .blobs
+            .iter()
+            .map(|(_, size)| (size.compressed as i64, size.uncompressed as i64))
+            .unzip();
+        conn.execute(
+            "INSERT INTO blob_usage (backend, path, compressed, uncompressed)
+             SELECT $1, * FROM UNNEST($2::TEXT[], $3::BIGINT[], $4::BIGINT[])
+             ON CONFLICT (backend, path) DO NOTHING",
+            &[&storage.backend_name(), &paths, &compressed, &uncompressed],
+        )?;
+    }
+    Ok(())
+}
+
+/// Removes the recorded size of deleted blobs. Other paths are ignored.
+pub(crate) fn forget_blob_usage(
+    conn: &mut impl GenericClient,
+    storage: &Storage,
+    paths: &[String],
+) -> Result<()> {
+    conn.execute(
+        "DELETE FROM blob_usage WHERE backend = $1 AND path = ANY($2)",
+        &[&storage.backend_name(), &paths],
+    )?;
+    Ok(())
+}
+
+/// Adds to the size recorded for the files of a release. Used for the build logs, which are kept
+/// for every build of the release.
+pub(crate) fn add_storage_usage(
+    conn: &mut impl GenericClient,
+    storage: &Storage,
+    release_id: i32,
+    kind: UsageKind,
+    size: StorageSize,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO storage_usage (release_id, kind, backend, compressed, uncompressed)
+         VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (release_id, kind) DO UPDATE
+         SET
+            backend = EXCLUDED.backend,
+            compressed = storage_usage.compressed + EXCLUDED.compressed,
+            uncompressed = storage_usage.uncompressed + EXCLUDED.uncompressed,
+            updated = CURRENT_TIMESTAMP",
+        &[
+            &release_id,
+            &kind.as_str(),
+            &storage.backend_name(),
+            &(size.compressed as i64),
+            &(size.uncompressed as i64),
+        ],
+    )?;
+    Ok(())
+}
+
+/// The size of all files of a kind in a storage backend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StorageUsageTotal {
+    pub backend: String,
+    pub kind: String,
+    /// The number of releases, or of blobs for the blobs.
+    pub count: u64,
+    pub size: StorageSize,
+}
+
+/// The size of all files of all releases of a crate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CrateStorageUsage {
+    pub name: String,
+    pub releases: u64,
+    pub size: StorageSize,
+}
+
+/// The recorded storage usage, in total and of the crates using the most space.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct StorageUsageReport {
+    pub totals: Vec<StorageUsageTotal>,
+    pub top_crates: Vec<CrateStorageUsage>,
+}
+
+impl fmt::Display for StorageUsageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "total:")?;
+        for total in &self.totals {
+            writeln!(
+                f,
+                "  {} {}: {} bytes stored, {} bytes uncompressed, in {} {}",
+                total.backend,
+                total.kind,
+                total.size.compressed,
+                total.size.uncompressed,
+                total.count,
+                if total.kind == BLOBS_KIND {
+                    "blobs"
+                } else {
+                    "releases"
+                }
+            )?;
+        }
+        write!(f, "top {} crates:", self.top_crates.len())?;
+        for krate in &self.top_crates {
+            write!(
+                f,
+                "\n  {}: {} bytes stored, {} bytes uncompressed, in {} releases",
+                krate.name, krate.size.compressed, krate.size.uncompressed, krate.releases
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn storage_size(row: &postgres::Row) -> StorageSize {
+    StorageSize {
+        compressed: row.get::<_, i64>("compressed") as u64,
+        uncompressed: row.get::<_, i64>("uncompressed") as u64,
+    }
+}
+
+/// Sums up the recorded storage usage per backend and kind of files, with the blobs as their own
+/// kind.
+pub(crate) fn storage_usage_totals(conn: &mut Client) -> Result<Vec<StorageUsageTotal>> {
+    Ok(conn
+        .query(
+            "SELECT * FROM (
+                SELECT
+                    backend,
+                    kind,
+                    COUNT(*) AS count,
+                    SUM(compressed)::BIGINT AS compressed,
+                    SUM(uncompressed)::BIGINT AS uncompressed
+                FROM storage_usage
+                GROUP BY backend, kind
+                UNION ALL
+                SELECT
+                    backend,
+                    $1 AS kind,
+                    COUNT(*) AS count,
+                    SUM(compressed)::BIGINT AS compressed,
+                    SUM(uncompressed)::BIGINT AS uncompressed
+                FROM blob_usage
+                GROUP BY backend
+             ) AS totals
+             ORDER BY backend, kind",
+            &[&BLOBS_KIND],
+        )?
+        .iter()
+        .map(|row| StorageUsageTotal {
+            backend: row.get("backend"),
+            kind: row.get("kind"),
+            count: row.get::<_, i64>("count") as u64,
+            size: storage_size(row),
+        })
+        .collect())
+}
+
+/// Loads the totals and the `top` crates using the most space in the storage backend, without
+/// the blobs they share.
+pub fn storage_usage_report(conn: &mut Client, top: usize) -> Result<StorageUsageReport> {
+    let top_crates = conn
+        .query(
+            "SELECT
+                crates.name,
+                COUNT(DISTINCT storage_usage.release_id) AS releases,
+                SUM(storage_usage.compressed)::BIGINT AS compressed,
+                SUM(storage_usage.uncompressed)::BIGINT AS uncompressed
+             FROM storage_usage
+             INNER JOIN releases ON releases.id = storage_usage.release_id
+             INNER JOIN crates ON crates.id = releases.crate_id
+             GROUP BY crates.name
+             ORDER BY compressed DESC, crates.name
+             LIMIT $1",
+            &[&(top as i64)],
+        )?
+        .iter()
+        .map(|row| CrateStorageUsage {
+            name: row.get("name"),
+            releases: row.get::<_, i64>("releases") as u64,
+            size: storage_size(row),
+        })
+        .collect();
+
+    Ok(StorageUsageReport {
+        totals: storage_usage_totals(conn)?,
+        top_crates,
+    })
+}
+
+/// The outcome of a backfill run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UsageBackfillReport {
+    pub releases: usize,
+    pub failed: usize,
+    /// The size of the files of the backfilled releases.
+    pub size: StorageSize,
+}
+
+impl fmt::Display for UsageBackfillReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "recorded the storage usage of {} releases with {} bytes stored and {} bytes \
+             uncompressed, {} releases failed",
+            self.releases, self.size.compressed, self.size.uncompressed, self.failed
+        )
+    }
+}
+
+/// Records the storage usage of all releases without any recorded usage, one release after
+/// another, by reading their files back from the storage backend.
+///
+/// Releases without any stored files are checked again on every run.
+pub fn backfill_storage_usage(
+    conn: &mut Client,
+    storage: &Storage,
+    options: &ReleaseBatchOptions,
+) -> Result<UsageBackfillReport> {
+    let mut report = UsageBackfillReport::default();
+    let mut releases = ReleaseBatches::new(
+        "SELECT releases.id, crates.name, releases.version, releases.archive_storage
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE
+            releases.id > $1 AND
+            NOT EXISTS (
+                SELECT 1 FROM storage_usage WHERE storage_usage.release_id = releases.id
+            )
+         ORDER BY releases.id
+         LIMIT $2",
+        Vec::new(),
+        options,
+    );
+
+    while let Some(row) = releases.next(conn)? {
+        let id: i32 = row.get("id");
+        let name: String = row.get("name");
+        let version: String = row.get("version");
+
+        match backfill_release(conn, storage, id, &name, &version, row.get(3))
+            .with_context(|| format!("failed to record the storage usage of {} {}", name, version))
+        {
+            Ok(size) => {
+                info!(
+                    "{} {} uses {} bytes ({} bytes uncompressed)",
+                    name, version, size.compressed, size.uncompressed
+                );
+                report.releases += 1;
+                report.size += size;
+            }
+            Err(err) => {
+                report.failed += 1;
+                report_error(&err);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+fn backfill_release(
+    conn: &mut Client,
+    storage: &Storage,
+    release_id: i32,
+    name: &str,
+    version: &str,
+    archive_storage: bool,
+) -> Result<StorageSize> {
+    let mut usage = Vec::new();
+    for (kind, archive_path, prefix) in [
+        (
+            UsageKind::Rustdoc,
+            rustdoc_archive_path(name, version),
+            format!("rustdoc/{}/{}/", name, version),
+        ),
+        (
+            UsageKind::Sources,
+            source_archive_path(name, version),
+            format!("sources/{}/{}/", name, version),
+        ),
+    ] {
+        let archive_usage = if archive_storage {
+            archive_usage(conn, storage, &archive_path)?
+        } else {
+            prefix_size(storage, &prefix)?.map(|size| ArchiveUsage {
+                size,
+                blobs: Vec::new(),
+            })
+        };
+        if let Some(archive_usage) = archive_usage {
+            usage.push((kind, archive_usage));
+        }
+    }
+
+    let mut build_logs = None;
+    for row in conn.query("SELECT id FROM builds WHERE rid = $1", &[&release_id])? {
+        let build_id: i32 = row.get("id");
+        if let Some(size) = prefix_size(storage, &format!("build-logs/{}/", build_id))? {
+            *build_logs.get_or_insert_with(StorageSize::default) += size;
+        }
+    }
+
+    let mut total = StorageSize::default();
+    let mut transaction = conn.transaction()?;
+    for (kind, archive_usage) in usage {
+        record_storage_usage(&mut transaction, storage, release_id, kind, &archive_usage)?;
+        total += archive_usage.size;
+        for (_, size) in archive_usage.blobs {
+            total += size;
+        }
+    }
+    if let Some(size) = build_logs {
+        record_storage_usage(
+            &mut transaction,
+            storage,
+            release_id,
+            UsageKind::BuildLogs,
+            &ArchiveUsage {
+                size,
+                blobs: Vec::new(),
+            },
+        )?;
+        total += size;
+    }
+    transaction.commit()?;
+    Ok(total)
+}
+
+/// The size of an archive and its index, or of the manifest and the blobs not recorded yet of a
+/// content-addressed release, or `None` if there is no archive.
+///
+/// Only the sizes listed by the storage backend and the central directory of the archive are
+/// read, not the files themselves.
+fn archive_usage(
+    conn: &mut Client,
+    storage: &Storage,
+    archive_path: &str,
+) -> Result<Option<ArchiveUsage>> {
+    let index_path = format!("{}.index", archive_path);
+    let mut archive_size = None;
+    let mut usage = ArchiveUsage::default();
+    for object in storage.list_page(archive_path, "", 2)? {
+        if object.path == archive_path {
+            archive_size = Some(object.size);
+        } else if object.path != index_path {
+            continue;
+        }
+        usage.size.compressed += object.size;
+    }
+
+    if let Some(archive_size) = archive_size {
+        usage.size.uncompressed = zip_uncompressed_size(storage, archive_path, archive_size)?;
+        return Ok(Some(usage));
+    }
+
+    // content-addressed releases only have the manifest in place of the index
+    let manifest = match storage.get_remote_index(archive_path) {
+        Ok(manifest) => manifest,
+        // releases without documentation only have a source archive
+        Err(err) if err.is::<PathNotFoundError>() => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    usage.size.uncompressed = manifest.len() as u64;
+    let hashes = archive_index::blobs_in_slice(&manifest)?
+        .context("the index of a release without an archive isn't a manifest")?;
+
+    let paths: Vec<String> = hashes
+        .iter()
+        .map(dedup::blob_path)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let recorded: HashSet<String> = conn
+        .query(
+            "SELECT path FROM blob_usage WHERE backend = $1 AND path = ANY($2)",
+            &[&storage.backend_name(), &paths],
+        )?
+        .iter()
+        .map(|row| row.get("path"))
+        .collect();
+    for path in paths {
+        if recorded.contains(&path) {
+            continue;
+        }
+        // blob paths all have the same length, so no other blob starts with this path
+        if let Some(object) = storage.list_page(&path, "", 1)?.pop() {
+            let size = StorageSize {
+                compressed: object.size,
+                uncompressed: object.size,
+            };
+            usage.blobs.push((path, size));
+        }
+    }
+    Ok(Some(usage))
+}
+
+/// Sums up the uncompressed size of the entries of a zip archive, reading only its central
+/// directory from the storage backend.
+fn zip_uncompressed_size(storage: &Storage, archive_path: &str, archive_size: u64) -> Result<u64> {
+    const END_SIZE: u64 = 22;
+    const ZIP64_LOCATOR_SIZE: u64 = 20;
+    const ZIP64_END_SIZE: u64 = 56;
+    const ENTRY_SIZE: usize = 46;
+    const ZIP64_EXTRA_ID: u64 = 0x0001;
+
+    let read = |start: u64, len: u64| -> Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        Ok(storage
+            .get_range(archive_path, len as usize, start..=start + len - 1, None)?
+            .content)
+    };
+
+    // the archives are written without a comment, so the end of the central directory is at the
+    // very end, and the locator of the zip64 end of large archives right before it
+    let tail_len = archive_size.min(END_SIZE + ZIP64_LOCATOR_SIZE);
+    if tail_len < END_SIZE {
+        bail!("{} is too short for a zip archive", archive_path);
+    }
+    let tail = read(archive_size - tail_len, tail_len)?;
+    let end = &tail[tail.len() - END_SIZE as usize..];
+    if le(&end[0..4]) != 0x0605_4b50 {
+        bail!("{} doesn't end with a zip central directory", archive_path);
+    }
+    let (mut directory_size, mut directory_start) = (le(&end[12..16]), le(&end[16..20]));
+    if directory_size == 0xffff_ffff || directory_start == 0xffff_ffff {
+        let locator = &tail[..ZIP64_LOCATOR_SIZE as usize];
+        if tail.len() < (END_SIZE + ZIP64_LOCATOR_SIZE) as usize
+            || le(&locator[0..4]) != 0x0706_4b50
+        {
+            bail!("{} has no zip64 end of the central directory", archive_path);
+        }
+        let zip64_end = read(le(&locator[8..16]), ZIP64_END_SIZE)?;
+        if le(&zip64_end[0..4]) != 0x0606_4b50 {
+            bail!("{} has no zip64 end of the central directory", archive_path);
+        }
+        directory_size = le(&zip64_end[40..48]);
+        directory_start = le(&zip64_end[48..56]);
+    }
+
+    let directory = read(directory_start, directory_size)?;
+    let mut uncompressed = 0;
+    let mut rest = &directory[..];
+    while !rest.is_empty() {
+        if rest.len() < ENTRY_SIZE || le(&rest[0..4]) != 0x0201_4b50 {
+            bail!("{} has a broken zip central directory", archive_path);
+        }
+        let name_len = le(&rest[28..30]) as usize;
+        let extra_len = le(&rest[30..32]) as usize;
+        let comment_len = le(&rest[32..34]) as usize;
+        let entry_len = ENTRY_SIZE + name_len + extra_len + comment_len;
+        if rest.len() < entry_len {
+            bail!("{} has a broken zip central directory", archive_path);
+        }
+
+        let mut size = le(&rest[24..28]);
+        if size == 0xffff_ffff {
+            // the real size is the first field of the zip64 extra field
+            let mut extra = &rest[ENTRY_SIZE + name_len..ENTRY_SIZE + name_len + extra_len];
+            while extra.len() >= 4 {
+                let field_len = le(&extra[2..4]) as usize;
+                if le(&extra[0..2]) == ZIP64_EXTRA_ID && field_len >= 8 && extra.len() >= 12 {
+                    size = le(&extra[4..12]);
+                    break;
+                }
+                extra = &extra[(4 + field_len).min(extra.len())..];
+            }
+        }
+        uncompressed += size;
+        rest = &rest[entry_len..];
+    }
+    Ok(uncompressed)
+}
+
+/// Reads a little endian number of up to eight bytes.
+fn le(bytes: &[u8]) -> u64 {
+    let mut buffer = [0; 8];
+    buffer[..bytes.len()].copy_from_slice(bytes);
+    u64::from_le_bytes(buffer)
+}
+
+/// The size of the separately stored files under `prefix`, or `None` if there are none.
+fn prefix_size(storage: &Storage, prefix: &str) -> Result<Option<StorageSize>> {
+    let mut size = None;
+    let mut start_after = String::new();
+    loop {
+        let page = storage.list_page(prefix, &start_after, LIST_PAGE_SIZE)?;
+        start_after = match page.last() {
+            Some(object) => object.path.clone(),
+            None => return Ok(size),
+        };
+
+        for object in page {
+            *size.get_or_insert_with(StorageSize::default) += StorageSize {
+                compressed: object.size,
+                uncompressed: object.size,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    fn recorded_usage(conn: &mut Client) -> Result<Vec<(String, String, StorageSize)>> {
+        Ok(conn
+            .query(
+                "SELECT releases.version, storage_usage.kind, storage_usage.compressed,
+                        storage_usage.uncompressed
+                 FROM storage_usage
+                 INNER JOIN releases ON releases.id = storage_usage.release_id
+                 ORDER BY releases.version, storage_usage.kind",
+                &[],
+            )?
+            .iter()
+            .map(|row| (row.get("version"), row.get("kind"), storage_size(row)))
+            .collect())
+    }
+
+    #[test]
+    fn usage_is_recorded_and_backfilled() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .archive_storage(true)
+                .rustdoc_file_with("foo/index.html", b"<html>foo</html>")
+                .source_file("src/lib.rs", b"//! foo")
+                .create()?;
+            env.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .rustdoc_file_with("foo/index.html", b"<html>foo 0.2</html>")
+                .create()?;
+            env.fake_release()
+                .name("bar")
+                .version("0.3.0")
+                .archive_storage(true)
+                .create()?;
+
+            let mut conn = env.db().conn();
+            let recorded = recorded_usage(&mut conn)?;
+            let kinds: Vec<_> = recorded
+                .iter()
+                .map(|(version, kind, _)| (version.as_str(), kind.as_str()))
+                .collect();
+            assert_eq!(
+                kinds,
+                [
+                    ("0.1.0", "build-logs"),
+                    ("0.1.0", "rustdoc"),
+                    ("0.1.0", "sources"),
+                    // only the archives are measured when they're stored
+                    ("0.2.0", "build-logs"),
+                    ("0.3.0", "build-logs"),
+                    ("0.3.0", "rustdoc"),
+                    ("0.3.0", "sources"),
+                ]
+            );
+            let (_, _, build_log) = &recorded[0];
+            assert_eq!(build_log.uncompressed, "It works!".len() as u64);
+
+            // the backfill measures the same sizes from the listed files and the archives, only
+            // the original size of separately stored files is unknown
+            conn.execute("DELETE FROM storage_usage", &[])?;
+            let storage = env.storage();
+            let options = ReleaseBatchOptions::default();
+            let report = backfill_storage_usage(&mut conn, &storage, &options)?;
+            assert_eq!((report.releases, report.failed), (3, 0));
+
+            let mut backfilled = recorded_usage(&mut conn)?;
+            let (version, kind, legacy_rustdoc) = backfilled.remove(4);
+            assert_eq!((version.as_str(), kind.as_str()), ("0.2.0", "rustdoc"));
+            assert_eq!(legacy_rustdoc.uncompressed, legacy_rustdoc.compressed);
+            for ((version, kind, size), (_, _, recorded_size)) in
+                backfilled.iter_mut().zip(&recorded)
+            {
+                assert_eq!(size.compressed, recorded_size.compressed);
+                if kind == "build-logs" {
+                    assert_eq!(size.uncompressed, size.compressed, "{}", version);
+                    size.uncompressed = recorded_size.uncompressed;
+                }
+            }
+            assert_eq!(backfilled, recorded);
+
+            let mut total = StorageSize::default();
+            for (_, _, size) in &recorded_usage(&mut conn)? {
+                total += *size;
+            }
+            assert_eq!(report.size, total);
+
+            let report = backfill_storage_usage(&mut conn, &storage, &options)?;
+            assert_eq!(report, UsageBackfillReport::default());
+
+            let report = storage_usage_report(&mut conn, 1)?;
+            let totals: Vec<_> = report
+                .totals
+                .iter()
+                .map(|total| (total.backend.as_str(), total.kind.as_str(), total.count))
+                .collect();
+            assert_eq!(
+                totals,
+                [
+                    ("database", "build-logs", 3),
+                    ("database", "rustdoc", 3),
+                    ("database", "sources", 2),
+                ]
+            );
+            assert_eq!(report.top_crates.len(), 1);
+            assert_eq!(report.top_crates[0].name, "foo");
+            assert_eq!(report.top_crates[0].releases, 2);
+
+            Ok(())
+        })
+    }
+
+    fn recorded_blobs(conn: &mut Client) -> Result<Vec<(String, StorageSize)>> {
+        Ok(conn
+            .query(
+                "SELECT path, compressed, uncompressed FROM blob_usage ORDER BY path",
+                &[],
+            )?
+            .iter()
+            .map(|row| (row.get("path"), storage_size(row)))
+            .collect())
+    }
+
+    #[test]
+    fn content_addressed_usage_is_backfilled() {
+        wrapper(|env| {
+            env.override_config(|config| config.content_addressed_storage = true);
+            for version in ["0.1.0", "0.2.0"] {
+                env.fake_release()
+                    .name("foo")
+                    .version(version)
+                    .archive_storage(true)
+                    .rustdoc_file_with("static.css", b"body {}")
+                    .rustdoc_file_with("foo/index.html", version.as_bytes())
+                    .create()?;
+            }
+
+            let mut conn = env.db().conn();
+            let recorded = recorded_usage(&mut conn)?;
+            let blobs = recorded_blobs(&mut conn)?;
+            // the shared blobs are only recorded once, the releases only count their manifests
+            let storage = env.storage();
+            let index = storage.get_remote_index("rustdoc/foo/0.1.0.zip")?;
+            let (_, _, rustdoc) = recorded[1];
+            assert_eq!(rustdoc.uncompressed, index.len() as u64);
+            let hashes: HashSet<_> = ["0.1.0", "0.2.0"]
+                .iter()
+                .flat_map(|version| {
+                    [
+                        rustdoc_archive_path("foo", version),
+                        source_archive_path("foo", version),
+                    ]
+                })
+                .map(|archive_path| {
+                    let index = storage.get_remote_index(&archive_path)?;
+                    Ok(archive_index::blobs_in_slice(&index)?.unwrap())
+                })
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .flatten()
+                .collect();
+            assert_eq!(blobs.len(), hashes.len());
+
+            let report = storage_usage_report(&mut conn, 1)?;
+            let total = report
+                .totals
+                .iter()
+                .find(|total| total.kind == BLOBS_KIND)
+                .unwrap();
+            assert_eq!(total.count, blobs.len() as u64);
+
+            conn.execute("DELETE FROM storage_usage", &[])?;
+            conn.execute("DELETE FROM blob_usage", &[])?;
+            let options = ReleaseBatchOptions::default();
+            let report = backfill_storage_usage(&mut conn, &storage, &options)?;
+            assert_eq!((report.releases, report.failed), (2, 0));
+            let archives = |usage: Vec<(String, String, StorageSize)>| {
+                usage
+                    .into_iter()
+                    .filter(|(_, kind, _)| kind != "build-logs")
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(archives(recorded_usage(&mut conn)?), archives(recorded));
+            let backfilled = recorded_blobs(&mut conn)?;
+            assert_eq!(backfilled.len(), blobs.len());
+            for ((path, size), (recorded_path, recorded_size)) in backfilled.iter().zip(&blobs) {
+                assert_eq!(path, recorded_path);
+                assert_eq!(size.compressed, recorded_size.compressed);
+            }
+
+            // deleted blobs aren't counted anymore
+            let paths: Vec<_> = blobs.iter().map(|(path, _)| path.clone()).collect();
+            forget_blob_usage(&mut *conn, &storage, &paths[..1])?;
+            assert_eq!(recorded_blobs(&mut conn)?.len(), blobs.len() - 1);
+
+            Ok(())
+        })
+    }
+}
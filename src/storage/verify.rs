@@ -0,0 +1,425 @@
+//! Checking that the files of releases are actually in the storage backend and readable.
+//!
+//! The problems found in a release are stored in the `storage_failures` table, replacing the
+//! ones found when it was checked before.
+
+use super::{
+    archive_index, rustdoc_archive_path, source_archive_path, PathNotFoundError,
+    ReleaseBatchOptions, ReleaseBatches,
+};
+use crate::error::Result;
+use crate::utils::report_error;
+use crate::{BuildQueue, Storage};
+use anyhow::Context as _;
+use log::{info, warn};
+use postgres::Client;
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub struct VerifyOptions {
+    pub releases: ReleaseBatchOptions,
+    /// The share of releases to check, between 0 and 1.
+    pub sample: f64,
+    /// The most entries read from each archive, spread over the whole archive. All entries are
+    /// read if it's `None`.
+    pub entries: Option<usize>,
+    /// Releases with problems are queued for a rebuild with this priority.
+    pub rebuild_priority: Option<i32>,
+}
+
+/// The outcome of a verification run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub releases: usize,
+    pub entries: usize,
+    pub broken: usize,
+    pub queued: usize,
+    /// Releases that couldn't be checked, for example because the storage backend wasn't
+    /// reachable.
+    pub failed: usize,
+    /// The id of the last release that was processed.
+    pub last_release_id: Option<i32>,
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "checked {} releases and {} archive entries, {} releases are broken, {} were queued \
+             for a rebuild, {} releases couldn't be checked",
+            self.releases, self.entries, self.broken, self.queued, self.failed
+        )?;
+        if let Some(id) = self.last_release_id {
+            write!(f, "\nlast release id: {}", id)?;
+        }
+        Ok(())
+    }
+}
+
+/// A problem with a file of a release.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StorageFailure {
+    path: String,
+    error: String,
+}
+
+/// Checks the files of the releases, one after another.
+pub fn verify_storage(
+    conn: &mut Client,
+    storage: &Storage,
+    build_queue: &BuildQueue,
+    options: &VerifyOptions,
+) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let mut releases = ReleaseBatches::new(
+        "SELECT
+            releases.id,
+            crates.name,
+            releases.version,
+            releases.rustdoc_status,
+            releases.archive_storage
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE
+            releases.id > $1 AND
+            ($3::FLOAT8 >= 1 OR random() < $3::FLOAT8)
+         ORDER BY releases.id
+         LIMIT $2",
+        vec![&options.sample],
+        &options.releases,
+    );
+
+    while let Some(row) = releases.next(conn)? {
+        let id: i32 = row.get("id");
+        let name: String = row.get("name");
+        let version: String = row.get("version");
+
+        let checked = verify_release(
+            storage,
+            &name,
+            &version,
+            row.get("rustdoc_status"),
+            row.get("archive_storage"),
+            options.entries,
+        )
+        .and_then(|(entries, failures)| {
+            record_failures(conn, id, &failures)?;
+            Ok((entries, failures))
+        })
+        .with_context(|| format!("failed to verify {} {}", name, version));
+
+        match checked {
+            Ok((entries, failures)) => {
+                report.releases += 1;
+                report.entries += entries;
+                if failures.is_empty() {
+                    info!("verified {} {}", name, version);
+                } else {
+                    warn!("{} {} has {} broken files", name, version, failures.len());
+                    report.broken += 1;
+                    if let Some(priority) = options.rebuild_priority {
+                        build_queue.add_crate(&name, &version, priority, None)?;
+                        report.queued += 1;
+                    }
+                }
+            }
+            Err(err) => {
+                report.failed += 1;
+                report_error(&err);
+            }
+        }
+        report.last_release_id = Some(id);
+    }
+
+    Ok(report)
+}
+
+/// Returns how many archive entries were read, and the problems found.
+fn verify_release(
+    storage: &Storage,
+    name: &str,
+    version: &str,
+    rustdoc_status: bool,
+    archive_storage: bool,
+    max_entries: Option<usize>,
+) -> Result<(usize, Vec<StorageFailure>)> {
+    let mut failures = Vec::new();
+    let mut failure = |path: String, error: String| failures.push(StorageFailure { path, error });
+
+    if !archive_storage {
+        let prefix = format!("rustdoc/{}/{}/", name, version);
+        if rustdoc_status && storage.list_prefix(&prefix)?.is_empty() {
+            failure(prefix, "the documentation is missing".into());
+        }
+        return Ok((0, failures));
+    }
+
+    let mut archives = vec![source_archive_path(name, version)];
+    if rustdoc_status {
+        archives.push(rustdoc_archive_path(name, version));
+    }
+
+    let mut entries = 0;
+    for archive_path in archives {
+        let index_path = format!("{}.index", archive_path);
+        let archive_exists = storage.exists(&archive_path)?;
+        // the remote index, a missing or broken one would be hidden by the local cache
+        let index = match storage.get_remote_index(&archive_path) {
+            Ok(index) => index,
+            Err(err) if err.is::<PathNotFoundError>() => {
+                if archive_exists {
+                    failure(index_path, "the index is missing".into());
+                } else {
+                    failure(archive_path, "the archive is missing".into());
+                }
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        // content-addressed releases only have a manifest in place of the index
+        if !archive_exists && !matches!(archive_index::blobs_in_slice(&index), Ok(Some(_))) {
+            failure(archive_path, "the archive is missing".into());
+            continue;
+        }
+
+        let paths = match archive_index::paths_in_slice(&index) {
+            Ok(paths) => paths,
+            Err(err) => {
+                failure(index_path, format!("{:#}", err));
+                continue;
+            }
+        };
+        for path in sample(&paths, max_entries) {
+            entries += 1;
+            if let Err(err) =
+                storage.get_from_archive_with_index(&archive_path, &index, path, usize::MAX)
+            {
+                failure(format!("{}/{}", archive_path, path), format!("{:#}", err));
+            }
+        }
+    }
+
+    Ok((entries, failures))
+}
+
+/// Picks at most `max` files spread evenly over all paths, skipping directories.
+fn sample(paths: &[String], max: Option<usize>) -> Vec<&str> {
+    let files: Vec<_> = paths
+        .iter()
+        .map(String::as_str)
+        .filter(|path| !path.ends_with('/'))
+        .collect();
+    match max {
+        Some(max) if max < files.len() => (0..max).map(|i| files[i * files.len() / max]).collect(),
+        _ => files,
+    }
+}
+
+fn record_failures(conn: &mut Client, release_id: i32, failures: &[StorageFailure]) -> Result<()> {
+    let mut transaction = conn.transaction()?;
+    transaction.execute(
+        "DELETE FROM storage_failures WHERE release_id = $1;",
+        &[&release_id],
+    )?;
+    for failure in failures {
+        transaction.execute(
+            "INSERT INTO storage_failures (release_id, path, error)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (release_id, path) DO UPDATE SET error = EXCLUDED.error;",
+            &[&release_id, &failure.path, &failure.error],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{wrapper, TestEnvironment};
+
+    fn options() -> VerifyOptions {
+        VerifyOptions {
+            releases: ReleaseBatchOptions::default(),
+            sample: 1.0,
+            entries: None,
+            rebuild_priority: None,
+        }
+    }
+
+    fn failures(env: &TestEnvironment, name: &str) -> Result<Vec<String>> {
+        Ok(env
+            .db()
+            .conn()
+            .query(
+                "SELECT storage_failures.path
+                 FROM storage_failures
+                 INNER JOIN releases ON releases.id = storage_failures.release_id
+                 INNER JOIN crates ON crates.id = releases.crate_id
+                 WHERE crates.name = $1
+                 ORDER BY storage_failures.path",
+                &[&name],
+            )?
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect())
+    }
+
+    #[test]
+    fn broken_releases_are_recorded_and_queued() {
+        wrapper(|env| {
+            for name in ["foo", "bar", "baz"] {
+                env.fake_release()
+                    .name(name)
+                    .version("0.1.0")
+                    .archive_storage(true)
+                    .rustdoc_file_with(&format!("{}/index.html", name), b"<html></html>")
+                    .source_file("src/lib.rs", b"//! lib")
+                    .create()?;
+            }
+            env.fake_release()
+                .name("legacy")
+                .version("0.1.0")
+                .create()?;
+
+            let storage = env.storage();
+            storage.delete_prefix("rustdoc/bar/0.1.0.zip")?;
+            storage.delete_prefix("sources/baz/0.1.0.zip.index")?;
+            storage.delete_prefix("rustdoc/legacy/")?;
+
+            let mut conn = env.db().conn();
+            let report = verify_storage(
+                &mut conn,
+                &storage,
+                &env.build_queue(),
+                &VerifyOptions {
+                    rebuild_priority: Some(10),
+                    ..options()
+                },
+            )?;
+            assert_eq!(
+                (report.releases, report.broken, report.queued, report.failed),
+                (4, 3, 3, 0)
+            );
+            assert!(failures(env, "foo")?.is_empty());
+            assert_eq!(failures(env, "bar")?, ["rustdoc/bar/0.1.0.zip"]);
+            assert_eq!(failures(env, "baz")?, ["sources/baz/0.1.0.zip.index"]);
+            assert_eq!(failures(env, "legacy")?, ["rustdoc/legacy/0.1.0/"]);
+
+            let queued: Vec<_> = env
+                .build_queue()
+                .queued_crates()?
+                .into_iter()
+                .map(|krate| (krate.name, krate.priority))
+                .collect();
+            assert_eq!(queued.len(), 3);
+            assert!(queued.contains(&("bar".into(), 10)));
+
+            // fixed releases lose their failures when they're checked again
+            env.fake_release()
+                .name("bar")
+                .version("0.1.0")
+                .archive_storage(true)
+                .create()?;
+            verify_storage(&mut conn, &storage, &env.build_queue(), &options())?;
+            assert!(failures(env, "bar")?.is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn unreadable_entries_are_recorded() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .archive_storage(true)
+                .source_file("src/lib.rs", b"//! lib")
+                .create()?;
+
+            // an archive that doesn't match its index anymore
+            let storage = env.storage();
+            storage.store_one("sources/foo/0.1.0.zip", b"not a zip".to_vec())?;
+
+            let mut conn = env.db().conn();
+            let report = verify_storage(&mut conn, &storage, &env.build_queue(), &options())?;
+            // the source file, and its page and the crate's index in the documentation
+            assert_eq!((report.entries, report.broken), (3, 1));
+            assert_eq!(failures(env, "foo")?, ["sources/foo/0.1.0.zip/src/lib.rs"]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn broken_remote_indexes_are_recorded() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .archive_storage(true)
+                .source_file("src/lib.rs", b"//! lib")
+                .create()?;
+
+            // the locally cached indexes are still fine
+            let storage = env.storage();
+            storage.get_from_archive("sources/foo/0.1.0.zip", "src/lib.rs", usize::MAX, None)?;
+            storage.store_one("sources/foo/0.1.0.zip.index", b"not an index".to_vec())?;
+            storage.delete_prefix("rustdoc/foo/0.1.0.zip.index")?;
+
+            let mut conn = env.db().conn();
+            let report = verify_storage(&mut conn, &storage, &env.build_queue(), &options())?;
+            assert_eq!(report.broken, 1);
+            assert_eq!(
+                failures(env, "foo")?,
+                ["rustdoc/foo/0.1.0.zip.index", "sources/foo/0.1.0.zip.index"]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn content_addressed_releases_are_verified() {
+        wrapper(|env| {
+            env.override_config(|config| config.content_addressed_storage = true);
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .archive_storage(true)
+                .source_file("src/lib.rs", b"//! lib")
+                .create()?;
+
+            let storage = env.storage();
+            let mut conn = env.db().conn();
+            let report = verify_storage(&mut conn, &storage, &env.build_queue(), &options())?;
+            assert_eq!((report.entries, report.broken), (3, 0));
+
+            let index = storage.get_remote_index("sources/foo/0.1.0.zip")?;
+            let blobs: Vec<_> = archive_index::blobs_in_slice(&index)?
+                .unwrap()
+                .iter()
+                .map(crate::storage::dedup::blob_path)
+                .collect();
+            storage.delete_paths(&blobs)?;
+            let report = verify_storage(&mut conn, &storage, &env.build_queue(), &options())?;
+            assert_eq!(report.broken, 1);
+            assert_eq!(failures(env, "foo")?, ["sources/foo/0.1.0.zip/src/lib.rs"]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn entries_are_sampled() {
+        let paths: Vec<_> = ["a", "b/", "b/c", "d", "e", "f"]
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        assert_eq!(sample(&paths, None), ["a", "b/c", "d", "e", "f"]);
+        assert_eq!(sample(&paths, Some(2)), ["a", "d"]);
+        assert_eq!(sample(&paths, Some(10)).len(), 5);
+    }
+}
@@ -1,9 +1,14 @@
 use super::TestDatabase;
 
-use crate::docbuilder::{BuildResult, DocCoverage};
+use crate::docbuilder::{
+    BuildResult, DocCoverage, ExceededLimit, FeatureItem, FileCoverage, Limits, UndocumentedItem,
+};
 use crate::error::Result;
 use crate::index::api::{CrateData, CrateOwner, ReleaseData};
-use crate::storage::{rustdoc_archive_path, source_archive_path, Storage};
+use crate::storage::{
+    add_storage_usage, record_storage_usage, rustdoc_archive_path, source_archive_path, Storage,
+    UsageKind,
+};
 use crate::utils::{Dependency, MetadataPackage, Target};
 use anyhow::Context;
 use chrono::{DateTime, Utc};
@@ -22,6 +27,7 @@ pub(crate) struct FakeRelease<'a> {
     /// name, content
     rustdoc_files: Vec<(&'a str, &'a [u8])>,
     doc_targets: Vec<String>,
+    doc_feature_profiles: Vec<String>,
     default_target: Option<&'a str>,
     registry_crate_data: CrateData,
     registry_release_data: ReleaseData,
@@ -32,6 +38,9 @@ pub(crate) struct FakeRelease<'a> {
     readme: Option<&'a str>,
     github_stats: Option<FakeGithubStats>,
     doc_coverage: Option<DocCoverage>,
+    file_coverage: Vec<FileCoverage>,
+    undocumented_items: Vec<UndocumentedItem>,
+    feature_items: Vec<FeatureItem>,
 }
 
 pub(crate) struct FakeBuild {
@@ -81,6 +90,7 @@ impl<'a> FakeRelease<'a> {
             source_files: Vec::new(),
             rustdoc_files: Vec::new(),
             doc_targets: Vec::new(),
+            doc_feature_profiles: Vec::new(),
             default_target: None,
             registry_crate_data: CrateData { owners: Vec::new() },
             registry_release_data: ReleaseData {
@@ -93,6 +103,9 @@ impl<'a> FakeRelease<'a> {
             readme: None,
             github_stats: None,
             doc_coverage: None,
+            file_coverage: Vec::new(),
+            undocumented_items: Vec::new(),
+            feature_items: Vec::new(),
             archive_storage: false,
         }
     }
@@ -182,6 +195,11 @@ impl<'a> FakeRelease<'a> {
         self
     }
 
+    pub(crate) fn add_feature_profile(mut self, profile: &str) -> Self {
+        self.doc_feature_profiles.push(profile.into());
+        self
+    }
+
     pub(crate) fn binary(mut self, bin: bool) -> Self {
         self.has_docs = !bin;
         if bin {
@@ -224,11 +242,69 @@ impl<'a> FakeRelease<'a> {
         }
     }
 
+    /// Adds the coverage of a source file, and the file to the total coverage of the release.
+    pub(crate) fn file_coverage(
+        mut self,
+        file: &str,
+        documented_items: i32,
+        total_items: i32,
+    ) -> Self {
+        let coverage = self.doc_coverage.get_or_insert(DocCoverage {
+            total_items: 0,
+            documented_items: 0,
+            total_items_needing_examples: 0,
+            items_with_examples: 0,
+        });
+        coverage.total_items += total_items;
+        coverage.documented_items += documented_items;
+
+        self.file_coverage.push(FileCoverage {
+            file: file.into(),
+            total_items,
+            documented_items,
+            total_items_needing_examples: 0,
+            items_with_examples: 0,
+        });
+        self
+    }
+
+    pub(crate) fn undocumented_item(
+        mut self,
+        module: &str,
+        kind: &str,
+        name: &str,
+        url: &str,
+    ) -> Self {
+        self.undocumented_items.push(UndocumentedItem {
+            module: module.into(),
+            kind: kind.into(),
+            name: name.into(),
+            url: url.into(),
+        });
+        self
+    }
+
     pub(crate) fn features(mut self, features: HashMap<String, Vec<String>>) -> Self {
         self.package.features = features;
         self
     }
 
+    pub(crate) fn feature_item(
+        mut self,
+        kind: &str,
+        path: &str,
+        url: &str,
+        features: &[&str],
+    ) -> Self {
+        self.feature_items.push(FeatureItem {
+            kind: kind.into(),
+            path: path.into(),
+            url: url.into(),
+            features: features.iter().map(|&feature| feature.into()).collect(),
+        });
+        self
+    }
+
     pub(crate) fn github_stats(
         mut self,
         repo: impl Into<String>,
@@ -309,7 +385,7 @@ impl<'a> FakeRelease<'a> {
             Ok(())
         };
 
-        let upload_files = |kind: FileKind, source_directory: &Path| {
+        let upload_files = |kind: FileKind, source_directory: &Path| -> Result<_> {
             log::debug!(
                 "adding directory {:?} from {}",
                 kind,
@@ -321,28 +397,29 @@ impl<'a> FakeRelease<'a> {
                     FileKind::Sources => source_archive_path(&package.name, &package.version),
                 };
                 log::debug!("store in archive: {:?}", archive);
-                let (files_list, new_alg) =
+                let (files_list, new_alg, size) =
                     crate::db::add_path_into_remote_archive(&storage, &archive, source_directory)?;
                 let mut hm = HashSet::new();
                 hm.insert(new_alg);
-                Ok((files_list, hm))
+                Ok((files_list, hm, Some(size)))
             } else {
                 let prefix = match kind {
                     FileKind::Rustdoc => "rustdoc",
                     FileKind::Sources => "sources",
                 };
-                crate::db::add_path_into_database(
+                let (files_list, algs) = crate::db::add_path_into_database(
                     &storage,
                     &format!("{}/{}/{}/", prefix, package.name, package.version),
                     source_directory,
-                )
+                )?;
+                Ok((files_list, algs, None))
             }
         };
 
         log::debug!("before upload source");
         let source_tmp = create_temp_dir();
         store_files_into(&self.source_files, source_tmp.path())?;
-        let (source_meta, algs) = upload_files(FileKind::Sources, source_tmp.path())?;
+        let (source_meta, algs, source_size) = upload_files(FileKind::Sources, source_tmp.path())?;
         log::debug!("added source files {}", source_meta);
 
         // If the test didn't add custom builds, inject a default one
@@ -351,6 +428,8 @@ impl<'a> FakeRelease<'a> {
         }
         let last_build_result = &self.builds.last().unwrap().result;
 
+        let mut rustdoc_size = None;
+
         if last_build_result.successful {
             let index = [&package.name, "index.html"].join("/");
             if package.is_library() && !rustdoc_files.iter().any(|(path, _)| path == &index) {
@@ -373,8 +452,17 @@ impl<'a> FakeRelease<'a> {
                 log::debug!("added platform files for {}", platform);
             }
 
-            let (rustdoc_meta, _) = upload_files(FileKind::Rustdoc, rustdoc_path)?;
+            for profile in &self.doc_feature_profiles {
+                let profile_dir = rustdoc_path.join(crate::storage::feature_profile_dir(profile));
+                fs::create_dir(&profile_dir)?;
+
+                store_files_into(&rustdoc_files, &profile_dir)?;
+                log::debug!("added feature profile files for {}", profile);
+            }
+
+            let (rustdoc_meta, _, size) = upload_files(FileKind::Rustdoc, rustdoc_path)?;
             log::debug!("uploaded rustdoc files: {}", rustdoc_meta);
+            rustdoc_size = size;
         }
 
         let repository = match self.github_stats {
@@ -400,6 +488,7 @@ impl<'a> FakeRelease<'a> {
             default_target,
             source_meta,
             self.doc_targets,
+            self.doc_feature_profiles,
             &self.registry_release_data,
             self.has_docs,
             self.has_examples,
@@ -412,12 +501,27 @@ impl<'a> FakeRelease<'a> {
             &package.name,
             &self.registry_crate_data,
         )?;
+        for (kind, size) in [
+            (UsageKind::Rustdoc, rustdoc_size),
+            (UsageKind::Sources, source_size),
+        ] {
+            if let Some(size) = size {
+                record_storage_usage(&mut *db.conn(), &storage, release_id, kind, &size)?;
+            }
+        }
         for build in &self.builds {
             build.create(&mut db.conn(), &*storage, release_id, default_target)?;
         }
         if let Some(coverage) = self.doc_coverage {
             crate::db::add_doc_coverage(&mut db.conn(), release_id, coverage)?;
         }
+        crate::db::add_coverage_details(
+            &mut db.conn(),
+            release_id,
+            &self.file_coverage,
+            &self.undocumented_items,
+        )?;
+        crate::db::add_feature_items(&mut db.conn(), release_id, &self.feature_items)?;
 
         Ok(release_id)
     }
@@ -500,6 +604,21 @@ impl FakeBuild {
         }
     }
 
+    pub(crate) fn exceeded_limit(mut self, limit: ExceededLimit) -> Self {
+        self.result.exceeded_limit = Some(limit);
+        self
+    }
+
+    pub(crate) fn escalated_limits(mut self, limits: Limits) -> Self {
+        self.result.escalated_limits = Some(limits);
+        self
+    }
+
+    pub(crate) fn metadata_warning(mut self, warning: impl Into<String>) -> Self {
+        self.result.metadata_warnings.push(warning.into());
+        self
+    }
+
     fn create(
         &self,
         conn: &mut Client,
@@ -518,7 +637,8 @@ impl FakeBuild {
 
         if let Some(s3_build_log) = self.s3_build_log.as_deref() {
             let path = format!("build-logs/{}/{}.txt", build_id, default_target);
-            storage.store_one(path, s3_build_log)?;
+            let (_, size) = storage.store_one(path, s3_build_log)?;
+            add_storage_usage(conn, storage, release_id, UsageKind::BuildLogs, size)?;
         }
 
         Ok(())
@@ -534,6 +654,9 @@ impl Default for FakeBuild {
                 rustc_version: "rustc 2.0.0-nightly (000000000 1970-01-01)".into(),
                 docsrs_version: "docs.rs 1.0.0 (000000000 1970-01-01)".into(),
                 successful: true,
+                metadata_warnings: Vec::new(),
+                exceeded_limit: None,
+                escalated_limits: None,
             },
         }
     }
@@ -145,9 +145,8 @@ impl TestEnvironment {
     }
 
     fn cleanup(self) {
-        if let Some(frontend) = self.frontend.into_inner() {
-            frontend.server.leak();
-        }
+        // stop the web server before cleaning up the storage it serves
+        drop(self.frontend.into_inner());
         if let Some(storage) = self.storage.get() {
             storage
                 .cleanup_after_test()
@@ -181,6 +180,10 @@ impl TestEnvironment {
         config.local_archive_cache_path =
             std::env::temp_dir().join(format!("docsrs-test-index-{}", rand::random::<u64>()));
 
+        // Tests modify releases without notifying the web server, so don't cache pages.
+        config.crate_details_cache_size = 0;
+        config.rustdoc_html_cache_size = 0;
+
         config
     }
 
@@ -424,6 +427,12 @@ impl TestFrontend {
         self.client.request(Method::GET, url)
     }
 
+    pub(crate) fn post(&self, url: &str) -> RequestBuilder {
+        let url = self.build_url(url);
+        log::debug!("posting {url}");
+        self.client.request(Method::POST, url)
+    }
+
     pub(crate) fn get_no_redirect(&self, url: &str) -> RequestBuilder {
         let url = self.build_url(url);
         log::debug!("getting {url} (no redirects)");
@@ -41,6 +41,10 @@ impl CargoMetadata {
     pub(crate) fn root(&self) -> &Package {
         &self.root
     }
+
+    pub(crate) fn into_root(self) -> Package {
+        self.root
+    }
 }
 
 #[derive(Deserialize, Serialize, Default)]
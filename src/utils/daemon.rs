@@ -3,8 +3,9 @@
 //! This daemon will start web server, track new packages and build them
 
 use crate::{
-    utils::{queue_builder, report_error},
-    BuildQueue, Config, Context, Index, RustwideBuilder,
+    cdn,
+    utils::{report_error, start_build_workers},
+    BuildQueue, Config, Context, Index,
 };
 use anyhow::{anyhow, Context as _, Error};
 use log::{debug, info};
@@ -94,12 +95,38 @@ pub fn start_background_repository_stats_updater(context: &dyn Context) -> Resul
     Ok(())
 }
 
+/// Sends the invalidations queued by builds and deletions to the CDN, if one is configured.
+fn start_cdn_invalidation(context: &dyn Context) -> Result<(), Error> {
+    let backend = match cdn::create_backend(&*context.config()?)? {
+        Some(backend) => backend,
+        None => return Ok(()),
+    };
+    let pool = context.pool()?;
+    let metrics = context.metrics()?;
+    cron("cdn invalidation", Duration::from_secs(60), move || {
+        let mut conn = pool.get()?;
+        while cdn::handle_queued_invalidations(&mut conn, &*backend, &metrics)? > 0 {}
+        Ok(())
+    })
+}
+
+/// Returns the crates of expired leases to the queue, if remote builders are enabled.
+pub fn start_lease_expiration(context: &dyn Context) -> Result<(), Error> {
+    if context.config()?.remote_builder_token.is_none() {
+        return Ok(());
+    }
+    let build_queue = context.build_queue()?;
+    cron("lease expiration", Duration::from_secs(60), move || {
+        build_queue.expire_leases()
+    })
+}
+
 pub fn start_daemon(context: &dyn Context, enable_registry_watcher: bool) -> Result<(), Error> {
     // Start the web server before doing anything more expensive
     // Please check with an administrator before changing this (see #1172 for context).
     info!("Starting web server");
     let server = crate::Server::start(None, context)?;
-    let server_thread = thread::spawn(|| drop(server));
+    let server_thread = thread::spawn(|| server.wait());
 
     if enable_registry_watcher {
         // check new crates every minute
@@ -107,18 +134,13 @@ pub fn start_daemon(context: &dyn Context, enable_registry_watcher: bool) -> Res
     }
 
     // build new crates every minute
-    let build_queue = context.build_queue()?;
-    let rustwide_builder = RustwideBuilder::init(context)?;
-    thread::Builder::new()
-        .name("build queue reader".to_string())
-        .spawn(move || {
-            queue_builder(rustwide_builder, build_queue).unwrap();
-        })
-        .unwrap();
+    start_build_workers(context, context.build_queue()?)?;
 
     start_background_repository_stats_updater(context)?;
+    start_cdn_invalidation(context)?;
+    start_lease_expiration(context)?;
 
-    // Never returns; `server` blocks indefinitely when dropped
+    // Never returns; `server` only stops when it fails
     // NOTE: if a anyhow occurred earlier in `start_daemon`, the server will _not_ be joined -
     // instead it will get killed when the process exits.
     server_thread
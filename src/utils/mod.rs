@@ -2,10 +2,10 @@
 
 pub(crate) use self::cargo_metadata::{CargoMetadata, Package as MetadataPackage};
 pub(crate) use self::copy::copy_dir_all;
-pub use self::daemon::{start_daemon, watch_registry};
+pub use self::daemon::{start_daemon, start_lease_expiration, watch_registry};
 pub(crate) use self::html::rewrite_lol;
 pub use self::queue::{get_crate_priority, remove_crate_priority, set_crate_priority};
-pub use self::queue_builder::queue_builder;
+pub use self::queue_builder::start_build_workers;
 pub(crate) use self::rustc_version::{get_correct_docsrs_style_file, parse_rustc_version};
 
 #[cfg(test)]
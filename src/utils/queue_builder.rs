@@ -1,19 +1,58 @@
-use crate::{docbuilder::RustwideBuilder, utils::report_error, BuildQueue};
-use anyhow::{Context, Error};
-use log::{debug, error, warn};
+use crate::{docbuilder::RustwideBuilder, utils::report_error, BuildQueue, Context};
+use anyhow::{Context as _, Error};
+use log::{debug, error, info, warn};
 use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 use std::time::Duration;
-use std::{fs, io, thread};
+use std::{fs, io};
 
 pub(crate) const TEMPDIR_PREFIX: &str = "docsrs-docs";
 
-pub fn queue_builder(
-    mut builder: RustwideBuilder,
+/// Starts the threads building crates from the queue, as many as configured with
+/// `DOCSRS_BUILD_WORKERS`.
+pub fn start_build_workers(
+    context: &dyn Context,
     build_queue: Arc<BuildQueue>,
-) -> Result<(), Error> {
+) -> Result<Vec<JoinHandle<()>>, Error> {
+    if let Err(e) = remove_tempdirs(TEMPDIR_PREFIX) {
+        report_error(&anyhow::anyhow!(e).context("failed to remove temporary directories"));
+    }
+
+    // Creating a builder purges all build directories, so they are all created before the first
+    // build starts.
+    let workers = context.config()?.build_workers.max(1);
+    let builders = (0..workers)
+        .map(|id| {
+            let mut builder = RustwideBuilder::init(context)?;
+            // every worker only cleans up its own temporary directories
+            builder.set_tempdir_prefix(worker_tempdir_prefix(id));
+            Ok(builder)
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    info!("starting {} build workers", workers);
+
+    builders
+        .into_iter()
+        .enumerate()
+        .map(|(id, builder)| {
+            let build_queue = build_queue.clone();
+            thread::Builder::new()
+                .name(format!("build worker {}", id))
+                .spawn(move || queue_builder(builder, build_queue))
+                .map_err(Into::into)
+        })
+        .collect()
+}
+
+/// The prefix of the temporary directories of the build worker `id`.
+fn worker_tempdir_prefix(id: usize) -> String {
+    format!("{}-worker{}-", TEMPDIR_PREFIX, id)
+}
+
+fn queue_builder(mut builder: RustwideBuilder, build_queue: Arc<BuildQueue>) {
     loop {
-        if let Err(e) = remove_tempdirs() {
+        if let Err(e) = remove_tempdirs(builder.tempdir_prefix()) {
             report_error(&anyhow::anyhow!(e).context("failed to remove temporary directories"));
         }
 
@@ -57,8 +96,8 @@ pub fn queue_builder(
 
 /// Sometimes, when the server hits a hard crash or a build thread panics,
 /// rustwide_builder won't actually remove the temporary directories it creates.
-/// Remove them now to avoid running out of disk space.
-fn remove_tempdirs() -> Result<(), io::Error> {
+/// Remove the ones starting with `prefix` now to avoid running out of disk space.
+fn remove_tempdirs(prefix: &str) -> Result<(), io::Error> {
     // NOTE: hardcodes that `tempfile::tempdir()` uses `std::env::temp_dir`.
     for entry in std::fs::read_dir(std::env::temp_dir())? {
         let entry = entry?;
@@ -67,7 +106,7 @@ fn remove_tempdirs() -> Result<(), io::Error> {
         }
 
         if let Some(dir_name) = entry.path().file_name() {
-            if dir_name.to_string_lossy().starts_with(TEMPDIR_PREFIX) {
+            if dir_name.to_string_lossy().starts_with(prefix) {
                 fs::remove_dir_all(entry.path())?;
             }
         }
@@ -101,7 +140,7 @@ mod tests {
 
         assert!(dir_with_prefix.path().exists());
 
-        remove_tempdirs().unwrap();
+        remove_tempdirs(TEMPDIR_PREFIX).unwrap();
 
         assert!(!dir_with_prefix.path().exists());
         assert!(!file_inside.exists());
@@ -111,4 +150,21 @@ mod tests {
         assert!(other_file.path().exists());
         assert!(other_dir.path().exists());
     }
+
+    #[test]
+    fn workers_only_remove_their_tempdirs() {
+        let own_dir = tempfile::Builder::new()
+            .prefix(&worker_tempdir_prefix(0))
+            .tempdir()
+            .unwrap();
+        let other_dir = tempfile::Builder::new()
+            .prefix(&worker_tempdir_prefix(1))
+            .tempdir()
+            .unwrap();
+
+        remove_tempdirs(&worker_tempdir_prefix(0)).unwrap();
+
+        assert!(!own_dir.path().exists());
+        assert!(other_dir.path().exists());
+    }
 }
@@ -1,13 +1,18 @@
 use crate::{
     db::Pool,
+    docbuilder::Limits,
     impl_webpage,
-    web::{file::File, page::WebPage, MetaData, Nope},
+    web::{error::AxumResult, file::File, MetaData, Nope},
     Config, Storage,
 };
+use anyhow::Context as _;
+use axum::{
+    extract::{Extension, Path},
+    response::IntoResponse,
+};
 use chrono::{DateTime, Utc};
-use iron::{IronError, IronResult, Request, Response};
-use router::Router;
 use serde::Serialize;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub(crate) struct BuildDetails {
@@ -17,6 +22,9 @@ pub(crate) struct BuildDetails {
     build_status: bool,
     build_time: DateTime<Utc>,
     output: String,
+    metadata_warnings: Vec<String>,
+    exceeded_limit: Option<String>,
+    escalated_limits: Option<Limits>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
@@ -29,66 +37,78 @@ impl_webpage! {
     BuildDetailsPage = "crate/build_details.html",
 }
 
-pub fn build_details_handler(req: &mut Request) -> IronResult<Response> {
-    let storage = extension!(req, Storage);
-    let config = extension!(req, Config);
-    let router = extension!(req, Router);
-    let name = cexpect!(req, router.find("name"));
-    let version = cexpect!(req, router.find("version"));
-    let id: i32 = cexpect!(req, router.find("id"))
-        .parse()
-        .map_err(|_| -> IronError { Nope::BuildNotFound.into() })?;
-
-    let mut conn = extension!(req, Pool).get()?;
-
-    let row = ctry!(
-        req,
-        conn.query_opt(
-            "SELECT
-                builds.rustc_version,
-                builds.docsrs_version,
-                builds.build_status,
-                builds.build_time,
-                builds.output,
-                releases.default_target
-             FROM builds
-             INNER JOIN releases ON releases.id = builds.rid
-             INNER JOIN crates ON releases.crate_id = crates.id
-             WHERE builds.id = $1 AND crates.name = $2 AND releases.version = $3",
-            &[&id, &name, &version]
-        )
-    );
-
-    let build_details = if let Some(row) = row {
-        let output = if let Some(output) = row.get("output") {
-            output
-        } else {
-            let target: String = row.get("default_target");
-            let path = format!("build-logs/{}/{}.txt", id, target);
-            let file = ctry!(req, File::from_path(storage, &path, config));
-            ctry!(req, String::from_utf8(file.0.content))
-        };
-        BuildDetails {
-            id,
-            rustc_version: row.get("rustc_version"),
-            docsrs_version: row.get("docsrs_version"),
-            build_status: row.get("build_status"),
-            build_time: row.get("build_time"),
-            output,
-        }
+pub(crate) async fn build_details_handler(
+    Path((name, version, id)): Path<(String, String, String)>,
+    Extension(pool): Extension<Pool>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(storage): Extension<Arc<Storage>>,
+) -> AxumResult<impl IntoResponse> {
+    let id: i32 = id.parse().map_err(|_| Nope::BuildNotFound)?;
+
+    let (row, metadata) = super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+
+        let row = conn
+            .query_opt(
+                "SELECT
+                    builds.rustc_version,
+                    builds.docsrs_version,
+                    builds.build_status,
+                    builds.build_time,
+                    builds.output,
+                    builds.metadata_warnings,
+                    builds.exceeded_limit,
+                    builds.escalated_limits,
+                    releases.default_target
+                 FROM builds
+                 INNER JOIN releases ON releases.id = builds.rid
+                 INNER JOIN crates ON releases.crate_id = crates.id
+                 WHERE builds.id = $1 AND crates.name = $2 AND releases.version = $3",
+                &[&id, &name, &version],
+            )?
+            .ok_or(Nope::BuildNotFound)?;
+
+        let metadata = MetaData::from_crate(&mut conn, &name, &version, &version)
+            .ok_or(Nope::CrateNotFound)?;
+        Ok((row, metadata))
+    })
+    .await?;
+
+    let output = if let Some(output) = row.get("output") {
+        output
     } else {
-        return Err(Nope::BuildNotFound.into());
+        let target: String = row.get("default_target");
+        let path = format!("build-logs/{}/{}.txt", id, target);
+        let file = File::from_path(&storage, &path, &config).await?;
+        String::from_utf8(file.0.content).context("the build log is not valid UTF-8")?
+    };
+
+    let build_details = BuildDetails {
+        id,
+        rustc_version: row.get("rustc_version"),
+        docsrs_version: row.get("docsrs_version"),
+        build_status: row.get("build_status"),
+        build_time: row.get("build_time"),
+        output,
+        metadata_warnings: serde_json::from_value(row.get("metadata_warnings"))
+            .context("invalid metadata warnings")?,
+        exceeded_limit: row.get("exceeded_limit"),
+        escalated_limits: row
+            .get::<_, Option<serde_json::Value>>("escalated_limits")
+            .map(serde_json::from_value)
+            .transpose()
+            .context("invalid escalated limits")?,
     };
 
-    BuildDetailsPage {
-        metadata: cexpect!(req, MetaData::from_crate(&mut conn, name, version, version)),
+    Ok(BuildDetailsPage {
+        metadata,
         build_details,
-    }
-    .into_response(req)
+    })
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::docbuilder::{ExceededLimit, Limits};
     use crate::test::{wrapper, FakeBuild};
     use kuchiki::traits::TendrilSink;
     use test_case::test_case;
@@ -188,6 +208,79 @@ mod tests {
         });
     }
 
+    #[test]
+    fn metadata_warnings() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .builds(vec![
+                    FakeBuild::default().metadata_warning("unknown key `all_features`")
+                ])
+                .create()?;
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/0.1.0/builds")
+                    .send()?
+                    .text()?,
+            );
+
+            let node = page.select("ul > li a.release").unwrap().next().unwrap();
+            let attrs = node.attributes.borrow();
+            let url = attrs.get("href").unwrap();
+
+            let page = kuchiki::parse_html().one(env.frontend().get(url).send()?.text()?);
+
+            let log = page.select("pre").unwrap().next().unwrap().text_contents();
+
+            assert!(log.contains("# docs.rs metadata warnings\nunknown key `all_features`\n"));
+            assert!(log.contains("It works!"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn escalated_limits() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .builds(vec![
+                    FakeBuild::default()
+                        .successful(false)
+                        .exceeded_limit(ExceededLimit::Timeout),
+                    FakeBuild::default().escalated_limits(Limits::default()),
+                ])
+                .create()?;
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/0.1.0/builds")
+                    .send()?
+                    .text()?,
+            );
+
+            let mut logs = Vec::new();
+            for node in page.select("ul > li a.release").unwrap() {
+                let url = node.attributes.borrow().get("href").unwrap().to_owned();
+                let page = kuchiki::parse_html().one(env.frontend().get(&url).send()?.text()?);
+                logs.push(page.select("pre").unwrap().next().unwrap().text_contents());
+            }
+
+            assert_eq!(logs.len(), 2);
+            assert!(logs
+                .iter()
+                .any(|log| log.contains("# docs.rs exceeded limit\ntimeout\n")));
+            assert!(logs
+                .iter()
+                .any(|log| log.contains("# docs.rs escalated limits\nmemory: 3 GB\n")));
+
+            Ok(())
+        });
+    }
+
     #[test_case("42")]
     #[test_case("nan")]
     fn non_existing_build(build_id: &str) {
@@ -0,0 +1,539 @@
+//! The API used by remote builders to lease crates from the build queue and to upload their
+//! builds, see [`crate::docbuilder::RemoteBuilder`].
+//!
+//! All endpoints require the token configured with `DOCSRS_REMOTE_BUILDER_TOKEN`, and the API
+//! is disabled without one.
+
+use super::{
+    error::{AxumResult, Nope},
+    has_bearer_token,
+};
+use crate::{
+    db::Pool,
+    docbuilder::{extract_build_archive, LeaseResponse, Publisher},
+    index::api::Api,
+    repositories::RepositoryStatsUpdater,
+    utils::queue_builder::TEMPDIR_PREFIX,
+    BuildQueue, Config, Index, Metrics, Storage,
+};
+use anyhow::Context as _;
+use axum::{
+    extract::{BodyStream, ConnectInfo, Extension, Path, Query},
+    headers::{ContentLength, HeaderMapExt},
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+use log::warn;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::{
+    io::{Seek, Write},
+    net::SocketAddr,
+    sync::Arc,
+};
+
+/// The longest error message of a failed build remote builders can send, in bytes.
+const MAX_ERROR_SIZE: u64 = 1024 * 1024;
+
+/// The registry index, which is only opened once the first build is published: cloning it can
+/// take a while, and web servers without remote builders never need it.
+pub(super) struct RegistryIndex {
+    config: Arc<Config>,
+    index: OnceCell<Index>,
+}
+
+impl RegistryIndex {
+    pub(super) fn new(config: Arc<Config>) -> Self {
+        Self {
+            config,
+            index: OnceCell::new(),
+        }
+    }
+
+    /// The API of the registry, if its index could be opened.
+    fn api(&self) -> Option<&Api> {
+        match self
+            .index
+            .get_or_try_init(|| Index::from_config(&self.config))
+        {
+            Ok(index) => Some(index.api()),
+            Err(err) => {
+                warn!("failed to open the registry index: {:#}", err);
+                None
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for RegistryIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RegistryIndex")
+            .field("opened", &self.index.get().is_some())
+            .finish()
+    }
+}
+
+/// Checks the token of the remote builder, returning the response to send if it's invalid.
+fn unauthorized(config: &Config, headers: &HeaderMap) -> AxumResult<Option<Response>> {
+    let token = config
+        .remote_builder_token
+        .as_deref()
+        .ok_or(Nope::ResourceNotFound)?;
+
+    Ok(if has_bearer_token(headers, token) {
+        None
+    } else {
+        Some((StatusCode::UNAUTHORIZED, "invalid remote builder token").into_response())
+    })
+}
+
+fn lease_lost() -> Response {
+    (StatusCode::CONFLICT, "the lease expired").into_response()
+}
+
+/// Copies the request body to `dest`, returning the response to send if it's larger than
+/// `limit` bytes.
+async fn read_body(
+    headers: &HeaderMap,
+    mut body: BodyStream,
+    limit: u64,
+    mut dest: impl Write,
+) -> AxumResult<Option<Response>> {
+    let too_large = || {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("the request body is larger than {} bytes", limit),
+        )
+            .into_response()
+    };
+    if matches!(headers.typed_get::<ContentLength>(), Some(ContentLength(length)) if length > limit)
+    {
+        return Ok(Some(too_large()));
+    }
+
+    let mut copied = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.context("failed to read the request body")?;
+        copied += chunk.len() as u64;
+        if copied > limit {
+            return Ok(Some(too_large()));
+        }
+        dest.write_all(&chunk)
+            .context("failed to write the request body")?;
+    }
+    Ok(None)
+}
+
+#[derive(Deserialize)]
+pub(super) struct LeaseParams {
+    builder: Option<String>,
+}
+
+/// `POST /-/builder/lease?builder=<name>`, leases the next crate in the queue.
+///
+/// Responds with a [`LeaseResponse`], or with `204 No Content` if the queue is empty.
+pub(super) async fn lease_handler(
+    Query(params): Query<LeaseParams>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(build_queue): Extension<Arc<BuildQueue>>,
+    Extension(pool): Extension<Pool>,
+    headers: HeaderMap,
+) -> AxumResult<Response> {
+    if let Some(response) = unauthorized(&config, &headers)? {
+        return Ok(response);
+    }
+
+    let builder = params.builder.unwrap_or_else(|| remote_addr.to_string());
+
+    super::spawn_blocking(move || {
+        let lease = match build_queue.lease_next_crate(&builder)? {
+            Some(lease) => lease,
+            None => return Ok(StatusCode::NO_CONTENT.into_response()),
+        };
+
+        let mut conn = pool.get()?;
+        let limits = lease.limits(&mut conn)?;
+        let response = LeaseResponse {
+            lease,
+            limits,
+            lease_duration: config.remote_build_lease_duration,
+        };
+
+        Ok((
+            [(CONTENT_TYPE, "application/json")],
+            serde_json::to_string(&response).context("failed to serialize the lease")?,
+        )
+            .into_response())
+    })
+    .await
+}
+
+/// `POST /-/builder/lease/:lease/heartbeat`, renews the lease while the crate is built.
+pub(super) async fn heartbeat_handler(
+    Path(lease_id): Path<String>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(build_queue): Extension<Arc<BuildQueue>>,
+    headers: HeaderMap,
+) -> AxumResult<Response> {
+    if let Some(response) = unauthorized(&config, &headers)? {
+        return Ok(response);
+    }
+
+    super::spawn_blocking(move || {
+        Ok(match build_queue.renew_lease(&lease_id)? {
+            Some(_) => StatusCode::OK.into_response(),
+            None => lease_lost(),
+        })
+    })
+    .await
+}
+
+/// `POST /-/builder/lease/:lease/fail`, returns the crate to the queue after a failed build.
+///
+/// The body contains the error, which is reported like the errors of local builds.
+pub(super) async fn fail_handler(
+    Path(lease_id): Path<String>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(build_queue): Extension<Arc<BuildQueue>>,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> AxumResult<Response> {
+    if let Some(response) = unauthorized(&config, &headers)? {
+        return Ok(response);
+    }
+
+    let mut error = Vec::new();
+    if let Some(response) = read_body(&headers, body, MAX_ERROR_SIZE, &mut error).await? {
+        return Ok(response);
+    }
+    let error = String::from_utf8_lossy(&error).into_owned();
+
+    super::spawn_blocking(move || {
+        Ok(if build_queue.finish_lease(&lease_id, Err(error))? {
+            StatusCode::OK.into_response()
+        } else {
+            lease_lost()
+        })
+    })
+    .await
+}
+
+/// `POST /-/builder/lease/:lease/result`, publishes the build of the leased crate.
+///
+/// The body is the zip archive written by [`crate::docbuilder::write_build_archive`].
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn result_handler(
+    Path(lease_id): Path<String>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(build_queue): Extension<Arc<BuildQueue>>,
+    Extension(pool): Extension<Pool>,
+    Extension(storage): Extension<Arc<Storage>>,
+    Extension(metrics): Extension<Arc<Metrics>>,
+    Extension(repository_stats_updater): Extension<Arc<RepositoryStatsUpdater>>,
+    Extension(registry_index): Extension<Arc<RegistryIndex>>,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> AxumResult<Response> {
+    if let Some(response) = unauthorized(&config, &headers)? {
+        return Ok(response);
+    }
+
+    let limit = config.remote_build_max_upload_size;
+    let mut archive = tempfile::tempfile().context("failed to create the archive file")?;
+    if let Some(response) = read_body(&headers, body, limit, &mut archive).await? {
+        return Ok(response);
+    }
+    archive
+        .rewind()
+        .context("failed to rewind the archive file")?;
+
+    super::spawn_blocking(move || {
+        let lease = match build_queue.renew_lease(&lease_id)? {
+            Some(lease) => lease,
+            None => return Ok(lease_lost()),
+        };
+
+        let dir = tempfile::Builder::new()
+            .prefix(TEMPDIR_PREFIX)
+            .tempdir()
+            .context("failed to create the build directory")?;
+        let mut output = match extract_build_archive(archive, dir.path()) {
+            Ok(output) => output,
+            Err(err) => {
+                return Ok((
+                    StatusCode::BAD_REQUEST,
+                    format!("invalid build archive: {:#}", err),
+                )
+                    .into_response())
+            }
+        };
+
+        // the escalation is recorded by the queue, not by the builder
+        output.result.escalated_limits = lease.escalated_limits.clone();
+
+        let publisher = Publisher {
+            storage: &storage,
+            metrics: &metrics,
+            config: &config,
+            repository_stats_updater: &repository_stats_updater,
+            registry: registry_index.api(),
+        };
+        let mut conn = pool.get()?;
+        let published = publisher.publish(
+            &mut conn,
+            &lease.name,
+            &lease.version,
+            &output,
+            &dir.path().join("doc"),
+            &dir.path().join("source"),
+        );
+
+        let response = match published {
+            Ok(_) => {
+                let limits = lease.limits(&mut conn)?;
+                let retry_limits = limits.escalate(&output.result, &config.build);
+                if build_queue.finish_lease(&lease_id, Ok(retry_limits))? {
+                    StatusCode::OK.into_response()
+                } else {
+                    lease_lost()
+                }
+            }
+            Err(err) => {
+                let message = format!("failed to publish the build: {:#}", err);
+                build_queue.finish_lease(&lease_id, Err(message.clone()))?;
+                (StatusCode::INTERNAL_SERVER_ERROR, message).into_response()
+            }
+        };
+        dir.close()
+            .context("failed to remove the build directory")?;
+        Ok(response)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docbuilder::{write_build_archive, BuildOutput, BuildResult, Limits};
+    use crate::test::{wrapper, TestEnvironment};
+    use crate::utils::{MetadataPackage, Target};
+    use reqwest::{blocking::RequestBuilder, StatusCode};
+    use std::{fs, io};
+
+    const TOKEN: &str = "secret";
+
+    fn enable_api(env: &TestEnvironment) {
+        enable_api_with(env, |_| {});
+    }
+
+    fn enable_api_with(env: &TestEnvironment, f: impl FnOnce(&mut Config)) {
+        env.override_config(|config| {
+            config.remote_builder_token = Some(TOKEN.into());
+            // publishing loads the release data from the registry, which isn't available here
+            config.registry_url = Some("/nonexistent/registry".into());
+            f(config);
+        });
+    }
+
+    fn post(env: &TestEnvironment, path: &str) -> RequestBuilder {
+        env.frontend().post(path).bearer_auth(TOKEN)
+    }
+
+    fn lease(env: &TestEnvironment) -> anyhow::Result<LeaseResponse> {
+        let response = post(env, "/-/builder/lease?builder=test").send()?;
+        assert_eq!(response.status(), StatusCode::OK);
+        Ok(response.json()?)
+    }
+
+    fn queue_row(env: &TestEnvironment) -> anyhow::Result<Option<(i32, Option<String>)>> {
+        Ok(env
+            .db()
+            .conn()
+            .query_opt("SELECT attempt, leased_by FROM queue", &[])?
+            .map(|row| (row.get(0), row.get(1))))
+    }
+
+    #[test]
+    fn disabled_without_token() {
+        wrapper(|env| {
+            let response = post(env, "/-/builder/lease").send()?;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            response.text()?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn invalid_token() {
+        wrapper(|env| {
+            enable_api(env);
+            env.build_queue().add_crate("foo", "0.1.0", 0, None)?;
+
+            for request in [
+                env.frontend().post("/-/builder/lease"),
+                env.frontend().post("/-/builder/lease").bearer_auth("wrong"),
+            ] {
+                let response = request.send()?;
+                assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+                response.text()?;
+            }
+            assert_eq!(queue_row(env)?, Some((0, None)));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn lease_heartbeat_and_fail() {
+        wrapper(|env| {
+            enable_api(env);
+
+            let response = post(env, "/-/builder/lease").send()?;
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            response.text()?;
+
+            env.build_queue().add_crate("foo", "0.1.0", 0, None)?;
+            let leased = lease(env)?;
+            assert_eq!(leased.lease.name, "foo");
+            assert_eq!(leased.lease.version, "0.1.0");
+            assert_eq!(leased.limits, Limits::default());
+            assert_eq!(
+                leased.lease_duration,
+                env.config().remote_build_lease_duration
+            );
+            assert_eq!(queue_row(env)?, Some((0, Some("test".into()))));
+
+            // the crate isn't leased twice
+            let response = post(env, "/-/builder/lease").send()?;
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            response.text()?;
+
+            let heartbeat = format!("/-/builder/lease/{}/heartbeat", leased.lease.id);
+            let response = post(env, &heartbeat).send()?;
+            assert_eq!(response.status(), StatusCode::OK);
+            response.text()?;
+
+            let response = post(env, &format!("/-/builder/lease/{}/fail", leased.lease.id))
+                .body("the build failed")
+                .send()?;
+            assert_eq!(response.status(), StatusCode::OK);
+            response.text()?;
+            assert_eq!(queue_row(env)?, Some((1, None)));
+
+            let response = post(env, &heartbeat).send()?;
+            assert_eq!(response.status(), StatusCode::CONFLICT);
+            response.text()?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn upload_publishes_the_build() {
+        wrapper(|env| {
+            enable_api(env);
+            env.build_queue().add_crate("foo", "0.1.0", 0, None)?;
+            let leased = lease(env)?;
+
+            let dir = tempfile::tempdir()?;
+            fs::create_dir_all(dir.path().join("doc/foo"))?;
+            fs::write(dir.path().join("doc/foo/index.html"), "<html>foo</html>")?;
+            fs::create_dir_all(dir.path().join("source/src"))?;
+            fs::write(dir.path().join("source/src/lib.rs"), "//! foo")?;
+
+            let output = BuildOutput {
+                result: BuildResult {
+                    rustc_version: "rustc 1.70.0 (90c541806 2023-05-31)".into(),
+                    docsrs_version: "docs.rs 1.0.0".into(),
+                    successful: true,
+                    metadata_warnings: Vec::new(),
+                    exceeded_limit: None,
+                    escalated_limits: None,
+                },
+                default_target: "x86_64-unknown-linux-gnu".into(),
+                package: MetadataPackage {
+                    id: "foo 0.1.0".into(),
+                    name: "foo".into(),
+                    version: "0.1.0".into(),
+                    targets: vec![Target::dummy_lib("foo".into(), None)],
+                    ..MetadataPackage::default()
+                },
+                has_docs: true,
+                has_examples: false,
+                successful_targets: vec!["x86_64-unknown-linux-gnu".into()],
+                successful_profiles: Vec::new(),
+                doc_coverage: None,
+                file_coverage: Vec::new(),
+                undocumented: Vec::new(),
+                feature_items: Vec::new(),
+                build_log: "building foo".into(),
+            };
+            let mut archive = io::Cursor::new(Vec::new());
+            write_build_archive(&output, dir.path(), &mut archive)?;
+
+            let result = format!("/-/builder/lease/{}/result", leased.lease.id);
+            let response = post(env, &result).body(archive.into_inner()).send()?;
+            assert_eq!(response.status(), StatusCode::OK);
+            response.text()?;
+
+            assert_eq!(queue_row(env)?, None);
+            let row = env.db().conn().query_one(
+                "SELECT releases.rustdoc_status, builds.build_status
+                 FROM releases
+                 INNER JOIN crates ON crates.id = releases.crate_id
+                 INNER JOIN builds ON builds.rid = releases.id
+                 WHERE crates.name = 'foo' AND releases.version = '0.1.0'",
+                &[],
+            )?;
+            assert!(row.get::<_, bool>(0));
+            assert!(row.get::<_, bool>(1));
+            assert!(env
+                .storage()
+                .exists_in_archive("rustdoc/foo/0.1.0.zip", "foo/index.html")?);
+            assert!(env
+                .storage()
+                .exists_in_archive("sources/foo/0.1.0.zip", "src/lib.rs")?);
+
+            // the lease is finished, so the build can't be uploaded again
+            let response = post(env, &result).body(Vec::new()).send()?;
+            assert_eq!(response.status(), StatusCode::CONFLICT);
+            response.text()?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn large_uploads_are_rejected() {
+        wrapper(|env| {
+            enable_api_with(env, |config| config.remote_build_max_upload_size = 10);
+            env.build_queue().add_crate("foo", "0.1.0", 0, None)?;
+            let leased = lease(env)?;
+
+            let result = format!("/-/builder/lease/{}/result", leased.lease.id);
+            let response = post(env, &result).body(vec![0; 11]).send()?;
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            response.text()?;
+
+            // the crate stays leased to the builder
+            assert_eq!(queue_row(env)?, Some((0, Some("test".into()))));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn archive_paths_are_checked() -> anyhow::Result<()> {
+        let mut archive = io::Cursor::new(Vec::new());
+        let mut zip = zip::ZipWriter::new(&mut archive);
+        zip.start_file(
+            "doc/../../escaped",
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored),
+        )?;
+        zip.finish()?;
+        drop(zip);
+
+        let dir = tempfile::tempdir()?;
+        archive.set_position(0);
+        assert!(extract_build_archive(archive, dir.path()).is_err());
+        Ok(())
+    }
+}
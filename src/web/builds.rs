@@ -1,19 +1,22 @@
-use super::{match_version, redirect_base, MatchSemver};
+use super::{is_json_request, json_response, match_subpage_version};
 use crate::{
     db::Pool,
     docbuilder::Limits,
     impl_webpage,
-    web::{page::WebPage, MetaData},
-};
-use chrono::{DateTime, Utc};
-use iron::{
-    headers::{
-        AccessControlAllowOrigin, CacheControl, CacheDirective, ContentType, Expires, HttpDate,
+    web::{
+        error::{AxumResult, Nope},
+        MetaData,
     },
-    status, IronResult, Request, Response, Url,
 };
-use router::Router;
+use axum::{
+    extract::{Extension, Path},
+    headers::{Expires, HeaderMapExt},
+    http::{header::CACHE_CONTROL, HeaderMap, HeaderValue, Uri},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
 use serde::Serialize;
+use std::time::SystemTime;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub(crate) struct Build {
@@ -35,45 +38,30 @@ impl_webpage! {
     BuildsPage = "crate/builds.html",
 }
 
-pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
-    let router = extension!(req, Router);
-    let name = cexpect!(req, router.find("name"));
-    let req_version = router.find("version");
-
-    let mut conn = extension!(req, Pool).get()?;
-    let limits = ctry!(req, Limits::for_crate(&mut conn, name));
-
-    let is_json = req
-        .url
-        .path()
-        .last()
-        .map_or(false, |segment| segment.ends_with(".json"));
-
-    let (version, version_or_latest) =
-        match match_version(&mut conn, name, req_version).and_then(|m| m.assume_exact())? {
-            MatchSemver::Exact((version, _)) => (version.clone(), version),
-            MatchSemver::Latest((version, _)) => (version, "latest".to_string()),
-
-            MatchSemver::Semver((version, _)) => {
-                let ext = if is_json { ".json" } else { "" };
-                let url = ctry!(
-                    req,
-                    Url::parse(&format!(
-                        "{}/crate/{}/{}/builds{}",
-                        redirect_base(req),
-                        name,
-                        version,
-                        ext,
-                    )),
-                );
-
-                return Ok(super::redirect(url));
-            }
+pub(crate) async fn build_list_handler(
+    Path((name, req_version)): Path<(String, String)>,
+    Extension(pool): Extension<Pool>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> AxumResult<Response> {
+    let json = is_json_request(&uri);
+    super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        let limits = Limits::for_crate(&mut conn, &name)?;
+
+        let (version, version_or_latest) = match match_subpage_version(
+            &headers,
+            &uri,
+            &mut conn,
+            &name,
+            Some(&req_version),
+            "builds",
+        )? {
+            Ok(versions) => versions,
+            Err(redirect) => return Ok(redirect),
         };
 
-    let query = ctry!(
-        req,
-        conn.query(
+        let query = conn.query(
             "SELECT crates.name,
                 releases.version,
                 releases.description,
@@ -89,44 +77,41 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
              INNER JOIN crates ON releases.crate_id = crates.id
              WHERE crates.name = $1 AND releases.version = $2
              ORDER BY id DESC",
-            &[&name, &version]
-        )
-    );
-
-    let builds: Vec<_> = query
-        .into_iter()
-        .map(|row| Build {
-            id: row.get("id"),
-            rustc_version: row.get("rustc_version"),
-            docsrs_version: row.get("docsrs_version"),
-            build_status: row.get("build_status"),
-            build_time: row.get("build_time"),
-        })
-        .collect();
-
-    if is_json {
-        let mut resp = Response::with((status::Ok, serde_json::to_string(&builds).unwrap()));
-        resp.headers.set(ContentType::json());
-        resp.headers.set(Expires(HttpDate(time::now())));
-        resp.headers.set(CacheControl(vec![
-            CacheDirective::NoCache,
-            CacheDirective::NoStore,
-            CacheDirective::MustRevalidate,
-        ]));
-        resp.headers.set(AccessControlAllowOrigin::Any);
-
-        Ok(resp)
-    } else {
-        BuildsPage {
-            metadata: cexpect!(
-                req,
-                MetaData::from_crate(&mut conn, name, &version, &version_or_latest)
-            ),
-            builds,
-            limits,
+            &[&name, &version],
+        )?;
+
+        let builds: Vec<_> = query
+            .into_iter()
+            .map(|row| Build {
+                id: row.get("id"),
+                rustc_version: row.get("rustc_version"),
+                docsrs_version: row.get("docsrs_version"),
+                build_status: row.get("build_status"),
+                build_time: row.get("build_time"),
+            })
+            .collect();
+
+        if json {
+            let mut resp = json_response(&builds);
+            resp.headers_mut()
+                .typed_insert(Expires::from(SystemTime::now()));
+            resp.headers_mut().insert(
+                CACHE_CONTROL,
+                HeaderValue::from_static("no-cache, no-store, must-revalidate"),
+            );
+
+            Ok(resp)
+        } else {
+            Ok(BuildsPage {
+                metadata: MetaData::from_crate(&mut conn, &name, &version, &version_or_latest)
+                    .ok_or(Nope::CrateNotFound)?,
+                builds,
+                limits,
+            }
+            .into_response())
         }
-        .into_response(req)
-    }
+    })
+    .await
 }
 
 #[cfg(test)]
@@ -0,0 +1,430 @@
+//! In-process caches for the details of releases and rendered rustdoc pages
+
+use crate::{
+    db::{
+        notify::{changed_crate_name, RELEASE_CHANGED_CHANNEL},
+        Pool,
+    },
+    utils::report_error,
+    web::crate_details::CrateDetails,
+    Config, Metrics,
+};
+use anyhow::{Context, Error};
+use log::debug;
+use lru::LruCache;
+use postgres::fallible_iterator::FallibleIterator;
+use std::{
+    hash::Hash,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const CRATE_DETAILS: &str = "crate_details";
+const RUSTDOC_HTML: &str = "rustdoc_html";
+
+/// The crate name, version and `version_or_latest` passed to `CrateDetails::new`
+type CrateDetailsKey = (String, String, String);
+/// The crate name and the path and query of the page
+type RustdocHtmlKey = (String, String);
+
+/// Caches `CrateDetails` and the rewritten HTML of rustdoc pages.
+///
+/// All entries of a crate are dropped when the builder or the registry watcher notify the web
+/// server that one of its releases changed, see [`crate::db::notify`].
+#[derive(Debug)]
+pub(crate) struct PageCache {
+    crate_details: Option<Mutex<LruCache<CrateDetailsKey, Entry<CrateDetails>>>>,
+    rustdoc_html: Option<Mutex<HtmlCache>>,
+    ttl: Duration,
+    /// Incremented whenever entries are dropped, so that values loaded before that aren't
+    /// inserted afterwards.
+    generation: AtomicU64,
+    metrics: Arc<Metrics>,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    value: T,
+    inserted: Instant,
+}
+
+#[derive(Debug)]
+struct HtmlCache {
+    entries: LruCache<RustdocHtmlKey, Entry<Vec<u8>>>,
+    size: usize,
+    max_size: usize,
+}
+
+impl PageCache {
+    pub(crate) fn new(config: &Config, metrics: Arc<Metrics>) -> Self {
+        Self {
+            crate_details: NonZeroUsize::new(config.crate_details_cache_size)
+                .map(|size| Mutex::new(LruCache::new(size))),
+            rustdoc_html: (config.rustdoc_html_cache_size > 0).then(|| {
+                Mutex::new(HtmlCache {
+                    entries: LruCache::unbounded(),
+                    size: 0,
+                    max_size: config.rustdoc_html_cache_size,
+                })
+            }),
+            ttl: Duration::from_secs(config.page_cache_ttl),
+            generation: AtomicU64::new(0),
+            metrics,
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.crate_details.is_some() || self.rustdoc_html.is_some()
+    }
+
+    /// Returns a token that has to be passed when inserting values derived from other cached
+    /// values, to avoid inserting them if the cache was invalidated in the meantime.
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Returns the cached `CrateDetails` of the release, or loads and caches them.
+    pub(crate) fn crate_details(
+        &self,
+        name: &str,
+        version: &str,
+        version_or_latest: &str,
+        load: impl FnOnce() -> Result<Option<CrateDetails>, Error>,
+    ) -> Result<Option<CrateDetails>, Error> {
+        let cache = match &self.crate_details {
+            Some(cache) => cache,
+            None => return load(),
+        };
+        let key = (
+            name.to_string(),
+            version.to_string(),
+            version_or_latest.to_string(),
+        );
+
+        let cached =
+            get_fresh(&mut cache.lock().unwrap(), &key, self.ttl).map(|entry| entry.value.clone());
+        self.record_lookup(CRATE_DETAILS, cached.is_some());
+        if let Some(details) = cached {
+            return Ok(Some(details));
+        }
+
+        let generation = self.generation();
+        let details = load()?;
+        if let Some(details) = &details {
+            let mut cache = cache.lock().unwrap();
+            if generation == self.generation() {
+                cache.put(key, Entry::new(details.clone()));
+            }
+        }
+        Ok(details)
+    }
+
+    /// Returns the cached HTML of a rustdoc page.
+    pub(crate) fn rustdoc_html(&self, name: &str, path: &str) -> Option<Vec<u8>> {
+        let cache = self.rustdoc_html.as_ref()?;
+        let key = (name.to_string(), path.to_string());
+
+        let mut cache = cache.lock().unwrap();
+        let expired = cache
+            .entries
+            .peek(&key)
+            .map_or(false, |entry| entry.inserted.elapsed() >= self.ttl);
+        if expired {
+            cache.remove(&key);
+            self.update_html_size(&cache);
+        }
+        let html = cache.entries.get(&key).map(|entry| entry.value.clone());
+        drop(cache);
+
+        self.record_lookup(RUSTDOC_HTML, html.is_some());
+        html
+    }
+
+    /// Caches the HTML of a rustdoc page, evicting the least recently used pages if needed.
+    pub(crate) fn insert_rustdoc_html(&self, name: &str, path: &str, html: &[u8], generation: u64) {
+        let cache = match &self.rustdoc_html {
+            Some(cache) => cache,
+            None => return,
+        };
+
+        let mut cache = cache.lock().unwrap();
+        if html.len() > cache.max_size || generation != self.generation() {
+            return;
+        }
+
+        let key = (name.to_string(), path.to_string());
+        cache.remove(&key);
+        while cache.size + html.len() > cache.max_size {
+            match cache.entries.pop_lru() {
+                Some((_, entry)) => cache.size -= entry.value.len(),
+                None => break,
+            }
+        }
+        cache.size += html.len();
+        cache.entries.put(key, Entry::new(html.to_vec()));
+        self.update_html_size(&cache);
+    }
+
+    /// Drops all entries of a crate.
+    pub(crate) fn invalidate(&self, name: &str) {
+        debug!("invalidating cached pages of {}", name);
+        let mut details = self.crate_details.as_ref().map(|c| c.lock().unwrap());
+        let mut html = self.rustdoc_html.as_ref().map(|c| c.lock().unwrap());
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        if let Some(details) = &mut details {
+            for key in keys_of_crate(details, name, |key| &key.0) {
+                details.pop(&key);
+            }
+        }
+        if let Some(html) = &mut html {
+            for key in keys_of_crate(&html.entries, name, |key| &key.0) {
+                html.remove(&key);
+            }
+            self.update_html_size(html);
+        }
+    }
+
+    /// Drops all entries.
+    pub(crate) fn clear(&self) {
+        let mut details = self.crate_details.as_ref().map(|c| c.lock().unwrap());
+        let mut html = self.rustdoc_html.as_ref().map(|c| c.lock().unwrap());
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        if let Some(details) = &mut details {
+            details.clear();
+        }
+        if let Some(html) = &mut html {
+            html.entries.clear();
+            html.size = 0;
+            self.update_html_size(html);
+        }
+    }
+
+    /// Starts a thread listening for changed releases and invalidating their entries.
+    pub(crate) fn start_invalidation_listener(self: &Arc<Self>, pool: Pool) -> Result<(), Error> {
+        let cache = Arc::clone(self);
+        thread::Builder::new()
+            .name("page cache invalidation".into())
+            .spawn(move || loop {
+                if let Err(err) = cache
+                    .listen_for_changes(&pool)
+                    .context("failed to listen for changed releases")
+                {
+                    report_error(&err);
+                }
+                // Changes might be missed until listening again.
+                cache.clear();
+                thread::sleep(Duration::from_secs(1));
+            })?;
+        Ok(())
+    }
+
+    fn listen_for_changes(&self, pool: &Pool) -> Result<(), Error> {
+        let mut conn = pool.get()?;
+        let schema: String = conn.query_one("SELECT current_schema()", &[])?.get(0);
+        conn.batch_execute(&format!("LISTEN {}", RELEASE_CHANGED_CHANNEL))?;
+        // Anything cached before listening might already be outdated.
+        self.clear();
+
+        let mut notifications = conn.notifications();
+        let mut notifications = notifications.blocking_iter();
+        while let Some(notification) = notifications.next()? {
+            if let Some(name) = changed_crate_name(notification.payload(), &schema) {
+                self.invalidate(name);
+            }
+        }
+        Ok(())
+    }
+
+    fn record_lookup(&self, cache: &str, hit: bool) {
+        let counter = if hit {
+            &self.metrics.page_cache_hits
+        } else {
+            &self.metrics.page_cache_misses
+        };
+        counter.with_label_values(&[cache]).inc();
+    }
+
+    fn update_html_size(&self, cache: &HtmlCache) {
+        self.metrics.rustdoc_html_cache_bytes.set(cache.size as i64);
+    }
+}
+
+impl<T> Entry<T> {
+    fn new(value: T) -> Self {
+        Self {
+            value,
+            inserted: Instant::now(),
+        }
+    }
+}
+
+impl HtmlCache {
+    fn remove(&mut self, key: &RustdocHtmlKey) {
+        if let Some(entry) = self.entries.pop(key) {
+            self.size -= entry.value.len();
+        }
+    }
+}
+
+/// Returns the entry if it's younger than `ttl`, dropping it otherwise.
+fn get_fresh<'a, K: Hash + Eq, V>(
+    cache: &'a mut LruCache<K, Entry<V>>,
+    key: &K,
+    ttl: Duration,
+) -> Option<&'a Entry<V>> {
+    if cache.peek(key)?.inserted.elapsed() >= ttl {
+        cache.pop(key);
+        return None;
+    }
+    cache.get(key)
+}
+
+fn keys_of_crate<K: Hash + Eq + Clone, V>(
+    cache: &LruCache<K, V>,
+    name: &str,
+    crate_name: impl Fn(&K) -> &String,
+) -> Vec<K> {
+    cache
+        .iter()
+        .map(|(key, _)| key)
+        .filter(|key| crate_name(key) == name)
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{wrapper, TestEnvironment};
+
+    fn html_cache(env: &TestEnvironment, size: usize) -> PageCache {
+        let mut config = env.base_config();
+        config.rustdoc_html_cache_size = size;
+        PageCache::new(&config, env.metrics())
+    }
+
+    #[test]
+    fn rustdoc_html_is_bounded_by_size() {
+        wrapper(|env| {
+            let metrics = env.metrics();
+            let cache = html_cache(env, 10);
+            let generation = cache.generation();
+
+            cache.insert_rustdoc_html("foo", "a", b"aaaa", generation);
+            cache.insert_rustdoc_html("foo", "b", b"bbbb", generation);
+            assert_eq!(cache.rustdoc_html("foo", "a"), Some(b"aaaa".to_vec()));
+
+            // `b` is the least recently used page now.
+            cache.insert_rustdoc_html("foo", "c", b"cccc", generation);
+            assert_eq!(cache.rustdoc_html("foo", "b"), None);
+            assert_eq!(cache.rustdoc_html("foo", "a"), Some(b"aaaa".to_vec()));
+            assert_eq!(cache.rustdoc_html("foo", "c"), Some(b"cccc".to_vec()));
+            assert_eq!(metrics.rustdoc_html_cache_bytes.get(), 8);
+
+            // Pages larger than the whole cache are never stored.
+            cache.insert_rustdoc_html("foo", "d", b"ddddddddddd", generation);
+            assert_eq!(cache.rustdoc_html("foo", "d"), None);
+            assert_eq!(metrics.rustdoc_html_cache_bytes.get(), 8);
+
+            let hits = metrics.page_cache_hits.with_label_values(&[RUSTDOC_HTML]);
+            let misses = metrics.page_cache_misses.with_label_values(&[RUSTDOC_HTML]);
+            assert_eq!(hits.get(), 3);
+            assert_eq!(misses.get(), 2);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn invalidate_drops_entries_of_crate() {
+        wrapper(|env| {
+            let cache = html_cache(env, 100);
+            let generation = cache.generation();
+
+            cache.insert_rustdoc_html("foo", "a", b"foo", generation);
+            cache.insert_rustdoc_html("bar", "a", b"bar", generation);
+            cache.invalidate("foo");
+
+            assert_eq!(cache.rustdoc_html("foo", "a"), None);
+            assert_eq!(cache.rustdoc_html("bar", "a"), Some(b"bar".to_vec()));
+
+            // Pages rendered before the invalidation might be outdated.
+            cache.insert_rustdoc_html("foo", "a", b"foo", generation);
+            assert_eq!(cache.rustdoc_html("foo", "a"), None);
+
+            cache.clear();
+            assert_eq!(cache.rustdoc_html("bar", "a"), None);
+            assert_eq!(env.metrics().rustdoc_html_cache_bytes.get(), 0);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        wrapper(|env| {
+            let mut config = env.base_config();
+            config.rustdoc_html_cache_size = 100;
+            config.page_cache_ttl = 0;
+            let cache = PageCache::new(&config, env.metrics());
+
+            cache.insert_rustdoc_html("foo", "a", b"foo", cache.generation());
+            assert_eq!(cache.rustdoc_html("foo", "a"), None);
+            assert_eq!(env.metrics().rustdoc_html_cache_bytes.get(), 0);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn crate_details_are_cached() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let mut config = env.base_config();
+            config.crate_details_cache_size = 10;
+            let metrics = env.metrics();
+            let cache = PageCache::new(&config, metrics.clone());
+
+            let load = || CrateDetails::new(&mut *env.db().conn(), "foo", "0.1.0", "0.1.0", None);
+            let details = cache.crate_details("foo", "0.1.0", "0.1.0", load)?;
+            assert!(details.is_some());
+            let cached = cache.crate_details("foo", "0.1.0", "0.1.0", || {
+                panic!("the crate details should be cached")
+            })?;
+            assert_eq!(cached, details);
+
+            // Missing releases aren't cached.
+            assert!(cache
+                .crate_details("foo", "0.2.0", "0.2.0", || Ok(None))?
+                .is_none());
+            assert!(cache
+                .crate_details("foo", "0.2.0", "0.2.0", || Ok(None))?
+                .is_none());
+
+            cache.invalidate("foo");
+            let mut loaded = false;
+            cache.crate_details("foo", "0.1.0", "0.1.0", || {
+                loaded = true;
+                load()
+            })?;
+            assert!(loaded);
+
+            let hits = metrics.page_cache_hits.with_label_values(&[CRATE_DETAILS]);
+            let misses = metrics
+                .page_cache_misses
+                .with_label_values(&[CRATE_DETAILS]);
+            assert_eq!(hits.get(), 1);
+            assert_eq!(misses.get(), 4);
+
+            Ok(())
+        })
+    }
+}
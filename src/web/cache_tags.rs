@@ -0,0 +1,99 @@
+//! Cache tags, which let an edge cache purge related responses together
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::collections::HashMap;
+
+/// Cache tag of the responses that only change when docs.rs is deployed.
+pub(super) const STATIC_TAG: &str = "static";
+/// Cache tag of the responses listing releases of many crates.
+const RELEASES_LIST_TAG: &str = "releases-list";
+
+/// The cache tags of a response.
+#[derive(Debug, Default)]
+pub(super) struct CacheTags(Vec<String>);
+
+impl CacheTags {
+    pub(super) fn fixed(tag: &str) -> Self {
+        Self(vec![tag.to_string()])
+    }
+
+    /// Sets the `Surrogate-Key` and `Cache-Tag` headers of the response, so an edge cache can
+    /// purge everything belonging to a crate without wildcard invalidations.
+    pub(super) fn set_headers(&self, response: &mut Response) {
+        if self.0.is_empty() {
+            return;
+        }
+        // crate names and versions only contain characters valid in headers
+        if let (Ok(surrogate_key), Ok(cache_tag)) = (
+            HeaderValue::from_str(&self.0.join(" ")),
+            HeaderValue::from_str(&self.0.join(",")),
+        ) {
+            let headers = response.headers_mut();
+            headers.insert("Surrogate-Key", surrogate_key);
+            headers.insert("Cache-Tag", cache_tag);
+        }
+    }
+}
+
+/// The cache tags of the responses of a route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum RouteTags {
+    Fixed(&'static str),
+    /// The crate and version in the URL of the request.
+    Crate,
+}
+
+impl RouteTags {
+    pub(super) fn for_pattern(pattern: &str) -> Self {
+        if pattern.contains(":crate") || pattern.contains(":name") {
+            RouteTags::Crate
+        } else if pattern == "/" || pattern.starts_with("/releases") || pattern.contains("sitemap")
+        {
+            RouteTags::Fixed(RELEASES_LIST_TAG)
+        } else {
+            RouteTags::Fixed(STATIC_TAG)
+        }
+    }
+
+    fn tags(&self, params: Option<&HashMap<String, String>>) -> CacheTags {
+        match self {
+            RouteTags::Fixed(tag) => CacheTags::fixed(tag),
+            RouteTags::Crate => {
+                let params = match params {
+                    Some(params) => params,
+                    None => return CacheTags::default(),
+                };
+                let name = match params.get("crate").or_else(|| params.get("name")) {
+                    // crates.io treats names as case-insensitive and `-` the same as `_`, so
+                    // responses for all spellings have to be purged together.
+                    Some(name) => name.to_lowercase().replace('_', "-"),
+                    None => return CacheTags::default(),
+                };
+
+                let mut tags = vec![format!("crate:{}", name)];
+                if let Some(version) = params.get("version") {
+                    tags.push(format!("crate:{}:{}", name, version));
+                }
+                CacheTags(tags)
+            }
+        }
+    }
+}
+
+/// Tags the responses of a route, including its error pages.
+pub(super) async fn cache_tags_middleware<B>(
+    State(route_tags): State<RouteTags>,
+    params: Option<Path<HashMap<String, String>>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let tags = route_tags.tags(params.as_ref().map(|Path(params)| params));
+    let mut response = next.run(req).await;
+    tags.set_headers(&mut response);
+    response
+}
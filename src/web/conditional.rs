@@ -0,0 +1,117 @@
+//! Conditional requests with `ETag` and `Last-Modified` validators
+
+use crate::BUILD_VERSION;
+use axum::{
+    headers::{ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch, LastModified},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::time::SystemTime;
+
+/// The validators of a response, used to answer conditional requests with `304 Not Modified`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CacheValidators {
+    etag: ETag,
+    last_modified: Option<DateTime<Utc>>,
+}
+
+impl CacheValidators {
+    /// Creates a strong `ETag` from everything the response depends on.
+    ///
+    /// The docs.rs version is always part of the tag, since a deploy can change the templates.
+    pub(crate) fn new(key: impl AsRef<[u8]>) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(BUILD_VERSION);
+        hasher.update([0]);
+        hasher.update(key.as_ref());
+        let tag: String = hasher.finalize()[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        CacheValidators {
+            etag: format!("\"{}\"", tag)
+                .parse()
+                .expect("a hex string is a valid entity tag"),
+            last_modified: None,
+        }
+    }
+
+    pub(crate) fn last_modified(mut self, last_modified: DateTime<Utc>) -> Self {
+        self.last_modified = Some(last_modified);
+        self
+    }
+
+    /// Returns whether the client already has the current version of the response.
+    ///
+    /// Following RFC 7232, `If-Modified-Since` is ignored when `If-None-Match` is present.
+    /// `If-None-Match: *` never matches, because the validators are computed without
+    /// checking that the resource exists.
+    pub(crate) fn is_fresh(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+            // the precondition fails when one of the tags matches with the weak comparison
+            return if_none_match != IfNoneMatch::any()
+                && !if_none_match.precondition_passes(&self.etag);
+        }
+
+        match (headers.typed_get::<IfModifiedSince>(), self.last_modified) {
+            // HTTP dates only have a precision of seconds, which the comparison respects.
+            (Some(since), Some(last_modified)) => !since.is_modified(last_modified.into()),
+            _ => false,
+        }
+    }
+
+    /// Sets the `ETag` and `Last-Modified` headers of the response.
+    pub(crate) fn set_headers(&self, response: &mut Response) {
+        let headers = response.headers_mut();
+        headers.typed_insert(self.etag.clone());
+        if let Some(last_modified) = self.last_modified {
+            headers.typed_insert(http_date(last_modified));
+        }
+    }
+
+    /// Creates an empty `304 Not Modified` response carrying the validators.
+    ///
+    /// Callers still need to set the `Cache-Control` header they would set on the full response.
+    pub(crate) fn not_modified(&self) -> Response {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        self.set_headers(&mut response);
+        response
+    }
+}
+
+pub(crate) fn http_date(date: DateTime<Utc>) -> LastModified {
+    LastModified::from(SystemTime::from(date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn strong_etag_depends_on_key() {
+        let first = CacheValidators::new("1/foo/index.html");
+        assert_eq!(first, CacheValidators::new("1/foo/index.html"));
+        assert_ne!(first, CacheValidators::new("2/foo/index.html"));
+        // a strong tag of 32 hex characters, in quotes
+        let mut headers = HeaderMap::new();
+        headers.typed_insert(first.etag);
+        let etag = headers["etag"].to_str().unwrap();
+        assert_eq!(etag.len(), 34);
+        assert!(etag.starts_with('"'));
+    }
+
+    #[test]
+    fn http_date_format() {
+        let date = Utc.ymd(2021, 3, 4).and_hms(10, 20, 30);
+        let mut headers = HeaderMap::new();
+        headers.typed_insert(http_date(date));
+        assert_eq!(
+            headers["last-modified"].to_str().unwrap(),
+            "Thu, 04 Mar 2021 10:20:30 GMT"
+        );
+    }
+}
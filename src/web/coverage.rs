@@ -0,0 +1,354 @@
+use super::{is_json_request, json_response, match_subpage_version};
+use crate::{
+    db::Pool,
+    impl_webpage,
+    web::{
+        error::{AxumResult, Nope},
+        MetaData,
+    },
+};
+use anyhow::anyhow;
+use axum::{
+    extract::{Extension, Path},
+    http::{HeaderMap, Uri},
+    response::{IntoResponse, Response},
+};
+use postgres::Client;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Coverage {
+    total_items: i32,
+    documented_items: i32,
+    total_items_needing_examples: i32,
+    items_with_examples: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct FileCoverage {
+    file: String,
+    total_items: i32,
+    documented_items: i32,
+    total_items_needing_examples: i32,
+    items_with_examples: i32,
+}
+
+/// A public item without documentation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct UndocumentedItem {
+    kind: String,
+    name: String,
+    url: String,
+}
+
+/// The coverage of one version of the crate, for the chart across all versions.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct CoverageHistoryEntry {
+    version: String,
+    documented_items: i32,
+    total_items: i32,
+    percent: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct CoveragePage {
+    metadata: MetaData,
+    coverage: Option<Coverage>,
+    files: Vec<FileCoverage>,
+    /// The undocumented items, keyed by the path of their module.
+    undocumented: BTreeMap<String, Vec<UndocumentedItem>>,
+    history: Vec<CoverageHistoryEntry>,
+}
+
+impl_webpage! {
+    CoveragePage = "crate/coverage.html",
+}
+
+#[derive(Debug, Serialize)]
+struct CoverageJson {
+    coverage: Option<Coverage>,
+    files: Vec<FileCoverage>,
+    undocumented: BTreeMap<String, Vec<UndocumentedItem>>,
+    history: Vec<CoverageHistoryEntry>,
+}
+
+pub(crate) async fn coverage_handler(
+    Path((name, req_version)): Path<(String, String)>,
+    Extension(pool): Extension<Pool>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> AxumResult<Response> {
+    super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        let (version, version_or_latest) = match match_subpage_version(
+            &headers,
+            &uri,
+            &mut conn,
+            &name,
+            Some(&req_version),
+            "coverage",
+        )? {
+            Ok(versions) => versions,
+            Err(redirect) => return Ok(redirect),
+        };
+
+        let row = conn
+            .query_opt(
+                "SELECT
+                    releases.id,
+                    doc_coverage.total_items,
+                    doc_coverage.documented_items,
+                    doc_coverage.total_items_needing_examples,
+                    doc_coverage.items_with_examples
+                FROM releases
+                INNER JOIN crates ON crates.id = releases.crate_id
+                LEFT JOIN doc_coverage ON doc_coverage.release_id = releases.id
+                WHERE crates.name = $1 AND releases.version = $2",
+                &[&name, &version],
+            )?
+            .ok_or_else(|| anyhow!("the matched release {} {} is missing", name, version))?;
+
+        let release_id: i32 = row.get(0);
+        let coverage = match (row.get(1), row.get(2), row.get(3), row.get(4)) {
+            (
+                Some(total_items),
+                Some(documented_items),
+                Some(total_items_needing_examples),
+                Some(items_with_examples),
+            ) => Some(Coverage {
+                total_items,
+                documented_items,
+                total_items_needing_examples,
+                items_with_examples,
+            }),
+            _ => None,
+        };
+        let files = get_file_coverage(&mut conn, release_id)?;
+        let undocumented = get_undocumented_items(&mut conn, release_id)?;
+        let history = get_coverage_history(&mut conn, &name)?;
+
+        if is_json_request(&uri) {
+            return Ok(json_response(&CoverageJson {
+                coverage,
+                files,
+                undocumented,
+                history,
+            }));
+        }
+
+        Ok(CoveragePage {
+            metadata: MetaData::from_crate(&mut conn, &name, &version, &version_or_latest)
+                .ok_or(Nope::CrateNotFound)?,
+            coverage,
+            files,
+            undocumented,
+            history,
+        }
+        .into_response())
+    })
+    .await
+}
+
+fn get_file_coverage(
+    conn: &mut Client,
+    release_id: i32,
+) -> Result<Vec<FileCoverage>, postgres::Error> {
+    Ok(conn
+        .query(
+            "SELECT file, total_items, documented_items,
+                    total_items_needing_examples, items_with_examples
+             FROM doc_coverage_files
+             WHERE release_id = $1
+             ORDER BY file",
+            &[&release_id],
+        )?
+        .into_iter()
+        .map(|row| FileCoverage {
+            file: row.get(0),
+            total_items: row.get(1),
+            documented_items: row.get(2),
+            total_items_needing_examples: row.get(3),
+            items_with_examples: row.get(4),
+        })
+        .collect())
+}
+
+fn get_undocumented_items(
+    conn: &mut Client,
+    release_id: i32,
+) -> Result<BTreeMap<String, Vec<UndocumentedItem>>, postgres::Error> {
+    let mut items: BTreeMap<String, Vec<UndocumentedItem>> = BTreeMap::new();
+    for row in conn.query(
+        "SELECT module, kind, name, url
+         FROM undocumented_items
+         WHERE release_id = $1
+         ORDER BY module, name, kind",
+        &[&release_id],
+    )? {
+        items.entry(row.get(0)).or_default().push(UndocumentedItem {
+            kind: row.get(1),
+            name: row.get(2),
+            url: row.get(3),
+        });
+    }
+    Ok(items)
+}
+
+/// Returns the coverage of all versions of the crate, oldest first.
+fn get_coverage_history(
+    conn: &mut Client,
+    name: &str,
+) -> Result<Vec<CoverageHistoryEntry>, postgres::Error> {
+    Ok(conn
+        .query(
+            "SELECT releases.version, doc_coverage.documented_items, doc_coverage.total_items
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             INNER JOIN doc_coverage ON doc_coverage.release_id = releases.id
+             WHERE crates.name = $1 AND doc_coverage.total_items > 0
+             ORDER BY releases.release_time",
+            &[&name],
+        )?
+        .into_iter()
+        .map(|row| {
+            let documented_items: i32 = row.get(1);
+            let total_items: i32 = row.get(2);
+            CoverageHistoryEntry {
+                version: row.get(0),
+                documented_items,
+                total_items,
+                percent: (documented_items as f32 * 1000.0 / total_items as f32).round() / 10.0,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::{assert_redirect, wrapper};
+    use kuchiki::traits::TendrilSink;
+    use serde_json::Value;
+
+    #[test]
+    fn coverage_page() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .file_coverage("src/lib.rs", 1, 4)
+                .create()?;
+            env.fake_release()
+                .name("foo")
+                .version("0.2.0")
+                .file_coverage("src/lib.rs", 3, 4)
+                .file_coverage("src/net.rs", 1, 1)
+                .undocumented_item("foo", "struct", "Bar", "foo/struct.Bar.html")
+                .undocumented_item("foo::net", "fn", "connect", "foo/net/fn.connect.html")
+                .create()?;
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/latest/coverage")
+                    .send()?
+                    .text()?,
+            );
+
+            let files: Vec<_> = page
+                .select("#coverage-files tbody td:first-child")
+                .unwrap()
+                .map(|file| file.text_contents())
+                .collect();
+            assert_eq!(files, vec!["src/lib.rs", "src/net.rs"]);
+
+            let links: Vec<_> = page
+                .select("#undocumented-items a")
+                .unwrap()
+                .map(|link| link.attributes.borrow().get("href").unwrap().to_owned())
+                .collect();
+            assert_eq!(
+                links,
+                vec![
+                    "/foo/latest/foo/struct.Bar.html",
+                    "/foo/latest/foo/net/fn.connect.html"
+                ]
+            );
+
+            let bars: Vec<_> = page
+                .select(".coverage-chart rect title")
+                .unwrap()
+                .map(|title| title.text_contents())
+                .collect();
+            assert_eq!(bars, vec!["0.1.0: 25.0%", "0.2.0: 80.0%"]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn coverage_json() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .file_coverage("src/lib.rs", 1, 2)
+                .undocumented_item("foo", "fn", "bar", "foo/fn.bar.html")
+                .create()?;
+
+            let json: Value = env
+                .frontend()
+                .get("/crate/foo/0.1.0/coverage.json")
+                .send()?
+                .json()?;
+            assert_eq!(json["coverage"]["documented_items"], 1);
+            assert_eq!(json["coverage"]["total_items"], 2);
+            assert_eq!(json["files"][0]["file"], "src/lib.rs");
+            assert_eq!(
+                json["undocumented"]["foo"],
+                serde_json::json!([{"kind": "fn", "name": "bar", "url": "foo/fn.bar.html"}])
+            );
+            assert_eq!(json["history"][0]["percent"], 50.0);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn without_coverage() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/0.1.0/coverage")
+                    .send()?
+                    .text()?,
+            );
+            assert!(page.select_first("#coverage-files").is_err());
+            assert!(page
+                .select_first("#main")
+                .unwrap()
+                .text_contents()
+                .contains("No documentation coverage"));
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn semver_redirect() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            assert_redirect(
+                "/crate/foo/~0.1/coverage",
+                "/crate/foo/0.1.0/coverage",
+                env.frontend(),
+            )?;
+            assert_redirect(
+                "/crate/foo/~0.1/coverage.json",
+                "/crate/foo/0.1.0/coverage.json",
+                env.frontend(),
+            )?;
+            Ok(())
+        });
+    }
+}
@@ -1,14 +1,26 @@
 use super::{match_version, redirect_base, render_markdown, MatchSemver, MetaData};
 use crate::utils::{get_correct_docsrs_style_file, report_error};
-use crate::{db::Pool, impl_webpage, repositories::RepositoryStatsUpdater, web::page::WebPage};
+use crate::{
+    db::Pool,
+    impl_webpage,
+    repositories::RepositoryStatsUpdater,
+    web::{
+        cache::PageCache,
+        error::{AxumResult, Nope},
+    },
+};
 use anyhow::anyhow;
+use axum::{
+    extract::{Extension, Path},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
 use chrono::{DateTime, Utc};
-use iron::prelude::*;
-use iron::Url;
 use postgres::GenericClient;
-use router::Router;
-use serde::{ser::Serializer, Serialize};
+use serde::{ser::Serializer, Deserialize, Serialize};
 use serde_json::Value;
+use std::sync::Arc;
+use url::Url;
 
 // TODO: Add target name and versions
 
@@ -47,6 +59,12 @@ pub struct CrateDetails {
     pub(crate) crate_id: i32,
     /// Database id for this release
     pub(crate) release_id: i32,
+    /// Database id of the latest build of this release, which changes when it is rebuilt
+    #[serde(skip)]
+    latest_build_id: Option<i32>,
+    /// When any release of this crate was last built
+    #[serde(skip)]
+    pub(crate) last_build_time: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -115,6 +133,7 @@ impl CrateDetails {
                 releases.is_library,
                 releases.yanked,
                 releases.doc_targets,
+                releases.doc_feature_profiles,
                 releases.license,
                 releases.documentation_url,
                 releases.default_target,
@@ -122,7 +141,18 @@ impl CrateDetails {
                 doc_coverage.total_items,
                 doc_coverage.documented_items,
                 doc_coverage.total_items_needing_examples,
-                doc_coverage.items_with_examples
+                doc_coverage.items_with_examples,
+                (
+                    SELECT MAX(builds.id)
+                    FROM builds
+                    WHERE builds.rid = releases.id
+                ) AS latest_build_id,
+                (
+                    SELECT MAX(builds.build_time)
+                    FROM builds
+                    INNER JOIN releases AS crate_releases ON crate_releases.id = builds.rid
+                    WHERE crate_releases.crate_id = crates.id
+                ) AS last_build_time
             FROM releases
             INNER JOIN crates ON releases.crate_id = crates.id
             LEFT JOIN doc_coverage ON doc_coverage.release_id = releases.id
@@ -163,6 +193,9 @@ impl CrateDetails {
             target_name: krate.get("target_name"),
             default_target: krate.get("default_target"),
             doc_targets: MetaData::parse_doc_targets(krate.get("doc_targets")),
+            doc_feature_profiles: MetaData::parse_doc_feature_profiles(
+                krate.get("doc_feature_profiles"),
+            ),
             yanked: krate.get("yanked"),
             rustdoc_css_file: get_correct_docsrs_style_file(krate.get("doc_rustc_version"))?,
         };
@@ -197,6 +230,8 @@ impl CrateDetails {
             items_with_examples: krate.get("items_with_examples"),
             crate_id,
             release_id,
+            latest_build_id: krate.get("latest_build_id"),
+            last_build_time: krate.get("last_build_time"),
         };
 
         // get owners
@@ -225,6 +260,25 @@ impl CrateDetails {
         Ok(Some(crate_details))
     }
 
+    /// Returns everything about the crate that can change the pages rendered for this release
+    /// without their files changing, to be used as part of their `ETag`.
+    pub(crate) fn cache_key(&self) -> String {
+        let mut key = format!("{}:{:?}", self.release_id, self.latest_build_id);
+        // The owners are not queried in a stable order.
+        let mut owners: Vec<&str> = self.owners.iter().map(|(login, _)| &**login).collect();
+        owners.sort_unstable();
+        for login in owners {
+            key.push_str(&format!(";{}", login));
+        }
+        for release in &self.releases {
+            key.push_str(&format!(
+                ";{}:{}:{}:{}",
+                release.id, release.build_status, release.rustdoc_status, release.yanked
+            ));
+        }
+        key
+    }
+
     /// Returns the latest non-yanked, non-prerelease release of this crate (or latest
     /// yanked/prereleased if that is all that exist).
     pub fn latest_release(&self) -> &Release {
@@ -292,58 +346,69 @@ impl_webpage! {
     CrateDetailsPage = "crate/details.html",
 }
 
-pub fn crate_details_handler(req: &mut Request) -> IronResult<Response> {
-    let router = extension!(req, Router);
-    // this handler must always called with a crate name
-    let name = cexpect!(req, router.find("name"));
-    let req_version = router.find("version");
+#[derive(Deserialize)]
+pub(crate) struct CrateDetailHandlerParams {
+    name: String,
+    version: Option<String>,
+}
 
-    if req_version == None {
-        let url = ctry!(
-            req,
-            Url::parse(&format!("{}/crate/{}/latest", redirect_base(req), name,)),
-        );
-        return Ok(super::redirect(url));
-    }
+pub(crate) async fn crate_details_handler(
+    Path(params): Path<CrateDetailHandlerParams>,
+    Extension(pool): Extension<Pool>,
+    Extension(updater): Extension<Arc<RepositoryStatsUpdater>>,
+    Extension(page_cache): Extension<Arc<PageCache>>,
+    headers: HeaderMap,
+) -> AxumResult<Response> {
+    let CrateDetailHandlerParams { name, version } = params;
+    let req_version = match version {
+        Some(version) => version,
+        None => {
+            let url = Url::parse(&format!(
+                "{}/crate/{}/latest",
+                redirect_base(&headers),
+                name
+            ))
+            .map_err(|err| anyhow!(err).context("failed to build the redirect URL"))?;
+            return Ok(super::redirect(url));
+        }
+    };
+
+    super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
 
-    let mut conn = extension!(req, Pool).get()?;
-
-    let found_version =
-        match_version(&mut conn, name, req_version).and_then(|m| m.assume_exact())?;
-    let (version, version_or_latest) = match found_version {
-        MatchSemver::Exact((version, _)) => (version.clone(), version),
-        MatchSemver::Latest((version, _)) => (version, "latest".to_string()),
-        MatchSemver::Semver((version, _)) => {
-            let url = ctry!(
-                req,
-                Url::parse(&format!(
+        let found_version =
+            match_version(&mut conn, &name, Some(&req_version)).and_then(|m| m.assume_exact())?;
+        let (version, version_or_latest) = match found_version {
+            MatchSemver::Exact((version, _)) => (version.clone(), version),
+            MatchSemver::Latest((version, _)) => (version, "latest".to_string()),
+            MatchSemver::Semver((version, _)) => {
+                let url = Url::parse(&format!(
                     "{}/crate/{}/{}",
-                    redirect_base(req),
+                    redirect_base(&headers),
                     name,
                     version
-                )),
-            );
+                ))
+                .map_err(|err| anyhow!(err).context("failed to build the redirect URL"))?;
 
-            return Ok(super::redirect(url));
-        }
-    };
+                return Ok(super::redirect(url));
+            }
+        };
 
-    let updater = extension!(req, RepositoryStatsUpdater);
-    let details = cexpect!(
-        req,
-        ctry!(
-            req,
-            CrateDetails::new(
-                &mut *conn,
-                name,
-                &version,
-                &version_or_latest,
-                Some(updater)
-            )
-        )
-    );
-
-    CrateDetailsPage { details }.into_response(req)
+        let details = page_cache
+            .crate_details(&name, &version, &version_or_latest, || {
+                CrateDetails::new(
+                    &mut *conn,
+                    &name,
+                    &version,
+                    &version_or_latest,
+                    Some(&updater),
+                )
+            })?
+            .ok_or(Nope::VersionNotFound)?;
+
+        Ok(CrateDetailsPage { details }.into_response())
+    })
+    .await
 }
 
 #[cfg(test)]
@@ -1,10 +1,20 @@
 use crate::config::Config;
-use iron::{AfterMiddleware, BeforeMiddleware, IronResult, Request, Response};
-use std::fmt::Write;
+use axum::{
+    http::{header::CONTENT_TYPE, HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 pub(super) struct Csp {
     nonce: String,
-    suppress: bool,
+    suppress: AtomicBool,
 }
 
 impl Csp {
@@ -16,12 +26,12 @@ impl Csp {
 
         Self {
             nonce: base64::encode(&random),
-            suppress: false,
+            suppress: AtomicBool::new(false),
         }
     }
 
-    pub(super) fn suppress(&mut self, suppress: bool) {
-        self.suppress = suppress;
+    pub(super) fn suppress(&self, suppress: bool) {
+        self.suppress.store(suppress, Ordering::Relaxed);
     }
 
     pub(super) fn nonce(&self) -> &str {
@@ -29,7 +39,7 @@ impl Csp {
     }
 
     fn render(&self, content_type: ContentType) -> Option<String> {
-        if self.suppress {
+        if self.suppress.load(Ordering::Relaxed) {
             return None;
         }
         let mut result = String::new();
@@ -80,61 +90,48 @@ impl Csp {
     }
 }
 
-impl iron::typemap::Key for Csp {
-    type Value = Csp;
-}
-
 enum ContentType {
     Html,
     Svg,
     Other,
 }
 
-pub(super) struct CspMiddleware;
-
-impl BeforeMiddleware for CspMiddleware {
-    fn before(&self, req: &mut Request) -> IronResult<()> {
-        req.extensions.insert::<Csp>(Csp::new());
-        Ok(())
-    }
-}
-
-impl AfterMiddleware for CspMiddleware {
-    fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
-        let config = req
-            .extensions
-            .get::<Config>()
-            .expect("missing Config")
-            .clone();
-        let csp = req.extensions.get_mut::<Csp>().expect("missing CSP");
-
-        let content_type = res
-            .headers
-            .get_raw("Content-Type")
-            .and_then(|headers| headers.get(0))
-            .map(|header| header.as_slice());
-
-        let preset = match content_type {
-            Some(b"text/html; charset=utf-8") => ContentType::Html,
-            Some(b"text/svg+xml") => ContentType::Svg,
-            _ => ContentType::Other,
-        };
-
-        if let Some(rendered) = csp.render(preset) {
-            res.headers.set_raw(
-                // The Report-Only header tells the browser to just log CSP failures instead of
-                // actually enforcing them. This is useful to check if the CSP works without
-                // impacting production traffic.
-                if config.csp_report_only {
-                    "Content-Security-Policy-Report-Only"
-                } else {
-                    "Content-Security-Policy"
-                },
-                vec![rendered.as_bytes().to_vec()],
-            );
-        }
-        Ok(res)
+/// Adds a fresh [`Csp`] to the request extensions, and the policy it renders to the response.
+pub(super) async fn csp_middleware<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let csp_report_only = req
+        .extensions()
+        .get::<Arc<Config>>()
+        .expect("missing Config")
+        .csp_report_only;
+    let csp = Arc::new(Csp::new());
+    req.extensions_mut().insert(csp.clone());
+
+    let mut response = next.run(req).await;
+
+    let preset = match response
+        .headers()
+        .get(CONTENT_TYPE)
+        .map(HeaderValue::as_bytes)
+    {
+        Some(b"text/html; charset=utf-8") => ContentType::Html,
+        Some(b"text/svg+xml") => ContentType::Svg,
+        _ => ContentType::Other,
+    };
+
+    if let Some(rendered) = csp.render(preset) {
+        response.headers_mut().insert(
+            // The Report-Only header tells the browser to just log CSP failures instead of
+            // actually enforcing them. This is useful to check if the CSP works without
+            // impacting production traffic.
+            if csp_report_only {
+                HeaderName::from_static("content-security-policy-report-only")
+            } else {
+                HeaderName::from_static("content-security-policy")
+            },
+            HeaderValue::from_str(&rendered).expect("invalid CSP header"),
+        );
     }
+    response
 }
 
 #[cfg(test)]
@@ -150,7 +147,7 @@ mod tests {
 
     #[test]
     fn test_csp_suppressed() {
-        let mut csp = Csp::new();
+        let csp = Csp::new();
         csp.suppress(true);
 
         assert!(csp.render(ContentType::Other).is_none());
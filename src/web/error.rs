@@ -1,10 +1,14 @@
 use crate::{
     db::PoolError,
-    web::{page::WebPage, releases::Search, ErrorPage},
+    utils::report_error,
+    web::{releases::Search, ErrorPage},
+};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
-use iron::{status::Status, Handler, IronError, IronResult, Request, Response};
 
-#[derive(Debug, Copy, Clone, thiserror::Error)]
+#[derive(Debug, thiserror::Error)]
 pub enum Nope {
     #[error("Requested resource not found")]
     ResourceNotFound,
@@ -19,47 +23,29 @@ pub enum Nope {
     #[error("Search yielded no results")]
     NoResults,
     #[error("Internal server error")]
-    InternalServerError,
-}
-
-impl From<Nope> for IronError {
-    fn from(err: Nope) -> IronError {
-        use iron::status;
-
-        let status = match err {
-            Nope::ResourceNotFound
-            | Nope::BuildNotFound
-            | Nope::CrateNotFound
-            | Nope::OwnerNotFound
-            | Nope::VersionNotFound
-            | Nope::NoResults => status::NotFound,
-            Nope::InternalServerError => status::InternalServerError,
-        };
-
-        IronError::new(err, status)
-    }
+    InternalError(#[from] anyhow::Error),
 }
 
-impl Handler for Nope {
-    fn handle(&self, req: &mut Request) -> IronResult<Response> {
-        match *self {
+impl IntoResponse for Nope {
+    fn into_response(self) -> Response {
+        match self {
             Nope::ResourceNotFound => {
                 // user tried to navigate to a resource (doc page/file) that doesn't exist
                 // TODO: Display the attempted page
                 ErrorPage {
                     title: "The requested resource does not exist",
                     message: Some("no such resource".into()),
-                    status: Status::NotFound,
+                    status: StatusCode::NOT_FOUND,
                 }
-                .into_response(req)
+                .into_response()
             }
 
             Nope::BuildNotFound => ErrorPage {
                 title: "The requested build does not exist",
                 message: Some("no such build".into()),
-                status: Status::NotFound,
+                status: StatusCode::NOT_FOUND,
             }
-            .into_response(req),
+            .into_response(),
 
             Nope::CrateNotFound => {
                 // user tried to navigate to a crate that doesn't exist
@@ -67,17 +53,17 @@ impl Handler for Nope {
                 ErrorPage {
                     title: "The requested crate does not exist",
                     message: Some("no such crate".into()),
-                    status: Status::NotFound,
+                    status: StatusCode::NOT_FOUND,
                 }
-                .into_response(req)
+                .into_response()
             }
 
             Nope::OwnerNotFound => ErrorPage {
                 title: "The requested owner does not exist",
                 message: Some("no such owner".into()),
-                status: Status::NotFound,
+                status: StatusCode::NOT_FOUND,
             }
-            .into_response(req),
+            .into_response(),
 
             Nope::VersionNotFound => {
                 // user tried to navigate to a crate with a version that does not exist
@@ -85,53 +71,51 @@ impl Handler for Nope {
                 ErrorPage {
                     title: "The requested version does not exist",
                     message: Some("no such version for this crate".into()),
-                    status: Status::NotFound,
+                    status: StatusCode::NOT_FOUND,
                 }
-                .into_response(req)
+                .into_response()
             }
 
             Nope::NoResults => {
-                let mut params = req.url.as_ref().query_pairs();
-
-                if let Some((_, query)) = params.find(|(key, _)| key == "query") {
-                    // this used to be a search
-                    Search {
-                        title: format!("No crates found matching '{}'", query),
-                        search_query: Some(query.into_owned()),
-                        status: Status::NotFound,
-                        ..Default::default()
-                    }
-                    .into_response(req)
-                } else {
-                    // user did a search with no search terms
-                    Search {
-                        title: "No results given for empty search query".to_owned(),
-                        status: Status::NotFound,
-                        ..Default::default()
-                    }
-                    .into_response(req)
+                // user did a search with no search terms, searches which found nothing are
+                // answered by the search handler itself
+                Search {
+                    title: "No results given for empty search query".to_owned(),
+                    status: StatusCode::NOT_FOUND,
+                    ..Default::default()
                 }
+                .into_response()
             }
 
-            Nope::InternalServerError => {
-                // something went wrong, details should have been logged
+            Nope::InternalError(err) => {
+                // something went wrong, log the details and show a generic error page
+                report_error(&err);
                 ErrorPage {
                     title: "Internal server error",
                     message: Some("internal server error".into()),
-                    status: Status::InternalServerError,
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
                 }
-                .into_response(req)
+                .into_response()
             }
         }
     }
 }
 
-impl From<PoolError> for IronError {
-    fn from(err: PoolError) -> IronError {
-        IronError::new(err, Status::InternalServerError)
+impl From<PoolError> for Nope {
+    fn from(err: PoolError) -> Nope {
+        Nope::InternalError(err.into())
     }
 }
 
+impl From<postgres::Error> for Nope {
+    fn from(err: postgres::Error) -> Nope {
+        Nope::InternalError(err.into())
+    }
+}
+
+/// The result of a handler, whose errors are rendered as error pages.
+pub(crate) type AxumResult<T> = Result<T, Nope>;
+
 #[cfg(test)]
 mod tests {
     use crate::test::wrapper;
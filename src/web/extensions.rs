@@ -1,10 +1,11 @@
-use crate::web::page::TemplateData;
+use crate::web::{builder_api::RegistryIndex, cache::PageCache, page::TemplateData};
 use crate::{
     db::Pool, repositories::RepositoryStatsUpdater, BuildQueue, Config, Context, Metrics, Storage,
 };
 use anyhow::Error;
-use iron::{BeforeMiddleware, IronResult, Request};
+use axum::{Extension, Router};
 use std::sync::Arc;
+use tower::ServiceBuilder;
 
 #[derive(Debug, Clone)]
 pub(super) struct InjectExtensions {
@@ -15,6 +16,8 @@ pub(super) struct InjectExtensions {
     metrics: Arc<Metrics>,
     template_data: Arc<TemplateData>,
     repository_stats_updater: Arc<RepositoryStatsUpdater>,
+    page_cache: Arc<PageCache>,
+    registry_index: Arc<RegistryIndex>,
 }
 
 impl InjectExtensions {
@@ -22,47 +25,43 @@ impl InjectExtensions {
         context: &dyn Context,
         template_data: Arc<TemplateData>,
     ) -> Result<Self, Error> {
+        let pool = context.pool()?;
+        let config = context.config()?;
+        let metrics = context.metrics()?;
+
+        let page_cache = Arc::new(PageCache::new(&config, metrics.clone()));
+        if page_cache.is_enabled() {
+            page_cache.start_invalidation_listener(pool.clone())?;
+        }
+
+        let registry_index = Arc::new(RegistryIndex::new(config.clone()));
+
         Ok(Self {
             build_queue: context.build_queue()?,
-            pool: context.pool()?,
-            config: context.config()?,
+            pool,
+            config,
             storage: context.storage()?,
-            metrics: context.metrics()?,
+            metrics,
             repository_stats_updater: context.repository_stats_updater()?,
             template_data,
+            page_cache,
+            registry_index,
         })
     }
-}
 
-impl BeforeMiddleware for InjectExtensions {
-    fn before(&self, req: &mut Request) -> IronResult<()> {
-        req.extensions
-            .insert::<BuildQueue>(self.build_queue.clone());
-        req.extensions.insert::<Pool>(self.pool.clone());
-        req.extensions.insert::<Config>(self.config.clone());
-        req.extensions.insert::<Storage>(self.storage.clone());
-        req.extensions.insert::<Metrics>(self.metrics.clone());
-        req.extensions
-            .insert::<TemplateData>(self.template_data.clone());
-        req.extensions
-            .insert::<RepositoryStatsUpdater>(self.repository_stats_updater.clone());
-
-        Ok(())
+    /// Makes the shared state available to all handlers as request extensions.
+    pub(super) fn apply(self, router: Router) -> Router {
+        router.layer(
+            ServiceBuilder::new()
+                .layer(Extension(self.build_queue))
+                .layer(Extension(self.pool))
+                .layer(Extension(self.config))
+                .layer(Extension(self.storage))
+                .layer(Extension(self.metrics))
+                .layer(Extension(self.template_data))
+                .layer(Extension(self.repository_stats_updater))
+                .layer(Extension(self.page_cache))
+                .layer(Extension(self.registry_index)),
+        )
     }
 }
-
-macro_rules! key {
-    ($key:ty => $value:ty) => {
-        impl iron::typemap::Key for $key {
-            type Value = $value;
-        }
-    };
-}
-
-key!(BuildQueue => Arc<BuildQueue>);
-key!(Pool => Pool);
-key!(Config => Arc<Config>);
-key!(Storage => Arc<Storage>);
-key!(Metrics => Arc<Metrics>);
-key!(TemplateData => Arc<TemplateData>);
-key!(RepositoryStatsUpdater => Arc<RepositoryStatsUpdater>);
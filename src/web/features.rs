@@ -1,83 +1,127 @@
-use super::{match_version, redirect_base, MatchSemver};
+use super::{is_json_request, json_response, match_subpage_version};
 use crate::db::types::Feature;
 use crate::{
     db::Pool,
     impl_webpage,
-    web::{page::WebPage, MetaData},
+    web::{
+        error::{AxumResult, Nope},
+        MetaData,
+    },
 };
-use iron::{IronResult, Request, Response, Url};
-use router::Router;
+use anyhow::anyhow;
+use axum::{
+    extract::{Extension, Path},
+    http::{HeaderMap, Uri},
+    response::{IntoResponse, Response},
+};
+use postgres::Client;
 use serde::Serialize;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 
 const DEFAULT_NAME: &str = "default";
 
+/// A public item which is only available with a given feature flag.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct FeatureItem {
+    kind: String,
+    path: String,
+    url: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 struct FeaturesPage {
     metadata: MetaData,
     features: Option<Vec<Feature>>,
     default_len: usize,
+    /// The items gated behind each feature, keyed by the feature name.
+    items: BTreeMap<String, Vec<FeatureItem>>,
 }
 
 impl_webpage! {
     FeaturesPage = "crate/features.html",
 }
 
-pub fn build_features_handler(req: &mut Request) -> IronResult<Response> {
-    let router = extension!(req, Router);
-    let name = cexpect!(req, router.find("name"));
-    let req_version = router.find("version");
-
-    let mut conn = extension!(req, Pool).get()?;
-    let (version, version_or_latest) =
-        match match_version(&mut conn, name, req_version).and_then(|m| m.assume_exact())? {
-            MatchSemver::Exact((version, _)) => (version.clone(), version),
-            MatchSemver::Latest((version, _)) => (version, "latest".to_string()),
-
-            MatchSemver::Semver((version, _)) => {
-                let url = ctry!(
-                    req,
-                    Url::parse(&format!(
-                        "{}/crate/{}/{}/features",
-                        redirect_base(req),
-                        name,
-                        version
-                    )),
-                );
-
-                return Ok(super::redirect(url));
-            }
+#[derive(Debug, Serialize)]
+struct FeaturesJson {
+    features: Option<Vec<Feature>>,
+    items: BTreeMap<String, Vec<FeatureItem>>,
+}
+
+pub(crate) async fn build_features_handler(
+    Path((name, req_version)): Path<(String, String)>,
+    Extension(pool): Extension<Pool>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> AxumResult<Response> {
+    super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        let (version, version_or_latest) = match match_subpage_version(
+            &headers,
+            &uri,
+            &mut conn,
+            &name,
+            Some(&req_version),
+            "features",
+        )? {
+            Ok(versions) => versions,
+            Err(redirect) => return Ok(redirect),
         };
-    let rows = ctry!(
-        req,
-        conn.query(
-            "SELECT releases.features FROM releases
+        let rows = conn.query(
+            "SELECT releases.id, releases.features FROM releases
             INNER JOIN crates ON crates.id = releases.crate_id
             WHERE crates.name = $1 AND releases.version = $2",
-            &[&name, &version]
-        )
-    );
+            &[&name, &version],
+        )?;
 
-    let row = cexpect!(req, rows.get(0));
+        let row = rows
+            .get(0)
+            .ok_or_else(|| anyhow!("the matched release {} {} is missing", name, version))?;
+        let items = get_feature_items(&mut conn, row.get(0))?;
 
-    let mut features = None;
-    let mut default_len = 0;
+        let mut features = None;
+        let mut default_len = 0;
 
-    if let Some(raw) = row.get(0) {
-        let result = order_features_and_count_default_len(raw);
-        features = Some(result.0);
-        default_len = result.1;
-    }
+        if let Some(raw) = row.get(1) {
+            let result = order_features_and_count_default_len(raw);
+            features = Some(result.0);
+            default_len = result.1;
+        }
 
-    FeaturesPage {
-        metadata: cexpect!(
-            req,
-            MetaData::from_crate(&mut conn, name, &version, &version_or_latest)
-        ),
-        features,
-        default_len,
+        if is_json_request(&uri) {
+            return Ok(json_response(&FeaturesJson { features, items }));
+        }
+
+        Ok(FeaturesPage {
+            metadata: MetaData::from_crate(&mut conn, &name, &version, &version_or_latest)
+                .ok_or(Nope::CrateNotFound)?,
+            features,
+            default_len,
+            items,
+        }
+        .into_response())
+    })
+    .await
+}
+
+fn get_feature_items(
+    conn: &mut Client,
+    release_id: i32,
+) -> Result<BTreeMap<String, Vec<FeatureItem>>, postgres::Error> {
+    let mut items: BTreeMap<String, Vec<FeatureItem>> = BTreeMap::new();
+    for row in conn.query(
+        "SELECT feature, kind, path, url
+         FROM feature_items
+         WHERE release_id = $1
+         ORDER BY feature, path, kind",
+        &[&release_id],
+    )? {
+        items.entry(row.get(0)).or_default().push(FeatureItem {
+            kind: row.get(1),
+            path: row.get(2),
+            url: row.get(3),
+        });
     }
-    .into_response(req)
+    Ok(items)
 }
 
 fn order_features_and_count_default_len(raw: Vec<Feature>) -> (Vec<Feature>, usize) {
@@ -123,12 +167,14 @@ fn get_feature_map(raw: Vec<Feature>) -> HashMap<String, Feature> {
 #[cfg(test)]
 mod tests {
     use crate::db::types::Feature;
-    use crate::test::wrapper;
+    use crate::test::{assert_redirect, wrapper};
     use crate::web::features::{
         get_feature_map, get_tree_structure_from_default, order_features_and_count_default_len,
         DEFAULT_NAME,
     };
+    use kuchiki::traits::TendrilSink;
     use reqwest::StatusCode;
+    use serde_json::Value;
     use std::collections::HashMap;
 
     #[test]
@@ -272,6 +318,70 @@ mod tests {
         });
     }
 
+    #[test]
+    fn feature_items() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .feature_item("struct", "foo::Bar", "foo/struct.Bar.html", &["feature1"])
+                .feature_item(
+                    "fn",
+                    "foo::baz",
+                    "foo/fn.baz.html",
+                    &["feature1", "feature2"],
+                )
+                .create()?;
+
+            let page = kuchiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/latest/features")
+                    .send()?
+                    .text()?,
+            );
+            let links: Vec<_> = page
+                .select("#feature1-items a")
+                .unwrap()
+                .map(|link| link.attributes.borrow().get("href").unwrap().to_owned())
+                .collect();
+            assert_eq!(
+                links,
+                vec![
+                    "/foo/latest/foo/struct.Bar.html",
+                    "/foo/latest/foo/fn.baz.html"
+                ]
+            );
+
+            let json: Value = env
+                .frontend()
+                .get("/crate/foo/0.1.0/features.json")
+                .send()?
+                .json()?;
+            assert_eq!(
+                json["items"]["feature2"],
+                serde_json::json!([
+                    {"kind": "fn", "path": "foo::baz", "url": "foo/fn.baz.html"},
+                ])
+            );
+            assert_eq!(json["features"][0]["name"], "default");
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn json_semver_redirect() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            assert_redirect(
+                "/crate/foo/~0.1/features.json",
+                "/crate/foo/0.1.0/features.json",
+                env.frontend(),
+            )?;
+            Ok(())
+        });
+    }
+
     #[test]
     fn crate_version_not_found() {
         wrapper(|env| {
@@ -1,47 +1,82 @@
 //! Database based file handler
 
+use super::conditional::{http_date, CacheValidators};
 use crate::storage::{Blob, Storage};
 use crate::{error::Result, Config};
-use iron::{status, Response};
+use axum::{
+    body::{boxed, Full},
+    headers::HeaderMapExt,
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::Response,
+};
 
 #[derive(Debug)]
 pub(crate) struct File(pub(crate) Blob);
 
 impl File {
     /// Gets file from database
-    pub(super) fn from_path(storage: &Storage, path: &str, config: &Config) -> Result<File> {
+    pub(super) async fn from_path(storage: &Storage, path: &str, config: &Config) -> Result<File> {
         let max_size = if path.ends_with(".html") {
             config.max_file_size_html
         } else {
             config.max_file_size
         };
 
-        Ok(File(storage.get(path, max_size)?))
+        Ok(File(storage.get_async(path, max_size).await?))
     }
 
-    /// Consumes File and creates a iron response
-    pub(super) fn serve(self) -> Response {
-        use iron::headers::{CacheControl, CacheDirective, ContentType, HttpDate, LastModified};
+    /// Consumes File and creates a response, or a `304 Not Modified` response when the client
+    /// already has this version of the file.
+    pub(super) fn serve(self, headers: &HeaderMap) -> Response {
+        let validators = CacheValidators::new(format!(
+            "{}\n{}",
+            self.0.path,
+            self.0.date_updated.to_rfc3339()
+        ))
+        .last_modified(self.0.date_updated);
+
+        if validators.is_fresh(headers) {
+            let mut response = validators.not_modified();
+            // The Content Security Policy depends on the content type.
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, self.content_type());
+            response
+                .headers_mut()
+                .insert(CACHE_CONTROL, Self::cache_control());
+            return response;
+        }
+        self.serve_with(&validators)
+    }
 
-        let mut response = Response::with((status::Ok, self.0.content));
-        let cache = vec![
-            CacheDirective::Public,
-            CacheDirective::MaxAge(super::STATIC_FILE_CACHE_DURATION as u32),
-        ];
-        response
-            .headers
-            .set(ContentType(self.0.mime.parse().unwrap()));
-        response.headers.set(CacheControl(cache));
-        // FIXME: This is so horrible
-        response.headers.set(LastModified(HttpDate(
-            time::strptime(
-                &self.0.date_updated.format("%a, %d %b %Y %T %Z").to_string(),
-                "%a, %d %b %Y %T %Z",
-            )
-            .unwrap(),
-        )));
+    /// Consumes File and creates a response with the given validators.
+    pub(super) fn serve_with(self, validators: &CacheValidators) -> Response {
+        let content_type = self.content_type();
+        let mut response = Response::new(boxed(Full::from(self.0.content)));
+        *response.status_mut() = StatusCode::OK;
+
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, content_type);
+        headers.insert(CACHE_CONTROL, Self::cache_control());
+        headers.typed_insert(http_date(self.0.date_updated));
+        validators.set_headers(&mut response);
         response
     }
+
+    fn content_type(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.0.mime).expect("invalid mime type of a stored file")
+    }
+
+    pub(super) fn cache_control() -> HeaderValue {
+        HeaderValue::from_str(&format!(
+            "public, max-age={}",
+            super::STATIC_FILE_CACHE_DURATION
+        ))
+        .unwrap()
+    }
 }
 
 #[cfg(test)]
@@ -57,18 +92,19 @@ mod tests {
 
             env.fake_release().create()?;
 
-            let mut file = File::from_path(
-                &env.storage(),
-                "rustdoc/fake-package/1.0.0/fake-package/index.html",
-                &env.config(),
-            )
-            .unwrap();
+            let mut file = tokio::runtime::Runtime::new()?
+                .block_on(File::from_path(
+                    &env.storage(),
+                    "rustdoc/fake-package/1.0.0/fake-package/index.html",
+                    &env.config(),
+                ))
+                .unwrap();
             file.0.date_updated = now;
 
-            let resp = file.serve();
+            let resp = file.serve_with(&CacheValidators::new("key"));
             assert_eq!(
-                resp.headers.get_raw("Last-Modified").unwrap(),
-                [now.format("%a, %d %b %Y %T GMT").to_string().into_bytes()].as_ref(),
+                resp.headers()["Last-Modified"],
+                now.format("%a, %d %b %Y %T GMT").to_string(),
             );
 
             Ok(())
@@ -97,12 +133,13 @@ mod tests {
                 .rustdoc_file_with("big.js", &[b'A'; MAX_SIZE * 2] as &[u8])
                 .create()?;
 
+            let runtime = tokio::runtime::Runtime::new()?;
             let file = |path| {
-                File::from_path(
+                runtime.block_on(File::from_path(
                     &env.storage(),
                     &format!("rustdoc/dummy/0.1.0/{}", path),
                     &env.config(),
-                )
+                ))
             };
             let assert_len = |len, path| {
                 assert_eq!(len, file(path).unwrap().0.content.len());
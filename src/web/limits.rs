@@ -0,0 +1,151 @@
+use crate::{
+    db::{overrides::Overrides, Pool},
+    docbuilder::Limits,
+    impl_webpage,
+    web::error::AxumResult,
+};
+use axum::{extract::Extension, response::IntoResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct LastBuild {
+    version: String,
+    successful: bool,
+    time: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct OverriddenCrate {
+    name: String,
+    overrides: Overrides,
+    last_build: Option<LastBuild>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct LimitsPage {
+    crates: Vec<OverriddenCrate>,
+    default_limits: Limits,
+}
+
+impl_webpage! {
+    LimitsPage = "admin/limits.html",
+}
+
+/// Lists all crates with overridden sandbox limits, with the outcome of their latest build.
+pub(crate) async fn limits_handler(
+    Extension(pool): Extension<Pool>,
+) -> AxumResult<impl IntoResponse> {
+    let crates = super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        let rows = conn.query(
+            "SELECT sandbox_overrides.*, last_build.version, last_build.build_status,
+                    last_build.build_time
+             FROM sandbox_overrides
+             LEFT JOIN LATERAL (
+                 SELECT releases.version, builds.build_status, builds.build_time
+                 FROM crates
+                 INNER JOIN releases ON releases.crate_id = crates.id
+                 INNER JOIN builds ON builds.rid = releases.id
+                 WHERE crates.name = sandbox_overrides.crate_name
+                 ORDER BY builds.build_time DESC
+                 LIMIT 1
+             ) AS last_build ON TRUE
+             ORDER BY sandbox_overrides.crate_name",
+            &[],
+        )?;
+
+        Ok(rows
+            .iter()
+            .map(|row| OverriddenCrate {
+                name: row.get("crate_name"),
+                overrides: Overrides::from_row(row),
+                last_build: row
+                    .get::<_, Option<String>>("version")
+                    .map(|version| LastBuild {
+                        version,
+                        successful: row.get("build_status"),
+                        time: row.get("build_time"),
+                    }),
+            })
+            .collect())
+    })
+    .await?;
+
+    Ok(LimitsPage {
+        crates,
+        default_limits: Limits::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::overrides::{set_overrides, Overrides};
+    use crate::test::wrapper;
+    use kuchiki::traits::TendrilSink;
+    use std::time::Duration;
+
+    #[test]
+    fn lists_overridden_crates() {
+        wrapper(|env| {
+            env.override_config(|config| config.admin_token = Some("admin".into()));
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .build_result_failed()
+                .create()?;
+            set_overrides(
+                &mut env.db().conn(),
+                "foo",
+                &Overrides {
+                    timeout: Some(Duration::from_secs(20 * 60)),
+                    networking: Some(true),
+                    ..Overrides::default()
+                },
+            )?;
+            set_overrides(
+                &mut env.db().conn(),
+                "unreleased",
+                &Overrides {
+                    // stays below 2 GB, so the database can be downgraded after the test
+                    memory: Some(1024 * 1024 * 1024),
+                    ..Overrides::default()
+                },
+            )?;
+
+            let response = env
+                .frontend()
+                .get("/-/admin/limits")
+                .bearer_auth("admin")
+                .send()?;
+            assert!(response.status().is_success());
+            let page = kuchiki::parse_html().one(response.text()?);
+
+            let rows: Vec<Vec<String>> = page
+                .select("table.overrides tbody tr")
+                .unwrap()
+                .map(|row| {
+                    row.as_node()
+                        .select("td")
+                        .unwrap()
+                        .map(|cell| {
+                            cell.text_contents()
+                                .split_whitespace()
+                                .collect::<Vec<_>>()
+                                .join(" ")
+                        })
+                        .collect()
+                })
+                .collect();
+            assert_eq!(rows.len(), 2);
+            assert_eq!(rows[0][0], "foo");
+            assert_eq!(rows[0][4], "allowed");
+            assert!(rows[0][5].starts_with("0.1.0 failed"));
+            assert_eq!(rows[1][0], "unreleased");
+            assert_eq!(rows[1][1], "1 GB");
+            assert_eq!(rows[1][5], "never built");
+
+            Ok(())
+        })
+    }
+}
@@ -1,26 +1,32 @@
 use crate::db::Pool;
+use crate::web::error::AxumResult;
 use crate::BuildQueue;
 use crate::Metrics;
-use iron::headers::ContentType;
-use iron::prelude::*;
-use iron::status::Status;
+use axum::{
+    extract::{Extension, State},
+    http::{header::CONTENT_TYPE, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
 use prometheus::{Encoder, HistogramVec, TextEncoder};
-use std::time::{Duration, Instant};
-
-pub(super) fn metrics_handler(req: &mut Request) -> IronResult<Response> {
-    let metrics = extension!(req, Metrics);
-    let pool = extension!(req, Pool);
-    let queue = extension!(req, BuildQueue);
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+pub(super) async fn metrics_handler(
+    Extension(metrics): Extension<Arc<Metrics>>,
+    Extension(pool): Extension<Pool>,
+    Extension(queue): Extension<Arc<BuildQueue>>,
+) -> AxumResult<impl IntoResponse> {
+    let families = super::spawn_blocking(move || Ok(metrics.gather(&pool, &queue)?)).await?;
 
     let mut buffer = Vec::new();
-    let families = ctry!(req, metrics.gather(pool, queue));
-    ctry!(req, TextEncoder::new().encode(&families, &mut buffer));
-
-    let mut resp = Response::with(buffer);
-    resp.status = Some(Status::Ok);
-    resp.headers.set(ContentType::plaintext());
+    TextEncoder::new()
+        .encode(&families, &mut buffer)
+        .map_err(anyhow::Error::from)?;
 
-    Ok(resp)
+    Ok(([(CONTENT_TYPE, "text/plain; charset=utf-8")], buffer))
 }
 
 /// Converts a `Duration` to seconds, used by prometheus internally
@@ -30,38 +36,32 @@ fn duration_to_seconds(d: Duration) -> f64 {
     d.as_secs() as f64 + nanos
 }
 
-pub(super) struct RequestRecorder {
-    handler: Box<dyn iron::Handler>,
-    route_name: String,
-}
-
-impl RequestRecorder {
-    pub fn new(handler: impl iron::Handler, route: impl Into<String>) -> Self {
-        Self {
-            handler: Box::new(handler),
-            route_name: route.into(),
-        }
-    }
-}
-
-impl iron::Handler for RequestRecorder {
-    fn handle(&self, request: &mut Request) -> IronResult<Response> {
-        let start = Instant::now();
-        let result = self.handler.handle(request);
-        let resp_time = duration_to_seconds(start.elapsed());
-
-        let metrics = extension!(request, Metrics);
-        metrics
-            .routes_visited
-            .with_label_values(&[&self.route_name])
-            .inc();
-        metrics
-            .response_time
-            .with_label_values(&[&self.route_name])
-            .observe(resp_time);
-
-        result
-    }
+/// Records the number of requests to a route, and how long it took to answer them.
+pub(super) async fn request_recorder<B>(
+    State(route_name): State<Arc<str>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let metrics = req
+        .extensions()
+        .get::<Arc<Metrics>>()
+        .expect("missing Metrics from the request extensions")
+        .clone();
+
+    let start = Instant::now();
+    let response = next.run(req).await;
+    let resp_time = duration_to_seconds(start.elapsed());
+
+    metrics
+        .routes_visited
+        .with_label_values(&[&route_name])
+        .inc();
+    metrics
+        .response_time
+        .with_label_values(&[&route_name])
+        .observe(resp_time);
+
+    response
 }
 
 struct RenderingTime {
@@ -5,81 +5,23 @@ pub(crate) mod page;
 use crate::utils::get_correct_docsrs_style_file;
 use crate::utils::report_error;
 use anyhow::anyhow;
-use log::info;
+use log::{error, info};
 use serde_json::Value;
 
-/// ctry! (cratesfyitry) is extremely similar to try! and itry!
-/// except it returns an error page response instead of plain Err.
-#[macro_export]
-macro_rules! ctry {
-    ($req:expr, $result:expr $(,)?) => {
-        match $result {
-            Ok(success) => success,
-            Err(error) => {
-                let request: &::iron::Request = $req;
-
-                // This is very ugly, but it makes it impossible to get a type inference error
-                // from this macro
-                let web_error = $crate::web::ErrorPage {
-                    title: "Internal Server Error",
-                    message: ::std::option::Option::Some(::std::borrow::Cow::Owned(error.to_string())),
-                    status: ::iron::status::InternalServerError,
-                };
-
-                let error = anyhow::anyhow!(error)
-                    .context(format!("called `ctry!()` on an `Err` value while attempting to fetch the route {:?}", request.url));
-                $crate::utils::report_error(&error);
-
-                return $crate::web::page::WebPage::into_response(web_error, request);
-            }
-        }
-    };
-}
-
-/// cexpect will check an option and if it's not Some
-/// it will return an error page response
-macro_rules! cexpect {
-    ($req:expr, $option:expr $(,)?) => {
-        match $option {
-            Some(success) => success,
-            None => {
-                let request: &::iron::Request = $req;
-
-                // This is very ugly, but it makes it impossible to get a type inference error
-                // from this macro
-                let web_error = $crate::web::ErrorPage {
-                    title: "Internal Server Error",
-                    message: None,
-                    status: ::iron::status::InternalServerError,
-                };
-
-                let error = anyhow::anyhow!("called `cexpect!()` on a `None` value while attempting to fetch the route {:?}", request.url);
-                $crate::utils::report_error(&error);
-
-                return $crate::web::page::WebPage::into_response(web_error, request);
-            }
-        }
-    };
-}
-
-/// Gets an extension from Request
-macro_rules! extension {
-    ($req:expr, $ext:ty) => {{
-        // Bind $req so we can have good type errors and avoid re-evaluation
-        let request: &::iron::Request = $req;
-
-        cexpect!(request, request.extensions.get::<$ext>())
-    }};
-}
-
 mod build_details;
+mod builder_api;
 mod builds;
+mod cache;
+mod cache_tags;
+mod conditional;
+mod coverage;
 pub(crate) mod crate_details;
 mod csp;
 mod error;
 mod extensions;
 mod features;
 mod file;
+mod limits;
 pub(crate) mod metrics;
 mod releases;
 mod routes;
@@ -87,131 +29,68 @@ mod rustdoc;
 mod sitemap;
 mod source;
 mod statics;
+mod storage_usage;
 
 use crate::{impl_webpage, Context};
 use anyhow::Error;
+use axum::{
+    headers::{authorization::Bearer, Authorization, Expires, HeaderMapExt},
+    http::{
+        header::{ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE, HOST, LOCATION},
+        HeaderMap, HeaderValue, StatusCode, Uri,
+    },
+    middleware,
+    response::{IntoResponse, Response},
+    Router,
+};
 use chrono::{DateTime, Utc};
-use csp::CspMiddleware;
-use error::Nope;
+use error::{AxumResult, Nope};
 use extensions::InjectExtensions;
-use iron::{
-    self,
-    headers::{Expires, HttpDate},
-    modifiers::Redirect,
-    status,
-    status::Status,
-    Chain, Handler, Iron, IronError, IronResult, Listening, Request, Response, Url,
-};
 use page::TemplateData;
 use postgres::Client;
-use router::{NoRoute, TrailingSlash};
 use semver::{Version, VersionReq};
 use serde::Serialize;
-use std::{borrow::Cow, net::SocketAddr, sync::Arc};
+use std::{
+    borrow::Cow,
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::SystemTime,
+};
+use tokio::sync::oneshot;
+use tower_http::catch_panic::CatchPanicLayer;
+use url::Url;
 
 /// Duration of static files for staticfile and DatabaseFileHandler (in seconds)
 const STATIC_FILE_CACHE_DURATION: u64 = 60 * 60 * 24 * 30 * 12; // 12 months
 
 const DEFAULT_BIND: &str = "0.0.0.0:3000";
 
-struct MainHandler {
-    shared_resource_handler: Box<dyn Handler>,
-    router_handler: Box<dyn Handler>,
-    inject_extensions: InjectExtensions,
-}
-
-impl MainHandler {
-    fn chain<H: Handler>(inject_extensions: InjectExtensions, base: H) -> Chain {
-        let mut chain = Chain::new(base);
-        chain.link_before(inject_extensions);
+/// Builds the application serving all docs.rs pages.
+fn build_app(context: &dyn Context, template_data: Arc<TemplateData>) -> Result<Router, Error> {
+    let extensions = InjectExtensions::new(context, template_data)?;
 
-        chain.link_before(CspMiddleware);
-        chain.link_after(CspMiddleware);
-
-        chain
-    }
+    // The layers added last run first. Global rustdoc files are served before routing, so they
+    // take precedence over the files of a crate (see #1327).
+    let router = routes::build_routes()
+        .into_router()
+        .layer(middleware::from_fn(rustdoc::shared_resource_middleware))
+        .layer(middleware::from_fn(page::render_templates))
+        .layer(middleware::from_fn(csp::csp_middleware));
 
-    fn new(template_data: Arc<TemplateData>, context: &dyn Context) -> Result<MainHandler, Error> {
-        let inject_extensions = InjectExtensions::new(context, template_data)?;
-
-        let routes = routes::build_routes();
-        let shared_resources =
-            Self::chain(inject_extensions.clone(), rustdoc::SharedResourceHandler);
-        let router_chain = Self::chain(inject_extensions.clone(), routes.iron_router());
-
-        Ok(MainHandler {
-            shared_resource_handler: Box::new(shared_resources),
-            router_handler: Box::new(router_chain),
-            inject_extensions,
-        })
-    }
+    Ok(extensions.apply(router).layer(CatchPanicLayer::new()))
 }
 
-impl Handler for MainHandler {
-    fn handle(&self, req: &mut Request) -> IronResult<Response> {
-        fn if_404(
-            e: IronError,
-            handle: impl FnOnce() -> IronResult<Response>,
-        ) -> IronResult<Response> {
-            if e.response.status == Some(status::NotFound) {
-                // the routes are ordered from least specific to most; give precedence to the
-                // new error message.
-                handle()
-            } else {
-                Err(e)
-            }
-        }
-
-        // This is kind of a mess.
-        //
-        // Almost all files should be served through the `router_handler`; eventually
-        // `shared_resource_handler` should go through the router too.
-        //
-        // Unfortunately, combining `shared_resource_handler` with the `router_handler` breaks
-        // things, because right now `shared_resource_handler` allows requesting files from *any*
-        // subdirectory and the router requires us to give a specific path. Changing them to a
-        // specific path means that buggy docs from 2018 will have missing CSS (#1181) so until
-        // that's fixed, we need to keep the current (buggy) behavior.
-        //
-        // It's important that `shared_resource_handler` comes first so that global rustdoc files take
-        // precedence over local ones (see #1327).
-        self.shared_resource_handler
-            .handle(req)
-            .or_else(|e| if_404(e, || self.router_handler.handle(req)))
-            .or_else(|e| {
-                // in some cases the iron router will return a redirect as an `IronError`.
-                // Here we convert these into an `Ok(Response)`.
-                if e.error.downcast_ref::<TrailingSlash>().is_some()
-                    || e.response.status == Some(status::MovedPermanently)
-                {
-                    Ok(e.response)
-                } else {
-                    Err(e)
-                }
-            })
-            .or_else(|e| {
-                let err = if let Some(err) = e.error.downcast_ref::<error::Nope>() {
-                    *err
-                } else if e.error.downcast_ref::<NoRoute>().is_some()
-                    || e.response.status == Some(status::NotFound)
-                {
-                    error::Nope::ResourceNotFound
-                } else if e.response.status == Some(status::InternalServerError) {
-                    report_error(&anyhow!("internal server error: {}", e.error));
-                    error::Nope::InternalServerError
-                } else {
-                    report_error(&anyhow!(
-                        "No error page for status {:?}; {}",
-                        e.response.status,
-                        e.error
-                    ));
-                    // TODO: add in support for other errors that are actually used
-                    error::Nope::InternalServerError
-                };
-
-                Self::chain(self.inject_extensions.clone(), err).handle(req)
-            })
-    }
+/// Runs the synchronous parts of a handler, like the database queries, on the blocking thread
+/// pool.
+async fn spawn_blocking<F, R>(f: F) -> AxumResult<R>
+where
+    F: FnOnce() -> AxumResult<R> + Send + 'static,
+    R: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|err| Nope::InternalError(err.into()))?
 }
 
 #[derive(Debug)]
@@ -308,15 +187,11 @@ fn match_version(
         return Err(Nope::CrateNotFound);
     }
 
-    // version is an Option<&str> from router::Router::get, need to decode first.
-    // Any encoding errors we treat as _any version_.
-    use iron::url::percent_encoding::percent_decode;
-    let req_version = input_version
-        .and_then(|v| percent_decode(v.as_bytes()).decode_utf8().ok())
-        .unwrap_or_else(|| "*".into());
+    // the version from the path parameters is already percent-decoded
+    let req_version = input_version.unwrap_or("*");
 
     // first check for exact match, we can't expect users to use semver in query
-    if let Ok(parsed_req_version) = Version::parse(&req_version) {
+    if let Ok(parsed_req_version) = Version::parse(req_version) {
         if let Some(release) = releases
             .iter()
             .find(|release| release.version == parsed_req_version)
@@ -334,7 +209,7 @@ fn match_version(
     let req_semver = if req_version == "newest" || req_version == "latest" {
         VersionReq::STAR
     } else {
-        VersionReq::parse(&req_version).map_err(|err| {
+        VersionReq::parse(req_version).map_err(|err| {
             log::info!(
                 "could not parse version requirement \"{}\": {:?}",
                 req_version,
@@ -404,9 +279,11 @@ fn render_markdown(text: &str) -> String {
     markdown_to_html(text, &options)
 }
 
-#[must_use = "`Server` blocks indefinitely when dropped"]
+#[must_use = "`Server` is shut down when dropped"]
 pub struct Server {
-    inner: Listening,
+    addr: SocketAddr,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
 }
 
 impl Server {
@@ -423,31 +300,72 @@ impl Server {
         template_data: Arc<TemplateData>,
         context: &dyn Context,
     ) -> Result<Self, Error> {
-        let mut iron = Iron::new(MainHandler::new(template_data, context)?);
+        let app = build_app(context, template_data)?;
+
+        let listener = TcpListener::bind(addr)
+            .unwrap_or_else(|_| panic!("Failed to bind to socket on {}", addr));
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+
+        let mut runtime = tokio::runtime::Builder::new_multi_thread();
+        runtime.enable_all().thread_name("web server");
         if cfg!(test) {
-            iron.threads = 1;
+            runtime.worker_threads(1);
         }
-        let inner = iron
-            .http(addr)
-            .unwrap_or_else(|_| panic!("Failed to bind to socket on {}", addr));
+        let runtime = runtime.build()?;
+
+        let (shutdown, shutdown_signal) = oneshot::channel();
+        let thread = thread::Builder::new()
+            .name("web server".into())
+            .spawn(move || {
+                let result = runtime.block_on(async {
+                    axum::Server::from_tcp(listener)?
+                        // the remote address is the default name of the remote builders
+                        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                        .with_graceful_shutdown(async {
+                            // an error means the `Server` was dropped, which should stop it too
+                            let _ = shutdown_signal.await;
+                        })
+                        .await?;
+                    Ok::<_, Error>(())
+                });
+                if let Err(err) = result {
+                    report_error(&err);
+                }
+            })?;
 
-        Ok(Server { inner })
+        Ok(Server {
+            addr,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
     }
-
     pub(crate) fn addr(&self) -> SocketAddr {
-        self.inner.socket
+        self.addr
     }
 
-    /// Iron is bugged, and it never closes the server even when the listener is dropped. To
-    /// avoid never-ending tests this method forgets about the server, leaking it and allowing the
-    /// program to end.
-    ///
-    /// The OS will then close all the dangling servers once the process exits.
-    ///
-    /// https://docs.rs/iron/0.5/iron/struct.Listening.html#method.close
-    #[cfg(test)]
-    pub(crate) fn leak(self) {
-        std::mem::forget(self.inner);
+    /// Blocks until the server stops, which only happens when it fails.
+    pub fn wait(mut self) {
+        self.join();
+    }
+
+    fn join(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("the web server panicked");
+            }
+        }
+    }
+}
+
+impl Drop for Server {
+    /// Stops accepting connections and waits for the requests in progress to finish.
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            // an error means the server already stopped
+            let _ = shutdown.send(());
+        }
+        self.join();
     }
 }
 
@@ -479,32 +397,107 @@ fn duration_to_str(init: DateTime<Utc>) -> String {
     }
 }
 
-/// Creates a `Response` which redirects to the given path on the scheme/host/port from the given
-/// `Request`.
+/// Creates a `Response` which redirects to the given URL.
 fn redirect(url: Url) -> Response {
-    let mut resp = Response::with((status::Found, Redirect(url)));
-    resp.headers.set(Expires(HttpDate(time::now())));
+    let mut resp = (
+        StatusCode::FOUND,
+        [(
+            LOCATION,
+            HeaderValue::from_str(url.as_str()).expect("URLs are valid header values"),
+        )],
+    )
+        .into_response();
+    resp.headers_mut()
+        .typed_insert(Expires::from(SystemTime::now()));
 
     resp
 }
 
-fn redirect_base(req: &Request) -> String {
-    // Try to get the scheme from CloudFront first, and then from iron
-    let scheme = req
-        .headers
-        .get_raw("cloudfront-forwarded-proto")
-        .and_then(|values| values.get(0))
-        .and_then(|value| std::str::from_utf8(value).ok())
+/// The scheme and host the request was sent to, which absolute redirects start with.
+fn redirect_base(headers: &HeaderMap) -> String {
+    // Try to get the scheme from CloudFront first, we only serve plain HTTP ourselves
+    let scheme = headers
+        .get("cloudfront-forwarded-proto")
+        .and_then(|value| value.to_str().ok())
         .filter(|proto| *proto == "http" || *proto == "https")
-        .unwrap_or_else(|| req.url.scheme());
+        .unwrap_or("http");
+
+    // The host header includes the port if it's needed
+    let host = headers
+        .get(HOST)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("localhost");
+    format!("{}://{}", scheme, host)
+}
 
-    // Only include the port if it's needed
-    let port = req.url.port();
-    if port == 80 {
-        format!("{}://{}", scheme, req.url.host())
-    } else {
-        format!("{}://{}:{}", scheme, req.url.host(), port)
-    }
+/// Checks the bearer token of the request, without leaking the length of the matching prefix
+/// through the timing.
+fn has_bearer_token(headers: &HeaderMap, token: &str) -> bool {
+    let constant_time_eq = |a: &[u8], b: &[u8]| {
+        a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    };
+    headers
+        .typed_get::<Authorization<Bearer>>()
+        .map_or(false, |auth| {
+            constant_time_eq(auth.token().as_bytes(), token.as_bytes())
+        })
+}
+
+/// Whether the JSON variant of a page is requested, by adding `.json` to its path.
+fn is_json_request(uri: &Uri) -> bool {
+    uri.path()
+        .rsplit('/')
+        .next()
+        .map_or(false, |segment| segment.ends_with(".json"))
+}
+
+/// Matches the version of a crate subpage like `/crate/:name/:version/features`, returning the
+/// exact version and the version to use in links, which is `latest` on the pages of the latest
+/// release.
+///
+/// Semver requirements are answered with a redirect to the `page` of the matching version, which
+/// keeps the `.json` suffix of JSON requests.
+fn match_subpage_version(
+    headers: &HeaderMap,
+    uri: &Uri,
+    conn: &mut Client,
+    name: &str,
+    req_version: Option<&str>,
+    page: &str,
+) -> AxumResult<Result<(String, String), Response>> {
+    Ok(
+        match match_version(conn, name, req_version).and_then(|m| m.assume_exact())? {
+            MatchSemver::Exact((version, _)) => Ok((version.clone(), version)),
+            MatchSemver::Latest((version, _)) => Ok((version, "latest".to_string())),
+
+            MatchSemver::Semver((version, _)) => {
+                let ext = if is_json_request(uri) { ".json" } else { "" };
+                let url = Url::parse(&format!(
+                    "{}/crate/{}/{}/{}{}",
+                    redirect_base(headers),
+                    name,
+                    version,
+                    page,
+                    ext,
+                ))
+                .map_err(|err| anyhow!(err).context("failed to build the redirect URL"))?;
+
+                Err(redirect(url))
+            }
+        },
+    )
+}
+
+/// The JSON variant of a page, which can be loaded from other origins.
+fn json_response(value: &impl Serialize) -> Response {
+    (
+        [
+            (CONTENT_TYPE, "application/json"),
+            (ACCESS_CONTROL_ALLOW_ORIGIN, "*"),
+        ],
+        serde_json::to_string(value).unwrap(),
+    )
+        .into_response()
 }
 
 /// MetaData used in header
@@ -521,6 +514,8 @@ pub(crate) struct MetaData {
     pub(crate) rustdoc_status: bool,
     pub(crate) default_target: String,
     pub(crate) doc_targets: Vec<String>,
+    /// The feature profiles this release was additionally documented with.
+    pub(crate) doc_feature_profiles: Vec<String>,
     pub(crate) yanked: bool,
     /// CSS file to use depending on the rustdoc version used to generate this version of this
     /// crate.
@@ -544,7 +539,8 @@ impl MetaData {
                        releases.default_target,
                        releases.doc_targets,
                        releases.yanked,
-                       releases.doc_rustc_version
+                       releases.doc_rustc_version,
+                       releases.doc_feature_profiles
                 FROM releases
                 INNER JOIN crates ON crates.id = releases.crate_id
                 WHERE crates.name = $1 AND releases.version = $2",
@@ -563,11 +559,27 @@ impl MetaData {
             rustdoc_status: row.get(4),
             default_target: row.get(5),
             doc_targets: MetaData::parse_doc_targets(row.get(6)),
+            doc_feature_profiles: MetaData::parse_doc_feature_profiles(row.get(9)),
             yanked: row.get(7),
             rustdoc_css_file: get_correct_docsrs_style_file(row.get(8)).unwrap(),
         })
     }
 
+    /// Whether `dir`, the first path segment after the version, selects the docs of a
+    /// non-default target or of a feature profile.
+    pub(crate) fn is_doc_variant_dir(&self, dir: &str) -> bool {
+        self.doc_targets.iter().any(|target| target == dir)
+            || self.feature_profile_for_dir(dir).is_some()
+    }
+
+    /// Returns the feature profile whose docs are stored in `dir`.
+    pub(crate) fn feature_profile_for_dir(&self, dir: &str) -> Option<&str> {
+        self.doc_feature_profiles
+            .iter()
+            .find(|profile| crate::storage::feature_profile_dir(profile) == dir)
+            .map(String::as_str)
+    }
+
     fn parse_doc_targets(targets: Value) -> Vec<String> {
         targets
             .as_array()
@@ -579,6 +591,22 @@ impl MetaData {
             })
             .unwrap_or_else(Vec::new)
     }
+
+    /// Parses the names of the feature profiles a release was built with, skipping names which
+    /// can't be part of a URL.
+    fn parse_doc_feature_profiles(profiles: Value) -> Vec<String> {
+        profiles
+            .as_array()
+            .map(|array| {
+                array
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .filter(|name| docsrs_metadata::is_valid_feature_profile_name(name))
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -588,7 +616,7 @@ pub(crate) struct ErrorPage {
     /// The error message, displayed as a description
     pub message: Option<Cow<'static, str>>,
     #[serde(skip)]
-    pub status: Status,
+    pub status: StatusCode,
 }
 
 impl_webpage! {
@@ -950,6 +978,7 @@ mod test {
                 "x86_64-unknown-linux-gnu".to_string(),
                 "arm64-unknown-linux-gnu".to_string(),
             ],
+            doc_feature_profiles: vec!["tokio".to_string()],
             yanked: false,
             rustdoc_css_file: "rustdoc.css".to_string(),
         };
@@ -966,6 +995,7 @@ mod test {
                 "x86_64-unknown-linux-gnu",
                 "arm64-unknown-linux-gnu",
             ],
+            "doc_feature_profiles": ["tokio"],
             "yanked": false,
             "rustdoc_css_file": "rustdoc.css",
         });
@@ -985,6 +1015,7 @@ mod test {
                 "x86_64-unknown-linux-gnu",
                 "arm64-unknown-linux-gnu",
             ],
+            "doc_feature_profiles": ["tokio"],
             "yanked": false,
             "rustdoc_css_file": "rustdoc.css",
         });
@@ -1004,6 +1035,7 @@ mod test {
                 "x86_64-unknown-linux-gnu",
                 "arm64-unknown-linux-gnu",
             ],
+            "doc_feature_profiles": ["tokio"],
             "yanked": false,
             "rustdoc_css_file": "rustdoc.css",
         });
@@ -1028,6 +1060,7 @@ mod test {
                     rustdoc_status: true,
                     default_target: "x86_64-unknown-linux-gnu".to_string(),
                     doc_targets: vec![],
+                    doc_feature_profiles: vec![],
                     yanked: false,
                     rustdoc_css_file: "rustdoc.css".to_string(),
                 },
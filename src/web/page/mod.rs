@@ -2,7 +2,7 @@ mod templates;
 mod web_page;
 
 pub(crate) use templates::TemplateData;
-pub(super) use web_page::WebPage;
+pub(super) use web_page::{render_templates, WebPage};
 
 use serde::Serialize;
 
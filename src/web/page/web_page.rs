@@ -1,17 +1,23 @@
 use super::TemplateData;
-use crate::ctry;
-use crate::web::csp::Csp;
-use iron::{
-    headers::{CacheControl, ContentType},
-    response::Response,
-    status::Status,
-    IronResult, Request,
+use crate::{
+    utils::report_error,
+    web::{csp::Csp, ErrorPage},
+};
+use anyhow::anyhow;
+use axum::{
+    body::{boxed, Body},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE},
+        HeaderValue, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
 };
 use serde::Serialize;
-use std::borrow::Cow;
+use std::{borrow::Cow, sync::Arc};
 use tera::Context;
 
-/// When making using a custom status, use a closure that coerces to a `fn(&Self) -> Status`
+/// When making using a custom status, use a closure that coerces to a `fn(&Self) -> StatusCode`
 #[macro_export]
 macro_rules! impl_webpage {
     ($page:ty = $template:literal $(, status = $status:expr)? $(, content_type = $content_type:expr)? $(,)?) => {
@@ -26,81 +32,128 @@ macro_rules! impl_webpage {
             }
 
             $(
-                fn get_status(&self) -> ::iron::status::Status {
-                    let status: fn(&Self) -> ::iron::status::Status = $status;
+                fn get_status(&self) -> ::axum::http::StatusCode {
+                    let status: fn(&Self) -> ::axum::http::StatusCode = $status;
                     (status)(self)
                 }
             )?
 
             $(
-                fn content_type() -> ::iron::headers::ContentType {
+                fn content_type() -> &'static str {
                     $content_type
                 }
             )?
         }
+
+        impl ::axum::response::IntoResponse for $page {
+            fn into_response(self) -> ::axum::response::Response {
+                $crate::web::page::WebPage::render(self)
+            }
+        }
     };
 }
 
-#[derive(Serialize)]
-struct TemplateContext<'a, T> {
-    csp_nonce: &'a str,
-    #[serde(flatten)]
-    page: &'a T,
+/// The template and context of a page, rendered by [`render_templates`] once the response leaves
+/// the handler.
+#[derive(Clone)]
+pub(crate) struct DelayedTemplateRender {
+    pub template: Cow<'static, str>,
+    pub context: Context,
 }
 
 /// The central trait that rendering pages revolves around, it handles selecting and rendering the template
 pub trait WebPage: Serialize + Sized {
-    /// Turn the current instance into a `Response`, ready to be served
+    /// Turn the current instance into a `Response`, ready to be served.
+    ///
+    /// The template is only rendered by the [`render_templates`] middleware, which has the CSP
+    /// nonce of the request.
     // TODO: We could cache similar pages using the `&Context`
-    fn into_response(self, req: &Request) -> IronResult<Response> {
-        let csp_nonce = req
-            .extensions
-            .get::<Csp>()
-            .expect("missing CSP from the request extensions")
-            .nonce();
-
-        let ctx = Context::from_serialize(&TemplateContext {
-            csp_nonce,
-            page: &self,
-        })
-        .unwrap();
-        let status = self.get_status();
-        let result = req
-            .extensions
-            .get::<TemplateData>()
-            .expect("missing TemplateData from the request extensions")
-            .templates
-            .render(&self.template(), &ctx);
-
-        let rendered = if status.is_server_error() {
-            // avoid infinite loop if error.html somehow fails to load
-            result.expect("error while serving error page")
-        } else {
-            ctry!(req, result)
-        };
-
-        let mut response = Response::with((status, rendered));
-        response.headers.set(Self::content_type());
-        response.headers.set(Self::cache_control());
-
-        Ok(response)
+    fn render(self) -> Response {
+        let context = Context::from_serialize(&self).expect("failed to serialize the page");
+
+        let mut response = Response::builder()
+            .status(self.get_status())
+            .header(CONTENT_TYPE, Self::content_type());
+        if let Some(cache_control) = Self::cache_control() {
+            response = response.header(CACHE_CONTROL, cache_control);
+        }
+
+        let mut response = response
+            .body(boxed(Body::empty()))
+            .expect("failed to build the response");
+        response.extensions_mut().insert(DelayedTemplateRender {
+            template: self.template(),
+            context,
+        });
+        response
     }
 
     /// The name of the template to be rendered
     fn template(&self) -> Cow<'static, str>;
 
     /// Gets the status of the request, defaults to `Ok`
-    fn get_status(&self) -> Status {
-        Status::Ok
+    fn get_status(&self) -> StatusCode {
+        StatusCode::OK
     }
 
     /// The content type that the template should be served with, defaults to html
-    fn content_type() -> ContentType {
-        ContentType::html()
+    fn content_type() -> &'static str {
+        "text/html; charset=utf-8"
     }
 
     /// The contents of the Cache-Control header. Defaults to no caching.
-    fn cache_control() -> CacheControl {
-        CacheControl(vec![])
+    fn cache_control() -> Option<HeaderValue> {
+        None
+    }
+}
+
+/// Renders the templates of the pages returned by the handlers.
+pub(crate) async fn render_templates<B>(req: Request<B>, next: Next<B>) -> Response {
+    let templates = req
+        .extensions()
+        .get::<Arc<TemplateData>>()
+        .expect("missing TemplateData from the request extensions")
+        .clone();
+    let csp = req
+        .extensions()
+        .get::<Arc<Csp>>()
+        .expect("missing CSP from the request extensions")
+        .clone();
+
+    let response = next.run(req).await;
+    render_response(response, &templates, csp.nonce())
+}
+
+fn render_response(mut response: Response, templates: &TemplateData, csp_nonce: &str) -> Response {
+    let DelayedTemplateRender {
+        template,
+        mut context,
+    } = match response.extensions_mut().remove::<DelayedTemplateRender>() {
+        Some(render) => render,
+        None => return response,
+    };
+    context.insert("csp_nonce", csp_nonce);
+
+    match templates.templates.render(&template, &context) {
+        Ok(rendered) => {
+            // the routes set the length of the empty body the page was returned with
+            response.headers_mut().remove(CONTENT_LENGTH);
+            *response.body_mut() = boxed(Body::from(rendered));
+            response
+        }
+        // avoid infinite loop if error.html somehow fails to load
+        Err(err) if response.status().is_server_error() => {
+            panic!("error while serving error page: {:?}", err)
+        }
+        Err(err) => {
+            let message = err.to_string();
+            report_error(&anyhow!(err).context(format!("failed to render template {}", template)));
+            let error_page = ErrorPage {
+                title: "Internal Server Error",
+                message: Some(Cow::Owned(message)),
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            render_response(error_page.into_response(), templates, csp_nonce)
+        }
     }
 }
@@ -5,24 +5,26 @@ use crate::{
     db::{Pool, PoolClient},
     impl_webpage,
     utils::report_error,
-    web::{error::Nope, match_version, page::WebPage, redirect_base},
-    BuildQueue, Config,
+    web::{
+        error::{AxumResult, Nope},
+        match_version, redirect_base, ErrorPage,
+    },
+    BuildQueue, Config, Metrics,
 };
 use anyhow::{anyhow, Result};
-use chrono::{DateTime, NaiveDate, Utc};
-use iron::{
-    headers::{ContentType, Expires, HttpDate},
-    mime::{Mime, SubLevel, TopLevel},
-    modifiers::Redirect,
-    status, IronError, IronResult, Request, Response, Url,
+use axum::{
+    extract::{Extension, Path, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
 };
+use chrono::{DateTime, NaiveDate, Utc};
 use log::{debug, warn};
 use postgres::Client;
-use router::Router;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::str;
-use url::form_urlencoded;
+use std::sync::Arc;
+use url::{form_urlencoded, Url};
 
 /// Number of release in home page
 const RELEASES_IN_HOME: i64 = 15;
@@ -189,7 +191,7 @@ fn get_search_results(
     use crate::utils::APP_USER_AGENT;
     use once_cell::sync::Lazy;
     use reqwest::blocking::Client as HttpClient;
-    use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, USER_AGENT};
+    use reqwest::header::{HeaderValue, ACCEPT, USER_AGENT};
 
     static HTTP_CLIENT: Lazy<HttpClient> = Lazy::new(|| {
         let mut headers = HeaderMap::new();
@@ -297,11 +299,19 @@ impl_webpage! {
     HomePage = "core/home.html",
 }
 
-pub fn home_page(req: &mut Request) -> IronResult<Response> {
-    let mut conn = extension!(req, Pool).get()?;
-    let recent_releases = get_releases(&mut conn, 1, RELEASES_IN_HOME, Order::ReleaseTime);
+pub(crate) async fn home_page(Extension(pool): Extension<Pool>) -> AxumResult<impl IntoResponse> {
+    let recent_releases = super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        Ok(get_releases(
+            &mut conn,
+            1,
+            RELEASES_IN_HOME,
+            Order::ReleaseTime,
+        ))
+    })
+    .await?;
 
-    HomePage { recent_releases }.into_response(req)
+    Ok(HomePage { recent_releases })
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
@@ -311,14 +321,24 @@ struct ReleaseFeed {
 
 impl_webpage! {
     ReleaseFeed  = "releases/feed.xml",
-    content_type = ContentType(Mime(TopLevel::Application, SubLevel::Xml, vec![])),
+    content_type = "application/xml",
 }
 
-pub fn releases_feed_handler(req: &mut Request) -> IronResult<Response> {
-    let mut conn = extension!(req, Pool).get()?;
-    let recent_releases = get_releases(&mut conn, 1, RELEASES_IN_FEED, Order::ReleaseTime);
+pub(crate) async fn releases_feed_handler(
+    Extension(pool): Extension<Pool>,
+) -> AxumResult<impl IntoResponse> {
+    let recent_releases = super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        Ok(get_releases(
+            &mut conn,
+            1,
+            RELEASES_IN_FEED,
+            Order::ReleaseTime,
+        ))
+    })
+    .await?;
 
-    ReleaseFeed { recent_releases }.into_response(req)
+    Ok(ReleaseFeed { recent_releases })
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
@@ -347,11 +367,17 @@ pub(super) enum ReleaseType {
     Search,
 }
 
-fn releases_handler(req: &mut Request, release_type: ReleaseType) -> IronResult<Response> {
-    let page_number: i64 = extension!(req, Router)
-        .find("page")
-        .and_then(|page_num| page_num.parse().ok())
-        .unwrap_or(1);
+/// Parses the optional page number of a releases list, which starts at 1.
+fn page_number(page: Option<Path<String>>) -> i64 {
+    page.and_then(|Path(page)| page.parse().ok()).unwrap_or(1)
+}
+
+async fn releases_handler(
+    pool: Pool,
+    page: Option<Path<String>>,
+    release_type: ReleaseType,
+) -> AxumResult<impl IntoResponse> {
+    let page_number = page_number(page);
 
     let (description, release_order) = match release_type {
         ReleaseType::Recent => ("Recently uploaded crates", Order::ReleaseTime),
@@ -367,10 +393,16 @@ fn releases_handler(req: &mut Request, release_type: ReleaseType) -> IronResult<
         ),
     };
 
-    let releases = {
-        let mut conn = extension!(req, Pool).get()?;
-        get_releases(&mut conn, page_number, RELEASES_IN_RELEASES, release_order)
-    };
+    let releases = super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        Ok(get_releases(
+            &mut conn,
+            page_number,
+            RELEASES_IN_RELEASES,
+            release_order,
+        ))
+    })
+    .await?;
 
     // Show next and previous page buttons
     let (show_next_page, show_previous_page) = (
@@ -378,7 +410,7 @@ fn releases_handler(req: &mut Request, release_type: ReleaseType) -> IronResult<
         page_number != 1,
     );
 
-    ViewReleases {
+    Ok(ViewReleases {
         releases,
         description: description.into(),
         release_type,
@@ -386,49 +418,74 @@ fn releases_handler(req: &mut Request, release_type: ReleaseType) -> IronResult<
         show_previous_page,
         page_number,
         owner: None,
-    }
-    .into_response(req)
+    })
+}
+
+pub(crate) async fn recent_releases_handler(
+    page: Option<Path<String>>,
+    Extension(pool): Extension<Pool>,
+) -> AxumResult<impl IntoResponse> {
+    releases_handler(pool, page, ReleaseType::Recent).await
 }
 
-pub fn recent_releases_handler(req: &mut Request) -> IronResult<Response> {
-    releases_handler(req, ReleaseType::Recent)
+pub(crate) async fn releases_by_stars_handler(
+    page: Option<Path<String>>,
+    Extension(pool): Extension<Pool>,
+) -> AxumResult<impl IntoResponse> {
+    releases_handler(pool, page, ReleaseType::Stars).await
 }
 
-pub fn releases_by_stars_handler(req: &mut Request) -> IronResult<Response> {
-    releases_handler(req, ReleaseType::Stars)
+pub(crate) async fn releases_recent_failures_handler(
+    page: Option<Path<String>>,
+    Extension(pool): Extension<Pool>,
+) -> AxumResult<impl IntoResponse> {
+    releases_handler(pool, page, ReleaseType::RecentFailures).await
 }
 
-pub fn releases_recent_failures_handler(req: &mut Request) -> IronResult<Response> {
-    releases_handler(req, ReleaseType::RecentFailures)
+pub(crate) async fn releases_failures_by_stars_handler(
+    page: Option<Path<String>>,
+    Extension(pool): Extension<Pool>,
+) -> AxumResult<impl IntoResponse> {
+    releases_handler(pool, page, ReleaseType::Failures).await
 }
 
-pub fn releases_failures_by_stars_handler(req: &mut Request) -> IronResult<Response> {
-    releases_handler(req, ReleaseType::Failures)
+#[derive(Deserialize)]
+pub(crate) struct OwnerParams {
+    owner: String,
+    page: Option<String>,
 }
 
-pub fn owner_handler(req: &mut Request) -> IronResult<Response> {
-    let router = extension!(req, Router);
+pub(crate) async fn owner_handler(
+    Path(params): Path<OwnerParams>,
+    Extension(pool): Extension<Pool>,
+) -> AxumResult<impl IntoResponse> {
     // page number of releases
-    let page_number: i64 = router
-        .find("page")
+    let page_number: i64 = params
+        .page
         .and_then(|page_num| page_num.parse().ok())
         .unwrap_or(1);
-    let owner_route_value = router.find("owner").unwrap();
 
-    let (owner_name, releases) = {
-        let mut conn = extension!(req, Pool).get()?;
-
-        // We need to keep the owner_route_value unchanged, as we may render paginated links in the page.
-        // Changing the owner_route_value directly will cause the link to change, for example: @foobar -> foobar.
-        let mut owner = owner_route_value;
-        if owner.starts_with('@') {
-            owner = &owner[1..];
-        }
-        get_releases_by_owner(&mut conn, page_number, RELEASES_IN_RELEASES, owner)
-    };
+    // We need to keep the owner route value unchanged, as we may render paginated links in the page.
+    // Changing the route value directly will cause the link to change, for example: @foobar -> foobar.
+    let owner_route_value = params.owner;
+    let owner = owner_route_value
+        .strip_prefix('@')
+        .unwrap_or(&owner_route_value)
+        .to_owned();
+
+    let (owner_name, releases) = super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        Ok(get_releases_by_owner(
+            &mut conn,
+            page_number,
+            RELEASES_IN_RELEASES,
+            &owner,
+        ))
+    })
+    .await?;
 
     if releases.is_empty() {
-        return Err(Nope::OwnerNotFound.into());
+        return Err(Nope::OwnerNotFound);
     }
 
     // Show next and previous page buttons
@@ -437,16 +494,15 @@ pub fn owner_handler(req: &mut Request) -> IronResult<Response> {
         page_number != 1,
     );
 
-    ViewReleases {
+    Ok(ViewReleases {
         releases,
         description: format!("Crates from {}", owner_name),
         release_type: ReleaseType::Owner,
         show_next_page,
         show_previous_page,
         page_number,
-        owner: Some(owner_route_value.into()),
-    }
-    .into_response(req)
+        owner: Some(owner_route_value),
+    })
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -460,7 +516,7 @@ pub(super) struct Search {
     /// This should always be `ReleaseType::Search`
     pub(super) release_type: ReleaseType,
     #[serde(skip)]
-    pub(super) status: iron::status::Status,
+    pub(super) status: StatusCode,
 }
 
 impl Default for Search {
@@ -472,12 +528,34 @@ impl Default for Search {
             previous_page_link: None,
             next_page_link: None,
             release_type: ReleaseType::Search,
-            status: iron::status::Ok,
+            status: StatusCode::OK,
+        }
+    }
+}
+
+impl Search {
+    /// The page of a search which found nothing, or of a search without search terms.
+    fn no_results(query: Option<String>) -> Response {
+        match query {
+            // this used to be a search
+            Some(query) => Search {
+                title: format!("No crates found matching '{}'", query),
+                search_query: Some(query),
+                status: StatusCode::NOT_FOUND,
+                ..Default::default()
+            }
+            .into_response(),
+            None => Nope::NoResults.into_response(),
         }
     }
 }
 
-fn redirect_to_random_crate(req: &Request, conn: &mut PoolClient) -> IronResult<Response> {
+fn redirect_to_random_crate(
+    headers: &HeaderMap,
+    config: &Config,
+    metrics: &Metrics,
+    conn: &mut PoolClient,
+) -> AxumResult<Option<Response>> {
     // We try to find a random crate and redirect to it.
     //
     // The query is efficient, but relies on a static factor which depends
@@ -485,56 +563,49 @@ fn redirect_to_random_crate(req: &Request, conn: &mut PoolClient) -> IronResult<
     //
     // If random-crate-searches end up being empty, increase that value.
 
-    let config = extension!(req, Config);
-    let rows = ctry!(
-        req,
-        conn.query(
-            "WITH params AS (
-                    -- get maximum possible id-value in crates-table
-                    SELECT last_value AS max_id FROM crates_id_seq
-                )
-                SELECT
-                    crates.name,
-                    releases.version,
-                    releases.target_name
-                FROM (
-                    -- generate random numbers in the ID-range.
-                    SELECT DISTINCT 1 + trunc(random() * params.max_id)::INTEGER AS id
-                    FROM params, generate_series(1, $1)
-                ) AS r
-                INNER JOIN crates ON r.id = crates.id
-                INNER JOIN releases ON crates.latest_version_id = releases.id
-                INNER JOIN repositories ON releases.repository_id = repositories.id
-                WHERE
-                    releases.rustdoc_status = TRUE AND
-                    repositories.stars >= 100
-                LIMIT 1",
-            &[&(config.random_crate_search_view_size as i32)]
-        )
-    );
+    let rows = conn.query(
+        "WITH params AS (
+                -- get maximum possible id-value in crates-table
+                SELECT last_value AS max_id FROM crates_id_seq
+            )
+            SELECT
+                crates.name,
+                releases.version,
+                releases.target_name
+            FROM (
+                -- generate random numbers in the ID-range.
+                SELECT DISTINCT 1 + trunc(random() * params.max_id)::INTEGER AS id
+                FROM params, generate_series(1, $1)
+            ) AS r
+            INNER JOIN crates ON r.id = crates.id
+            INNER JOIN releases ON crates.latest_version_id = releases.id
+            INNER JOIN repositories ON releases.repository_id = repositories.id
+            WHERE
+                releases.rustdoc_status = TRUE AND
+                repositories.stars >= 100
+            LIMIT 1",
+        &[&(config.random_crate_search_view_size as i32)],
+    )?;
 
     if let Some(row) = rows.into_iter().next() {
         let name: String = row.get("name");
         let version: String = row.get("version");
         let target_name: String = row.get("target_name");
-        let url = ctry!(
-            req,
-            Url::parse(&format!(
-                "{}/{}/{}/{}/",
-                redirect_base(req),
-                name,
-                version,
-                target_name
-            )),
-        );
-
-        let metrics = extension!(req, crate::Metrics).clone();
+        let url = Url::parse(&format!(
+            "{}/{}/{}/{}/",
+            redirect_base(headers),
+            name,
+            version,
+            target_name
+        ))
+        .map_err(|err| anyhow!(err).context("failed to build the redirect URL"))?;
+
         metrics.im_feeling_lucky_searches.inc();
 
-        Ok(super::redirect(url))
+        Ok(Some(super::redirect(url)))
     } else {
         report_error(&anyhow!("found no result in random crate search"));
-        Err(Nope::NoResults.into())
+        Ok(None)
     }
 }
 
@@ -543,84 +614,95 @@ impl_webpage! {
     status = |search| search.status,
 }
 
-pub fn search_handler(req: &mut Request) -> IronResult<Response> {
-    let url = req.url.as_ref();
-    let params: HashMap<_, _> = url.query_pairs().collect();
-    let query = params
-        .get("query")
-        .map(|q| q.to_string())
-        .unwrap_or_else(|| "".to_string());
-
-    let mut conn = extension!(req, Pool).get()?;
-
-    // check if I am feeling lucky button pressed and redirect user to crate page
-    // if there is a match. Also check for paths to items within crates.
-    if params.contains_key("i-am-feeling-lucky") || query.contains("::") {
-        // redirect to a random crate if query is empty
-        if query.is_empty() {
-            return redirect_to_random_crate(req, &mut conn);
-        }
-
-        let mut queries = std::collections::BTreeMap::new();
+/// Renders a failed search as an internal server error that still shows the
+/// error message, so that failures from crates.io are visible to the user.
+fn search_error_response(err: anyhow::Error) -> Response {
+    let page = ErrorPage {
+        title: "Internal Server Error",
+        message: Some(err.to_string().into()),
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    report_error(&err.context("failed to fetch the search results"));
+    page.into_response()
+}
 
-        let krate = match query.split_once("::") {
-            Some((krate, query)) => {
-                queries.insert("search", query);
-                krate.to_string()
+pub(crate) async fn search_handler(
+    Query(params): Query<HashMap<String, String>>,
+    Extension(pool): Extension<Pool>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(metrics): Extension<Arc<Metrics>>,
+    headers: HeaderMap,
+) -> AxumResult<Response> {
+    let query_param = params.get("query").cloned();
+    let query = query_param.clone().unwrap_or_default();
+
+    super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+
+        // check if I am feeling lucky button pressed and redirect user to crate page
+        // if there is a match. Also check for paths to items within crates.
+        if params.contains_key("i-am-feeling-lucky") || query.contains("::") {
+            // redirect to a random crate if query is empty
+            if query.is_empty() {
+                return Ok(
+                    redirect_to_random_crate(&headers, &config, &metrics, &mut conn)?
+                        .unwrap_or_else(|| Search::no_results(query_param)),
+                );
             }
-            None => query.clone(),
-        };
 
-        queries.extend(
-            params
-                .iter()
-                .filter(|(k, _)| !matches!(k.as_ref(), "i-am-feeling-lucky" | "query"))
-                .map(|(k, v)| (k.as_ref(), v.as_ref())),
-        );
-
-        // since we never pass a version into `match_version` here, we'll never get
-        // `MatchVersion::Exact`, so the distinction between `Exact` and `Semver` doesn't
-        // matter
-        if let Ok(matchver) = match_version(&mut conn, &krate, None) {
-            let (version, _) = matchver.version.into_parts();
-            let krate = matchver.corrected_name.unwrap_or(krate);
-
-            let base = redirect_base(req);
-            let url = if matchver.rustdoc_status {
-                let target_name = matchver.target_name;
-                let path = format!("{base}/{krate}/{version}/{target_name}/");
-                if queries.is_empty() {
-                    ctry!(req, Url::parse(&path))
-                } else {
-                    ctry!(
-                        req,
-                        Url::from_generic_url(ctry!(
-                            req,
-                            iron::url::Url::parse_with_params(&path, queries)
-                        ))
-                    )
+            let mut queries = std::collections::BTreeMap::new();
+
+            let krate = match query.split_once("::") {
+                Some((krate, query)) => {
+                    queries.insert("search", query);
+                    krate.to_string()
                 }
-            } else {
-                ctry!(req, Url::parse(&format!("{base}/crate/{krate}/{version}")))
+                None => query.clone(),
             };
 
-            let mut resp = Response::with((status::Found, Redirect(url)));
-            resp.headers.set(Expires(HttpDate(time::now())));
+            queries.extend(
+                params
+                    .iter()
+                    .filter(|(k, _)| !matches!(k.as_ref(), "i-am-feeling-lucky" | "query"))
+                    .map(|(k, v)| (k.as_str(), v.as_str())),
+            );
+
+            // since we never pass a version into `match_version` here, we'll never get
+            // `MatchVersion::Exact`, so the distinction between `Exact` and `Semver` doesn't
+            // matter
+            if let Ok(matchver) = match_version(&mut conn, &krate, None) {
+                let (version, _) = matchver.version.into_parts();
+                let krate = matchver.corrected_name.unwrap_or(krate);
+
+                let base = redirect_base(&headers);
+                let url = if matchver.rustdoc_status {
+                    let target_name = matchver.target_name;
+                    let path = format!("{base}/{krate}/{version}/{target_name}/");
+                    if queries.is_empty() {
+                        Url::parse(&path)
+                    } else {
+                        Url::parse_with_params(&path, queries)
+                    }
+                } else {
+                    Url::parse(&format!("{base}/crate/{krate}/{version}"))
+                }
+                .map_err(|err| anyhow!(err).context("failed to build the redirect URL"))?;
 
-            return Ok(resp);
+                return Ok(super::redirect(url));
+            }
         }
-    }
 
-    let search_result = ctry!(
-        req,
-        if let Some(paginate) = params.get("paginate") {
-            let decoded = base64::decode(&paginate.as_bytes()).map_err(|e| -> IronError {
-                warn!(
-                    "error when decoding pagination base64 string \"{}\": {:?}",
-                    paginate, e
-                );
-                Nope::NoResults.into()
-            })?;
+        let search_result = if let Some(paginate) = params.get("paginate") {
+            let decoded = match base64::decode(paginate.as_bytes()) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    warn!(
+                        "error when decoding pagination base64 string \"{}\": {:?}",
+                        paginate, e
+                    );
+                    return Ok(Search::no_results(query_param));
+                }
+            };
             let query_params = String::from_utf8_lossy(&decoded);
 
             if !query_params.starts_with('?') {
@@ -634,45 +716,50 @@ pub fn search_handler(req: &mut Request) -> IronResult<Response> {
                     "didn't get query args in `paginate` arguments for search: \"{}\"",
                     query_params
                 );
-                return Err(Nope::NoResults.into());
+                return Ok(Search::no_results(query_param));
             }
 
-            get_search_results(&mut conn, &query_params)
+            match get_search_results(&mut conn, &query_params) {
+                Ok(result) => result,
+                Err(err) => return Ok(search_error_response(err)),
+            }
         } else if !query.is_empty() {
             let query_params: String = form_urlencoded::Serializer::new(String::new())
                 .append_pair("q", &query)
                 .append_pair("per_page", &RELEASES_IN_RELEASES.to_string())
                 .finish();
 
-            get_search_results(&mut conn, &format!("?{}", &query_params))
+            match get_search_results(&mut conn, &format!("?{}", &query_params)) {
+                Ok(result) => result,
+                Err(err) => return Ok(search_error_response(err)),
+            }
         } else {
-            return Err(Nope::NoResults.into());
-        }
-    );
+            return Ok(Search::no_results(query_param));
+        };
 
-    let executed_query = search_result
-        .executed_query
-        .unwrap_or_else(|| "".to_string());
+        let executed_query = search_result.executed_query.unwrap_or_default();
 
-    let title = if search_result.results.is_empty() {
-        format!("No results found for '{}'", executed_query)
-    } else {
-        format!("Search results for '{}'", executed_query)
-    };
+        let title = if search_result.results.is_empty() {
+            format!("No results found for '{}'", executed_query)
+        } else {
+            format!("Search results for '{}'", executed_query)
+        };
 
-    Search {
-        title,
-        results: search_result.results,
-        search_query: Some(executed_query),
-        next_page_link: search_result
-            .next_page
-            .map(|params| format!("/releases/search?paginate={}", base64::encode(params))),
-        previous_page_link: search_result
-            .prev_page
-            .map(|params| format!("/releases/search?paginate={}", base64::encode(params))),
-        ..Default::default()
-    }
-    .into_response(req)
+        Ok(Search {
+            title,
+            results: search_result.results,
+            search_query: Some(executed_query),
+            next_page_link: search_result
+                .next_page
+                .map(|params| format!("/releases/search?paginate={}", base64::encode(params))),
+            previous_page_link: search_result
+                .prev_page
+                .map(|params| format!("/releases/search?paginate={}", base64::encode(params))),
+            ..Default::default()
+        }
+        .into_response())
+    })
+    .await
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -687,53 +774,55 @@ impl_webpage! {
     ReleaseActivity = "releases/activity.html",
 }
 
-pub fn activity_handler(req: &mut Request) -> IronResult<Response> {
-    let mut conn = extension!(req, Pool).get()?;
-
-    let data: Vec<(NaiveDate, i64, i64)> = ctry!(
-        req,
-        conn.query(
-            "
-            WITH dates AS (
-                -- we need this series so that days in the statistic that don't have any releases are included
-                SELECT generate_series(
-                        CURRENT_DATE - INTERVAL '30 days',
-                        CURRENT_DATE - INTERVAL '1 day',
-                        '1 day'::interval
-                    )::date AS date_
-            ),
-            release_stats AS (
+pub(crate) async fn activity_handler(
+    Extension(pool): Extension<Pool>,
+) -> AxumResult<impl IntoResponse> {
+    let data: Vec<(NaiveDate, i64, i64)> = super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        Ok(conn
+            .query(
+                "
+                WITH dates AS (
+                    -- we need this series so that days in the statistic that don't have any releases are included
+                    SELECT generate_series(
+                            CURRENT_DATE - INTERVAL '30 days',
+                            CURRENT_DATE - INTERVAL '1 day',
+                            '1 day'::interval
+                        )::date AS date_
+                ),
+                release_stats AS (
+                    SELECT
+                        release_time::date AS date_,
+                        COUNT(*) AS counts,
+                        SUM(CAST((is_library = TRUE AND build_status = FALSE) AS INT)) AS failures
+                    FROM
+                        releases
+                    WHERE
+                        release_time >= CURRENT_DATE - INTERVAL '30 days' AND
+                        release_time < CURRENT_DATE
+                    GROUP BY
+                        release_time::date
+                )
                 SELECT
-                    release_time::date AS date_,
-                    COUNT(*) AS counts,
-                    SUM(CAST((is_library = TRUE AND build_status = FALSE) AS INT)) AS failures
+                    dates.date_ AS date,
+                    COALESCE(rs.counts, 0) AS counts,
+                    COALESCE(rs.failures, 0) AS failures
                 FROM
-                    releases
-                WHERE
-                    release_time >= CURRENT_DATE - INTERVAL '30 days' AND
-                    release_time < CURRENT_DATE
-                GROUP BY
-                    release_time::date
-            )
-            SELECT
-                dates.date_ AS date,
-                COALESCE(rs.counts, 0) AS counts,
-                COALESCE(rs.failures, 0) AS failures
-            FROM
-                dates
-                LEFT OUTER JOIN Release_stats AS rs ON dates.date_ = rs.date_
-
-            ORDER BY
-                dates.date_
-            ",
+                    dates
+                    LEFT OUTER JOIN Release_stats AS rs ON dates.date_ = rs.date_
+
+                ORDER BY
+                    dates.date_
+                ",
             &[],
-        )
-    )
-    .into_iter()
-    .map(|row| (row.get(0), row.get(1), row.get(2)))
-    .collect();
+            )?
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect())
+    })
+    .await?;
 
-    ReleaseActivity {
+    Ok(ReleaseActivity {
         description: "Monthly release activity",
         dates: data
             .iter()
@@ -741,8 +830,7 @@ pub fn activity_handler(req: &mut Request) -> IronResult<Response> {
             .collect(),
         counts: data.iter().map(|&d| d.1).collect(),
         failures: data.iter().map(|&d| d.2).collect(),
-    }
-    .into_response(req)
+    })
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -755,8 +843,10 @@ impl_webpage! {
     BuildQueuePage = "releases/build_queue.html",
 }
 
-pub fn build_queue_handler(req: &mut Request) -> IronResult<Response> {
-    let mut queue = ctry!(req, extension!(req, BuildQueue).queued_crates());
+pub(crate) async fn build_queue_handler(
+    Extension(build_queue): Extension<Arc<BuildQueue>>,
+) -> AxumResult<impl IntoResponse> {
+    let mut queue = super::spawn_blocking(move || Ok(build_queue.queued_crates()?)).await?;
     for krate in queue.iter_mut() {
         // The priority here is inverted: in the database if a crate has a higher priority it
         // will be built after everything else, which is counter-intuitive for people not
@@ -764,11 +854,10 @@ pub fn build_queue_handler(req: &mut Request) -> IronResult<Response> {
         krate.priority = -krate.priority;
     }
 
-    BuildQueuePage {
+    Ok(BuildQueuePage {
         description: "List of crates scheduled to build",
         queue,
-    }
-    .into_response(req)
+    })
 }
 
 #[cfg(test)]
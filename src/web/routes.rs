@@ -1,13 +1,23 @@
 use crate::web::page::WebPage;
+use crate::Config;
 
-use super::metrics::RequestRecorder;
+use super::cache_tags::{cache_tags_middleware, RouteTags};
+use super::error::Nope;
+use super::metrics::request_recorder;
 use ::std::borrow::Cow;
-use iron::{
-    headers::{CacheControl, CacheDirective},
-    middleware::Handler,
+use axum::{
+    extract::State,
+    handler::Handler,
+    http::{
+        header::{CACHE_CONTROL, LOCATION, WWW_AUTHENTICATE},
+        HeaderValue, Request, StatusCode, Uri,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post, MethodRouter},
+    Router,
 };
-use router::Router;
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::Arc};
 
 // REFACTOR: Break this into smaller initialization functions
 pub(super) fn build_routes() -> Routes {
@@ -17,8 +27,12 @@ pub(super) fn build_routes() -> Routes {
     // must live at the site root:
     //   https://developers.google.com/search/reference/robots_txt#handling-http-result-codes
     //   https://support.google.com/webmasters/answer/183668?hl=en
-    routes.static_resource("/robots.txt", PermanentRedirect("/-/static/robots.txt"));
-    routes.static_resource("/favicon.ico", PermanentRedirect("/-/static/favicon.ico"));
+    routes.static_resource("/robots.txt", || async {
+        permanent_redirect("/-/static/robots.txt")
+    });
+    routes.static_resource("/favicon.ico", || async {
+        permanent_redirect("/-/static/favicon.ico")
+    });
     routes.internal_page("/sitemap.xml", super::sitemap::sitemapindex_handler);
     routes.internal_page(
         "/-/sitemap/:letter/sitemap.xml",
@@ -27,13 +41,11 @@ pub(super) fn build_routes() -> Routes {
 
     // This should not need to be served from the root as we reference the inner path in links,
     // but clients might have cached the url and need to update it.
-    routes.static_resource(
-        "/opensearch.xml",
-        PermanentRedirect("/-/static/opensearch.xml"),
-    );
+    routes.static_resource("/opensearch.xml", || async {
+        permanent_redirect("/-/static/opensearch.xml")
+    });
 
-    routes.static_resource("/-/static/:single", super::statics::static_handler);
-    routes.static_resource("/-/static/*", super::statics::static_handler);
+    routes.static_resource("/-/static/*path", super::statics::static_handler);
     routes.internal_page("/-/storage-change-detection.html", {
         #[derive(Debug, serde::Serialize)]
         struct StorageChangeDetection {}
@@ -42,16 +54,30 @@ pub(super) fn build_routes() -> Routes {
             fn template(&self) -> Cow<'static, str> {
                 "storage-change-detection.html".into()
             }
-            fn cache_control() -> CacheControl {
-                CacheControl(vec![CacheDirective::MaxAge(604800)])
+            fn cache_control() -> Option<HeaderValue> {
+                Some(HeaderValue::from_static("max-age=604800"))
             }
         }
-        fn storage_change_detection(req: &mut iron::Request) -> iron::IronResult<iron::Response> {
-            crate::web::page::WebPage::into_response(StorageChangeDetection {}, req)
+        async fn storage_change_detection() -> Response {
+            StorageChangeDetection {}.render()
         }
         storage_change_detection
     });
 
+    routes.api_endpoint("/-/builder/lease", super::builder_api::lease_handler);
+    routes.api_endpoint(
+        "/-/builder/lease/:lease/heartbeat",
+        super::builder_api::heartbeat_handler,
+    );
+    routes.api_endpoint(
+        "/-/builder/lease/:lease/fail",
+        super::builder_api::fail_handler,
+    );
+    routes.api_endpoint(
+        "/-/builder/lease/:lease/result",
+        super::builder_api::result_handler,
+    );
+
     routes.internal_page("/", super::releases::home_page);
 
     routes.internal_page("/about", super::sitemap::about_handler);
@@ -66,6 +92,11 @@ pub(super) fn build_routes() -> Routes {
     routes.internal_page("/releases/activity", super::releases::activity_handler);
     routes.internal_page("/releases/search", super::releases::search_handler);
     routes.internal_page("/releases/queue", super::releases::build_queue_handler);
+    routes.admin_page("/-/admin/limits", super::limits::limits_handler);
+    routes.admin_page(
+        "/-/admin/storage",
+        super::storage_usage::storage_usage_handler,
+    );
     routes.internal_page(
         "/releases/recent/:page",
         super::releases::recent_releases_handler,
@@ -116,20 +147,29 @@ pub(super) fn build_routes() -> Routes {
         "/crate/:name/:version/features",
         super::features::build_features_handler,
     );
+    routes.static_resource(
+        "/crate/:name/:version/features.json",
+        super::features::build_features_handler,
+    );
     routes.internal_page(
-        "/crate/:name/:version/source",
-        SimpleRedirect::new(|url| url.set_path(&format!("{}/", url.path()))),
+        "/crate/:name/:version/coverage",
+        super::coverage::coverage_handler,
     );
+    routes.static_resource(
+        "/crate/:name/:version/coverage.json",
+        super::coverage::coverage_handler,
+    );
+    routes.internal_page("/crate/:name/:version/source", add_trailing_slash);
     routes.internal_page(
         "/crate/:name/:version/source/",
         super::source::source_browser_handler,
     );
     routes.internal_page(
-        "/crate/:name/:version/source/*",
+        "/crate/:name/:version/source/*path",
         super::source::source_browser_handler,
     );
     routes.internal_page(
-        "/crate/:name/:version/target-redirect/*",
+        "/crate/:name/:version/target-redirect/*path",
         super::rustdoc::target_redirect_handler,
     );
 
@@ -161,24 +201,29 @@ pub(super) fn build_routes() -> Routes {
         super::rustdoc::rustdoc_html_server_handler,
     );
     routes.rustdoc_page(
-        "/:crate/:version/:target/*.html",
+        "/:crate/:version/:target/*path",
         super::rustdoc::rustdoc_html_server_handler,
     );
 
     routes
 }
 
-/// This wrapper class aids the construction of iron's Router, with docs.rs-specific additions to
-/// it. Routes are supposed to be added by the build_routes function, which calls methods in this
-/// struct depending on the type of route being added.
+/// This wrapper class aids the construction of the axum `Router`, with docs.rs-specific additions
+/// to it. Routes are supposed to be added by the build_routes function, which calls methods in
+/// this struct depending on the type of route being added.
 pub(super) struct Routes {
     /// Normal GET routes.
-    get: Vec<(String, Box<dyn Handler>)>,
-    /// GET routes serving rustdoc content. The BlockBlacklistedPrefixes middleware is added
+    get: Vec<(String, MethodRouter)>,
+    /// GET routes serving rustdoc content. The blacklisted prefixes middleware is added
     /// automatically to all of them.
-    rustdoc_get: Vec<(String, Box<dyn Handler>)>,
-    /// Prefixes of all the internal routes. This data is used to power the
-    /// BlockBlacklistedPrefixes middleware.
+    rustdoc_get: Vec<(String, MethodRouter)>,
+    /// POST routes of the APIs used by other docs.rs services.
+    post: Vec<(String, MethodRouter)>,
+    /// The redirects from internal pages with a trailing slash to the pages without it, unless
+    /// a page is explicitly routed there.
+    trailing_slash_redirects: Vec<(String, MethodRouter)>,
+    /// Prefixes of all the internal routes. This data is used to power the blacklisted
+    /// prefixes middleware.
     page_prefixes: HashSet<String>,
 }
 
@@ -187,39 +232,56 @@ impl Routes {
         Self {
             get: Vec::new(),
             rustdoc_get: Vec::new(),
+            post: Vec::new(),
+            trailing_slash_redirects: Vec::new(),
             page_prefixes: HashSet::new(),
         }
     }
 
-    pub(super) fn page_prefixes(&self) -> HashSet<String> {
-        self.page_prefixes.clone()
-    }
-
-    pub(super) fn iron_router(mut self) -> Router {
+    pub(super) fn into_router(self) -> Router {
         let mut router = Router::new();
-        for (pattern, handler) in self.get.drain(..) {
-            router.get(&pattern, handler, calculate_id(&pattern));
+
+        let explicit: HashSet<String> = self
+            .get
+            .iter()
+            .map(|(pattern, _)| pattern.clone())
+            .collect();
+        for (pattern, route) in self.trailing_slash_redirects {
+            if !explicit.contains(&pattern) {
+                router = router.route(&pattern, route);
+            }
+        }
+        for (pattern, route) in self.get {
+            router = router.route(&pattern, route);
+        }
+        for (pattern, route) in self.post {
+            router = router.route(&pattern, route);
         }
 
         // All rustdoc pages have the prefixes of other docs.rs pages blacklisted. This prevents,
         // for example, a crate named "about" from hijacking /about/0.1.0/index.html.
-        let blacklist = self.page_prefixes();
-        for (pattern, handler) in self.rustdoc_get.drain(..) {
-            router.get(
+        let blacklist = Arc::new(self.page_prefixes);
+        for (pattern, route) in self.rustdoc_get {
+            router = router.route(
                 &pattern,
-                BlockBlacklistedPrefixes::new(blacklist.clone(), handler),
-                calculate_id(&pattern),
+                route.layer(middleware::from_fn_with_state(
+                    blacklist.clone(),
+                    block_blacklisted_prefixes_middleware,
+                )),
             );
         }
 
-        router
+        router.fallback(fallback)
     }
 
     /// A static resource is a normal page without any special behavior on the router side.
-    fn static_resource(&mut self, pattern: &str, handler: impl Handler) {
+    fn static_resource<H: Handler<T, ()>, T: 'static>(&mut self, pattern: &str, handler: H) {
         self.get.push((
             pattern.to_string(),
-            Box::new(RequestRecorder::new(handler, "static resource")),
+            recorded(
+                cache_tagged(get(handler), RouteTags::for_pattern(pattern)),
+                "static resource",
+            ),
         ));
     }
 
@@ -232,24 +294,20 @@ impl Routes {
     ///
     /// - If the page URL doesn't end with a slash, a redirect from the URL with the trailing slash
     /// to the one without is automatically added.
-    fn internal_page(&mut self, pattern: &str, handler: impl Handler) {
+    fn internal_page<H: Handler<T, ()>, T: 'static>(&mut self, pattern: &str, handler: H) {
+        let tags = RouteTags::for_pattern(pattern);
         self.get.push((
             pattern.to_string(),
-            Box::new(RequestRecorder::new(handler, pattern)),
+            recorded(cache_tagged(get(handler), tags), pattern),
         ));
 
         // Automatically add another route ending with / that redirects to the slash-less route.
-        if !pattern.ends_with('/') {
+        // Wildcards have to be at the end of a pattern, and already match the trailing slash.
+        if !pattern.ends_with('/') && !pattern.contains('*') {
             let pattern = format!("{}/", pattern);
-            self.get.push((
-                pattern.to_string(),
-                Box::new(RequestRecorder::new(
-                    SimpleRedirect::new(|url| {
-                        #[allow(clippy::unnecessary_to_owned)]
-                        url.set_path(&url.path().trim_end_matches('/').to_string())
-                    }),
-                    pattern,
-                )),
+            self.trailing_slash_redirects.push((
+                pattern.clone(),
+                recorded(cache_tagged(get(remove_trailing_slash), tags), &pattern),
             ));
         }
 
@@ -264,92 +322,139 @@ impl Routes {
         }
     }
 
+    /// An admin page is only served to requests with the token configured in
+    /// `DOCSRS_ADMIN_TOKEN`, and not at all without one. Its responses are never cached.
+    fn admin_page<H: Handler<T, ()>, T: 'static>(&mut self, pattern: &str, handler: H) {
+        self.get.push((
+            pattern.to_string(),
+            recorded(
+                get(handler).layer(middleware::from_fn(admin_only_middleware)),
+                pattern,
+            ),
+        ));
+    }
+
+    /// An API endpoint is only used by other docs.rs services, like the remote builders, and is
+    /// never cached.
+    fn api_endpoint<H: Handler<T, ()>, T: 'static>(&mut self, pattern: &str, handler: H) {
+        self.post
+            .push((pattern.to_string(), recorded(post(handler), "api endpoint")));
+    }
+
     /// A rustdoc page is a page serving generated documentation. It's similar to a static
     /// resource, but path prefixes are automatically blacklisted (see internal pages to learn more
     /// about page prefixes).
-    fn rustdoc_page(&mut self, pattern: &str, handler: impl Handler) {
-        self.get.push((
+    fn rustdoc_page<H: Handler<T, ()>, T: 'static>(&mut self, pattern: &str, handler: H) {
+        self.rustdoc_get.push((
             pattern.to_string(),
-            Box::new(RequestRecorder::new(handler, "rustdoc page")),
+            recorded(
+                cache_tagged(get(handler), RouteTags::for_pattern(pattern)),
+                "rustdoc page",
+            ),
         ));
     }
 }
 
-#[derive(Copy, Clone)]
-struct SimpleRedirect {
-    url_mangler: fn(&mut iron::url::Url),
+/// Records the requests to the route under `name` in the metrics.
+fn recorded(route: MethodRouter, name: &str) -> MethodRouter {
+    route.layer(middleware::from_fn_with_state(
+        Arc::<str>::from(name),
+        request_recorder,
+    ))
 }
 
-impl SimpleRedirect {
-    fn new(url_mangler: fn(&mut iron::url::Url)) -> Self {
-        Self { url_mangler }
-    }
+/// Tags the responses of the route, including its error pages.
+fn cache_tagged(route: MethodRouter, tags: RouteTags) -> MethodRouter {
+    route.layer(middleware::from_fn_with_state(tags, cache_tags_middleware))
 }
 
-impl Handler for SimpleRedirect {
-    fn handle(&self, req: &mut iron::Request) -> iron::IronResult<iron::Response> {
-        let mut url: iron::url::Url = req.url.clone().into();
-        (self.url_mangler)(&mut url);
-        Ok(iron::Response::with((
-            iron::status::Found,
-            iron::modifiers::Redirect(iron::Url::from_generic_url(url).unwrap()),
-        )))
-    }
+fn found(location: String) -> Response {
+    (StatusCode::FOUND, [(LOCATION, location)]).into_response()
+}
+
+/// Redirects to the path with the trailing slashes removed, keeping the query.
+async fn remove_trailing_slash(uri: Uri) -> Response {
+    let path = uri.path().trim_end_matches('/');
+    found(match uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_owned(),
+    })
+}
+
+/// Redirects to the path with a trailing slash, keeping the query.
+async fn add_trailing_slash(uri: Uri) -> Response {
+    found(match uri.query() {
+        Some(query) => format!("{}/?{}", uri.path(), query),
+        None => format!("{}/", uri.path()),
+    })
 }
 
-#[derive(Copy, Clone)]
-struct PermanentRedirect(&'static str);
+fn permanent_redirect(location: &'static str) -> Response {
+    (StatusCode::MOVED_PERMANENTLY, [(LOCATION, location)]).into_response()
+}
 
-impl Handler for PermanentRedirect {
-    fn handle(&self, _req: &mut iron::Request) -> iron::IronResult<iron::Response> {
-        Ok(iron::Response::with((
-            iron::status::MovedPermanently,
-            iron::modifiers::RedirectRaw(self.0.to_owned()),
-        )))
+/// Answers the requests not matching any route. Like the router used before, paths with a
+/// trailing slash are redirected to the path without it, which might match a route.
+async fn fallback(uri: Uri) -> Response {
+    let path = uri.path();
+    match path.strip_suffix('/') {
+        Some(without_slash) if !without_slash.is_empty() => {
+            let target = match uri.query() {
+                Some(query) => format!("{}?{}", without_slash, query),
+                None => without_slash.to_owned(),
+            };
+            (StatusCode::MOVED_PERMANENTLY, [(LOCATION, target)]).into_response()
+        }
+        _ => Nope::ResourceNotFound.into_response(),
     }
 }
 
-/// Iron Middleware that prevents requests to blacklisted prefixes.
+/// Middleware that prevents requests to blacklisted prefixes.
 ///
 /// In our application, a prefix is blacklisted if a docs.rs page exists below it. For example,
 /// since /releases/queue is a docs.rs page, /releases is a blacklisted prefix.
 ///
 /// The middleware must be used for all the pages serving crates at the top level, to prevent a
 /// crate from putting their own content in an URL that's supposed to be used by docs.rs.
-pub(super) struct BlockBlacklistedPrefixes {
-    blacklist: HashSet<String>,
-    handler: Box<dyn Handler>,
-}
-
-impl BlockBlacklistedPrefixes {
-    pub(super) fn new(blacklist: HashSet<String>, handler: Box<dyn Handler>) -> Self {
-        Self { blacklist, handler }
-    }
-}
-
-impl Handler for BlockBlacklistedPrefixes {
-    fn handle(&self, req: &mut iron::Request) -> iron::IronResult<iron::Response> {
-        if let Some(prefix) = req.url.path().first() {
-            if self.blacklist.contains(*prefix) {
-                return Err(super::error::Nope::CrateNotFound.into());
-            }
+async fn block_blacklisted_prefixes_middleware<B>(
+    State(blacklist): State<Arc<HashSet<String>>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(prefix) = req.uri().path().trim_start_matches('/').split('/').next() {
+        if blacklist.contains(prefix) {
+            return Nope::CrateNotFound.into_response();
         }
-        self.handler.handle(req)
     }
+    next.run(req).await
 }
 
-/// Automatically generate a Route ID from a pattern. Every non-alphanumeric character is replaced
-/// with `_`.
-fn calculate_id(pattern: &str) -> String {
-    let calculate_char = |c: char| {
-        if c.is_alphanumeric() || c == '-' {
-            c
-        } else {
-            '_'
-        }
+/// Only passes requests with the admin token to the handler.
+async fn admin_only_middleware<B>(req: Request<B>, next: Next<B>) -> Response {
+    let token = match req
+        .extensions()
+        .get::<Arc<Config>>()
+        .expect("missing Config from the request extensions")
+        .admin_token
+        .clone()
+    {
+        Some(token) => token,
+        None => return Nope::ResourceNotFound.into_response(),
     };
+    if !super::has_bearer_token(req.headers(), &token) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(WWW_AUTHENTICATE, "Bearer")],
+            "invalid admin token",
+        )
+            .into_response();
+    }
 
-    pattern.chars().map(calculate_char).collect()
+    let mut response = next.run(req).await;
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("private, no-store"));
+    response
 }
 
 #[cfg(test)]
@@ -376,4 +481,88 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn cache_tags() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("some_crate")
+                .version("0.1.0")
+                .rustdoc_file("some_crate/index.html")
+                .create()?;
+            let web = env.frontend();
+
+            let tags = |path: &str| -> Result<Option<String>, anyhow::Error> {
+                let resp = web.get(path).send()?;
+                let surrogate_key = resp
+                    .headers()
+                    .get("surrogate-key")
+                    .map(|value| value.to_str().unwrap().to_string());
+                let cache_tag = resp
+                    .headers()
+                    .get("cache-tag")
+                    .map(|value| value.to_str().unwrap().replace(',', " "));
+                resp.bytes()?;
+                assert_eq!(surrogate_key, cache_tag);
+                Ok(surrogate_key)
+            };
+
+            let crate_tags = "crate:some-crate crate:some-crate:0.1.0";
+            assert_eq!(tags("/some_crate/0.1.0/some_crate/")?.unwrap(), crate_tags);
+            assert_eq!(tags("/crate/some_crate/0.1.0")?.unwrap(), crate_tags);
+            assert_eq!(
+                tags("/crate/Some-Crate/latest")?.unwrap(),
+                "crate:some-crate crate:some-crate:latest"
+            );
+            // not found pages are purged when the crate is published
+            assert_eq!(
+                tags("/crate/other/1.0.0")?.unwrap(),
+                "crate:other crate:other:1.0.0"
+            );
+
+            assert_eq!(tags("/")?.unwrap(), "releases-list");
+            assert_eq!(tags("/releases/recent/2")?.unwrap(), "releases-list");
+            assert_eq!(tags("/-/static/style.css")?.unwrap(), "static");
+            assert_eq!(tags("/robots.txt")?.unwrap(), "static");
+            assert_eq!(tags("/about")?.unwrap(), "static");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn admin_pages_disabled_without_token() {
+        wrapper(|env| {
+            let response = env
+                .frontend()
+                .get("/-/admin/limits")
+                .bearer_auth("admin")
+                .send()?;
+            assert_eq!(response.status(), 404);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn admin_pages_need_the_token() {
+        wrapper(|env| {
+            env.override_config(|config| config.admin_token = Some("admin".into()));
+            let web = env.frontend();
+            for request in [
+                web.get("/-/admin/limits"),
+                web.get("/-/admin/storage").bearer_auth("wrong"),
+            ] {
+                let response = request.send()?;
+                assert_eq!(response.status(), 401);
+                assert_eq!(response.headers()["www-authenticate"], "Bearer");
+            }
+
+            let response = web.get("/-/admin/storage").bearer_auth("admin").send()?;
+            assert!(response.status().is_success());
+            assert_eq!(response.headers()["cache-control"], "private, no-store");
+            assert!(response.headers().get("surrogate-key").is_none());
+
+            Ok(())
+        });
+    }
 }
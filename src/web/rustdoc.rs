@@ -5,24 +5,44 @@ use crate::{
     repositories::RepositoryStatsUpdater,
     utils,
     web::{
-        crate_details::CrateDetails, csp::Csp, error::Nope, file::File, match_version,
-        metrics::RenderingTimesRecorder, redirect_base, MatchSemver, MetaData,
+        cache::PageCache,
+        cache_tags::{CacheTags, STATIC_TAG},
+        conditional::CacheValidators,
+        crate_details::CrateDetails,
+        csp::Csp,
+        error::{AxumResult, Nope},
+        file::File,
+        match_version,
+        metrics::RenderingTimesRecorder,
+        page::TemplateData,
+        redirect_base, MatchSemver, MetaData,
     },
     Config, Metrics, Storage,
 };
 use anyhow::{anyhow, Context};
-use iron::{
-    headers::{CacheControl, CacheDirective, Expires, HttpDate},
-    modifiers::Redirect,
-    status,
-    url::percent_encoding::percent_decode,
-    Handler, IronResult, Request, Response, Url,
+use axum::{
+    body::{boxed, Body, Full},
+    extract::{Extension, Path, Query},
+    handler::Handler,
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, LOCATION},
+        HeaderMap, HeaderValue, Request, StatusCode, Uri,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
 };
 use lol_html::errors::RewritingError;
 use once_cell::sync::Lazy;
-use router::Router;
-use serde::Serialize;
-use std::{collections::HashMap, fmt::Write, path::Path};
+use percent_encoding::percent_decode;
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    path::Path as FsPath,
+    sync::Arc,
+};
+use url::{form_urlencoded, Url};
 
 static DOC_RUST_LANG_ORG_REDIRECTS: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
     HashMap::from([
@@ -37,125 +57,141 @@ static DOC_RUST_LANG_ORG_REDIRECTS: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
     ])
 });
 
+#[derive(Deserialize)]
+pub(crate) struct RustdocRedirectorParams {
+    #[serde(rename = "crate")]
+    name: String,
+    version: Option<String>,
+    target: Option<String>,
+}
+
 /// Handler called for `/:crate` and `/:crate/:version` URLs. Automatically redirects to the docs
 /// or crate details page based on whether the given crate version was successfully built.
-pub fn rustdoc_redirector_handler(req: &mut Request) -> IronResult<Response> {
+pub(crate) async fn rustdoc_redirector_handler(
+    Path(params): Path<RustdocRedirectorParams>,
+    Extension(metrics): Extension<Arc<Metrics>>,
+    Extension(storage): Extension<Arc<Storage>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(pool): Extension<Pool>,
+    req: Request<Body>,
+) -> AxumResult<Response> {
     fn redirect_to_doc(
-        req: &Request,
+        uri: &Uri,
         url_str: String,
         permanent: bool,
         path_in_crate: Option<&str>,
-    ) -> IronResult<Response> {
-        let mut queries: std::collections::BTreeMap<
-            std::borrow::Cow<'_, str>,
-            std::borrow::Cow<'_, str>,
-        > = std::collections::BTreeMap::new();
+    ) -> AxumResult<Response> {
+        let mut queries: BTreeMap<Cow<'_, str>, Cow<'_, str>> = BTreeMap::new();
         if let Some(path) = path_in_crate {
             queries.insert("search".into(), path.into());
         }
-        let url: iron::url::Url = req.url.clone().into();
-        let query_pairs = url.query_pairs();
-        queries.extend(query_pairs);
+        queries.extend(form_urlencoded::parse(
+            uri.query().unwrap_or_default().as_bytes(),
+        ));
         let url = if queries.is_empty() {
-            ctry!(req, iron::url::Url::parse(&url_str))
+            Url::parse(&url_str)
         } else {
-            ctry!(req, iron::url::Url::parse_with_params(&url_str, queries))
-        };
+            Url::parse_with_params(&url_str, queries)
+        }
+        .map_err(|err| anyhow!(err).context("failed to build the redirect URL"))?;
         let (status_code, max_age) = if permanent {
-            (status::MovedPermanently, 86400)
+            (StatusCode::MOVED_PERMANENTLY, 86400)
         } else {
-            (status::Found, 0)
+            (StatusCode::FOUND, 0)
         };
-        let url = ctry!(req, Url::from_generic_url(url));
-        let mut resp = Response::with((status_code, Redirect(url)));
-        resp.headers
-            .set(CacheControl(vec![CacheDirective::MaxAge(max_age)]));
-        Ok(resp)
+        Ok((
+            status_code,
+            [
+                (
+                    LOCATION,
+                    HeaderValue::from_str(url.as_str()).expect("URLs are valid header values"),
+                ),
+                (
+                    CACHE_CONTROL,
+                    HeaderValue::from_str(&format!("max-age={}", max_age)).unwrap(),
+                ),
+            ],
+        )
+            .into_response())
     }
 
-    fn redirect_to_crate(req: &Request, name: &str, vers: &str) -> IronResult<Response> {
-        let url = ctry!(
-            req,
-            Url::parse(&format!("{}/crate/{}/{}", redirect_base(req), name, vers)),
-        );
-
-        let mut resp = Response::with((status::Found, Redirect(url)));
-        resp.headers.set(Expires(HttpDate(time::now())));
+    fn redirect_to_crate(headers: &HeaderMap, name: &str, vers: &str) -> AxumResult<Response> {
+        let url = Url::parse(&format!(
+            "{}/crate/{}/{}",
+            redirect_base(headers),
+            name,
+            vers
+        ))
+        .map_err(|err| anyhow!(err).context("failed to build the redirect URL"))?;
 
-        Ok(resp)
+        Ok(super::redirect(url))
     }
 
-    let metrics = extension!(req, Metrics).clone();
     let mut rendering_time = RenderingTimesRecorder::new(&metrics.rustdoc_redirect_rendering_times);
 
-    // this unwrap is safe because iron urls are always able to use `path_segments`
-    // i'm using this instead of `req.url.path()` to avoid allocating the Vec, and also to avoid
-    // keeping the borrow alive into the return statement
-    if req
-        .url
-        .as_ref()
-        .path_segments()
-        .unwrap()
-        .last()
-        .map_or(false, |s| s.ends_with(".js"))
-    {
+    let last_segment = req.uri().path().rsplit('/').next().unwrap_or_default();
+    if last_segment.ends_with(".js") {
         // javascript files should be handled by the file server instead of erroneously
         // redirecting to the crate root page
-        if req.url.as_ref().path_segments().unwrap().count() > 2 {
+        if req.uri().path().split('/').skip(1).count() > 2 {
             // this URL is actually from a crate-internal path, serve it there instead
             rendering_time.step("serve JS for crate");
-            return rustdoc_html_server_handler(req);
+            return Ok(rustdoc_html_server_handler.call(req, ()).await);
         } else {
             rendering_time.step("serve JS");
-            let storage = extension!(req, Storage);
-            let config = extension!(req, Config);
-
-            let path = req.url.path();
-            let path = path.join("/");
-            return match File::from_path(storage, &path, config) {
-                Ok(f) => Ok(f.serve()),
-                Err(..) => Err(Nope::ResourceNotFound.into()),
+
+            let path = req.uri().path().trim_start_matches('/');
+            return match File::from_path(&storage, path, &config).await {
+                Ok(f) => Ok(f.serve(req.headers())),
+                Err(..) => Err(Nope::ResourceNotFound),
             };
         }
-    } else if req
-        .url
-        .as_ref()
-        .path_segments()
-        .unwrap()
-        .last()
-        .map_or(false, |s| s.ends_with(".ico"))
-    {
+    } else if last_segment.ends_with(".ico") {
         // route .ico files into their dedicated handler so that docs.rs's favicon is always
         // displayed
         rendering_time.step("serve ICO");
-        return super::statics::ico_handler(req);
+        return super::statics::ico_handler(req.headers(), req.uri());
     }
 
-    let router = extension!(req, Router);
-    let mut conn = extension!(req, Pool).get()?;
+    let (parts, _) = req.into_parts();
+    let (headers, uri) = (&parts.headers, &parts.uri);
 
-    // this handler should never called without crate pattern
-    let crate_name = cexpect!(req, router.find("crate"));
-    let crate_name = percent_decode(crate_name.as_bytes())
-        .decode_utf8()
-        .unwrap_or_else(|_| crate_name.into());
-    let (mut crate_name, path_in_crate) = match crate_name.split_once("::") {
+    // an empty version comes from a doubled slash like `/crate//`, which is redirected to the
+    // path with the extra slash removed
+    if params.version.as_deref() == Some("") {
+        let path = uri.path().strip_suffix('/').unwrap_or_else(|| uri.path());
+        let target = match uri.query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path.to_owned(),
+        };
+        return Ok((StatusCode::MOVED_PERMANENTLY, [(LOCATION, target)]).into_response());
+    }
+
+    let (mut crate_name, path_in_crate) = match params.name.split_once("::") {
         Some((krate, path)) => (krate.to_string(), Some(path.to_string())),
-        None => (crate_name.to_string(), None),
+        None => (params.name.clone(), None),
     };
 
     if let Some(inner_path) = DOC_RUST_LANG_ORG_REDIRECTS.get(crate_name.as_str()) {
         let url = format!("https://doc.rust-lang.org/{inner_path}/");
-        return redirect_to_doc(req, url, false, path_in_crate.as_deref());
+        return redirect_to_doc(uri, url, false, path_in_crate.as_deref());
     }
 
-    let req_version = router.find("version");
-    let mut target = router.find("target");
+    let req_version = params.version;
+    let mut target = params.target;
 
     // it doesn't matter if the version that was given was exact or not, since we're redirecting
     // anyway
     rendering_time.step("match version");
-    let v = match_version(&mut conn, &crate_name, req_version)?;
+    let v = {
+        let (pool, crate_name, req_version) =
+            (pool.clone(), crate_name.clone(), req_version.clone());
+        super::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            match_version(&mut conn, &crate_name, req_version.as_deref())
+        })
+        .await?
+    };
     if let Some(new_name) = v.corrected_name {
         // `match_version` checked against -/_ typos, so if we have a name here we should
         // use that instead
@@ -163,45 +199,44 @@ pub fn rustdoc_redirector_handler(req: &mut Request) -> IronResult<Response> {
     }
     let (mut version, id) = v.version.into_parts();
 
-    if req_version == None || req_version == Some("latest") {
+    if req_version.is_none() || req_version.as_deref() == Some("latest") {
         version = "latest".to_string()
     }
 
     // get target name and whether it has docs
     // FIXME: This is a bit inefficient but allowing us to use less code in general
     rendering_time.step("fetch release doc status");
-    let (target_name, has_docs): (String, bool) = {
-        let rows = ctry!(
-            req,
-            conn.query(
-                "SELECT target_name, rustdoc_status
-                 FROM releases
-                 WHERE releases.id = $1",
-                &[&id]
-            ),
-        );
-
-        (rows[0].get(0), rows[0].get(1))
-    };
-
-    if target == Some("index.html") || target == Some(&target_name) {
+    let (target_name, has_docs): (String, bool) = super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        let rows = conn.query(
+            "SELECT target_name, rustdoc_status
+             FROM releases
+             WHERE releases.id = $1",
+            &[&id],
+        )?;
+
+        Ok((rows[0].get(0), rows[0].get(1)))
+    })
+    .await?;
+
+    if target.as_deref() == Some("index.html") || target.as_deref() == Some(&target_name) {
         target = None;
     }
 
     if has_docs {
         rendering_time.step("redirect to doc");
 
-        let base = redirect_base(req);
+        let base = redirect_base(headers);
         let url_str = if let Some(target) = target {
             format!("{base}/{crate_name}/{version}/{target}/{target_name}/")
         } else {
             format!("{base}/{crate_name}/{version}/{target_name}/")
         };
 
-        redirect_to_doc(req, url_str, version == "latest", path_in_crate.as_deref())
+        redirect_to_doc(uri, url_str, version == "latest", path_in_crate.as_deref())
     } else {
         rendering_time.step("redirect to crate");
-        redirect_to_crate(req, &crate_name, &version)
+        redirect_to_crate(headers, &crate_name, &version)
     }
 }
 
@@ -213,6 +248,8 @@ struct RustdocPage {
     latest_version: String,
     target: String,
     inner_path: String,
+    /// The feature profile of the displayed docs, empty for the main documentation.
+    feature_profile: String,
     // true if we are displaying the latest version of the crate, regardless
     // of whether the URL specifies a version number or the string "latest."
     is_latest_version: bool,
@@ -224,115 +261,125 @@ struct RustdocPage {
 }
 
 impl RustdocPage {
-    fn into_response(
+    /// Inserts the rustdoc file into our own page.
+    fn render(
         self,
         rustdoc_html: &[u8],
-        max_parse_memory: usize,
-        req: &mut Request,
         file_path: &str,
-    ) -> IronResult<Response> {
-        use iron::{headers::ContentType, status::Status};
-
-        let templates = req
-            .extensions
-            .get::<super::TemplateData>()
-            .expect("missing TemplateData from the request extensions");
-
-        let metrics = req
-            .extensions
-            .get::<crate::Metrics>()
-            .expect("missing Metrics from the request extensions");
-
-        let is_latest_url = self.is_latest_url;
+        templates: &TemplateData,
+        metrics: &Metrics,
+        config: &Config,
+    ) -> Result<Vec<u8>, anyhow::Error> {
         // Build the page of documentation
-        let ctx = ctry!(req, tera::Context::from_serialize(self));
-        let config = extension!(req, Config);
+        let ctx = tera::Context::from_serialize(self)?;
         // Extract the head and body of the rustdoc file so that we can insert it into our own html
         // while logging OOM errors from html rewriting
-        let html = match utils::rewrite_lol(rustdoc_html, max_parse_memory, ctx, templates) {
+        match utils::rewrite_lol(rustdoc_html, config.max_parse_memory, ctx, templates) {
             Err(RewritingError::MemoryLimitExceeded(..)) => {
                 metrics.html_rewrite_ooms.inc();
 
-                let err = anyhow!(
+                Err(anyhow!(
                     "Failed to serve the rustdoc file '{}' because rewriting it surpassed the memory limit of {} bytes",
                     file_path, config.max_parse_memory,
-                );
-
-                ctry!(req, Err(err))
+                ))
             }
-            result => ctry!(req, result),
-        };
+            result => Ok(result?),
+        }
+    }
+}
 
-        let mut response = Response::with((Status::Ok, html));
-        response.headers.set(ContentType::html());
-        if is_latest_url {
-            response
-                .headers
-                .set(CacheControl(vec![CacheDirective::MaxAge(0)]));
-        } else {
-            let mut directives = vec![];
-            if let Some(seconds) = config.cache_control_stale_while_revalidate {
-                directives.push(CacheDirective::Extension(
-                    "stale-while-revalidate".to_string(),
-                    Some(format!("{}", seconds)),
-                ));
-            }
+fn html_response(
+    html: Vec<u8>,
+    is_latest_url: bool,
+    validators: &CacheValidators,
+    config: &Config,
+) -> Response {
+    let mut response = Response::new(boxed(Full::from(html)));
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    validators.set_headers(&mut response);
+    set_cache_control(&mut response, is_latest_url, config);
+    response
+}
 
-            if let Some(seconds) = config.cache_control_max_age {
-                directives.push(CacheDirective::MaxAge(seconds));
-            }
+fn set_cache_control(response: &mut Response, is_latest_url: bool, config: &Config) {
+    if is_latest_url {
+        response
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static("max-age=0"));
+    } else {
+        let mut directives = vec![];
+        if let Some(seconds) = config.cache_control_stale_while_revalidate {
+            directives.push(format!("stale-while-revalidate={}", seconds));
+        }
 
-            if !directives.is_empty() {
-                response.headers.set(CacheControl(directives));
-            }
+        if let Some(seconds) = config.cache_control_max_age {
+            directives.push(format!("max-age={}", seconds));
+        }
+
+        if !directives.is_empty() {
+            response.headers_mut().insert(
+                CACHE_CONTROL,
+                HeaderValue::from_str(&directives.join(", "))
+                    .expect("cache directives are valid header values"),
+            );
         }
-        Ok(response)
     }
 }
 
+#[derive(Deserialize)]
+pub(crate) struct RustdocHtmlParams {
+    #[serde(rename = "crate")]
+    name: String,
+    version: String,
+}
+
 /// Serves documentation generated by rustdoc.
 ///
 /// This includes all HTML files for an individual crate, as well as the `search-index.js`, which is
 /// also crate-specific.
-pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
-    let metrics = extension!(req, Metrics).clone();
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn rustdoc_html_server_handler(
+    Path(params): Path<RustdocHtmlParams>,
+    Extension(metrics): Extension<Arc<Metrics>>,
+    Extension(templates): Extension<Arc<TemplateData>>,
+    Extension(pool): Extension<Pool>,
+    Extension(storage): Extension<Arc<Storage>>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(updater): Extension<Arc<RepositoryStatsUpdater>>,
+    Extension(page_cache): Extension<Arc<PageCache>>,
+    Extension(csp): Extension<Arc<Csp>>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> AxumResult<Response> {
     let mut rendering_time = RenderingTimesRecorder::new(&metrics.rustdoc_rendering_times);
 
     // Pages generated by Rustdoc are not ready to be served with a CSP yet.
-    req.extensions
-        .get_mut::<Csp>()
-        .expect("missing CSP")
-        .suppress(true);
-
-    // Get the request parameters
-    let router = extension!(req, Router);
+    csp.suppress(true);
 
     // Get the crate name and version from the request
-    let (name, url_version) = (
-        router.find("crate").unwrap_or("").to_string(),
-        router.find("version"),
-    );
-
-    let pool = extension!(req, Pool);
-    let mut conn = pool.get()?;
-    let config = extension!(req, Config);
-    let storage = extension!(req, Storage);
-    let mut req_path = req.url.path();
+    let RustdocHtmlParams {
+        name,
+        version: url_version,
+    } = params;
 
     // Remove the name and version from the path
-    req_path.drain(..2).for_each(drop);
+    let mut req_path: Vec<&str> = uri.path().split('/').skip(3).collect();
 
     // Convenience closure to allow for easy redirection
-    let redirect = |name: &str, vers: &str, path: &[&str]| -> IronResult<Response> {
+    let redirect = |name: &str, vers: &str, path: &[&str]| -> AxumResult<Response> {
         // Format and parse the redirect url
         let redirect_path = format!(
             "{}/{}/{}/{}",
-            redirect_base(req),
+            redirect_base(&headers),
             name,
             vers,
             path.join("/")
         );
-        let url = ctry!(req, Url::parse(&redirect_path));
+        let url = Url::parse(&redirect_path)
+            .map_err(|err| anyhow!(err).context("failed to build the redirect URL"))?;
 
         Ok(super::redirect(url))
     };
@@ -345,7 +392,14 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
     // * If both the name and the version are an exact match, return the version of the crate.
     // * If there is an exact match, but the requested crate name was corrected (dashes vs. underscores), redirect to the corrected name.
     // * If there is a semver (but not exact) match, redirect to the exact version.
-    let release_found = match_version(&mut conn, &name, url_version)?;
+    let release_found = {
+        let (pool, name) = (pool.clone(), name.clone());
+        super::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            match_version(&mut conn, &name, Some(&url_version))
+        })
+        .await?
+    };
 
     let (version, version_or_latest) = match release_found.version {
         MatchSemver::Exact((version, _)) => {
@@ -375,25 +429,38 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
         }
     };
 
-    let updater = extension!(req, RepositoryStatsUpdater);
+    let cache_generation = page_cache.generation();
 
     rendering_time.step("crate details");
 
     // Get the crate's details from the database
-    // NOTE: we know this crate must exist because we just checked it above (or else `match_version` is buggy)
-    let krate = cexpect!(
-        req,
-        ctry!(
-            req,
-            CrateDetails::new(
-                &mut *conn,
-                &name,
-                &version,
-                &version_or_latest,
-                Some(updater)
-            )
-        )
-    );
+    let krate = {
+        let (page_cache, name, version, version_or_latest) = (
+            page_cache.clone(),
+            name.clone(),
+            version.clone(),
+            version_or_latest.clone(),
+        );
+        super::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            page_cache
+                .crate_details(&name, &version, &version_or_latest, || {
+                    CrateDetails::new(
+                        &mut *conn,
+                        &name,
+                        &version,
+                        &version_or_latest,
+                        Some(&updater),
+                    )
+                })?
+                // NOTE: we know this crate must exist because we just checked it above (or else
+                // `match_version` is buggy)
+                .ok_or_else(|| {
+                    Nope::InternalError(anyhow!("missing details of {} {}", name, version))
+                })
+        })
+        .await?
+    };
 
     // if visiting the full path to the default target, remove the target from the path
     // expects a req_path that looks like `[/:target]/.*`
@@ -408,16 +475,70 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
         path.push_str("index.html");
         req_path.push("index.html");
     }
-    let mut path = ctry!(req, percent_decode(path.as_bytes()).decode_utf8());
+    let mut path = percent_decode(path.as_bytes())
+        .decode_utf8()
+        .context("the path isn't valid UTF-8")?
+        .into_owned();
+
+    // The path within this crate version's rustdoc output
+    let (target, inner_path) = {
+        let mut inner_path = req_path.clone();
+
+        let target = if inner_path.len() > 1 && krate.metadata.is_doc_variant_dir(inner_path[0]) {
+            inner_path.remove(0)
+        } else {
+            ""
+        };
+
+        (target, inner_path.join("/"))
+    };
+
+    let page_key = format!("{}\n{}", uri.path(), uri.query().unwrap_or_default());
+
+    // The documentation of a release only changes when it is rebuilt, so conditional requests
+    // can be answered before loading and rewriting the file.
+    let mut validators = CacheValidators::new(format!("{}\n{}", krate.cache_key(), page_key));
+    if let Some(last_build_time) = krate.last_build_time {
+        validators = validators.last_modified(last_build_time);
+    }
+    if validators.is_fresh(&headers) {
+        rendering_time.step("not modified");
+        let mut response = validators.not_modified();
+        if path.ends_with(".html") {
+            set_cache_control(&mut response, version_or_latest == "latest", &config);
+        } else {
+            response
+                .headers_mut()
+                .insert(CACHE_CONTROL, File::cache_control());
+        }
+        return Ok(response);
+    }
+
+    // Rewriting the HTML is the most expensive part of serving a page, so keep the rewritten
+    // pages of exact versions. They are dropped when a release of the crate changes.
+    let is_latest_url = version_or_latest == "latest";
+    let cache_html = !is_latest_url && path.ends_with(".html");
+    if cache_html {
+        if let Some(html) = page_cache.rustdoc_html(&name, &page_key) {
+            rendering_time.step("cached html");
+            metrics
+                .recently_accessed_releases
+                .record(krate.crate_id, krate.release_id, target);
+            return Ok(html_response(html, is_latest_url, &validators, &config));
+        }
+    }
 
     // Attempt to load the file from the database
-    let blob = match storage.fetch_rustdoc_file(
-        &name,
-        &version,
-        &path,
-        krate.archive_storage,
-        &mut rendering_time,
-    ) {
+    let blob = match storage
+        .fetch_rustdoc_file(
+            &name,
+            &version,
+            &path,
+            krate.archive_storage,
+            &mut rendering_time,
+        )
+        .await
+    {
         Ok(file) => file,
         Err(err) => {
             if !matches!(err.downcast_ref(), Some(Nope::ResourceNotFound))
@@ -426,13 +547,13 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
                 log::debug!("got error serving {}: {}", path, err);
             }
             // If it fails, we try again with /index.html at the end
-            path.to_mut().push_str("/index.html");
+            path.push_str("/index.html");
             req_path.push("index.html");
 
-            return if ctry!(
-                req,
-                storage.rustdoc_file_exists(&name, &version, &path, krate.archive_storage)
-            ) {
+            return if storage
+                .rustdoc_file_exists_async(&name, &version, &path, krate.archive_storage)
+                .await?
+            {
                 redirect(&name, &version_or_latest, &req_path)
             } else if req_path.first().map_or(false, |p| p.contains('-')) {
                 // This is a target, not a module; it may not have been built.
@@ -443,7 +564,7 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
                     &req_path,
                 )
             } else {
-                Err(Nope::ResourceNotFound.into())
+                Err(Nope::ResourceNotFound)
             };
         }
     };
@@ -452,7 +573,7 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
     if !path.ends_with(".html") {
         rendering_time.step("serve asset");
 
-        return Ok(File(blob).serve());
+        return Ok(File(blob).serve_with(&validators));
     }
 
     rendering_time.step("find latest path");
@@ -471,32 +592,10 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
         })
         // should be impossible unless there is a semver incompatible version in the db
         // Note that there is a redirect earlier for semver matches to the exact version
-        .map_err(|err| {
-            utils::report_error(&err);
-            Nope::InternalServerError
-        })?
+        .map_err(Nope::InternalError)?
         .pre
         .is_empty());
 
-    // The path within this crate version's rustdoc output
-    let (target, inner_path) = {
-        let mut inner_path = req_path.clone();
-
-        let target = if inner_path.len() > 1
-            && krate
-                .metadata
-                .doc_targets
-                .iter()
-                .any(|s| s == inner_path[0])
-        {
-            inner_path.remove(0)
-        } else {
-            ""
-        };
-
-        (target, inner_path.join("/"))
-    };
-
     // Find the path of the latest version for the `Go to latest` and `Permalink` links
     let target_redirect = if latest_release.build_status {
         let target = if target.is_empty() {
@@ -509,7 +608,7 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
         "".to_string()
     };
 
-    let query_string = if let Some(query) = req.url.query() {
+    let query_string = if let Some(query) = uri.query() {
         format!("?{}", query)
     } else {
         "".to_string()
@@ -538,6 +637,12 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
         .recently_accessed_releases
         .record(krate.crate_id, krate.release_id, target);
 
+    let feature_profile = krate
+        .metadata
+        .feature_profile_for_dir(target)
+        .unwrap_or_default()
+        .to_string();
+
     let target = if target.is_empty() {
         String::new()
     } else {
@@ -545,20 +650,32 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
     };
 
     rendering_time.step("rewrite html");
-    RustdocPage {
+    let page = RustdocPage {
         latest_path,
         canonical_url,
         permalink_path,
         latest_version,
         target,
         inner_path,
+        feature_profile,
         is_latest_version,
-        is_latest_url: version_or_latest == "latest",
+        is_latest_url,
         is_prerelease,
         metadata: krate.metadata.clone(),
         krate,
+    };
+    let html = {
+        let (metrics, config) = (metrics.clone(), config.clone());
+        super::spawn_blocking(move || {
+            Ok(page.render(&blob.content, &path, &templates, &metrics, &config)?)
+        })
+        .await?
+    };
+
+    if cache_html {
+        page_cache.insert_rustdoc_html(&name, &page_key, &html, cache_generation);
     }
-    .into_response(&blob.content, config.max_parse_memory, req, &path)
+    Ok(html_response(html, is_latest_url, &validators, &config))
 }
 
 /// Checks whether the given path exists.
@@ -574,17 +691,12 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
 fn path_for_version(file_path: &[&str], crate_details: &CrateDetails) -> String {
     // check if req_path[3] is the platform choice or the name of the crate
     // Note we don't require the platform to have a trailing slash.
-    let platform = if crate_details
-        .metadata
-        .doc_targets
-        .iter()
-        .any(|s| s == file_path[0])
-        && !file_path.is_empty()
-    {
-        file_path[0]
-    } else {
-        ""
-    };
+    let platform =
+        if crate_details.metadata.is_doc_variant_dir(file_path[0]) && !file_path.is_empty() {
+            file_path[0]
+        } else {
+            ""
+        };
     let is_source_view = if platform.is_empty() {
         // /{name}/{version}/src/{crate}/index.html
         file_path.first().copied() == Some("src")
@@ -621,38 +733,56 @@ fn path_for_version(file_path: &[&str], crate_details: &CrateDetails) -> String
     result
 }
 
-pub fn target_redirect_handler(req: &mut Request) -> IronResult<Response> {
-    let router = extension!(req, Router);
-    let name = cexpect!(req, router.find("name"));
-    let version = cexpect!(req, router.find("version"));
-
-    let pool = extension!(req, Pool);
-    let mut conn = pool.get()?;
-    let storage = extension!(req, Storage);
-    let base = redirect_base(req);
-    let updater = extension!(req, RepositoryStatsUpdater);
-
-    let release_found = match_version(&mut conn, name, Some(version))?;
+#[derive(Deserialize)]
+pub(crate) struct TargetRedirectParams {
+    name: String,
+    version: String,
+    path: String,
+}
 
-    let (version, version_or_latest) = match release_found.version {
-        MatchSemver::Exact((version, _)) => (version.clone(), version),
-        MatchSemver::Latest((version, _)) => (version, "latest".to_string()),
-        // semver matching not supported here
-        MatchSemver::Semver(_) => return Err(Nope::VersionNotFound.into()),
-    };
+pub(crate) async fn target_redirect_handler(
+    Path(params): Path<TargetRedirectParams>,
+    Extension(pool): Extension<Pool>,
+    Extension(storage): Extension<Arc<Storage>>,
+    Extension(updater): Extension<Arc<RepositoryStatsUpdater>>,
+    Extension(page_cache): Extension<Arc<PageCache>>,
+    headers: HeaderMap,
+) -> AxumResult<Response> {
+    let TargetRedirectParams {
+        name,
+        version,
+        path: target_path,
+    } = params;
+    let base = redirect_base(&headers);
+
+    let (version, version_or_latest, crate_details) = {
+        let name = name.clone();
+        super::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            let release_found = match_version(&mut conn, &name, Some(&version))?;
+
+            let (version, version_or_latest) = match release_found.version {
+                MatchSemver::Exact((version, _)) => (version.clone(), version),
+                MatchSemver::Latest((version, _)) => (version, "latest".to_string()),
+                // semver matching not supported here
+                MatchSemver::Semver(_) => return Err(Nope::VersionNotFound),
+            };
 
-    let crate_details = match ctry!(
-        req,
-        CrateDetails::new(
-            &mut *conn,
-            name,
-            &version,
-            &version_or_latest,
-            Some(updater)
-        )
-    ) {
-        Some(krate) => krate,
-        None => return Err(Nope::VersionNotFound.into()),
+            let crate_details = page_cache
+                .crate_details(&name, &version, &version_or_latest, || {
+                    CrateDetails::new(
+                        &mut *conn,
+                        &name,
+                        &version,
+                        &version_or_latest,
+                        Some(&updater),
+                    )
+                })?
+                .ok_or(Nope::VersionNotFound)?;
+
+            Ok((version, version_or_latest, crate_details))
+        })
+        .await?
     };
 
     //   [crate, :name, :version, target-redirect, :target, *path]
@@ -660,8 +790,7 @@ pub fn target_redirect_handler(req: &mut Request) -> IronResult<Response> {
     //   [:target?, *path]
     // path might be empty, but target is guaranteed to be there because of the route used
     let file_path = {
-        let mut path = req.url.path();
-        path.drain(0..4); // crate, name, version, target-redirect
+        let mut path: Vec<&str> = target_path.split('/').collect();
 
         if path[0] == crate_details.metadata.default_target {
             path.remove(0);
@@ -673,15 +802,15 @@ pub fn target_redirect_handler(req: &mut Request) -> IronResult<Response> {
         path
     };
 
-    let path = if ctry!(
-        req,
-        storage.rustdoc_file_exists(
-            name,
+    let path = if storage
+        .rustdoc_file_exists_async(
+            &name,
             &version,
             &file_path.join("/"),
-            crate_details.archive_storage
+            crate_details.archive_storage,
         )
-    ) {
+        .await?
+    {
         // Simple case: page exists in the other target & version, so just change these
         file_path.join("/")
     } else {
@@ -689,28 +818,36 @@ pub fn target_redirect_handler(req: &mut Request) -> IronResult<Response> {
     };
 
     let url = format!("{base}/{name}/{version_or_latest}/{path}");
+    let url =
+        Url::parse(&url).map_err(|err| anyhow!(err).context("failed to build the redirect URL"))?;
 
-    let url = ctry!(req, Url::parse(&url));
-    let mut resp = Response::with((status::Found, Redirect(url)));
-    resp.headers.set(Expires(HttpDate(time::now())));
+    Ok(super::redirect(url))
+}
 
-    Ok(resp)
+#[derive(Deserialize)]
+pub(crate) struct BadgeQueryParams {
+    version: Option<String>,
 }
 
-pub fn badge_handler(req: &mut Request) -> IronResult<Response> {
-    let version = req
-        .url
-        .as_ref()
-        .query_pairs()
-        .find(|(key, _)| key == "version");
-    let version = version
-        .as_ref()
-        .map(|(_, version)| version.as_ref())
-        .unwrap_or("latest");
-    let name = cexpect!(req, extension!(req, Router).find("crate"));
-    let url = format!("https://img.shields.io/docsrs/{}/{}", name, version);
-    let url = ctry!(req, Url::parse(&url));
-    Ok(Response::with((status::MovedPermanently, Redirect(url))))
+pub(crate) async fn badge_handler(
+    Path(name): Path<String>,
+    Query(query): Query<BadgeQueryParams>,
+) -> AxumResult<Response> {
+    let url = format!(
+        "https://img.shields.io/docsrs/{}/{}",
+        name,
+        query.version.as_deref().unwrap_or("latest")
+    );
+    let url =
+        Url::parse(&url).map_err(|err| anyhow!(err).context("failed to build the badge URL"))?;
+    Ok((
+        StatusCode::MOVED_PERMANENTLY,
+        [(
+            LOCATION,
+            HeaderValue::from_str(url.as_str()).expect("URLs are valid header values"),
+        )],
+    )
+        .into_response())
 }
 
 /// Serves shared web resources used by rustdoc-generated documentation.
@@ -718,29 +855,40 @@ pub fn badge_handler(req: &mut Request) -> IronResult<Response> {
 /// This includes common `css` and `js` files that only change when the compiler is updated, but are
 /// otherwise the same for all crates documented with that compiler. Those have a custom handler to
 /// deduplicate them and save space.
-pub struct SharedResourceHandler;
-
-impl Handler for SharedResourceHandler {
-    fn handle(&self, req: &mut Request) -> IronResult<Response> {
-        let path = req.url.path();
-        let filename = path.last().unwrap(); // unwrap is fine: vector is non-empty
-        if let Some(extension) = Path::new(filename).extension() {
-            if ["js", "css", "woff", "woff2", "svg", "png"]
-                .iter()
-                .any(|s| *s == extension)
-            {
-                let storage = extension!(req, Storage);
-                let config = extension!(req, Config);
-
-                if let Ok(file) = File::from_path(storage, filename, config) {
-                    return Ok(file.serve());
-                }
+pub(super) async fn shared_resource_middleware<B>(req: Request<B>, next: Next<B>) -> Response {
+    let filename = req
+        .uri()
+        .path()
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_owned();
+    if let Some(extension) = FsPath::new(&filename).extension() {
+        if ["js", "css", "woff", "woff2", "svg", "png"]
+            .iter()
+            .any(|s| *s == extension)
+        {
+            let storage = req
+                .extensions()
+                .get::<Arc<Storage>>()
+                .expect("missing Storage from the request extensions")
+                .clone();
+            let config = req
+                .extensions()
+                .get::<Arc<Config>>()
+                .expect("missing Config from the request extensions")
+                .clone();
+
+            if let Ok(file) = File::from_path(&storage, &filename, &config).await {
+                let mut response = file.serve(req.headers());
+                CacheTags::fixed(STATIC_TAG).set_headers(&mut response);
+                return response;
             }
         }
-
-        // Just always return a 404 here - the main handler will then try the other handlers
-        Err(Nope::ResourceNotFound.into())
     }
+
+    // the file isn't shared, let the routes handle the request
+    next.run(req).await
 }
 
 #[cfg(test)]
@@ -748,7 +896,7 @@ mod test {
     use crate::test::*;
     use anyhow::Context;
     use kuchiki::traits::TendrilSink;
-    use reqwest::{blocking::ClientBuilder, redirect, StatusCode};
+    use reqwest::{blocking::ClientBuilder, header, redirect, StatusCode};
     use std::collections::BTreeMap;
     use test_case::test_case;
 
@@ -925,6 +1073,126 @@ mod test {
         })
     }
 
+    #[test_case(true)]
+    #[test_case(false)]
+    fn conditional_requests(archive_storage: bool) {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.cache_control_max_age = Some(600);
+            });
+
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .archive_storage(archive_storage)
+                .rustdoc_file("dummy/index.html")
+                .rustdoc_file("dummy/some.js")
+                .create()?;
+
+            let web = env.frontend();
+            for path in &["/dummy/0.1.0/dummy/", "/dummy/0.1.0/dummy/some.js"] {
+                let resp = web.get(path).send()?;
+                assert!(resp.status().is_success());
+                let etag = resp.headers()[header::ETAG].clone();
+                let last_modified = resp.headers()[header::LAST_MODIFIED].clone();
+                let cache_control = resp.headers()[header::CACHE_CONTROL].clone();
+                resp.bytes()?;
+
+                let resp = web
+                    .get(path)
+                    .header(header::IF_NONE_MATCH, etag.clone())
+                    .send()?;
+                assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+                assert_eq!(resp.headers()[header::ETAG], etag);
+                assert_eq!(resp.headers()[header::CACHE_CONTROL], cache_control);
+                assert!(resp.bytes()?.is_empty());
+
+                let resp = web
+                    .get(path)
+                    .header(header::IF_MODIFIED_SINCE, last_modified)
+                    .send()?;
+                assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+            }
+
+            let resp = web.get("/dummy/0.1.0/dummy/").send()?;
+            let etag = resp.headers()[header::ETAG].clone();
+            resp.bytes()?;
+
+            // The same file is rendered differently at another URL
+            let resp = web
+                .get("/dummy/latest/dummy/")
+                .header(header::IF_NONE_MATCH, etag.clone())
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            resp.bytes()?;
+
+            // A new release changes the version list in the page of the old release
+            env.fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .archive_storage(archive_storage)
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+            let resp = web
+                .get("/dummy/0.1.0/dummy/")
+                .header(header::IF_NONE_MATCH, etag.clone())
+                .send()?;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_ne!(resp.headers()[header::ETAG], etag);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn cached_pages_are_invalidated() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.crate_details_cache_size = 10;
+                config.rustdoc_html_cache_size = 1024 * 1024;
+            });
+
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+
+            let web = env.frontend();
+            let html_hits = env
+                .metrics()
+                .page_cache_hits
+                .with_label_values(&["rustdoc_html"]);
+
+            let page = web.get("/dummy/0.1.0/dummy/").send()?.text()?;
+            assert_eq!(html_hits.get(), 0);
+            assert_eq!(web.get("/dummy/0.1.0/dummy/").send()?.text()?, page);
+            assert_eq!(html_hits.get(), 1);
+
+            // Only pages of exact versions are cached
+            web.get("/dummy/latest/dummy/").send()?.bytes()?;
+            web.get("/dummy/latest/dummy/").send()?.bytes()?;
+            assert_eq!(html_hits.get(), 1);
+
+            // A new release changes the version list in the page of the old release
+            env.fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .rustdoc_file("dummy/index.html")
+                .create()?;
+
+            // The web server is notified asynchronously
+            for _ in 0..50 {
+                let page = web.get("/dummy/0.1.0/dummy/").send()?.text()?;
+                if page.contains("0.2.0") {
+                    return Ok(());
+                }
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+            panic!("the cached page was not invalidated");
+        })
+    }
+
     #[test_case(true)]
     #[test_case(false)]
     fn go_to_latest_version(archive_storage: bool) {
@@ -1665,6 +1933,78 @@ mod test {
         });
     }
 
+    #[test_case(true)]
+    #[test_case(false)]
+    fn feature_profile_links(archive_storage: bool) {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .archive_storage(archive_storage)
+                .rustdoc_file("dummy/index.html")
+                .rustdoc_file("dummy/struct.Dummy.html")
+                .default_target("x86_64-unknown-linux-gnu")
+                .add_feature_profile("tokio")
+                .create()?;
+
+            let web = env.frontend();
+            assert_success("/dummy/0.1.0/profile-tokio/dummy/struct.Dummy.html", web)?;
+
+            let get_profile_menu = |path: &str| -> anyhow::Result<(String, Vec<(String, String)>)> {
+                let dom = kuchiki::parse_html().one(web.get(path).send()?.text()?);
+                let title = dom
+                    .select_first(r#"a[aria-label="Feature profile"] .title"#)
+                    .expect("missing feature profile menu")
+                    .text_contents();
+                let links = dom
+                    .select("#feature-profiles a")
+                    .expect("invalid selector")
+                    .map(|el| {
+                        let href = el.attributes.borrow().get("href").unwrap().to_string();
+                        (el.text_contents().trim().to_string(), href)
+                    })
+                    .collect();
+                Ok((title, links))
+            };
+
+            let (title, links) = get_profile_menu("/dummy/0.1.0/dummy/struct.Dummy.html")?;
+            assert_eq!(title, "Features: default");
+            assert_eq!(
+                links,
+                vec![
+                    (
+                        "default".to_string(),
+                        "/crate/dummy/0.1.0/target-redirect/x86_64-unknown-linux-gnu/dummy/struct.Dummy.html".to_string()
+                    ),
+                    (
+                        "tokio".to_string(),
+                        "/crate/dummy/0.1.0/target-redirect/profile-tokio/dummy/struct.Dummy.html".to_string()
+                    ),
+                ]
+            );
+            assert_redirect(&links[0].1, "/dummy/0.1.0/dummy/struct.Dummy.html", web)?;
+            assert_redirect(
+                &links[1].1,
+                "/dummy/0.1.0/profile-tokio/dummy/struct.Dummy.html",
+                web,
+            )?;
+
+            let (title, profile_links) =
+                get_profile_menu("/dummy/0.1.0/profile-tokio/dummy/struct.Dummy.html")?;
+            assert_eq!(title, "Features: tokio");
+            assert_eq!(profile_links, links);
+
+            // pages missing from the profile docs fall back to a search
+            assert_redirect(
+                "/crate/dummy/0.1.0/target-redirect/profile-tokio/dummy/struct.Missing.html",
+                "/dummy/0.1.0/profile-tokio/dummy/?search=Missing",
+                web,
+            )?;
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_target_redirect_not_found() {
         wrapper(|env| {
@@ -3,16 +3,14 @@ use crate::{
     docbuilder::Limits,
     impl_webpage,
     utils::{get_config, ConfigName},
-    web::error::Nope,
-    web::page::WebPage,
+    web::error::{AxumResult, Nope},
 };
-use chrono::{DateTime, Utc};
-use iron::{
-    headers::ContentType,
-    mime::{Mime, SubLevel, TopLevel},
-    IronResult, Request, Response,
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
-use router::Router;
+use chrono::{DateTime, Utc};
 use serde::Serialize;
 
 /// sitemap index
@@ -23,13 +21,13 @@ struct SitemapIndexXml {
 
 impl_webpage! {
     SitemapIndexXml   = "core/sitemapindex.xml",
-    content_type = ContentType(Mime(TopLevel::Application, SubLevel::Xml, vec![])),
+    content_type = "application/xml",
 }
 
-pub fn sitemapindex_handler(req: &mut Request) -> IronResult<Response> {
+pub(crate) async fn sitemapindex_handler() -> impl IntoResponse {
     let sitemaps: Vec<char> = ('a'..='z').collect();
 
-    SitemapIndexXml { sitemaps }.into_response(req)
+    SitemapIndexXml { sitemaps }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
@@ -47,24 +45,24 @@ struct SitemapXml {
 
 impl_webpage! {
     SitemapXml   = "core/sitemap.xml",
-    content_type = ContentType(Mime(TopLevel::Application, SubLevel::Xml, vec![])),
+    content_type = "application/xml",
 }
 
-pub fn sitemap_handler(req: &mut Request) -> IronResult<Response> {
-    let router = extension!(req, Router);
-    let letter = cexpect!(req, router.find("letter"));
-
+pub(crate) async fn sitemap_handler(
+    Path(letter): Path<String>,
+    Extension(pool): Extension<Pool>,
+) -> AxumResult<impl IntoResponse> {
     if letter.len() != 1 {
-        return Err(Nope::ResourceNotFound.into());
+        return Err(Nope::ResourceNotFound);
     } else if let Some(ch) = letter.chars().next() {
         if !(ch.is_ascii_lowercase()) {
-            return Err(Nope::ResourceNotFound.into());
+            return Err(Nope::ResourceNotFound);
         }
     }
 
-    let mut conn = extension!(req, Pool).get()?;
-    let query = conn
-        .query(
+    let releases = super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        let query = conn.query(
             "SELECT crates.name,
                     releases.target_name,
                     MAX(releases.release_time) as release_time
@@ -76,22 +74,23 @@ pub fn sitemap_handler(req: &mut Request) -> IronResult<Response> {
              GROUP BY crates.name, releases.target_name
              ",
             &[&format!("{}%", letter)],
-        )
-        .unwrap();
-
-    let releases = query
-        .into_iter()
-        .map(|row| SitemapRow {
-            crate_name: row.get("name"),
-            target_name: row.get("target_name"),
-            last_modified: row
-                .get::<_, DateTime<Utc>>("release_time")
-                .format("%+")
-                .to_string(),
-        })
-        .collect();
-
-    SitemapXml { releases }.into_response(req)
+        )?;
+
+        Ok(query
+            .into_iter()
+            .map(|row| SitemapRow {
+                crate_name: row.get("name"),
+                target_name: row.get("target_name"),
+                last_modified: row
+                    .get::<_, DateTime<Utc>>("release_time")
+                    .format("%+")
+                    .to_string(),
+            })
+            .collect())
+    })
+    .await?;
+
+    Ok(SitemapXml { releases })
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
@@ -106,20 +105,20 @@ struct AboutBuilds {
 
 impl_webpage!(AboutBuilds = "core/about/builds.html");
 
-pub fn about_builds_handler(req: &mut Request) -> IronResult<Response> {
-    let mut conn = extension!(req, Pool).get()?;
-
-    let rustc_version = ctry!(
-        req,
-        get_config::<String>(&mut conn, ConfigName::RustcVersion)
-    );
+pub(crate) async fn about_builds_handler(
+    Extension(pool): Extension<Pool>,
+) -> AxumResult<impl IntoResponse> {
+    let rustc_version = super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        Ok(get_config::<String>(&mut conn, ConfigName::RustcVersion)?)
+    })
+    .await?;
 
-    AboutBuilds {
+    Ok(AboutBuilds {
         rustc_version,
         limits: Limits::default(),
         active_tab: "builds",
-    }
-    .into_response(req)
+    })
 }
 
 #[derive(Serialize)]
@@ -131,11 +130,11 @@ struct AboutPage<'a> {
 
 impl_webpage!(AboutPage<'_> = |this: &AboutPage| this.template.clone().into());
 
-pub fn about_handler(req: &mut Request) -> IronResult<Response> {
+pub(crate) async fn about_handler(subpage: Option<Path<String>>) -> Response {
     use super::ErrorPage;
-    use iron::status::Status;
 
-    let name = match *req.url.path().last().expect("iron is broken") {
+    let subpage = subpage.map(|Path(subpage)| subpage);
+    let name = match subpage.as_deref().unwrap_or("about") {
         "about" | "index" => "index",
         x @ "badges" | x @ "metadata" | x @ "redirections" => x,
         _ => {
@@ -144,9 +143,9 @@ pub fn about_handler(req: &mut Request) -> IronResult<Response> {
             let page = ErrorPage {
                 title: "The requested page does not exist",
                 message: Some(msg.into()),
-                status: Status::NotFound,
+                status: StatusCode::NOT_FOUND,
             };
-            return page.into_response(req);
+            return page.into_response();
         }
     };
     let template = format!("core/about/{}.html", name);
@@ -154,7 +153,7 @@ pub fn about_handler(req: &mut Request) -> IronResult<Response> {
         template,
         active_tab: name,
     }
-    .into_response(req)
+    .into_response()
 }
 
 #[cfg(test)]
@@ -3,19 +3,29 @@
 use crate::{
     db::Pool,
     impl_webpage,
-    utils::get_correct_docsrs_style_file,
+    storage::{detect_mime, source_archive_path},
+    utils::{get_correct_docsrs_style_file, report_error},
     web::{
-        error::Nope, file::File as DbFile, match_version, page::WebPage, redirect_base,
-        MatchSemver, MetaData, Url,
+        conditional::CacheValidators,
+        csp::Csp,
+        error::{AxumResult, Nope},
+        file::File as DbFile,
+        match_version,
+        page::WebPage,
+        redirect_base, MatchSemver, MetaData,
     },
     Storage,
 };
-use iron::{IronResult, Request, Response};
-use postgres::Client;
-use router::Router;
-use serde::Serialize;
+use anyhow::anyhow;
+use axum::{
+    extract::{Extension, Path},
+    http::{header::CACHE_CONTROL, HeaderMap, Uri},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::cmp::Ordering;
+use std::{cmp::Ordering, sync::Arc};
+use url::Url;
 
 /// A source file's name and mime type
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Serialize)]
@@ -36,7 +46,8 @@ struct FileList {
 impl FileList {
     /// Gets FileList from a request path
     ///
-    /// All paths stored in database have this format:
+    /// The files of releases using archive storage are listed from the index of their source
+    /// archive. All paths stored in database for the other releases have this format:
     ///
     /// ```text
     /// [
@@ -51,15 +62,19 @@ impl FileList {
     /// This function is only returning FileList for requested directory. If is empty,
     /// it will return list of files (and dirs) for root directory. req_path must be a
     /// directory or empty for root directory.
-    fn from_path(
-        conn: &mut Client,
+    async fn from_path(
+        pool: Pool,
+        storage: &Storage,
         name: &str,
         version: &str,
         version_or_latest: &str,
         req_path: &str,
-    ) -> Option<FileList> {
-        let rows = conn
-            .query(
+        archive_storage: bool,
+    ) -> AxumResult<Option<FileList>> {
+        let (crate_name, crate_version) = (name.to_owned(), version.to_owned());
+        let rows = super::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            Ok(conn.query(
                 "SELECT crates.name,
                         releases.version,
                         releases.description,
@@ -69,94 +84,135 @@ impl FileList {
                         releases.default_target,
                         releases.doc_targets,
                         releases.yanked,
-                        releases.doc_rustc_version
+                        releases.doc_rustc_version,
+                        releases.doc_feature_profiles
                 FROM releases
                 LEFT OUTER JOIN crates ON crates.id = releases.crate_id
                 WHERE crates.name = $1 AND releases.version = $2",
-                &[&name, &version],
-            )
-            .unwrap();
+                &[&crate_name, &crate_version],
+            )?)
+        })
+        .await?;
 
         if rows.is_empty() {
-            return None;
+            return Ok(None);
         }
 
-        let files: Value = rows[0].try_get(5).ok()?;
-
-        let mut file_list = Vec::new();
-        if let Some(files) = files.as_array() {
-            file_list.reserve(files.len());
-
-            for file in files {
-                if let Some(file) = file.as_array() {
-                    let mime = file[0].as_str().unwrap();
-                    let path = file[1].as_str().unwrap();
-
-                    // skip .cargo-ok generated by cargo
-                    if path == ".cargo-ok" {
-                        continue;
-                    }
-
-                    // look only files for req_path
-                    if path.starts_with(&req_path) {
-                        // remove req_path from path to reach files in this directory
-                        let path = path.replace(&req_path, "");
-                        let path_splited: Vec<&str> = path.split('/').collect();
-
-                        // if path have '/' it is a directory
-                        let mime = if path_splited.len() > 1 {
-                            "dir".to_owned()
-                        } else {
-                            mime.to_owned()
-                        };
-
-                        let file = File {
-                            name: path_splited[0].to_owned(),
-                            mime,
-                        };
-
-                        // avoid adding duplicates, a directory may occur more than once
-                        if !file_list.contains(&file) {
-                            file_list.push(file);
-                        }
-                    }
+        let archived_files = if archive_storage {
+            match storage
+                .list_archive_dir(&source_archive_path(name, version), req_path)
+                .await
+            {
+                Ok(entries) => Some(entries),
+                // fall back to the list of files stored in the database
+                Err(err) => {
+                    report_error(&err.context(format!(
+                        "failed to list the source files of {} {} from the archive index",
+                        name, version
+                    )));
+                    None
                 }
             }
+        } else {
+            None
+        };
+        let mut file_list = match archived_files {
+            Some(entries) => entries
+                .into_iter()
+                .filter(|entry| !(req_path.is_empty() && entry.name == ".cargo-ok"))
+                .map(|entry| File {
+                    mime: if entry.is_dir {
+                        "dir".to_owned()
+                    } else {
+                        detect_mime(&entry.name).to_owned()
+                    },
+                    name: entry.name,
+                })
+                .collect(),
+            None => {
+                let files = rows[0].try_get::<_, Value>(5).ok();
+                match files.as_ref().and_then(Value::as_array) {
+                    Some(files) => files_from_json(files, req_path),
+                    None => return Ok(None),
+                }
+            }
+        };
+
+        if file_list.is_empty() {
+            return Ok(None);
+        }
+
+        file_list.sort_by(|a, b| {
+            // directories must be listed first
+            if a.mime == "dir" && b.mime != "dir" {
+                Ordering::Less
+            } else if a.mime != "dir" && b.mime == "dir" {
+                Ordering::Greater
+            } else {
+                a.name.to_lowercase().cmp(&b.name.to_lowercase())
+            }
+        });
 
-            if file_list.is_empty() {
-                return None;
+        Ok(Some(FileList {
+            metadata: MetaData {
+                name: rows[0].get(0),
+                version: rows[0].get(1),
+                version_or_latest: version_or_latest.to_string(),
+                description: rows[0].get(2),
+                target_name: rows[0].get(3),
+                rustdoc_status: rows[0].get(4),
+                default_target: rows[0].get(6),
+                doc_targets: MetaData::parse_doc_targets(rows[0].get(7)),
+                doc_feature_profiles: MetaData::parse_doc_feature_profiles(rows[0].get(10)),
+                yanked: rows[0].get(8),
+                rustdoc_css_file: get_correct_docsrs_style_file(rows[0].get(9)).unwrap(),
+            },
+            files: file_list,
+        }))
+    }
+}
+
+/// Lists the files and directories in `req_path` from the `files` column of a release.
+fn files_from_json(files: &[Value], req_path: &str) -> Vec<File> {
+    let mut file_list = Vec::with_capacity(files.len());
+
+    for file in files {
+        if let Some(file) = file.as_array() {
+            let mime = file[0].as_str().unwrap();
+            let path = file[1].as_str().unwrap();
+
+            // skip .cargo-ok generated by cargo
+            if path == ".cargo-ok" {
+                continue;
             }
 
-            file_list.sort_by(|a, b| {
-                // directories must be listed first
-                if a.mime == "dir" && b.mime != "dir" {
-                    Ordering::Less
-                } else if a.mime != "dir" && b.mime == "dir" {
-                    Ordering::Greater
+            // look only files for req_path
+            if path.starts_with(&req_path) {
+                // remove req_path from path to reach files in this directory
+                let path = path.replace(&req_path, "");
+                let path_splited: Vec<&str> = path.split('/').collect();
+
+                // if path have '/' it is a directory
+                let mime = if path_splited.len() > 1 {
+                    "dir".to_owned()
                 } else {
-                    a.name.to_lowercase().cmp(&b.name.to_lowercase())
+                    mime.to_owned()
+                };
+
+                let file = File {
+                    name: path_splited[0].to_owned(),
+                    mime,
+                };
+
+                // avoid adding duplicates, a directory may occur more than once
+                if !file_list.contains(&file) {
+                    file_list.push(file);
                 }
-            });
-
-            Some(FileList {
-                metadata: MetaData {
-                    name: rows[0].get(0),
-                    version: rows[0].get(1),
-                    version_or_latest: version_or_latest.to_string(),
-                    description: rows[0].get(2),
-                    target_name: rows[0].get(3),
-                    rustdoc_status: rows[0].get(4),
-                    default_target: rows[0].get(6),
-                    doc_targets: MetaData::parse_doc_targets(rows[0].get(7)),
-                    yanked: rows[0].get(8),
-                    rustdoc_css_file: get_correct_docsrs_style_file(rows[0].get(9)).unwrap(),
-                },
-                files: file_list,
-            })
-        } else {
-            None
+            }
         }
     }
+
+    file_list
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -171,95 +227,129 @@ impl_webpage! {
     SourcePage = "crate/source.html",
 }
 
-pub fn source_browser_handler(req: &mut Request) -> IronResult<Response> {
-    let router = extension!(req, Router);
-    let mut crate_name = cexpect!(req, router.find("name"));
-    let req_version = cexpect!(req, router.find("version"));
-    let pool = extension!(req, Pool);
-    let mut conn = pool.get()?;
-
-    let mut req_path = req.url.path();
-    // remove first elements from path which is /crate/:name/:version/source
-    req_path.drain(0..4);
-
-    let v = match_version(&mut conn, crate_name, Some(req_version))?;
-    if let Some(new_name) = &v.corrected_name {
-        // `match_version` checked against -/_ typos, so if we have a name here we should
-        // use that instead
-        crate_name = new_name;
-    }
-    let (version, version_or_latest) = match v.version {
-        MatchSemver::Latest((version, _)) => (version, "latest".to_string()),
-        MatchSemver::Exact((version, _)) => (version.clone(), version),
-        MatchSemver::Semver((version, _)) => {
-            let url = ctry!(
-                req,
-                Url::parse(&format!(
-                    "{}/crate/{}/{}/source/{}",
-                    redirect_base(req),
-                    crate_name,
-                    version,
-                    req_path.join("/"),
-                )),
-            );
-
-            return Ok(super::redirect(url));
-        }
-    };
-
-    // get path (req_path) for FileList::from_path and actual path for super::file::File::from_path
-    let (req_path, file_path) = {
-        let mut req_path = req.url.path();
-        // remove first elements from path which is /crate/:name/:version/source
-        for _ in 0..4 {
-            req_path.remove(0);
-        }
-        let file_path = req_path.join("/");
-
-        // FileList::from_path is only working for directories
-        // remove file name if it's not a directory
-        if let Some(last) = req_path.last_mut() {
-            if !last.is_empty() {
-                *last = "";
-            }
-        }
-
-        // remove crate name and version from req_path
-        let path = req_path
-            .join("/")
-            .replace(&format!("{}/{}/", crate_name, version), "");
+#[derive(Deserialize)]
+pub(crate) struct SourceBrowserHandlerParams {
+    name: String,
+    version: String,
+    #[serde(default)]
+    path: String,
+}
 
-        (path, file_path)
+pub(super) async fn source_browser_handler(
+    Path(params): Path<SourceBrowserHandlerParams>,
+    Extension(pool): Extension<Pool>,
+    Extension(storage): Extension<Arc<Storage>>,
+    Extension(csp): Extension<Arc<Csp>>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> AxumResult<Response> {
+    let SourceBrowserHandlerParams {
+        name,
+        version: req_version,
+        path: file_path,
+    } = params;
+
+    // FileList::from_path is only working for directories,
+    // remove the file name if the path isn't a directory
+    let req_path = match file_path.rfind('/') {
+        Some(end) => file_path[..=end].to_owned(),
+        None => String::new(),
     };
 
-    let storage = extension!(req, Storage);
-    let archive_storage: bool = {
-        let rows = ctry!(
-            req,
-            conn.query(
+    let (crate_name, version, version_or_latest, archive_storage, validators) = {
+        let pool = pool.clone();
+        let file_path = file_path.clone();
+        let base = redirect_base(&headers);
+        let validator_key = format!("{}\n{}", uri.path(), uri.query().unwrap_or_default());
+        let result = super::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+
+            let v = match_version(&mut conn, &name, Some(&req_version))?;
+            // `match_version` checked against -/_ typos, so if we have a name here we should
+            // use that instead
+            let crate_name = v.corrected_name.unwrap_or(name);
+            let (version, version_or_latest) = match v.version {
+                MatchSemver::Latest((version, _)) => (version, "latest".to_string()),
+                MatchSemver::Exact((version, _)) => (version.clone(), version),
+                MatchSemver::Semver((version, _)) => {
+                    let url = Url::parse(&format!(
+                        "{}/crate/{}/{}/source/{}",
+                        base, crate_name, version, file_path,
+                    ))
+                    .map_err(|err| anyhow!(err).context("failed to build the redirect URL"))?;
+
+                    return Ok(Err(super::redirect(url)));
+                }
+            };
+
+            let rows = conn.query(
                 "
-                SELECT archive_storage
-                FROM releases 
+                SELECT
+                    releases.archive_storage,
+                    releases.id,
+                    releases.yanked,
+                    builds.id,
+                    builds.build_time
+                FROM releases
                 INNER JOIN crates ON releases.crate_id = crates.id
-                WHERE 
-                    name = $1 AND 
+                LEFT JOIN builds ON builds.rid = releases.id
+                WHERE
+                    name = $1 AND
                     version = $2
+                ORDER BY builds.id DESC
+                LIMIT 1
                 ",
-                &[&crate_name, &version]
-            )
-        );
-        // this unwrap is safe because `match_version` guarantees that the `crate_name`/`version`
-        // combination exists.
-        let row = rows.get(0).unwrap();
+                &[&crate_name, &version],
+            )?;
+            // this unwrap is safe because `match_version` guarantees that the `crate_name`/`version`
+            // combination exists.
+            let row = rows.get(0).unwrap();
+
+            // The sources only change when the release is rebuilt.
+            let mut validators = CacheValidators::new(format!(
+                "{}:{:?}:{:?}\n{}",
+                row.get::<_, i32>(1),
+                row.get::<_, Option<bool>>(2),
+                row.get::<_, Option<i32>>(3),
+                validator_key,
+            ));
+            if let Some(build_time) = row.get(4) {
+                validators = validators.last_modified(build_time);
+            }
+
+            Ok(Ok((
+                crate_name,
+                version,
+                version_or_latest,
+                row.get::<_, bool>(0),
+                validators,
+            )))
+        })
+        .await?;
 
-        row.get::<_, bool>(0)
+        match result {
+            Ok(matched) => matched,
+            Err(redirect) => return Ok(redirect),
+        }
     };
 
+    if validators.is_fresh(&headers) {
+        // The cached page contains the nonce of the Content Security Policy it was served with,
+        // sending a new policy would block its scripts.
+        csp.suppress(true);
+        let mut response = validators.not_modified();
+        if let Some(cache_control) = SourcePage::cache_control() {
+            response.headers_mut().insert(CACHE_CONTROL, cache_control);
+        }
+        return Ok(response);
+    }
+
     // try to get actual file first
     // skip if request is a directory
-    let blob = if !file_path.ends_with('/') {
+    let blob = if !file_path.is_empty() && !file_path.ends_with('/') {
         storage
-            .fetch_source_file(crate_name, &version, &file_path, archive_storage)
+            .fetch_source_file(&crate_name, &version, &file_path, archive_storage)
+            .await
             .ok()
     } else {
         None
@@ -268,7 +358,7 @@ pub fn source_browser_handler(req: &mut Request) -> IronResult<Response> {
     let (file_content, is_rust_source) = if let Some(blob) = blob {
         // serve the file with DatabaseFileHandler if file isn't text and not empty
         if !blob.mime.starts_with("text") && !blob.is_empty() {
-            return Ok(DbFile(blob).serve());
+            return Ok(DbFile(blob).serve_with(&validators));
         } else if blob.mime.starts_with("text") && !blob.is_empty() {
             (
                 String::from_utf8(blob.content).ok(),
@@ -282,21 +372,26 @@ pub fn source_browser_handler(req: &mut Request) -> IronResult<Response> {
     };
 
     let file_list = FileList::from_path(
-        &mut conn,
-        crate_name,
+        pool,
+        &storage,
+        &crate_name,
         &version,
         &version_or_latest,
         &req_path,
+        archive_storage,
     )
+    .await?
     .ok_or(Nope::ResourceNotFound)?;
 
-    SourcePage {
+    let mut response = SourcePage {
         file_list,
         show_parent_link: !req_path.is_empty(),
         file_content,
         is_rust_source,
     }
-    .into_response(req)
+    .into_response();
+    validators.set_headers(&mut response);
+    Ok(response)
 }
 
 #[cfg(test)]
@@ -304,6 +399,43 @@ mod tests {
     use crate::test::*;
     use test_case::test_case;
 
+    #[test_case(true)]
+    #[test_case(false)]
+    fn conditional_requests(archive_storage: bool) {
+        wrapper(|env| {
+            env.fake_release()
+                .archive_storage(archive_storage)
+                .name("fake")
+                .version("0.1.0")
+                .source_file("some_filename.rs", b"some_random_content")
+                .create()?;
+            let web = env.frontend();
+
+            let resp = web
+                .get("/crate/fake/0.1.0/source/some_filename.rs")
+                .send()?;
+            assert!(resp.status().is_success());
+            assert!(resp.headers().contains_key("Content-Security-Policy"));
+            let etag = resp.headers()["ETag"].clone();
+            resp.bytes()?;
+
+            let resp = web
+                .get("/crate/fake/0.1.0/source/some_filename.rs")
+                .header("If-None-Match", etag.clone())
+                .send()?;
+            assert_eq!(resp.status(), 304);
+            assert_eq!(resp.headers()["ETag"], etag);
+            assert!(!resp.headers().contains_key("Content-Security-Policy"));
+
+            let resp = web
+                .get("/crate/fake/0.1.0/source/")
+                .header("If-None-Match", etag)
+                .send()?;
+            assert_eq!(resp.status(), 200);
+            Ok(())
+        });
+    }
+
     #[test_case(true)]
     #[test_case(false)]
     fn fetch_source_file_content(archive_storage: bool) {
@@ -1,15 +1,22 @@
-use super::{error::Nope, redirect, redirect_base, STATIC_FILE_CACHE_DURATION};
-use crate::utils::report_error;
-use anyhow::Context;
-use chrono::prelude::*;
-use iron::{
-    headers::CacheDirective,
-    headers::{CacheControl, ContentLength, ContentType, LastModified},
-    status::Status,
-    IronResult, Request, Response, Url,
+use super::{
+    conditional::CacheValidators,
+    error::{AxumResult, Nope},
+    redirect, redirect_base, STATIC_FILE_CACHE_DURATION,
+};
+use anyhow::{anyhow, Context};
+use axum::{
+    body::{boxed, Full},
+    extract::Path,
+    headers::{HeaderMapExt, LastModified},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE},
+        HeaderMap, HeaderValue, Uri,
+    },
+    response::Response,
 };
 use mime_guess::MimeGuess;
-use std::{ffi::OsStr, fs, path::Path};
+use std::{borrow::Cow, ffi::OsStr, fs, path::Path as FsPath, time::SystemTime};
+use url::Url;
 
 const VENDORED_CSS: &str = include_str!(concat!(env!("OUT_DIR"), "/vendored.css"));
 const STYLE_CSS: &str = include_str!(concat!(env!("OUT_DIR"), "/style.css"));
@@ -18,29 +25,30 @@ const RUSTDOC_2021_12_05_CSS: &str =
     include_str!(concat!(env!("OUT_DIR"), "/rustdoc-2021-12-05.css"));
 const STATIC_SEARCH_PATHS: &[&str] = &["static", "vendor"];
 
-pub(crate) fn static_handler(req: &mut Request) -> IronResult<Response> {
-    let mut file = req.url.path();
-    file.drain(..2).for_each(std::mem::drop);
-    let file = file.join("/");
-
-    Ok(match file.as_str() {
-        "vendored.css" => serve_resource(VENDORED_CSS, ContentType("text/css".parse().unwrap())),
-        "style.css" => serve_resource(STYLE_CSS, ContentType("text/css".parse().unwrap())),
-        "rustdoc.css" => serve_resource(RUSTDOC_CSS, ContentType("text/css".parse().unwrap())),
-        "rustdoc-2021-12-05.css" => serve_resource(
-            RUSTDOC_2021_12_05_CSS,
-            ContentType("text/css".parse().unwrap()),
-        ),
-        file => serve_file(file)?,
-    })
+pub(crate) async fn static_handler(
+    Path(file): Path<String>,
+    headers: HeaderMap,
+) -> AxumResult<Response> {
+    let css = Some("text/css");
+    let (resource, content_type): (Cow<'static, [u8]>, _) = match file.as_str() {
+        "vendored.css" => (VENDORED_CSS.as_bytes().into(), css),
+        "style.css" => (STYLE_CSS.as_bytes().into(), css),
+        "rustdoc.css" => (RUSTDOC_CSS.as_bytes().into(), css),
+        "rustdoc-2021-12-05.css" => (RUSTDOC_2021_12_05_CSS.as_bytes().into(), css),
+        _ => {
+            let (contents, content_type) = super::spawn_blocking(move || load_file(&file)).await?;
+            (contents.into(), content_type)
+        }
+    };
+    Ok(serve_resource(&headers, resource, content_type))
 }
 
-fn serve_file(file: &str) -> IronResult<Response> {
+fn load_file(file: &str) -> AxumResult<(Vec<u8>, Option<&'static str>)> {
     // Find the first path that actually exists
     let path = STATIC_SEARCH_PATHS
         .iter()
         .find_map(|root| {
-            let path = Path::new(root).join(file);
+            let path = FsPath::new(root).join(file);
             if !path.exists() {
                 return None;
             }
@@ -59,11 +67,7 @@ fn serve_file(file: &str) -> IronResult<Response> {
         .ok_or(Nope::ResourceNotFound)?;
 
     let contents = fs::read(&path)
-        .with_context(|| format!("failed to read static file {}", path.display()))
-        .map_err(|e| {
-            report_error(&e);
-            Nope::InternalServerError
-        })?;
+        .with_context(|| format!("failed to read static file {}", path.display()))?;
 
     // If we can detect the file's mime type, set it
     // MimeGuess misses a lot of the file types we need, so there's a small wrapper
@@ -72,69 +76,61 @@ fn serve_file(file: &str) -> IronResult<Response> {
         .extension()
         .and_then(OsStr::to_str)
         .and_then(|ext| match ext {
-            "eot" => Some(ContentType(
-                "application/vnd.ms-fontobject".parse().unwrap(),
-            )),
-            "woff2" => Some(ContentType("application/font-woff2".parse().unwrap())),
-            "ttf" => Some(ContentType("application/x-font-ttf".parse().unwrap())),
-
-            _ => MimeGuess::from_path(&path)
-                .first()
-                .map(|mime| ContentType(mime.as_ref().parse().unwrap())),
+            "eot" => Some("application/vnd.ms-fontobject"),
+            "woff2" => Some("application/font-woff2"),
+            "ttf" => Some("application/x-font-ttf"),
+
+            _ => MimeGuess::from_path(&path).first_raw(),
         });
 
     if file == "opensearch.xml" {
-        content_type = Some(ContentType(
-            "application/opensearchdescription+xml".parse().unwrap(),
-        ));
+        content_type = Some("application/opensearchdescription+xml");
     }
 
-    Ok(serve_resource(contents, content_type))
+    Ok((contents, content_type))
 }
 
-fn serve_resource<R, C>(resource: R, content_type: C) -> Response
-where
-    R: AsRef<[u8]>,
-    C: Into<Option<ContentType>>,
-{
-    let mut response = Response::with((Status::Ok, resource.as_ref()));
-
-    let cache = vec![
-        CacheDirective::Public,
-        CacheDirective::MaxAge(STATIC_FILE_CACHE_DURATION as u32),
-    ];
-    response.headers.set(CacheControl(cache));
+fn serve_resource(
+    headers: &HeaderMap,
+    resource: Cow<'static, [u8]>,
+    content_type: Option<&'static str>,
+) -> Response {
+    let validators = CacheValidators::new(&resource);
+    let mut response = if validators.is_fresh(headers) {
+        validators.not_modified()
+    } else {
+        let mut response = Response::new(boxed(Full::from(resource)));
+        response
+            .headers_mut()
+            .typed_insert(LastModified::from(SystemTime::now()));
+
+        if let Some(content_type) = content_type {
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+        validators.set_headers(&mut response);
+        response
+    };
 
-    response
-        .headers
-        .set(ContentLength(resource.as_ref().len() as u64));
-    response.headers.set(LastModified(
-        Utc::now()
-            .format("%a, %d %b %Y %T %Z")
-            .to_string()
-            .parse()
-            .unwrap(),
-    ));
-
-    if let Some(content_type) = content_type.into() {
-        response.headers.set(content_type);
-    }
+    response.headers_mut().insert(
+        CACHE_CONTROL,
+        HeaderValue::from_str(&format!("public, max-age={}", STATIC_FILE_CACHE_DURATION)).unwrap(),
+    );
 
     response
 }
 
-pub(super) fn ico_handler(req: &mut Request) -> IronResult<Response> {
-    if let Some(&"favicon.ico") = req.url.path().last() {
+pub(super) fn ico_handler(headers: &HeaderMap, uri: &Uri) -> AxumResult<Response> {
+    if uri.path().rsplit('/').next() == Some("favicon.ico") {
         // if we're looking for exactly "favicon.ico", we need to defer to the handler that
-        // actually serves it, so return a 404 here to make the main handler carry on
-        Err(Nope::ResourceNotFound.into())
+        // actually serves it, it's not served here
+        Err(Nope::ResourceNotFound)
     } else {
         // if we're looking for something like "favicon-20190317-1.35.0-nightly-c82834e2b.ico",
         // redirect to the plain one so that the above branch can trigger with the correct filename
-        let url = ctry!(
-            req,
-            Url::parse(&format!("{}/favicon.ico", redirect_base(req))),
-        );
+        let url = Url::parse(&format!("{}/favicon.ico", redirect_base(headers)))
+            .map_err(|err| anyhow!(err).context("failed to build the favicon URL"))?;
 
         Ok(redirect(url))
     }
@@ -142,10 +138,10 @@ pub(super) fn ico_handler(req: &mut Request) -> IronResult<Response> {
 
 #[cfg(test)]
 mod tests {
-    use iron::status::Status;
-
-    use super::{serve_file, STATIC_SEARCH_PATHS, STYLE_CSS, VENDORED_CSS};
+    use super::{load_file, STATIC_SEARCH_PATHS, STYLE_CSS, VENDORED_CSS};
     use crate::test::wrapper;
+    use crate::web::error::Nope;
+    use reqwest::{header, StatusCode};
     use std::fs;
 
     #[test]
@@ -287,6 +283,37 @@ mod tests {
         });
     }
 
+    #[test]
+    fn static_file_not_modified() {
+        wrapper(|env| {
+            let web = env.frontend();
+
+            for path in &["/-/static/style.css", "/-/static/menu.js"] {
+                let resp = web.get(path).send()?;
+                assert!(resp.status().is_success());
+                let etag = resp.headers()[header::ETAG].clone();
+                resp.bytes()?;
+
+                let resp = web
+                    .get(path)
+                    .header(header::IF_NONE_MATCH, etag.clone())
+                    .send()?;
+                assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+                assert_eq!(resp.headers()[header::ETAG], etag);
+                assert!(resp.headers().contains_key(header::CACHE_CONTROL));
+
+                let resp = web
+                    .get(path)
+                    .header(header::IF_NONE_MATCH, "\"something-else\"")
+                    .send()?;
+                assert_eq!(resp.status(), StatusCode::OK);
+                resp.bytes()?;
+            }
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn static_file_that_doesnt_exist() {
         wrapper(|env| {
@@ -337,15 +364,14 @@ mod tests {
         ];
 
         for path in PATHS {
-            // This doesn't test an actual web request as the web framework used at the time of
-            // writing this test (iron 0.5) already resolves `..` before calling any handler.
+            // This doesn't test an actual web request, the path parameters are percent-decoded
+            // before they're passed to the handler, so all these paths reach `load_file` with
+            // `..` in them.
             //
-            // Still, the test ensures the underlying function called by the request handler to
-            // serve the file also includes protection for path traversal, in the event we switch
-            // to a framework that doesn't include builtin protection in the future.
-            assert_eq!(
-                Some(Status::NotFound),
-                serve_file(path).unwrap_err().response.status,
+            // The test ensures the underlying function called by the request handler to serve the
+            // file includes protection for path traversal.
+            assert!(
+                matches!(load_file(path), Err(Nope::ResourceNotFound)),
                 "{} did not return a 404",
                 path
             );
@@ -0,0 +1,121 @@
+use crate::{
+    db::Pool,
+    impl_webpage,
+    storage::{storage_usage_report, StorageUsageReport},
+    web::error::AxumResult,
+};
+use axum::{extract::Extension, response::IntoResponse};
+use serde::Serialize;
+
+/// How many crates are listed on the page.
+const TOP_CRATES: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct StorageUsagePage {
+    report: StorageUsageReport,
+}
+
+impl_webpage! {
+    StorageUsagePage = "admin/storage.html",
+}
+
+/// Lists the space the files of all releases take per storage backend, and the crates using the
+/// most of it.
+pub(crate) async fn storage_usage_handler(
+    Extension(pool): Extension<Pool>,
+) -> AxumResult<impl IntoResponse> {
+    let report = super::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        Ok(storage_usage_report(&mut conn, TOP_CRATES)?)
+    })
+    .await?;
+
+    Ok(StorageUsagePage { report })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::wrapper;
+    use crate::Context;
+    use kuchiki::traits::TendrilSink;
+
+    #[test]
+    fn lists_top_crates() {
+        wrapper(|env| {
+            env.override_config(|config| config.admin_token = Some("admin".into()));
+            for version in ["0.1.0", "0.2.0"] {
+                env.fake_release()
+                    .name("foo")
+                    .version(version)
+                    .archive_storage(true)
+                    .rustdoc_file_with("foo/index.html", &[b'x'; 10_000])
+                    .create()?;
+            }
+            env.fake_release()
+                .name("bar")
+                .version("0.1.0")
+                .archive_storage(true)
+                .create()?;
+
+            let response = env
+                .frontend()
+                .get("/-/admin/storage")
+                .bearer_auth("admin")
+                .send()?;
+            assert!(response.status().is_success());
+            let page = kuchiki::parse_html().one(response.text()?);
+
+            let cells = |selector| -> Vec<Vec<String>> {
+                page.select(selector)
+                    .unwrap()
+                    .map(|row| {
+                        row.as_node()
+                            .select("td")
+                            .unwrap()
+                            .map(|cell| cell.text_contents().trim().to_owned())
+                            .collect()
+                    })
+                    .collect()
+            };
+            let totals = cells("table.totals tbody tr");
+            let kinds: Vec<_> = totals
+                .iter()
+                .map(|row| (row[0].as_str(), row[1].as_str(), row[2].as_str()))
+                .collect();
+            assert_eq!(
+                kinds,
+                [
+                    ("database", "build-logs", "3"),
+                    ("database", "rustdoc", "3"),
+                    ("database", "sources", "3"),
+                ]
+            );
+            let crates = cells("table.crates tbody tr");
+            let names: Vec<_> = crates
+                .iter()
+                .map(|row| (row[0].as_str(), row[1].as_str()))
+                .collect();
+            assert_eq!(names, [("foo", "2"), ("bar", "1")]);
+
+            // the totals are exported as metrics too
+            let families = env.metrics().gather(&env.pool()?, &env.build_queue())?;
+            let family = families
+                .iter()
+                .find(|family| family.get_name() == "docsrs_storage_usage_uncompressed_bytes")
+                .unwrap();
+            let rustdoc = family
+                .get_metric()
+                .iter()
+                .find(|metric| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .any(|label| label.get_name() == "kind" && label.get_value() == "rustdoc")
+                })
+                .unwrap();
+            assert!(rustdoc.get_gauge().get_value() >= 20_000.0);
+
+            Ok(())
+        })
+    }
+}